@@ -0,0 +1,71 @@
+//! Compares throughput of single-lock database with sharded one under many concurrent clients.
+//!
+//! Run with `cargo bench --bench throughput`.
+
+use std::time::{Duration, Instant};
+
+use redis_rs::{
+    client::Client,
+    server::{self, Config},
+    utils::command::Value,
+};
+use tokio::net::TcpListener;
+
+const CLIENTS: usize = 64;
+const OPS_PER_CLIENT: usize = 2_000;
+
+async fn spawn_server(shards: usize) -> anyhow::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?.to_string();
+    let config = Config {
+        shards: Some(shards),
+        ..Default::default()
+    };
+    tokio::spawn(server::run(listener, config));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    Ok(addr)
+}
+
+/// Runs mixed set/get workload with large values, so time spent under lock is noticeable.
+async fn run(shards: usize) -> anyhow::Result<f64> {
+    let addr = spawn_server(shards).await?;
+    let value = Value::Array((0..256).map(Value::Number).collect());
+
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..CLIENTS)
+        .map(|c| {
+            let addr = addr.clone();
+            let value = value.clone();
+            tokio::spawn(async move {
+                let mut client = Client::connect(&addr).await?;
+                for i in 0..OPS_PER_CLIENT {
+                    let key = format!("key:{}", (c * OPS_PER_CLIENT + i) % 1_024);
+                    if i % 2 == 0 {
+                        client.try_set(&key, value.clone()).await?;
+                    } else {
+                        client.try_get(&key).await?;
+                    }
+                }
+                anyhow::Ok(())
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await??;
+    }
+
+    Ok((CLIENTS * OPS_PER_CLIENT) as f64 / start.elapsed().as_secs_f64())
+}
+
+fn main() -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    for shards in [1, 4, 16, 64] {
+        let ops = runtime.block_on(run(shards))?;
+        println!("shards: {shards:>3} | {CLIENTS} clients | {ops:>10.0} ops/s");
+    }
+
+    Ok(())
+}
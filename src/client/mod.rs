@@ -1,13 +1,33 @@
-use tokio::net::TcpStream;
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use tokio::net::{TcpStream, UnixStream};
 
 use crate::{
     error::Error,
-    server::protocol::{Connection, Response},
-    utils::command::{Command, Value},
+    server::{
+        protocol::{Connection, Response, Transport},
+        storage::SortedSet,
+        stream::StreamId,
+    },
+    utils::command::{Command, Member, Value, to_score, wrong_arity},
 };
 
+mod convert;
+mod pipeline;
+mod scan;
+mod subscription;
+mod tls;
+
+pub use pipeline::Pipeline;
+pub use scan::ScanIter;
+pub use subscription::Subscription;
+pub use tls::TlsOptions;
+
+/// Connection of client, boxed so that the same client works over plain TCP and TLS.
+type ClientConnection = Connection<Box<dyn Transport>>;
+
 pub struct Client {
-    connection: Connection,
+    connection: ClientConnection,
 }
 
 fn response_to_value(response: Result<Response, Error>) -> Result<Option<Value>, Error> {
@@ -15,23 +35,87 @@ fn response_to_value(response: Result<Response, Error>) -> Result<Option<Value>,
         Ok(Response::Payload(val)) => Ok(Some(val)),
         Ok(Response::Null) => Ok(None),
         Ok(Response::Error(msg)) => Err(Error::DatabaseError { msg }),
+        Ok(Response::Multi(_)) => Err(Error::BadRequest {
+            msg: "unexpected multi-key response".into(),
+        }),
+        Ok(Response::Push(_)) => Err(Error::BadRequest {
+            msg: "unexpected push".into(),
+        }),
         Err(e) => Err(e),
     }
 }
 
+fn response_to_values(response: Result<Response, Error>) -> Result<Vec<Option<Value>>, Error> {
+    match response? {
+        Response::Multi(responses) => responses
+            .into_iter()
+            .map(|response| response_to_value(Ok(response)))
+            .collect(),
+        Response::Error(msg) => Err(Error::DatabaseError { msg }),
+        _ => Err(Error::BadRequest {
+            msg: "expected multi-key response".into(),
+        }),
+    }
+}
+
+fn check_expire(expire: Duration) -> Result<(), Error> {
+    // zero is reserved on the wire for keys without expiry
+    if expire.as_millis() == 0 {
+        return Err(Error::BadRequest {
+            msg: "invalid expire time".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Refuses command that would be sent without the arguments it needs.
+fn check_arity(empty: bool, command: &str) -> Result<(), Error> {
+    if empty {
+        return Err(wrong_arity(command));
+    }
+    Ok(())
+}
+
+fn check_timeout(timeout: Option<Duration>) -> Result<Option<Duration>, Error> {
+    match timeout {
+        // zero is reserved on the wire for waiting forever
+        Some(timeout) if timeout.as_millis() == 0 => Err(Error::BadRequest {
+            msg: "invalid timeout".into(),
+        }),
+        _ => Ok(timeout),
+    }
+}
+
 impl Client {
     pub async fn connect(to: &str) -> Result<Self, Error> {
         let stream = TcpStream::connect(to).await?;
 
-        let connection = Connection::new(stream);
+        Ok(Self::with_transport(stream))
+    }
+
+    /// Connects to server listening on Unix socket at `path`.
+    pub async fn connect_unix(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let stream = UnixStream::connect(path).await?;
+
+        Ok(Self::with_transport(stream))
+    }
 
-        Ok(Self { connection })
+    fn with_transport(stream: impl Transport + 'static) -> Self {
+        let stream: Box<dyn Transport> = Box::new(stream);
+        Self {
+            connection: Connection::new(stream),
+        }
     }
 
     pub async fn execute(&mut self, command: Command) -> Result<(), Error> {
         self.connection.write(command).await
     }
 
+    /// Starts queuing commands that will be sent together, see `Pipeline`.
+    pub fn pipeline(&mut self) -> Pipeline<'_> {
+        Pipeline::new(self)
+    }
+
     fn flatten_response_to_option(
         result: Result<Option<Response>, Error>,
     ) -> Result<Option<Value>, Error> {
@@ -59,6 +143,111 @@ impl Client {
         self.try_set(key, value).await.unwrap()
     }
 
+    /// Sets `key` that will be removed after `expire`.
+    pub async fn try_set_ex(
+        &mut self,
+        key: &str,
+        value: Value,
+        expire: Duration,
+    ) -> Result<Option<Value>, Error> {
+        check_expire(expire)?;
+        let command = Command::set_ex(key, value, expire);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn set_ex(&mut self, key: &str, value: Value, expire: Duration) -> Option<Value> {
+        self.try_set_ex(key, value, expire).await.unwrap()
+    }
+
+    /// Sets `key` only if it doesn't exist yet. Responds with `Value::Boolean` telling whether
+    /// value was written.
+    pub async fn try_set_nx(
+        &mut self,
+        key: &str,
+        value: Value,
+        expire: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        if let Some(expire) = expire {
+            check_expire(expire)?;
+        }
+        let command = Command::set_nx(key, value, expire);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn set_nx(
+        &mut self,
+        key: &str,
+        value: Value,
+        expire: Option<Duration>,
+    ) -> Option<Value> {
+        self.try_set_nx(key, value, expire).await.unwrap()
+    }
+
+    /// Sets `key` only if it already exists. Responds with `Value::Boolean` telling whether
+    /// value was written.
+    pub async fn try_set_xx(
+        &mut self,
+        key: &str,
+        value: Value,
+        expire: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        if let Some(expire) = expire {
+            check_expire(expire)?;
+        }
+        let command = Command::set_xx(key, value, expire);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn set_xx(
+        &mut self,
+        key: &str,
+        value: Value,
+        expire: Option<Duration>,
+    ) -> Option<Value> {
+        self.try_set_xx(key, value, expire).await.unwrap()
+    }
+
+    /// Sets `key` and returns its previous value. Same as `try_set`, named after redis
+    /// `GETSET` for readability.
+    pub async fn try_get_set(&mut self, key: &str, value: Value) -> Result<Option<Value>, Error> {
+        self.try_set(key, value).await
+    }
+
+    pub async fn get_set(&mut self, key: &str, value: Value) -> Option<Value> {
+        self.try_get_set(key, value).await.unwrap()
+    }
+
+    /// Replaces value of `key` only if it currently equals `expected`. Responds with
+    /// `Value::Boolean` telling whether value was swapped.
+    pub async fn try_compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Value,
+        value: Value,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::compare_and_swap(key, expected, value);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn compare_and_swap(
+        &mut self,
+        key: &str,
+        expected: Value,
+        value: Value,
+    ) -> Option<Value> {
+        self.try_compare_and_swap(key, expected, value)
+            .await
+            .unwrap()
+    }
+
     pub async fn try_delete(&mut self, key: &str) -> Result<Option<Value>, Error> {
         let command = Command::delete(key);
         self.execute(command).await?;
@@ -69,4 +258,1346 @@ impl Client {
     pub async fn delete(&mut self, key: &str) -> Option<Value> {
         self.try_delete(key).await.unwrap()
     }
+
+    /// Returns `Value::Boolean(false)` if key doesn't exist.
+    pub async fn try_expire(
+        &mut self,
+        key: &str,
+        expire: Duration,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::expire(key, expire);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn expire(&mut self, key: &str, expire: Duration) -> Option<Value> {
+        self.try_expire(key, expire).await.unwrap()
+    }
+
+    /// Returns remaining time to live in milliseconds, `-1` if key never expires or `None` if
+    /// key doesn't exist.
+    pub async fn try_ttl(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::ttl(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn ttl(&mut self, key: &str) -> Option<Value> {
+        self.try_ttl(key).await.unwrap()
+    }
+
+    /// Removes expiry from key. Returns `Value::Boolean(true)` if key had one.
+    pub async fn try_persist(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::persist(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn persist(&mut self, key: &str) -> Option<Value> {
+        self.try_persist(key).await.unwrap()
+    }
+
+    /// Writes snapshot on server and waits until it is done.
+    pub async fn try_save(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::save();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn save(&mut self) -> Option<Value> {
+        self.try_save().await.unwrap()
+    }
+
+    /// Starts writing snapshot in background on server.
+    pub async fn try_bg_save(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::bg_save();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn bg_save(&mut self) -> Option<Value> {
+        self.try_bg_save().await.unwrap()
+    }
+
+    /// Compacts append only file on server to current state of database.
+    pub async fn try_rewrite_aof(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::rewrite_aof();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn rewrite_aof(&mut self) -> Option<Value> {
+        self.try_rewrite_aof().await.unwrap()
+    }
+
+    /// Reads all `keys` at once. Missing keys are returned as `None`.
+    pub async fn try_mget(&mut self, keys: &[&str]) -> Result<Vec<Option<Value>>, Error> {
+        let command = Command::mget(keys);
+        self.execute(command).await?;
+
+        response_to_values(self.connection.read().await?.ok_or(Error::ConnectionClosed))
+    }
+
+    pub async fn mget(&mut self, keys: &[&str]) -> Vec<Option<Value>> {
+        self.try_mget(keys).await.unwrap()
+    }
+
+    /// Sets all `entries` atomically and returns their previous values.
+    pub async fn try_mset(
+        &mut self,
+        entries: Vec<(String, Value)>,
+    ) -> Result<Vec<Option<Value>>, Error> {
+        let command = Command::mset(entries);
+        self.execute(command).await?;
+
+        response_to_values(self.connection.read().await?.ok_or(Error::ConnectionClosed))
+    }
+
+    pub async fn mset(&mut self, entries: Vec<(String, Value)>) -> Vec<Option<Value>> {
+        self.try_mset(entries).await.unwrap()
+    }
+
+    /// Deletes all `keys` atomically and returns their values.
+    pub async fn try_mdel(&mut self, keys: &[&str]) -> Result<Vec<Option<Value>>, Error> {
+        let command = Command::mdel(keys);
+        self.execute(command).await?;
+
+        response_to_values(self.connection.read().await?.ok_or(Error::ConnectionClosed))
+    }
+
+    pub async fn mdel(&mut self, keys: &[&str]) -> Vec<Option<Value>> {
+        self.try_mdel(keys).await.unwrap()
+    }
+
+    /// Returns `Value::Number` with count of existing keys.
+    pub async fn try_exists(&mut self, keys: &[&str]) -> Result<Option<Value>, Error> {
+        let command = Command::exists(keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn exists(&mut self, keys: &[&str]) -> Option<Value> {
+        self.try_exists(keys).await.unwrap()
+    }
+
+    /// Returns `Value::Array` of all keys matching glob `pattern`. Walks the whole keyspace in
+    /// one go, `scan_iter` is cheaper for the server when there are many keys.
+    pub async fn try_keys(&mut self, pattern: &str) -> Result<Option<Value>, Error> {
+        let command = Command::keys(pattern);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn keys(&mut self, pattern: &str) -> Option<Value> {
+        self.try_keys(pattern).await.unwrap()
+    }
+
+    /// Returns keys of the batch after `cursor` (0 for the first one), optionally only those
+    /// matching glob `pattern`, together with cursor of the next batch. Cursor of 0 means that
+    /// all keys were walked. `count` is how many keys server walks, so batches can be empty.
+    pub async fn try_scan(
+        &mut self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> Result<(u64, Vec<String>), Error> {
+        let command = Command::scan(cursor, pattern, count);
+        self.execute(command).await?;
+
+        let bad_response = || Error::BadRequest {
+            msg: "expected cursor and array of keys".into(),
+        };
+        let Some(Value::Array(reply)) =
+            Self::flatten_response_to_option(self.connection.read().await)?
+        else {
+            return Err(bad_response());
+        };
+        let [Value::String(cursor), Value::Array(keys)] =
+            <[Value; 2]>::try_from(reply).map_err(|_| bad_response())?
+        else {
+            return Err(bad_response());
+        };
+        let cursor = cursor.parse().map_err(|_| bad_response())?;
+        let keys = keys
+            .into_iter()
+            .map(|key| match key {
+                Value::String(key) => Ok(key),
+                _ => Err(bad_response()),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((cursor, keys))
+    }
+
+    pub async fn scan(
+        &mut self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: Option<usize>,
+    ) -> (u64, Vec<String>) {
+        self.try_scan(cursor, pattern, count).await.unwrap()
+    }
+
+    /// Returns stream of all keys matching glob `pattern`, fetched with `SCAN` in batches of
+    /// about `count` keys.
+    pub fn scan_iter(&mut self, pattern: Option<&str>, count: Option<usize>) -> ScanIter<'_> {
+        ScanIter::new(self, pattern, count)
+    }
+
+    /// Returns `Value::String` with name of the type of value under `key`, `"none"` if it
+    /// doesn't exist.
+    pub async fn try_key_type(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::key_type(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn key_type(&mut self, key: &str) -> Option<Value> {
+        self.try_key_type(key).await.unwrap()
+    }
+
+    /// Moves value under `key` to `new_key`, overwriting it. Fails if `key` doesn't exist.
+    pub async fn try_rename(&mut self, key: &str, new_key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::rename(key, new_key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn rename(&mut self, key: &str, new_key: &str) -> Option<Value> {
+        self.try_rename(key, new_key).await.unwrap()
+    }
+
+    /// Same as `try_rename`, but only if `new_key` doesn't exist. Responds with
+    /// `Value::Boolean` telling whether value was moved.
+    pub async fn try_rename_nx(
+        &mut self,
+        key: &str,
+        new_key: &str,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::rename_nx(key, new_key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn rename_nx(&mut self, key: &str, new_key: &str) -> Option<Value> {
+        self.try_rename_nx(key, new_key).await.unwrap()
+    }
+
+    /// Returns `Value::Number` with count of all keys.
+    pub async fn try_db_size(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::db_size();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn db_size(&mut self) -> Option<Value> {
+        self.try_db_size().await.unwrap()
+    }
+
+    /// Removes all keys.
+    pub async fn try_flush_all(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::flush_all();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn flush_all(&mut self) -> Option<Value> {
+        self.try_flush_all().await.unwrap()
+    }
+
+    /// Authenticates connection as `user`, see `server::acl`.
+    pub async fn try_auth(&mut self, user: &str, password: &str) -> Result<Option<Value>, Error> {
+        let command = Command::auth(user, password);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn auth(&mut self, user: &str, password: &str) -> Option<Value> {
+        self.try_auth(user, password).await.unwrap()
+    }
+
+    /// Creates `user` or changes the existing one by applying `rules` in order, like
+    /// `["on", ">password", "~cache:*", "+@read"]`.
+    pub async fn try_acl_set_user(
+        &mut self,
+        user: &str,
+        rules: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::acl_set_user(user, rules);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn acl_set_user(&mut self, user: &str, rules: &[&str]) -> Option<Value> {
+        self.try_acl_set_user(user, rules).await.unwrap()
+    }
+
+    /// Returns `Value::Array` with line of ACL file for every user.
+    pub async fn try_acl_list(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::acl_list();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn acl_list(&mut self) -> Option<Value> {
+        self.try_acl_list().await.unwrap()
+    }
+
+    /// Returns name of the user that connection is authenticated as.
+    pub async fn try_acl_whoami(&mut self) -> Result<Option<Value>, Error> {
+        let command = Command::acl_whoami();
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn acl_whoami(&mut self) -> Option<Value> {
+        self.try_acl_whoami().await.unwrap()
+    }
+
+    /// Stops the server once all connections finish their current commands, this one
+    /// included. `save` overrides whether server writes snapshot before it exits.
+    pub async fn try_shutdown(&mut self, save: Option<bool>) -> Result<Option<Value>, Error> {
+        let command = Command::shutdown(save);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn shutdown(&mut self, save: Option<bool>) -> Option<Value> {
+        self.try_shutdown(save).await.unwrap()
+    }
+
+    pub async fn try_incr(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::incr(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn incr(&mut self, key: &str) -> Option<Value> {
+        self.try_incr(key).await.unwrap()
+    }
+
+    pub async fn try_decr(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::decr(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn decr(&mut self, key: &str) -> Option<Value> {
+        self.try_decr(key).await.unwrap()
+    }
+
+    pub async fn try_incr_by(&mut self, key: &str, delta: i64) -> Result<Option<Value>, Error> {
+        let command = Command::incr_by(key, delta);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn incr_by(&mut self, key: &str, delta: i64) -> Option<Value> {
+        self.try_incr_by(key, delta).await.unwrap()
+    }
+
+    /// Adds `delta` to number under `key`, returns the new value as float.
+    pub async fn try_incr_by_float(
+        &mut self,
+        key: &str,
+        delta: f64,
+    ) -> Result<Option<Value>, Error> {
+        // NaN is rejected while parsing, which server can't recover from
+        to_score(delta)?;
+        let command = Command::incr_by_float(key, delta);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn incr_by_float(&mut self, key: &str, delta: f64) -> Option<Value> {
+        self.try_incr_by_float(key, delta).await.unwrap()
+    }
+
+    pub async fn try_hset(
+        &mut self,
+        key: &str,
+        fields: HashMap<String, Value>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(fields.is_empty(), "hset")?;
+        let command = Command::hset(key, fields);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hset(&mut self, key: &str, fields: HashMap<String, Value>) -> Option<Value> {
+        self.try_hset(key, fields).await.unwrap()
+    }
+
+    pub async fn try_hget(&mut self, key: &str, field: &str) -> Result<Option<Value>, Error> {
+        let command = Command::hget(key, field);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hget(&mut self, key: &str, field: &str) -> Option<Value> {
+        self.try_hget(key, field).await.unwrap()
+    }
+
+    pub async fn try_hdel(&mut self, key: &str, fields: &[&str]) -> Result<Option<Value>, Error> {
+        let command = Command::hdel(key, fields);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hdel(&mut self, key: &str, fields: &[&str]) -> Option<Value> {
+        self.try_hdel(key, fields).await.unwrap()
+    }
+
+    pub async fn try_hgetall(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::hgetall(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hgetall(&mut self, key: &str) -> Option<Value> {
+        self.try_hgetall(key).await.unwrap()
+    }
+
+    pub async fn try_hincr_by(
+        &mut self,
+        key: &str,
+        field: &str,
+        delta: i64,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::hincr_by(key, field, delta);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hincr_by(&mut self, key: &str, field: &str, delta: i64) -> Option<Value> {
+        self.try_hincr_by(key, field, delta).await.unwrap()
+    }
+
+    pub async fn try_hexists(&mut self, key: &str, field: &str) -> Result<Option<Value>, Error> {
+        let command = Command::hexists(key, field);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn hexists(&mut self, key: &str, field: &str) -> Option<Value> {
+        self.try_hexists(key, field).await.unwrap()
+    }
+
+    /// Pushes `values` one by one to the front of list, returns its new length.
+    pub async fn try_lpush(
+        &mut self,
+        key: &str,
+        values: Vec<Value>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(values.is_empty(), "lpush")?;
+        let command = Command::lpush(key, values);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn lpush(&mut self, key: &str, values: Vec<Value>) -> Option<Value> {
+        self.try_lpush(key, values).await.unwrap()
+    }
+
+    /// Pushes `values` one by one to the back of list, returns its new length.
+    pub async fn try_rpush(
+        &mut self,
+        key: &str,
+        values: Vec<Value>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(values.is_empty(), "rpush")?;
+        let command = Command::rpush(key, values);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn rpush(&mut self, key: &str, values: Vec<Value>) -> Option<Value> {
+        self.try_rpush(key, values).await.unwrap()
+    }
+
+    pub async fn try_lpop(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::lpop(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn lpop(&mut self, key: &str) -> Option<Value> {
+        self.try_lpop(key).await.unwrap()
+    }
+
+    pub async fn try_rpop(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::rpop(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn rpop(&mut self, key: &str) -> Option<Value> {
+        self.try_rpop(key).await.unwrap()
+    }
+
+    /// Returns elements between `start` and `stop` (inclusive), negative indexes count from the end.
+    pub async fn try_lrange(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::lrange(key, start, stop);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn lrange(&mut self, key: &str, start: i64, stop: i64) -> Option<Value> {
+        self.try_lrange(key, start, stop).await.unwrap()
+    }
+
+    pub async fn try_llen(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::llen(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn llen(&mut self, key: &str) -> Option<Value> {
+        self.try_llen(key).await.unwrap()
+    }
+
+    pub async fn try_ltrim(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::ltrim(key, start, stop);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn ltrim(&mut self, key: &str, start: i64, stop: i64) -> Option<Value> {
+        self.try_ltrim(key, start, stop).await.unwrap()
+    }
+
+    pub async fn try_lindex(&mut self, key: &str, index: i64) -> Result<Option<Value>, Error> {
+        let command = Command::lindex(key, index);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn lindex(&mut self, key: &str, index: i64) -> Option<Value> {
+        self.try_lindex(key, index).await.unwrap()
+    }
+
+    pub async fn try_lset(
+        &mut self,
+        key: &str,
+        index: i64,
+        value: Value,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::lset(key, index, value);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn lset(&mut self, key: &str, index: i64, value: Value) -> Option<Value> {
+        self.try_lset(key, index, value).await.unwrap()
+    }
+
+    /// Pops from the front of the first non-empty list of `keys`, waiting up to `timeout` (or
+    /// forever if it's `None`) for a push. Responds with array of the key and popped value, or
+    /// `None` if timeout passed.
+    pub async fn try_blpop(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(keys.is_empty(), "blpop")?;
+        let command = Command::blpop(keys, check_timeout(timeout)?);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn blpop(&mut self, keys: &[&str], timeout: Option<Duration>) -> Option<Value> {
+        self.try_blpop(keys, timeout).await.unwrap()
+    }
+
+    /// Same as `try_blpop`, but pops from the back of the list.
+    pub async fn try_brpop(
+        &mut self,
+        keys: &[&str],
+        timeout: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(keys.is_empty(), "brpop")?;
+        let command = Command::brpop(keys, check_timeout(timeout)?);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn brpop(&mut self, keys: &[&str], timeout: Option<Duration>) -> Option<Value> {
+        self.try_brpop(keys, timeout).await.unwrap()
+    }
+
+    /// Adds `members` to set, returns how many of them weren't there before.
+    pub async fn try_sadd(
+        &mut self,
+        key: &str,
+        members: Vec<Member>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(members.is_empty(), "sadd")?;
+        let command = Command::sadd(key, members);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sadd(&mut self, key: &str, members: Vec<Member>) -> Option<Value> {
+        self.try_sadd(key, members).await.unwrap()
+    }
+
+    /// Removes `members` from set, returns how many of them were there.
+    pub async fn try_srem(
+        &mut self,
+        key: &str,
+        members: Vec<Member>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::srem(key, members);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn srem(&mut self, key: &str, members: Vec<Member>) -> Option<Value> {
+        self.try_srem(key, members).await.unwrap()
+    }
+
+    pub async fn try_sismember(
+        &mut self,
+        key: &str,
+        member: Member,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::sismember(key, member);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sismember(&mut self, key: &str, member: Member) -> Option<Value> {
+        self.try_sismember(key, member).await.unwrap()
+    }
+
+    /// Returns all members as `Value::Set`.
+    pub async fn try_smembers(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::smembers(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn smembers(&mut self, key: &str) -> Option<Value> {
+        self.try_smembers(key).await.unwrap()
+    }
+
+    pub async fn try_scard(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::scard(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn scard(&mut self, key: &str) -> Option<Value> {
+        self.try_scard(key).await.unwrap()
+    }
+
+    /// Removes and returns random member of set.
+    pub async fn try_spop(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::spop(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn spop(&mut self, key: &str) -> Option<Value> {
+        self.try_spop(key).await.unwrap()
+    }
+
+    pub async fn try_srandmember(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::srandmember(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn srandmember(&mut self, key: &str) -> Option<Value> {
+        self.try_srandmember(key).await.unwrap()
+    }
+
+    pub async fn try_sunion(&mut self, keys: &[&str]) -> Result<Option<Value>, Error> {
+        let command = Command::sunion(keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sunion(&mut self, keys: &[&str]) -> Option<Value> {
+        self.try_sunion(keys).await.unwrap()
+    }
+
+    pub async fn try_sinter(&mut self, keys: &[&str]) -> Result<Option<Value>, Error> {
+        let command = Command::sinter(keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sinter(&mut self, keys: &[&str]) -> Option<Value> {
+        self.try_sinter(keys).await.unwrap()
+    }
+
+    /// Returns members of the first set that aren't in any of the others.
+    pub async fn try_sdiff(&mut self, keys: &[&str]) -> Result<Option<Value>, Error> {
+        let command = Command::sdiff(keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sdiff(&mut self, keys: &[&str]) -> Option<Value> {
+        self.try_sdiff(keys).await.unwrap()
+    }
+
+    /// Same as `try_sunion`, but result replaces `destination`. Returns size of the result.
+    pub async fn try_sunion_store(
+        &mut self,
+        destination: &str,
+        keys: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::sunion_store(destination, keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sunion_store(&mut self, destination: &str, keys: &[&str]) -> Option<Value> {
+        self.try_sunion_store(destination, keys).await.unwrap()
+    }
+
+    pub async fn try_sinter_store(
+        &mut self,
+        destination: &str,
+        keys: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::sinter_store(destination, keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sinter_store(&mut self, destination: &str, keys: &[&str]) -> Option<Value> {
+        self.try_sinter_store(destination, keys).await.unwrap()
+    }
+
+    pub async fn try_sdiff_store(
+        &mut self,
+        destination: &str,
+        keys: &[&str],
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::sdiff_store(destination, keys);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn sdiff_store(&mut self, destination: &str, keys: &[&str]) -> Option<Value> {
+        self.try_sdiff_store(destination, keys).await.unwrap()
+    }
+
+    /// Adds `members` with their scores or updates scores of existing ones. Returns number of
+    /// added members.
+    pub async fn try_zadd(
+        &mut self,
+        key: &str,
+        members: Vec<(Member, f64)>,
+    ) -> Result<Option<Value>, Error> {
+        check_arity(members.is_empty(), "zadd")?;
+        let mut set = SortedSet::new();
+        for (member, score) in members {
+            set.insert(member, to_score(score)?);
+        }
+        let command = Command::zadd(key, set);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zadd(&mut self, key: &str, members: Vec<(Member, f64)>) -> Option<Value> {
+        self.try_zadd(key, members).await.unwrap()
+    }
+
+    pub async fn try_zrem(
+        &mut self,
+        key: &str,
+        members: Vec<Member>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::zrem(key, members);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrem(&mut self, key: &str, members: Vec<Member>) -> Option<Value> {
+        self.try_zrem(key, members).await.unwrap()
+    }
+
+    /// Returns score of `member` as float.
+    pub async fn try_zscore(&mut self, key: &str, member: Member) -> Result<Option<Value>, Error> {
+        let command = Command::zscore(key, member);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zscore(&mut self, key: &str, member: Member) -> Option<Value> {
+        self.try_zscore(key, member).await.unwrap()
+    }
+
+    /// Adds `delta` to score of `member`, returns the new score.
+    pub async fn try_zincr_by(
+        &mut self,
+        key: &str,
+        member: Member,
+        delta: f64,
+    ) -> Result<Option<Value>, Error> {
+        // NaN is rejected while parsing, which server can't recover from
+        to_score(delta)?;
+        let command = Command::zincr_by(key, member, delta);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zincr_by(&mut self, key: &str, member: Member, delta: f64) -> Option<Value> {
+        self.try_zincr_by(key, member, delta).await.unwrap()
+    }
+
+    pub async fn try_zrank(&mut self, key: &str, member: Member) -> Result<Option<Value>, Error> {
+        let command = Command::zrank(key, member);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrank(&mut self, key: &str, member: Member) -> Option<Value> {
+        self.try_zrank(key, member).await.unwrap()
+    }
+
+    pub async fn try_zrevrank(
+        &mut self,
+        key: &str,
+        member: Member,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::zrevrank(key, member);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrevrank(&mut self, key: &str, member: Member) -> Option<Value> {
+        self.try_zrevrank(key, member).await.unwrap()
+    }
+
+    /// Returns members between ranks `start` and `stop` (inclusive) as array of alternating
+    /// members and scores.
+    pub async fn try_zrange(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::zrange(key, start, stop);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrange(&mut self, key: &str, start: i64, stop: i64) -> Option<Value> {
+        self.try_zrange(key, start, stop).await.unwrap()
+    }
+
+    pub async fn try_zrevrange(
+        &mut self,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::zrevrange(key, start, stop);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrevrange(&mut self, key: &str, start: i64, stop: i64) -> Option<Value> {
+        self.try_zrevrange(key, start, stop).await.unwrap()
+    }
+
+    /// Returns members with score between `min` and `max` (inclusive) as array of alternating
+    /// members and scores. `limit` is offset and count of returned members.
+    pub async fn try_zrange_by_score(
+        &mut self,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Option<Value>, Error> {
+        to_score(min)?;
+        to_score(max)?;
+        let command = Command::zrange_by_score(key, min, max, limit);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrange_by_score(
+        &mut self,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: Option<(i64, i64)>,
+    ) -> Option<Value> {
+        self.try_zrange_by_score(key, min, max, limit)
+            .await
+            .unwrap()
+    }
+
+    pub async fn try_zrevrange_by_score(
+        &mut self,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: Option<(i64, i64)>,
+    ) -> Result<Option<Value>, Error> {
+        to_score(min)?;
+        to_score(max)?;
+        let command = Command::zrevrange_by_score(key, min, max, limit);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zrevrange_by_score(
+        &mut self,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: Option<(i64, i64)>,
+    ) -> Option<Value> {
+        self.try_zrevrange_by_score(key, min, max, limit)
+            .await
+            .unwrap()
+    }
+
+    pub async fn try_zcard(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::zcard(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zcard(&mut self, key: &str) -> Option<Value> {
+        self.try_zcard(key).await.unwrap()
+    }
+
+    /// Removes `count` members with the lowest scores, returns them the same way as `try_zrange`.
+    pub async fn try_zpopmin(&mut self, key: &str, count: i64) -> Result<Option<Value>, Error> {
+        let command = Command::zpopmin(key, count);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zpopmin(&mut self, key: &str, count: i64) -> Option<Value> {
+        self.try_zpopmin(key, count).await.unwrap()
+    }
+
+    pub async fn try_zpopmax(&mut self, key: &str, count: i64) -> Result<Option<Value>, Error> {
+        let command = Command::zpopmax(key, count);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn zpopmax(&mut self, key: &str, count: i64) -> Option<Value> {
+        self.try_zpopmax(key, count).await.unwrap()
+    }
+
+    /// Appends entry to stream under `key`, with id generated by server if `id` is `None`.
+    /// Returns id of the entry.
+    pub async fn try_xadd(
+        &mut self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: Vec<(String, Value)>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xadd(key, id, fields);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xadd(
+        &mut self,
+        key: &str,
+        id: Option<StreamId>,
+        fields: Vec<(String, Value)>,
+    ) -> Option<Value> {
+        self.try_xadd(key, id, fields).await.unwrap()
+    }
+
+    /// Returns entries with id between `start` and `end` (inclusive), each as array of its id
+    /// and alternating fields and values.
+    pub async fn try_xrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xrange(key, start, end, count);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Option<Value> {
+        self.try_xrange(key, start, end, count).await.unwrap()
+    }
+
+    /// Same as `try_xrange`, starting from the newest entry.
+    pub async fn try_xrevrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xrevrange(key, start, end, count);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xrevrange(
+        &mut self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Option<Value> {
+        self.try_xrevrange(key, start, end, count).await.unwrap()
+    }
+
+    pub async fn try_xlen(&mut self, key: &str) -> Result<Option<Value>, Error> {
+        let command = Command::xlen(key);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xlen(&mut self, key: &str) -> Option<Value> {
+        self.try_xlen(key).await.unwrap()
+    }
+
+    /// Removes the oldest entries, so that at most `max_len` remain. Returns how many were
+    /// removed.
+    pub async fn try_xtrim(&mut self, key: &str, max_len: u64) -> Result<Option<Value>, Error> {
+        let command = Command::xtrim(key, max_len);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xtrim(&mut self, key: &str, max_len: u64) -> Option<Value> {
+        self.try_xtrim(key, max_len).await.unwrap()
+    }
+
+    /// Reads entries with id greater than the given one from every stream, id of `None` only
+    /// reads entries added after the call. Waits up to `block` for entries if there are none
+    /// (zero waits forever), `None` doesn't wait. Responds with array of `[key, entries]` for
+    /// every stream that had any, or `None`.
+    pub async fn try_xread(
+        &mut self,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xread(streams, count, block);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xread(
+        &mut self,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Option<Value> {
+        self.try_xread(streams, count, block).await.unwrap()
+    }
+
+    /// Same as `try_xread`, but reads for `consumer` of `group`. Id of `None` reads entries not
+    /// delivered to the group yet and marks them as pending, otherwise pending entries of the
+    /// consumer are delivered again.
+    pub async fn try_xread_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xread_group(group, consumer, streams, count, block);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xread_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Option<Value> {
+        self.try_xread_group(group, consumer, streams, count, block)
+            .await
+            .unwrap()
+    }
+
+    /// Acknowledges pending entries of `group`, returns how many of them were pending.
+    pub async fn try_xack(
+        &mut self,
+        key: &str,
+        group: &str,
+        ids: &[StreamId],
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xack(key, group, ids);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xack(&mut self, key: &str, group: &str, ids: &[StreamId]) -> Option<Value> {
+        self.try_xack(key, group, ids).await.unwrap()
+    }
+
+    /// Creates consumer group that delivers entries added after `id`, or after the last entry
+    /// if it's `None`. Missing stream is created only if `create` is set.
+    pub async fn try_xgroup_create(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+        create: bool,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xgroup_create(key, group, id, create);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xgroup_create(
+        &mut self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+        create: bool,
+    ) -> Option<Value> {
+        self.try_xgroup_create(key, group, id, create)
+            .await
+            .unwrap()
+    }
+
+    /// Returns pending entries of `group`, each as array of id, consumer, milliseconds since
+    /// the last delivery and number of deliveries.
+    pub async fn try_xpending(
+        &mut self,
+        key: &str,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        consumer: Option<&str>,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::xpending(key, group, start, end, count, consumer);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn xpending(
+        &mut self,
+        key: &str,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        consumer: Option<&str>,
+    ) -> Option<Value> {
+        self.try_xpending(key, group, start, end, count, consumer)
+            .await
+            .unwrap()
+    }
+
+    /// Returns number of subscribers that received the message.
+    pub async fn try_publish(
+        &mut self,
+        channel: &str,
+        payload: Value,
+    ) -> Result<Option<Value>, Error> {
+        let command = Command::publish(channel, payload);
+        self.execute(command).await?;
+
+        Self::flatten_response_to_option(self.connection.read().await)
+    }
+
+    pub async fn publish(&mut self, channel: &str, payload: Value) -> Option<Value> {
+        self.try_publish(channel, payload).await.unwrap()
+    }
+
+    /// Subscribes to `channels` and turns client into stream of messages published to them.
+    pub async fn subscribe(self, channels: &[&str]) -> Result<Subscription, Error> {
+        self.into_subscription(Command::subscribe(channels)).await
+    }
+
+    /// Same as `subscribe`, but for channels matching glob `patterns`.
+    pub async fn psubscribe(self, patterns: &[&str]) -> Result<Subscription, Error> {
+        self.into_subscription(Command::psubscribe(patterns)).await
+    }
+
+    async fn into_subscription(mut self, command: Command) -> Result<Subscription, Error> {
+        self.execute(command).await?;
+        // acknowledgements come back in a single response, so nothing can be published before
+        // subscription is in place
+        match self.connection.read().await? {
+            Some(Response::Multi(_)) => Ok(Subscription::new(self.connection)),
+            Some(Response::Error(msg)) => Err(Error::DatabaseError { msg }),
+            Some(_) => Err(Error::BadRequest {
+                msg: "expected subscription acknowledgement".into(),
+            }),
+            None => Err(Error::ConnectionClosed),
+        }
+    }
+
+    /// Runs commands returned by `build` atomically. `keys` are watched before `build` is
+    /// called, so it can read their current state through the client. If any of them changes
+    /// before the commands run, nothing is applied and `build` is called again with fresh
+    /// state. Returns result of every command.
+    pub async fn try_transaction<F>(
+        &mut self,
+        keys: &[&str],
+        mut build: F,
+    ) -> Result<Vec<Result<Option<Value>, Error>>, Error>
+    where
+        F: AsyncFnMut(&mut Client) -> Result<Vec<Command>, Error>,
+    {
+        loop {
+            if !keys.is_empty() {
+                self.execute(Command::watch(keys)).await?;
+                Self::flatten_response_to_option(self.connection.read().await)?;
+            }
+
+            let commands = match build(self).await {
+                Ok(commands) => commands,
+                Err(e) => {
+                    self.execute(Command::unwatch()).await?;
+                    Self::flatten_response_to_option(self.connection.read().await)?;
+                    return Err(e);
+                }
+            };
+
+            let count = commands.len();
+            self.connection.feed(Command::multi()).await?;
+            for command in commands {
+                self.connection.feed(command).await?;
+            }
+            self.execute(Command::exec()).await?;
+
+            // acknowledgements of `MULTI` and of every queued command
+            for _ in 0..=count {
+                Self::flatten_response_to_option(self.connection.read().await)?;
+            }
+
+            match self.connection.read().await? {
+                // watched key changed, try again with fresh state
+                Some(Response::Null) => continue,
+                Some(Response::Multi(responses)) => {
+                    return Ok(responses
+                        .into_iter()
+                        .map(|response| response_to_value(Ok(response)))
+                        .collect());
+                }
+                Some(Response::Error(msg)) => return Err(Error::DatabaseError { msg }),
+                Some(_) => {
+                    return Err(Error::BadRequest {
+                        msg: "expected transaction results".into(),
+                    });
+                }
+                None => return Err(Error::ConnectionClosed),
+            }
+        }
+    }
+
+    pub async fn transaction<F>(
+        &mut self,
+        keys: &[&str],
+        build: F,
+    ) -> Vec<Result<Option<Value>, Error>>
+    where
+        F: AsyncFnMut(&mut Client) -> Result<Vec<Command>, Error>,
+    {
+        self.try_transaction(keys, build).await.unwrap()
+    }
 }
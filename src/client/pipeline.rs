@@ -0,0 +1,333 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    client::{Client, response_to_value},
+    error::Error,
+    server::{storage::SortedSet, stream::StreamId},
+    utils::command::{Command, Member, Value},
+};
+
+/// Queues commands and sends them in a single flush, so the whole batch costs one round trip.
+pub struct Pipeline<'a> {
+    client: &'a mut Client,
+    commands: Vec<Command>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub(crate) fn new(client: &'a mut Client) -> Self {
+        Self {
+            client,
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn command(mut self, command: Command) -> Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn get(self, key: &str) -> Self {
+        self.command(Command::get(key))
+    }
+
+    pub fn set(self, key: &str, value: Value) -> Self {
+        self.command(Command::set(key, value))
+    }
+
+    pub fn set_ex(self, key: &str, value: Value, expire: Duration) -> Self {
+        self.command(Command::set_ex(key, value, expire))
+    }
+
+    pub fn set_nx(self, key: &str, value: Value, expire: Option<Duration>) -> Self {
+        self.command(Command::set_nx(key, value, expire))
+    }
+
+    pub fn set_xx(self, key: &str, value: Value, expire: Option<Duration>) -> Self {
+        self.command(Command::set_xx(key, value, expire))
+    }
+
+    pub fn compare_and_swap(self, key: &str, expected: Value, value: Value) -> Self {
+        self.command(Command::compare_and_swap(key, expected, value))
+    }
+
+    pub fn delete(self, key: &str) -> Self {
+        self.command(Command::delete(key))
+    }
+
+    pub fn expire(self, key: &str, expire: Duration) -> Self {
+        self.command(Command::expire(key, expire))
+    }
+
+    pub fn ttl(self, key: &str) -> Self {
+        self.command(Command::ttl(key))
+    }
+
+    pub fn persist(self, key: &str) -> Self {
+        self.command(Command::persist(key))
+    }
+
+    pub fn key_type(self, key: &str) -> Self {
+        self.command(Command::key_type(key))
+    }
+
+    pub fn rename(self, key: &str, new_key: &str) -> Self {
+        self.command(Command::rename(key, new_key))
+    }
+
+    pub fn rename_nx(self, key: &str, new_key: &str) -> Self {
+        self.command(Command::rename_nx(key, new_key))
+    }
+
+    pub fn incr(self, key: &str) -> Self {
+        self.command(Command::incr(key))
+    }
+
+    pub fn decr(self, key: &str) -> Self {
+        self.command(Command::decr(key))
+    }
+
+    pub fn incr_by(self, key: &str, delta: i64) -> Self {
+        self.command(Command::incr_by(key, delta))
+    }
+
+    pub fn incr_by_float(self, key: &str, delta: f64) -> Self {
+        self.command(Command::incr_by_float(key, delta))
+    }
+
+    pub fn hset(self, key: &str, fields: HashMap<String, Value>) -> Self {
+        self.command(Command::hset(key, fields))
+    }
+
+    pub fn hget(self, key: &str, field: &str) -> Self {
+        self.command(Command::hget(key, field))
+    }
+
+    pub fn hdel(self, key: &str, fields: &[&str]) -> Self {
+        self.command(Command::hdel(key, fields))
+    }
+
+    pub fn hgetall(self, key: &str) -> Self {
+        self.command(Command::hgetall(key))
+    }
+
+    pub fn hincr_by(self, key: &str, field: &str, delta: i64) -> Self {
+        self.command(Command::hincr_by(key, field, delta))
+    }
+
+    pub fn hexists(self, key: &str, field: &str) -> Self {
+        self.command(Command::hexists(key, field))
+    }
+
+    pub fn lpush(self, key: &str, values: Vec<Value>) -> Self {
+        self.command(Command::lpush(key, values))
+    }
+
+    pub fn rpush(self, key: &str, values: Vec<Value>) -> Self {
+        self.command(Command::rpush(key, values))
+    }
+
+    pub fn lpop(self, key: &str) -> Self {
+        self.command(Command::lpop(key))
+    }
+
+    pub fn rpop(self, key: &str) -> Self {
+        self.command(Command::rpop(key))
+    }
+
+    pub fn lrange(self, key: &str, start: i64, stop: i64) -> Self {
+        self.command(Command::lrange(key, start, stop))
+    }
+
+    pub fn llen(self, key: &str) -> Self {
+        self.command(Command::llen(key))
+    }
+
+    pub fn ltrim(self, key: &str, start: i64, stop: i64) -> Self {
+        self.command(Command::ltrim(key, start, stop))
+    }
+
+    pub fn lindex(self, key: &str, index: i64) -> Self {
+        self.command(Command::lindex(key, index))
+    }
+
+    pub fn lset(self, key: &str, index: i64, value: Value) -> Self {
+        self.command(Command::lset(key, index, value))
+    }
+
+    pub fn sadd(self, key: &str, members: Vec<Member>) -> Self {
+        self.command(Command::sadd(key, members))
+    }
+
+    pub fn srem(self, key: &str, members: Vec<Member>) -> Self {
+        self.command(Command::srem(key, members))
+    }
+
+    pub fn sismember(self, key: &str, member: Member) -> Self {
+        self.command(Command::sismember(key, member))
+    }
+
+    pub fn smembers(self, key: &str) -> Self {
+        self.command(Command::smembers(key))
+    }
+
+    pub fn scard(self, key: &str) -> Self {
+        self.command(Command::scard(key))
+    }
+
+    pub fn spop(self, key: &str) -> Self {
+        self.command(Command::spop(key))
+    }
+
+    pub fn srandmember(self, key: &str) -> Self {
+        self.command(Command::srandmember(key))
+    }
+
+    pub fn sunion(self, keys: &[&str]) -> Self {
+        self.command(Command::sunion(keys))
+    }
+
+    pub fn sinter(self, keys: &[&str]) -> Self {
+        self.command(Command::sinter(keys))
+    }
+
+    pub fn sdiff(self, keys: &[&str]) -> Self {
+        self.command(Command::sdiff(keys))
+    }
+
+    pub fn sunion_store(self, destination: &str, keys: &[&str]) -> Self {
+        self.command(Command::sunion_store(destination, keys))
+    }
+
+    pub fn sinter_store(self, destination: &str, keys: &[&str]) -> Self {
+        self.command(Command::sinter_store(destination, keys))
+    }
+
+    pub fn sdiff_store(self, destination: &str, keys: &[&str]) -> Self {
+        self.command(Command::sdiff_store(destination, keys))
+    }
+
+    pub fn zadd(self, key: &str, members: SortedSet) -> Self {
+        self.command(Command::zadd(key, members))
+    }
+
+    pub fn zrem(self, key: &str, members: Vec<Member>) -> Self {
+        self.command(Command::zrem(key, members))
+    }
+
+    pub fn zscore(self, key: &str, member: Member) -> Self {
+        self.command(Command::zscore(key, member))
+    }
+
+    pub fn zincr_by(self, key: &str, member: Member, delta: f64) -> Self {
+        self.command(Command::zincr_by(key, member, delta))
+    }
+
+    pub fn zrank(self, key: &str, member: Member) -> Self {
+        self.command(Command::zrank(key, member))
+    }
+
+    pub fn zrevrank(self, key: &str, member: Member) -> Self {
+        self.command(Command::zrevrank(key, member))
+    }
+
+    pub fn zrange(self, key: &str, start: i64, stop: i64) -> Self {
+        self.command(Command::zrange(key, start, stop))
+    }
+
+    pub fn zrevrange(self, key: &str, start: i64, stop: i64) -> Self {
+        self.command(Command::zrevrange(key, start, stop))
+    }
+
+    pub fn zrange_by_score(self, key: &str, min: f64, max: f64, limit: Option<(i64, i64)>) -> Self {
+        self.command(Command::zrange_by_score(key, min, max, limit))
+    }
+
+    pub fn zrevrange_by_score(
+        self,
+        key: &str,
+        min: f64,
+        max: f64,
+        limit: Option<(i64, i64)>,
+    ) -> Self {
+        self.command(Command::zrevrange_by_score(key, min, max, limit))
+    }
+
+    pub fn zcard(self, key: &str) -> Self {
+        self.command(Command::zcard(key))
+    }
+
+    pub fn zpopmin(self, key: &str, count: i64) -> Self {
+        self.command(Command::zpopmin(key, count))
+    }
+
+    pub fn zpopmax(self, key: &str, count: i64) -> Self {
+        self.command(Command::zpopmax(key, count))
+    }
+
+    pub fn xadd(self, key: &str, id: Option<StreamId>, fields: Vec<(String, Value)>) -> Self {
+        self.command(Command::xadd(key, id, fields))
+    }
+
+    pub fn xrange(self, key: &str, start: StreamId, end: StreamId, count: Option<usize>) -> Self {
+        self.command(Command::xrange(key, start, end, count))
+    }
+
+    pub fn xrevrange(
+        self,
+        key: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+    ) -> Self {
+        self.command(Command::xrevrange(key, start, end, count))
+    }
+
+    pub fn xlen(self, key: &str) -> Self {
+        self.command(Command::xlen(key))
+    }
+
+    pub fn xtrim(self, key: &str, max_len: u64) -> Self {
+        self.command(Command::xtrim(key, max_len))
+    }
+
+    pub fn xack(self, key: &str, group: &str, ids: &[StreamId]) -> Self {
+        self.command(Command::xack(key, group, ids))
+    }
+
+    pub fn xgroup_create(self, key: &str, group: &str, id: Option<StreamId>, create: bool) -> Self {
+        self.command(Command::xgroup_create(key, group, id, create))
+    }
+
+    pub fn publish(self, channel: &str, payload: Value) -> Self {
+        self.command(Command::publish(channel, payload))
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Sends all queued commands and returns their results in the same order. Outer error
+    /// means that connection failed, inner ones are errors returned by database for single
+    /// commands.
+    pub async fn execute(self) -> Result<Vec<Result<Option<Value>, Error>>, Error> {
+        let connection = &mut self.client.connection;
+        let count = self.commands.len();
+
+        for command in self.commands {
+            connection.feed(command).await?;
+        }
+        connection.flush().await?;
+
+        let mut results = Vec::with_capacity(count);
+        for _ in 0..count {
+            let response = connection.read().await?.ok_or(Error::ConnectionClosed)?;
+            results.push(response_to_value(Ok(response)));
+        }
+
+        Ok(results)
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio_stream::Stream;
+
+use crate::{client::Client, error::Error};
+
+type ScanFuture<'a> =
+    Pin<Box<dyn Future<Output = (&'a mut Client, Result<(u64, Vec<String>), Error>)> + Send + 'a>>;
+
+/// Stream of all keys matching a pattern, fetched in batches with `SCAN`. Created by
+/// `Client::scan_iter`. Keys that exist for the whole iteration are returned at least once,
+/// the same key may be returned more than once if keys change in the meantime.
+pub struct ScanIter<'a> {
+    /// Taken by `scan` while next batch is requested.
+    client: Option<&'a mut Client>,
+    scan: Option<ScanFuture<'a>>,
+    pattern: Option<String>,
+    count: Option<usize>,
+    /// Keys of the last batch that were not returned yet.
+    keys: VecDeque<String>,
+    /// `None` once server has walked all keys.
+    cursor: Option<u64>,
+}
+
+impl<'a> ScanIter<'a> {
+    pub(crate) fn new(client: &'a mut Client, pattern: Option<&str>, count: Option<usize>) -> Self {
+        Self {
+            client: Some(client),
+            scan: None,
+            pattern: pattern.map(str::to_string),
+            count,
+            keys: VecDeque::new(),
+            cursor: Some(0),
+        }
+    }
+}
+
+impl<'a> Stream for ScanIter<'a> {
+    type Item = Result<String, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(key) = self.keys.pop_front() {
+                return Poll::Ready(Some(Ok(key)));
+            }
+
+            let mut scan = match self.scan.take() {
+                Some(scan) => scan,
+                None => {
+                    let (Some(cursor), Some(client)) = (self.cursor, self.client.take()) else {
+                        return Poll::Ready(None);
+                    };
+                    let pattern = self.pattern.clone();
+                    let count = self.count;
+                    Box::pin(async move {
+                        let batch = client.try_scan(cursor, pattern.as_deref(), count).await;
+                        (client, batch)
+                    })
+                }
+            };
+
+            let (client, batch) = match scan.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => {
+                    self.scan = Some(scan);
+                    return Poll::Pending;
+                }
+            };
+            self.client = Some(client);
+
+            match batch {
+                Ok((cursor, keys)) => {
+                    self.cursor = (cursor != 0).then_some(cursor);
+                    self.keys.extend(keys);
+                }
+                Err(e) => {
+                    self.cursor = None;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio_stream::Stream;
+
+use crate::{
+    client::ClientConnection,
+    error::Error,
+    server::{
+        protocol::Response,
+        pubsub::{Push, PushKind},
+    },
+};
+
+type ReadFuture =
+    Pin<Box<dyn Future<Output = (ClientConnection, Result<Option<Response>, Error>)> + Send>>;
+
+/// Stream of messages published to channels that client subscribed to. Created by
+/// `Client::subscribe` or `Client::psubscribe`, ends when server closes the connection.
+pub struct Subscription {
+    /// Taken by `read` while it waits for the next push.
+    connection: Option<ClientConnection>,
+    read: Option<ReadFuture>,
+}
+
+impl Subscription {
+    pub(crate) fn new(connection: ClientConnection) -> Self {
+        Self {
+            connection: Some(connection),
+            read: None,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<Push, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let mut read = match self.read.take() {
+                Some(read) => read,
+                None => {
+                    let Some(mut connection) = self.connection.take() else {
+                        return Poll::Ready(None);
+                    };
+                    Box::pin(async move {
+                        let response = connection.read::<Response>().await;
+                        (connection, response)
+                    })
+                }
+            };
+
+            let (connection, response) = match read.as_mut().poll(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => {
+                    self.read = Some(read);
+                    return Poll::Pending;
+                }
+            };
+
+            let item = match response {
+                Ok(Some(Response::Push(push))) => match push.kind {
+                    PushKind::Message | PushKind::PMessage => Ok(push),
+                    // acknowledgements of subscription changes
+                    _ => {
+                        self.connection = Some(connection);
+                        continue;
+                    }
+                },
+                Ok(Some(Response::Error(msg))) => Err(Error::DatabaseError { msg }),
+                Ok(Some(_)) => Err(Error::BadRequest {
+                    msg: "unexpected response on subscribed connection".into(),
+                }),
+                Ok(None) => return Poll::Ready(None),
+                // connection is dropped, framing can't be trusted anymore
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            };
+
+            self.connection = Some(connection);
+            return Poll::Ready(Some(item));
+        }
+    }
+}
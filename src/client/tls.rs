@@ -0,0 +1,82 @@
+use std::{path::PathBuf, sync::Arc};
+
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, pki_types::ServerName},
+};
+
+use crate::{
+    client::Client,
+    error::Error,
+    utils::tls::{load_certs, load_key, load_roots},
+};
+
+/// How `Client::connect_tls` verifies the server and identifies itself to it.
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    ca_path: PathBuf,
+    client_cert: Option<(PathBuf, PathBuf)>,
+    server_name: Option<String>,
+}
+
+impl TlsOptions {
+    /// Trusts servers with certificates signed by authority from PEM file at `ca_path`.
+    pub fn new(ca_path: impl Into<PathBuf>) -> Self {
+        Self {
+            ca_path: ca_path.into(),
+            client_cert: None,
+            server_name: None,
+        }
+    }
+
+    /// Presents certificate and its private key (both PEM files) to servers that require
+    /// mutual TLS.
+    pub fn with_client_cert(
+        mut self,
+        cert_path: impl Into<PathBuf>,
+        key_path: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_cert = Some((cert_path.into(), key_path.into()));
+        self
+    }
+
+    /// Name that server certificate has to be issued for, host of the address by default.
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    fn connector(&self) -> Result<TlsConnector, Error> {
+        let builder = ClientConfig::builder().with_root_certificates(load_roots(&self.ca_path)?);
+        let config = match &self.client_cert {
+            Some((cert_path, key_path)) => builder
+                .with_client_auth_cert(load_certs(cert_path)?, load_key(key_path)?)
+                .map_err(|e| Error::Tls { msg: e.to_string() })?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+}
+
+/// Host part of `host:port` address, without brackets around IPv6.
+fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+impl Client {
+    /// Connects to server that serves TLS, verifying its certificate according to `options`.
+    pub async fn connect_tls(to: &str, options: &TlsOptions) -> Result<Self, Error> {
+        let connector = options.connector()?;
+        let name = options.server_name.as_deref().unwrap_or_else(|| host(to));
+        let name = ServerName::try_from(name.to_string()).map_err(|e| Error::Tls {
+            msg: format!("invalid server name \"{name}\": {e}"),
+        })?;
+
+        let stream = TcpStream::connect(to).await?;
+        let stream = connector.connect(name, stream).await?;
+
+        Ok(Self::with_transport(stream))
+    }
+}
@@ -2,6 +2,7 @@ use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("invalid bytes")]
@@ -13,10 +14,51 @@ pub enum Error {
     #[error("connection was closed")]
     ConnectionClosed,
 
+    #[error("Protocol error: {msg}")]
+    Protocol { msg: String },
     #[error("bad request: {msg}")]
     BadRequest { msg: String },
     #[error("database responded with error: {msg}")]
     DatabaseError { msg: String },
+    #[error("persisted data is corrupted: {msg}")]
+    Corrupted { msg: String },
+    #[error("TLS error: {msg}")]
+    Tls { msg: String },
+
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("invalid expire time")]
+    InvalidExpire,
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("increment or decrement would overflow")]
+    Overflow,
+    #[error("no such key")]
+    NoSuchKey,
+    #[error("index out of range")]
+    OutOfRange,
+    #[error("value is not a valid float")]
+    NotFloat,
+    #[error("resulting score is not a number (NaN)")]
+    NanScore,
+    #[error("increment would produce NaN or Infinity")]
+    NotFinite,
+    #[error("invalid stream ID")]
+    InvalidStreamId,
+    #[error("the ID specified in XADD is equal or smaller than the target stream top item")]
+    StreamIdTooSmall,
+    #[error("NOGROUP No such key or consumer group")]
+    NoGroup,
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    GroupExists,
+    #[error("OOM command not allowed when used memory > 'maxmemory'")]
+    OutOfMemory,
+    #[error("NOAUTH Authentication required.")]
+    NoAuth,
+    #[error("WRONGPASS invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("NOPERM {msg}")]
+    NoPermission { msg: String },
 
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -1,21 +1,65 @@
-mod client;
-mod error;
-mod server;
-mod utils;
+pub mod client;
+pub mod error;
+pub mod server;
+pub mod utils;
+
+/// Returns path in temp directory unique for this process.
+#[cfg(test)]
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("redis-rs-{}-{}", std::process::id(), name))
+}
 
 #[cfg(test)]
 mod tests {
 
-    use std::time::Duration;
+    use std::{
+        collections::{HashMap, HashSet},
+        path::PathBuf,
+        time::Duration,
+    };
 
-    use tokio::time::sleep;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        time::{sleep, timeout},
+    };
+    use tokio_stream::StreamExt;
 
-    use crate::{client::Client, utils::command::Value};
+    use crate::{
+        client::{Client, TlsOptions},
+        error::Error,
+        server::{
+            self, Config,
+            aof::FsyncPolicy,
+            protocol::{Response, TcpWrite},
+            pubsub::PushKind,
+            storage::EvictionPolicy,
+            stream::StreamId,
+            tls::TlsConfig,
+        },
+        temp_path,
+        utils::command::{Command, Member, Value},
+    };
+
+    /// Spawns a server on a random local port and returns its address.
+    async fn spawn_server() -> anyhow::Result<String> {
+        spawn_server_with(Config::default()).await
+    }
+
+    async fn spawn_server_with(config: Config) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(server::run(listener, config));
+        // give server a moment to load persisted data before accepting
+        sleep(Duration::from_millis(50)).await;
+        Ok(addr)
+    }
 
     #[tokio::test]
     async fn test_client() -> anyhow::Result<()> {
-        simple_logger::init()?;
-        let mut client = Client::connect("127.0.0.1:5000").await?;
+        let _ = simple_logger::init();
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
 
         // let val_1 = Value::Number(1);
         // let val_2 = Value::String("maciek".into());
@@ -41,4 +85,1678 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_expiry() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        client
+            .try_set_ex("session", Value::Number(1), Duration::from_millis(200))
+            .await?;
+        let Some(Value::Number(ttl)) = client.try_ttl("session").await? else {
+            panic!("expected ttl of session");
+        };
+        assert!(ttl > 0 && ttl <= 200);
+
+        assert!(matches!(
+            client.try_persist("session").await?,
+            Some(Value::Boolean(true))
+        ));
+        assert!(matches!(
+            client.try_ttl("session").await?,
+            Some(Value::Number(-1))
+        ));
+
+        assert!(matches!(
+            client
+                .try_expire("session", Duration::from_millis(50))
+                .await?,
+            Some(Value::Boolean(true))
+        ));
+        assert!(matches!(
+            client
+                .try_expire("missing", Duration::from_millis(50))
+                .await?,
+            Some(Value::Boolean(false))
+        ));
+
+        sleep(Duration::from_millis(100)).await;
+
+        assert!(client.try_get("session").await?.is_none());
+        assert!(client.try_ttl("session").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_restore() -> anyhow::Result<()> {
+        let path = temp_path("snapshot.rrdb");
+        let config = Config {
+            snapshot_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let addr = spawn_server_with(config.clone()).await?;
+        let mut client = Client::connect(&addr).await?;
+        client
+            .try_set("blob", Value::Bytes(b"\r\n\0".to_vec()))
+            .await?;
+        client
+            .try_set_ex("short", Value::Number(1), Duration::from_millis(10))
+            .await?;
+        client.try_save().await?;
+
+        sleep(Duration::from_millis(20)).await;
+
+        let addr = spawn_server_with(config).await?;
+        let mut client = Client::connect(&addr).await?;
+        assert!(matches!(
+            client.try_get("blob").await?,
+            Some(Value::Bytes(bytes)) if bytes == b"\r\n\0"
+        ));
+        assert!(client.try_get("short").await?.is_none());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aof_replay_and_rewrite() -> anyhow::Result<()> {
+        let path = temp_path("log.aof");
+        let config = Config {
+            aof_path: Some(path.clone()),
+            aof_fsync: FsyncPolicy::Always,
+            ..Default::default()
+        };
+
+        let addr = spawn_server_with(config.clone()).await?;
+        let mut client = Client::connect(&addr).await?;
+        for i in 0..10 {
+            client.try_set("counter", Value::Number(i)).await?;
+        }
+        client.try_set("removed", Value::Boolean(true)).await?;
+        client.try_delete("removed").await?;
+        client
+            .try_set_ex("session", Value::Number(1), Duration::from_secs(60))
+            .await?;
+
+        let size_before = std::fs::metadata(&path)?.len();
+        client.try_rewrite_aof().await?;
+        assert!(std::fs::metadata(&path)?.len() < size_before);
+        client.try_set("after", Value::Number(1)).await?;
+
+        let addr = spawn_server_with(config).await?;
+        let mut client = Client::connect(&addr).await?;
+        assert!(matches!(
+            client.try_get("counter").await?,
+            Some(Value::Number(9))
+        ));
+        assert!(client.try_get("removed").await?.is_none());
+        assert!(matches!(
+            client.try_ttl("session").await?,
+            Some(Value::Number(ttl)) if ttl > 0
+        ));
+        assert!(client.try_get("after").await?.is_some());
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unparsable_request() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut stream = TcpStream::connect(&addr).await?;
+
+        // well framed GET whose key isn't utf-8, followed by valid one
+        let mut request = vec![b'g', 1, 0, 0, 0, 0xff, b'\r', b'\n'];
+        request.extend(Command::get("k").to_bytes());
+        stream.write_all(&request).await?;
+
+        let error = Error::BadRequest {
+            msg: "Invalid key utf-8 encoding".into(),
+        };
+        let mut expected = Response::error(&error.to_string()).to_bytes();
+        expected.extend(Response::Null.to_bytes());
+        let mut buf = vec![0; expected.len()];
+        timeout(Duration::from_secs(1), stream.read_exact(&mut buf)).await??;
+        assert_eq!(buf, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resp_client() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut stream = TcpStream::connect(&addr).await?;
+
+        async fn roundtrip(stream: &mut TcpStream, request: &[u8]) -> anyhow::Result<String> {
+            stream.write_all(request).await?;
+            let mut buf = vec![0; 1024];
+            let n = stream.read(&mut buf).await?;
+            Ok(String::from_utf8_lossy(&buf[..n]).into_owned())
+        }
+
+        let set = b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$4\r\nv\r\nv\r\n$2\r\nEX\r\n$2\r\n10\r\n";
+        assert_eq!(roundtrip(&mut stream, set).await?, "+OK\r\n");
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$3\r\nget\r\n$1\r\nk\r\n").await?,
+            "$4\r\nv\r\nv\r\n"
+        );
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$3\r\nTTL\r\n$1\r\nk\r\n").await?,
+            ":10\r\n"
+        );
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$3\r\nGET\r\n$7\r\nmissing\r\n").await?,
+            "$-1\r\n"
+        );
+        assert!(
+            roundtrip(&mut stream, b"*1\r\n$3\r\nGET\r\n")
+                .await?
+                .starts_with("-ERR wrong number of arguments")
+        );
+
+        let hello = roundtrip(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n").await?;
+        assert!(hello.starts_with('%'));
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$3\r\nDEL\r\n$1\r\nk\r\n").await?,
+            ":1\r\n"
+        );
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").await?,
+            "_\r\n"
+        );
+
+        // queued commands keep their own reply shapes inside EXEC
+        let transaction = b"*1\r\n$5\r\nMULTI\r\n\
+            *3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n\
+            *2\r\n$4\r\nINCR\r\n$1\r\nn\r\n\
+            *1\r\n$4\r\nEXEC\r\n";
+        assert_eq!(
+            roundtrip(&mut stream, transaction).await?,
+            "+OK\r\n+QUEUED\r\n+QUEUED\r\n*2\r\n+OK\r\n:1\r\n"
+        );
+
+        // scores are only sent with WITHSCORES, exclusive bound skips the lowest one
+        let zadd = b"*6\r\n$4\r\nZADD\r\n$1\r\nz\r\n$1\r\n1\r\n$1\r\na\r\n$3\r\n2.5\r\n$1\r\nb\r\n";
+        assert_eq!(roundtrip(&mut stream, zadd).await?, ":2\r\n");
+        let range = b"*4\r\n$13\r\nZRANGEBYSCORE\r\n$1\r\nz\r\n$2\r\n(1\r\n$4\r\n+inf\r\n";
+        assert_eq!(roundtrip(&mut stream, range).await?, "*1\r\n$1\r\nb\r\n");
+        let range =
+            b"*5\r\n$6\r\nZRANGE\r\n$1\r\nz\r\n$1\r\n0\r\n$2\r\n-1\r\n$10\r\nWITHSCORES\r\n";
+        assert_eq!(
+            roundtrip(&mut stream, range).await?,
+            "*4\r\n$1\r\na\r\n,1\r\n$1\r\nb\r\n,2.5\r\n"
+        );
+
+        assert_eq!(
+            roundtrip(&mut stream, b"*2\r\n$4\r\nTYPE\r\n$1\r\nz\r\n").await?,
+            "+zset\r\n"
+        );
+        let rename = b"*3\r\n$8\r\nRENAMENX\r\n$1\r\nz\r\n$1\r\nn\r\n";
+        assert_eq!(roundtrip(&mut stream, rename).await?, ":0\r\n");
+        let scan = b"*6\r\n$4\r\nSCAN\r\n$1\r\n0\r\n$5\r\nMATCH\r\n$1\r\nz\r\n$5\r\nCOUNT\r\n$3\r\n100\r\n";
+        assert_eq!(
+            roundtrip(&mut stream, scan).await?,
+            "*2\r\n$1\r\n0\r\n*1\r\n$1\r\nz\r\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pipeline() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let mut pipeline = client.pipeline();
+        for i in 0..100 {
+            pipeline = pipeline.set(&format!("key:{i}"), Value::Number(i));
+        }
+        let results = pipeline
+            .get("key:42")
+            .command(Command::save())
+            .delete("key:0")
+            .execute()
+            .await?;
+
+        assert_eq!(results.len(), 103);
+        assert!(results[..100].iter().all(|r| matches!(r, Ok(None))));
+        assert!(matches!(results[100], Ok(Some(Value::Number(42)))));
+        assert!(matches!(results[101], Err(Error::DatabaseError { .. })));
+        assert!(matches!(results[102], Ok(Some(Value::Number(0)))));
+
+        // connection stays usable after pipeline
+        assert!(client.try_get("key:0").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multi_key() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let entries = (0..20)
+            .map(|i| (format!("key:{i}"), Value::Number(i)))
+            .collect();
+        let old = client.try_mset(entries).await?;
+        assert!(old.iter().all(Option::is_none));
+
+        let values = client.try_mget(&["key:3", "missing", "key:19"]).await?;
+        assert!(matches!(
+            values.as_slice(),
+            [Some(Value::Number(3)), None, Some(Value::Number(19))]
+        ));
+
+        assert!(matches!(
+            client.try_exists(&["key:1", "key:1", "missing"]).await?,
+            Some(Value::Number(2))
+        ));
+
+        let deleted = client.try_mdel(&["key:1", "missing"]).await?;
+        assert!(matches!(deleted.as_slice(), [Some(Value::Number(1)), None]));
+        assert!(matches!(
+            client.try_exists(&["key:1"]).await?,
+            Some(Value::Number(0))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counters() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        assert!(matches!(
+            client.try_incr("hits").await?,
+            Some(Value::Number(1))
+        ));
+        assert!(matches!(
+            client.try_incr_by("hits", 41).await?,
+            Some(Value::Number(42))
+        ));
+        assert!(matches!(
+            client.try_decr("hits").await?,
+            Some(Value::Number(41))
+        ));
+
+        client.try_set("text", Value::String("10".into())).await?;
+        assert!(matches!(
+            client.try_incr("text").await?,
+            Some(Value::Number(11))
+        ));
+
+        client
+            .try_set("name", Value::String("maciek".into()))
+            .await?;
+        assert!(matches!(
+            client.try_incr("name").await,
+            Err(Error::DatabaseError { .. })
+        ));
+
+        client.try_set("max", Value::Number(i64::MAX)).await?;
+        assert!(matches!(
+            client.try_incr("max").await,
+            Err(Error::DatabaseError { .. })
+        ));
+        assert!(matches!(
+            client.try_get("max").await?,
+            Some(Value::Number(i64::MAX))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conditional_writes() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let lease = Some(Duration::from_secs(10));
+        assert_eq!(
+            client
+                .try_set_nx("lock", Value::String("a".into()), lease)
+                .await?,
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(
+            client
+                .try_set_nx("lock", Value::String("b".into()), lease)
+                .await?,
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            client.try_set_xx("missing", Value::Number(1), None).await?,
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(client.try_get("missing").await?, None);
+
+        assert_eq!(
+            client
+                .try_compare_and_swap("lock", Value::String("b".into()), Value::Number(1))
+                .await?,
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            client
+                .try_compare_and_swap("lock", Value::String("a".into()), Value::Number(1))
+                .await?,
+            Some(Value::Boolean(true))
+        );
+        // swap keeps the lease
+        assert!(matches!(
+            client.try_ttl("lock").await?,
+            Some(Value::Number(ms)) if ms > 0
+        ));
+
+        assert_eq!(
+            client.try_get_set("lock", Value::Number(2)).await?,
+            Some(Value::Number(1))
+        );
+        assert_eq!(
+            client.try_set_xx("lock", Value::Number(3), None).await?,
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(client.try_get("lock").await?, Some(Value::Number(3)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut publisher = Client::connect(&addr).await?;
+        let mut news = Client::connect(&addr).await?.subscribe(&["news"]).await?;
+        let mut all = Client::connect(&addr).await?.psubscribe(&["news*"]).await?;
+
+        assert_eq!(
+            publisher.try_publish("news", Value::Number(1)).await?,
+            Some(Value::Number(2))
+        );
+        assert_eq!(
+            publisher
+                .try_publish("news.sport", Value::Number(2))
+                .await?,
+            Some(Value::Number(1))
+        );
+        assert_eq!(
+            publisher.try_publish("weather", Value::Number(3)).await?,
+            Some(Value::Number(0))
+        );
+
+        let push = timeout(Duration::from_secs(1), news.next())
+            .await?
+            .unwrap()?;
+        assert_eq!(push.kind, PushKind::Message);
+        assert_eq!(push.channel, "news");
+        assert_eq!(push.payload, Value::Number(1));
+
+        let first = timeout(Duration::from_secs(1), all.next())
+            .await?
+            .unwrap()?;
+        let second = timeout(Duration::from_secs(1), all.next())
+            .await?
+            .unwrap()?;
+        assert_eq!(first.pattern.as_deref(), Some("news*"));
+        assert_eq!(first.payload, Value::Number(1));
+        assert_eq!(second.channel, "news.sport");
+        assert_eq!(second.payload, Value::Number(2));
+
+        // closed connection no longer counts as subscriber
+        drop(news);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            publisher.try_publish("news", Value::Number(4)).await?,
+            Some(Value::Number(1))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_transaction() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+        let mut other = Client::connect(&addr).await?;
+
+        client.try_set("balance", Value::Number(10)).await?;
+
+        let mut attempts = 0;
+        let results = client
+            .try_transaction(&["balance"], async |client| {
+                attempts += 1;
+                let Some(Value::Number(balance)) = client.try_get("balance").await? else {
+                    return Err(Error::BadRequest {
+                        msg: "balance is missing".into(),
+                    });
+                };
+                if attempts == 1 {
+                    // concurrent write between WATCH and EXEC aborts the first attempt
+                    other.try_incr("balance").await?;
+                }
+                Ok(vec![
+                    Command::set("balance", Value::Number(balance - 5)),
+                    Command::incr("withdrawn"),
+                ])
+            })
+            .await?;
+
+        assert_eq!(attempts, 2);
+        assert!(matches!(
+            results.as_slice(),
+            [Ok(Some(Value::Number(11))), Ok(Some(Value::Number(1)))]
+        ));
+        assert_eq!(client.try_get("balance").await?, Some(Value::Number(6)));
+
+        // write that changes nothing doesn't abort transaction watching the key
+        client
+            .try_hset(
+                "profile",
+                HashMap::from([("name".to_string(), Value::String("ann".into()))]),
+            )
+            .await?;
+        let mut attempts = 0;
+        let results = client
+            .try_transaction(&["profile"], async |_| {
+                attempts += 1;
+                if attempts > 1 {
+                    return Err(Error::BadRequest {
+                        msg: "no-op write aborted transaction".into(),
+                    });
+                }
+                assert_eq!(
+                    other.try_hdel("profile", &["missing"]).await?,
+                    Some(Value::Number(0))
+                );
+                Ok(vec![Command::hdel("profile", &["name"])])
+            })
+            .await?;
+        assert_eq!(attempts, 1);
+        assert!(matches!(results.as_slice(), [Ok(Some(Value::Number(1)))]));
+
+        // queued commands are discarded without being applied
+        let results = client
+            .pipeline()
+            .command(Command::multi())
+            .set("balance", Value::Number(0))
+            .command(Command::discard())
+            .execute()
+            .await?;
+        assert!(matches!(&results[1], Ok(Some(Value::String(s))) if s == "QUEUED"));
+        assert_eq!(client.try_get("balance").await?, Some(Value::Number(6)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_hash() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let fields = HashMap::from([
+            ("name".to_string(), Value::String("maciek".into())),
+            ("visits".to_string(), Value::Number(1)),
+        ]);
+        assert_eq!(
+            client.try_hset("user", fields.clone()).await?,
+            Some(Value::Number(2))
+        );
+        assert_eq!(
+            client.try_hincr_by("user", "visits", 2).await?,
+            Some(Value::Number(3))
+        );
+        assert_eq!(
+            client.try_hget("user", "name").await?,
+            Some(Value::String("maciek".into()))
+        );
+        assert_eq!(client.try_hget("user", "missing").await?, None);
+        assert!(matches!(
+            client.try_hset("empty", HashMap::new()).await,
+            Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+        ));
+        assert_eq!(
+            client.try_hexists("user", "visits").await?,
+            Some(Value::Boolean(true))
+        );
+
+        let Some(Value::Map(all)) = client.try_hgetall("user").await? else {
+            panic!("expected map");
+        };
+        assert_eq!(all.len(), 2);
+        assert_eq!(all["visits"], Value::Number(3));
+
+        assert_eq!(
+            client
+                .try_hdel("user", &["name", "visits", "missing"])
+                .await?,
+            Some(Value::Number(2))
+        );
+        // hash without fields is removed
+        assert_eq!(client.try_exists(&["user"]).await?, Some(Value::Number(0)));
+
+        client.try_set("plain", Value::Number(1)).await?;
+        assert!(matches!(
+            client.try_hget("plain", "field").await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+        client.try_hset("user", fields).await?;
+        assert!(matches!(
+            client.try_incr("user").await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let numbers = |values: &[i64]| values.iter().copied().map(Value::Number).collect();
+        assert_eq!(
+            client.try_rpush("queue", numbers(&[2, 3])).await?,
+            Some(Value::Number(2))
+        );
+        assert_eq!(
+            client.try_lpush("queue", numbers(&[1, 0])).await?,
+            Some(Value::Number(4))
+        );
+        assert_eq!(
+            client.try_lrange("queue", 0, -1).await?,
+            Some(Value::Array(numbers(&[0, 1, 2, 3])))
+        );
+        assert_eq!(
+            client.try_lrange("queue", -3, 100).await?,
+            Some(Value::Array(numbers(&[1, 2, 3])))
+        );
+        assert_eq!(
+            client.try_lrange("queue", 3, 1).await?,
+            Some(Value::Array(vec![]))
+        );
+        assert_eq!(client.try_llen("queue").await?, Some(Value::Number(4)));
+        assert_eq!(
+            client.try_lindex("queue", -1).await?,
+            Some(Value::Number(3))
+        );
+        assert_eq!(client.try_lindex("queue", 10).await?, None);
+
+        client.try_lset("queue", 1, Value::Number(10)).await?;
+        assert!(matches!(
+            client.try_lset("queue", 4, Value::Number(0)).await,
+            Err(Error::DatabaseError { msg }) if msg == "index out of range"
+        ));
+        assert!(matches!(
+            client.try_lset("missing", 0, Value::Number(0)).await,
+            Err(Error::DatabaseError { msg }) if msg == "no such key"
+        ));
+
+        assert_eq!(client.try_lpop("queue").await?, Some(Value::Number(0)));
+        assert_eq!(client.try_rpop("queue").await?, Some(Value::Number(3)));
+        client.try_ltrim("queue", 1, -1).await?;
+        assert_eq!(
+            client.try_lrange("queue", 0, -1).await?,
+            Some(Value::Array(numbers(&[2])))
+        );
+        // list without elements is removed
+        client.try_ltrim("queue", 1, 0).await?;
+        assert_eq!(client.try_exists(&["queue"]).await?, Some(Value::Number(0)));
+        assert_eq!(client.try_lpop("queue").await?, None);
+        assert!(matches!(
+            client.try_rpush("queue", Vec::new()).await,
+            Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+        ));
+
+        client.try_set("plain", Value::Number(1)).await?;
+        assert!(matches!(
+            client.try_lpush("plain", numbers(&[1])).await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_blocking_pop() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let started = std::time::Instant::now();
+        let wait = Duration::from_millis(100);
+        assert_eq!(client.try_blpop(&["jobs"], Some(wait)).await?, None);
+        assert!(started.elapsed() >= wait);
+        assert!(matches!(
+            client.try_brpop(&[], Some(wait)).await,
+            Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+        ));
+
+        // waiters are served in order in which they blocked
+        let mut workers = Vec::new();
+        for _ in 0..3 {
+            let mut worker = Client::connect(&addr).await?;
+            workers.push(tokio::spawn(async move {
+                worker.try_blpop(&["other", "jobs"], None).await
+            }));
+            sleep(Duration::from_millis(50)).await;
+        }
+        let jobs = vec![Value::Number(1), Value::Number(2), Value::Number(3)];
+        client.try_rpush("jobs", jobs.clone()).await?;
+        for (worker, job) in workers.into_iter().zip(jobs) {
+            let popped = timeout(Duration::from_secs(1), worker).await???;
+            assert_eq!(
+                popped,
+                Some(Value::Array(vec![Value::String("jobs".into()), job]))
+            );
+        }
+        assert_eq!(client.try_llen("jobs").await?, Some(Value::Number(0)));
+
+        // value available right away is popped without waiting
+        client.try_rpush("jobs", vec![Value::Number(4)]).await?;
+        assert_eq!(
+            client.try_brpop(&["jobs"], Some(wait)).await?,
+            Some(Value::Array(vec![
+                Value::String("jobs".into()),
+                Value::Number(4)
+            ]))
+        );
+
+        // waiter that disconnected doesn't take values from the others
+        let mut gone = Client::connect(&addr).await?;
+        let blocked = tokio::spawn(async move { gone.try_blpop(&["jobs"], None).await });
+        sleep(Duration::from_millis(50)).await;
+        blocked.abort();
+        sleep(Duration::from_millis(50)).await;
+        client.try_rpush("jobs", vec![Value::Number(5)]).await?;
+        assert_eq!(
+            client.try_blpop(&["jobs"], Some(wait)).await?,
+            Some(Value::Array(vec![
+                Value::String("jobs".into()),
+                Value::Number(5)
+            ]))
+        );
+
+        // timeout without deadline that fits into clock waits forever
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream
+            .write_all(b"*3\r\n$5\r\nBLPOP\r\n$4\r\njobs\r\n$20\r\n10000000000000000000\r\n")
+            .await?;
+        sleep(Duration::from_millis(50)).await;
+        client.try_rpush("jobs", vec![Value::Number(6)]).await?;
+        let mut buf = vec![0; 64];
+        let n = timeout(Duration::from_secs(1), stream.read(&mut buf)).await??;
+        assert_eq!(&buf[..n], b"*2\r\n$4\r\njobs\r\n:6\r\n");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_set() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let members = |names: &[&str]| -> Vec<Member> {
+            names
+                .iter()
+                .map(|name| Member::String(name.to_string()))
+                .collect()
+        };
+        let set = |names: &[&str]| Some(Value::Set(members(names).into_iter().collect()));
+
+        assert_eq!(
+            client.try_sadd("a", members(&["x", "y", "z", "x"])).await?,
+            Some(Value::Number(3))
+        );
+        assert_eq!(
+            client.try_sadd("b", members(&["y", "z", "w"])).await?,
+            Some(Value::Number(3))
+        );
+        assert!(matches!(
+            client.try_sadd("empty", Vec::new()).await,
+            Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+        ));
+        assert_eq!(
+            client
+                .try_sismember("a", Member::String("x".into()))
+                .await?,
+            Some(Value::Boolean(true))
+        );
+        // members of different types are different
+        assert_eq!(
+            client
+                .try_sismember("a", Member::Bytes(b"x".to_vec()))
+                .await?,
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(client.try_scard("a").await?, Some(Value::Number(3)));
+        assert_eq!(client.try_smembers("a").await?, set(&["x", "y", "z"]));
+
+        assert_eq!(
+            client.try_sunion(&["a", "b"]).await?,
+            set(&["x", "y", "z", "w"])
+        );
+        assert_eq!(client.try_sinter(&["a", "b"]).await?, set(&["y", "z"]));
+        assert_eq!(client.try_sinter(&["a", "missing"]).await?, set(&[]));
+        assert_eq!(client.try_sdiff(&["a", "b"]).await?, set(&["x"]));
+
+        assert_eq!(
+            client.try_sinter_store("both", &["a", "b"]).await?,
+            Some(Value::Number(2))
+        );
+        assert_eq!(client.try_smembers("both").await?, set(&["y", "z"]));
+        // empty result removes destination
+        assert_eq!(
+            client.try_sdiff_store("both", &["both", "a"]).await?,
+            Some(Value::Number(0))
+        );
+        assert_eq!(client.try_exists(&["both"]).await?, Some(Value::Number(0)));
+
+        let Some(Value::String(random)) = client.try_srandmember("a").await? else {
+            panic!("expected member");
+        };
+        assert!(["x", "y", "z"].contains(&random.as_str()));
+
+        let mut popped = HashSet::new();
+        while let Some(Value::String(member)) = client.try_spop("a").await? {
+            popped.insert(member);
+        }
+        assert_eq!(popped.len(), 3);
+        assert_eq!(client.try_exists(&["a"]).await?, Some(Value::Number(0)));
+
+        assert_eq!(
+            client.try_srem("b", members(&["w", "missing"])).await?,
+            Some(Value::Number(1))
+        );
+        client.try_set("plain", Value::Number(1)).await?;
+        assert!(matches!(
+            client.try_sunion(&["b", "plain"]).await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sorted_set() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let player = |name: &str| Member::String(name.into());
+        let scored = |entries: &[(&str, f64)]| {
+            Some(Value::Array(
+                entries
+                    .iter()
+                    .flat_map(|(member, score)| {
+                        [Value::String(member.to_string()), Value::Float(*score)]
+                    })
+                    .collect(),
+            ))
+        };
+
+        let members = vec![
+            (player("alice"), 30.0),
+            (player("bob"), 10.0),
+            (player("carol"), 20.0),
+            (player("dave"), 20.0),
+        ];
+        assert_eq!(
+            client.try_zadd("board", members).await?,
+            Some(Value::Number(4))
+        );
+        assert!(matches!(
+            client.try_zadd("empty", Vec::new()).await,
+            Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+        ));
+        assert_eq!(
+            client
+                .try_zadd("board", vec![(player("bob"), 15.5)])
+                .await?,
+            Some(Value::Number(0))
+        );
+        assert_eq!(client.try_zcard("board").await?, Some(Value::Number(4)));
+        assert_eq!(
+            client.try_zscore("board", player("bob")).await?,
+            Some(Value::Float(15.5))
+        );
+        assert_eq!(client.try_zscore("board", player("eve")).await?, None);
+        assert_eq!(
+            client.try_zincr_by("board", player("bob"), 20.0).await?,
+            Some(Value::Float(35.5))
+        );
+
+        // ties are ordered by member
+        assert_eq!(
+            client.try_zrange("board", 0, -1).await?,
+            scored(&[
+                ("carol", 20.0),
+                ("dave", 20.0),
+                ("alice", 30.0),
+                ("bob", 35.5)
+            ])
+        );
+        assert_eq!(
+            client.try_zrevrange("board", 0, 1).await?,
+            scored(&[("bob", 35.5), ("alice", 30.0)])
+        );
+        assert_eq!(
+            client.try_zrank("board", player("alice")).await?,
+            Some(Value::Number(2))
+        );
+        assert_eq!(
+            client.try_zrevrank("board", player("alice")).await?,
+            Some(Value::Number(1))
+        );
+        assert_eq!(client.try_zrank("board", player("eve")).await?, None);
+
+        assert_eq!(
+            client
+                .try_zrange_by_score("board", 20.0, 30.0, None)
+                .await?,
+            scored(&[("carol", 20.0), ("dave", 20.0), ("alice", 30.0)])
+        );
+        assert_eq!(
+            client
+                .try_zrange_by_score("board", f64::NEG_INFINITY, f64::INFINITY, Some((1, 2)))
+                .await?,
+            scored(&[("dave", 20.0), ("alice", 30.0)])
+        );
+        assert_eq!(
+            client
+                .try_zrevrange_by_score("board", 20.0, 40.0, Some((0, 1)))
+                .await?,
+            scored(&[("bob", 35.5)])
+        );
+        assert_eq!(
+            client
+                .try_zrange_by_score("board", 40.0, 10.0, None)
+                .await?,
+            scored(&[])
+        );
+
+        assert_eq!(
+            client.try_zpopmin("board", 1).await?,
+            scored(&[("carol", 20.0)])
+        );
+        assert_eq!(
+            client.try_zpopmax("board", 2).await?,
+            scored(&[("bob", 35.5), ("alice", 30.0)])
+        );
+        assert_eq!(
+            client
+                .try_zrem("board", vec![player("dave"), player("eve")])
+                .await?,
+            Some(Value::Number(1))
+        );
+        // sorted set without members is removed
+        assert_eq!(client.try_exists(&["board"]).await?, Some(Value::Number(0)));
+
+        client
+            .try_zadd("inf", vec![(player("top"), f64::INFINITY)])
+            .await?;
+        assert!(matches!(
+            client.try_zincr_by("inf", player("top"), f64::NEG_INFINITY).await,
+            Err(Error::DatabaseError { msg }) if msg.contains("NaN")
+        ));
+        assert!(matches!(
+            client
+                .try_zadd("inf", vec![(player("nan"), f64::NAN)])
+                .await,
+            Err(Error::NotFloat)
+        ));
+
+        client.try_set("plain", Value::Number(1)).await?;
+        assert!(matches!(
+            client.try_zcard("plain").await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_float() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        assert_eq!(
+            client.try_incr_by_float("temp", 1.5).await?,
+            Some(Value::Float(1.5))
+        );
+        client.try_incr_by("count", 10).await?;
+        assert_eq!(
+            client.try_incr_by_float("count", -0.25).await?,
+            Some(Value::Float(9.75))
+        );
+        client.try_set("text", Value::from("3.5e1")).await?;
+        let sum = client.try_incr_by_float("text", 1.0).await?.unwrap();
+        assert_eq!(sum.as_f64(), Some(36.0));
+        assert_eq!(sum.as_i64(), Some(36));
+
+        client.try_set("huge", Value::from(f64::MAX)).await?;
+        assert!(matches!(
+            client.try_incr_by_float("huge", f64::MAX).await,
+            Err(Error::DatabaseError { msg }) if msg.contains("Infinity")
+        ));
+        assert_eq!(client.try_get("huge").await?, Some(Value::Float(f64::MAX)));
+        assert!(matches!(
+            client.try_incr_by_float("temp", f64::NAN).await,
+            Err(Error::NotFloat)
+        ));
+        client.try_set("name", Value::from("maciek")).await?;
+        assert!(matches!(
+            client.try_incr_by_float("name", 1.0).await,
+            Err(Error::DatabaseError { .. })
+        ));
+
+        // nulls can only be stored inside other values
+        let row = Value::Array(vec![Value::from(Some(2.5)), Value::from(None::<i64>)]);
+        client.try_set("row", row.clone()).await?;
+        let Some(Value::Array(stored)) = client.try_get("row").await? else {
+            panic!("expected array");
+        };
+        assert_eq!(Value::Array(stored.clone()), row);
+        assert_eq!(stored[0].as_f64(), Some(2.5));
+        assert_eq!(stored[0].as_i64(), None);
+        assert!(stored[1].is_null());
+        assert_eq!(stored[1].as_str(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        let fields = |n: i64| vec![("n".to_string(), Value::Number(n))];
+        let entry = |id: &str, n: i64| {
+            Value::Array(vec![
+                Value::String(id.into()),
+                Value::Array(vec![Value::String("n".into()), Value::Number(n)]),
+            ])
+        };
+        let read = |key: &str, entries: Vec<Value>| {
+            Some(Value::Array(vec![Value::Array(vec![
+                Value::String(key.into()),
+                Value::Array(entries),
+            ])]))
+        };
+
+        for n in 1..=3 {
+            client
+                .try_xadd("log", Some(StreamId::new(n as u64, 0)), fields(n))
+                .await?;
+        }
+        assert!(matches!(
+            client
+                .try_xadd("log", Some(StreamId::new(3, 0)), fields(0))
+                .await,
+            Err(Error::DatabaseError { .. })
+        ));
+        // generated ids keep growing after explicit ones
+        let Some(Value::String(id)) = client.try_xadd("log", None, fields(4)).await? else {
+            panic!("expected id");
+        };
+        let id: StreamId = id.parse()?;
+        assert!(id > StreamId::new(3, 0));
+        assert_eq!(client.try_xlen("log").await?, Some(Value::Number(4)));
+
+        assert_eq!(
+            client
+                .try_xrange("log", StreamId::new(2, 0), StreamId::new(3, 0), None)
+                .await?,
+            Some(Value::Array(vec![entry("2-0", 2), entry("3-0", 3)]))
+        );
+        assert_eq!(
+            client
+                .try_xrevrange("log", StreamId::MIN, StreamId::MAX, Some(1))
+                .await?,
+            Some(Value::Array(vec![entry(&id.to_string(), 4)]))
+        );
+        assert_eq!(client.try_xtrim("log", 3).await?, Some(Value::Number(1)));
+        assert_eq!(
+            client
+                .try_xread(&[("log", Some(StreamId::new(2, 0)))], Some(1), None)
+                .await?,
+            read("log", vec![entry("3-0", 3)])
+        );
+        assert_eq!(client.try_xread(&[("log", None)], None, None).await?, None);
+
+        // blocked reader gets entry added later, even if it waits for new entries only
+        let mut reader = Client::connect(&addr).await?;
+        let blocked = tokio::spawn(async move {
+            reader
+                .try_xread(&[("events", None)], None, Some(Duration::ZERO))
+                .await
+        });
+        sleep(Duration::from_millis(50)).await;
+        client
+            .try_xadd("events", Some(StreamId::new(1, 0)), fields(1))
+            .await?;
+        assert_eq!(
+            timeout(Duration::from_secs(1), blocked).await???,
+            read("events", vec![entry("1-0", 1)])
+        );
+        let started = std::time::Instant::now();
+        let wait = Some(Duration::from_millis(100));
+        assert_eq!(
+            client.try_xread(&[("events", None)], None, wait).await?,
+            None
+        );
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // every entry is delivered to one consumer of the group
+        client
+            .try_xgroup_create("log", "workers", Some(StreamId::MIN), false)
+            .await?;
+        assert!(matches!(
+            client.try_xgroup_create("log", "workers", None, false).await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("BUSYGROUP")
+        ));
+        assert_eq!(
+            client
+                .try_xread_group("workers", "alice", &[("log", None)], Some(1), None)
+                .await?,
+            read("log", vec![entry("2-0", 2)])
+        );
+        assert_eq!(
+            client
+                .try_xread_group("workers", "bob", &[("log", None)], Some(1), None)
+                .await?,
+            read("log", vec![entry("3-0", 3)])
+        );
+        let Some(Value::Array(pending)) = client
+            .try_xpending("log", "workers", StreamId::MIN, StreamId::MAX, None, None)
+            .await?
+        else {
+            panic!("expected pending entries");
+        };
+        assert_eq!(pending.len(), 2);
+        let Value::Array(bob) = &pending[1] else {
+            panic!("expected pending entry");
+        };
+        assert_eq!(bob[0], Value::String("3-0".into()));
+        assert_eq!(bob[1], Value::String("bob".into()));
+        assert_eq!(bob[3], Value::Number(1));
+
+        // pending entries are delivered again until acknowledged
+        assert_eq!(
+            client
+                .try_xread_group(
+                    "workers",
+                    "alice",
+                    &[("log", Some(StreamId::MIN))],
+                    None,
+                    None
+                )
+                .await?,
+            read("log", vec![entry("2-0", 2)])
+        );
+        assert_eq!(
+            client
+                .try_xack(
+                    "log",
+                    "workers",
+                    &[StreamId::new(2, 0), StreamId::new(9, 0)]
+                )
+                .await?,
+            Some(Value::Number(1))
+        );
+        assert_eq!(
+            client
+                .try_xread_group(
+                    "workers",
+                    "alice",
+                    &[("log", Some(StreamId::MIN))],
+                    None,
+                    None
+                )
+                .await?,
+            read("log", vec![])
+        );
+
+        assert_eq!(
+            client
+                .try_xread_group("workers", "bob", &[("log", None)], None, None)
+                .await?,
+            read("log", vec![entry(&id.to_string(), 4)])
+        );
+
+        // blocked consumer is woken by a new entry
+        let mut worker = Client::connect(&addr).await?;
+        let blocked = tokio::spawn(async move {
+            worker
+                .try_xread_group(
+                    "workers",
+                    "carol",
+                    &[("log", None)],
+                    None,
+                    Some(Duration::ZERO),
+                )
+                .await
+        });
+        sleep(Duration::from_millis(50)).await;
+        client
+            .try_xadd("log", Some(StreamId::new(u64::MAX, 0)), fields(5))
+            .await?;
+        assert_eq!(
+            timeout(Duration::from_secs(1), blocked).await???,
+            read("log", vec![entry(&format!("{}-0", u64::MAX), 5)])
+        );
+
+        assert!(matches!(
+            client
+                .try_xread_group("nobody", "alice", &[("log", None)], None, None)
+                .await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("NOGROUP")
+        ));
+        client.try_set("plain", Value::Number(1)).await?;
+        assert!(matches!(
+            client.try_xlen("plain").await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("WRONGTYPE")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keyspace() -> anyhow::Result<()> {
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+
+        // first request decides protocol of the connection, KEYS must not look like RESP
+        assert_eq!(client.try_keys("*").await?, Some(Value::Array(Vec::new())));
+
+        for i in 0..50 {
+            client
+                .try_set(&format!("user:{i}"), Value::Number(i))
+                .await?;
+        }
+        client.try_lpush("queue", vec![Value::Number(1)]).await?;
+        assert_eq!(client.try_db_size().await?, Some(Value::Number(51)));
+
+        let Some(Value::Array(keys)) = client.try_keys("user:[1-2]?").await? else {
+            panic!("expected keys");
+        };
+        let keys: HashSet<_> = keys.iter().filter_map(Value::as_str).collect();
+        let expected: HashSet<_> = (10..30).map(|i| format!("user:{i}")).collect();
+        assert_eq!(keys, expected.iter().map(String::as_str).collect());
+
+        // small batches still cover every key exactly once while nothing changes
+        let (cursor, _) = client.try_scan(0, None, Some(1)).await?;
+        assert_ne!(cursor, 0);
+        let keys: Vec<_> = client
+            .scan_iter(Some("user:*"), Some(3))
+            .collect::<Result<_, _>>()
+            .await?;
+        assert_eq!(keys.len(), 50);
+        let keys: HashSet<_> = keys.into_iter().collect();
+        assert_eq!(keys.len(), 50);
+
+        // keys that exist for the whole scan are returned even if others are added meanwhile
+        let mut writer = Client::connect(&addr).await?;
+        let mut scanned = HashSet::new();
+        {
+            let mut iter = client.scan_iter(Some("user:*"), Some(5));
+            let mut added = 0;
+            while let Some(key) = iter.next().await {
+                scanned.insert(key?);
+                if added < 100 {
+                    writer
+                        .try_set(&format!("user:new:{added}"), Value::Number(added))
+                        .await?;
+                    added += 1;
+                }
+            }
+        }
+        assert!((0..50).all(|i| scanned.contains(&format!("user:{i}"))));
+
+        assert_eq!(
+            client.try_key_type("user:1").await?,
+            Some(Value::String("number".into()))
+        );
+        assert_eq!(
+            client.try_key_type("queue").await?,
+            Some(Value::String("list".into()))
+        );
+        assert_eq!(
+            client.try_key_type("missing").await?,
+            Some(Value::String("none".into()))
+        );
+
+        // expiry moves together with the value
+        client
+            .try_set_ex("temp", Value::Number(1), Duration::from_secs(100))
+            .await?;
+        client.try_rename("temp", "moved").await?;
+        assert_eq!(client.try_get("temp").await?, None);
+        assert_eq!(client.try_get("moved").await?, Some(Value::Number(1)));
+        assert!(matches!(
+            client.try_ttl("moved").await?,
+            Some(Value::Number(ms)) if ms > 0
+        ));
+        assert!(matches!(
+            client.try_rename("temp", "moved").await,
+            Err(Error::DatabaseError { msg }) if msg == "no such key"
+        ));
+        assert_eq!(
+            client.try_rename_nx("moved", "user:1").await?,
+            Some(Value::Boolean(false))
+        );
+        assert_eq!(
+            client.try_rename_nx("moved", "fresh").await?,
+            Some(Value::Boolean(true))
+        );
+
+        // list renamed into a key hands its values to connection blocked on that key
+        let mut blocked = Client::connect(&addr).await?;
+        let pop = tokio::spawn(async move { blocked.try_blpop(&["jobs"], None).await });
+        sleep(Duration::from_millis(50)).await;
+        client.try_rename("queue", "jobs").await?;
+        assert_eq!(
+            timeout(Duration::from_secs(1), pop).await???,
+            Some(Value::Array(vec![
+                Value::String("jobs".into()),
+                Value::Number(1)
+            ]))
+        );
+
+        client.try_flush_all().await?;
+        assert_eq!(client.try_db_size().await?, Some(Value::Number(0)));
+        assert_eq!(client.try_get("fresh").await?, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit() -> anyhow::Result<()> {
+        // every entry takes 2 bytes of key and 105 bytes of encoded string, single shard with
+        // few keys makes sampling see all of them, so eviction is exact
+        let value = Value::String("x".repeat(100));
+        let limited = |policy| Config {
+            shards: Some(1),
+            max_memory: Some(450),
+            eviction_policy: policy,
+            ..Default::default()
+        };
+
+        let addr = spawn_server_with(limited(EvictionPolicy::NoEviction)).await?;
+        let mut client = Client::connect(&addr).await?;
+        for i in 0..5 {
+            client.try_set(&format!("k{i}"), value.clone()).await?;
+        }
+        assert!(matches!(
+            client.try_set("k5", value.clone()).await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("OOM")
+        ));
+        // reads and deletes still work and free memory for writes
+        assert_eq!(client.try_get("k0").await?, Some(value.clone()));
+        client.try_delete("k0").await?;
+        client.try_set("k5", value.clone()).await?;
+
+        let addr = spawn_server_with(limited(EvictionPolicy::AllKeysLru)).await?;
+        let mut client = Client::connect(&addr).await?;
+        for i in 0..5 {
+            client.try_set(&format!("k{i}"), value.clone()).await?;
+        }
+        client.try_get("k0").await?;
+        client.try_set("k5", value.clone()).await?;
+        assert_eq!(client.try_db_size().await?, Some(Value::Number(5)));
+        assert!(client.try_get("k0").await?.is_some());
+        assert!(client.try_get("k1").await?.is_none());
+
+        let addr = spawn_server_with(limited(EvictionPolicy::VolatileTtl)).await?;
+        let mut client = Client::connect(&addr).await?;
+        client
+            .try_set_ex("k0", value.clone(), Duration::from_secs(100))
+            .await?;
+        client
+            .try_set_ex("k1", value.clone(), Duration::from_secs(10))
+            .await?;
+        for i in 2..5 {
+            client.try_set(&format!("k{i}"), value.clone()).await?;
+        }
+        client.try_set("k5", value.clone()).await?;
+        assert!(client.try_get("k1").await?.is_none());
+        client.try_set("k6", value.clone()).await?;
+        assert!(client.try_get("k0").await?.is_none());
+        // only keys with expiry can be evicted
+        assert!(matches!(
+            client.try_set("k7", value.clone()).await,
+            Err(Error::DatabaseError { msg }) if msg.starts_with("OOM")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_acl() -> anyhow::Result<()> {
+        let path = temp_path("users.acl");
+        std::fs::write(
+            &path,
+            "# nobody gets in without password\n\
+             user default off\n\
+             user admin on >admin-pass allkeys allcommands\n",
+        )?;
+        let config = Config {
+            acl_path: Some(path.clone()),
+            ..Default::default()
+        };
+        let addr = spawn_server_with(config).await?;
+
+        fn is_error<T>(result: Result<T, Error>, prefix: &str) -> bool {
+            matches!(result, Err(Error::DatabaseError { msg }) if msg.starts_with(prefix))
+        }
+
+        let mut admin = Client::connect(&addr).await?;
+        assert!(is_error(admin.try_get("cache:1").await, "NOAUTH"));
+        assert!(is_error(
+            admin.try_auth("admin", "wrong").await,
+            "WRONGPASS"
+        ));
+        assert!(is_error(admin.try_auth("default", "").await, "WRONGPASS"));
+        admin.try_auth("admin", "admin-pass").await?;
+        assert_eq!(
+            admin.try_acl_whoami().await?,
+            Some(Value::String("admin".into()))
+        );
+
+        admin
+            .try_acl_set_user("reader", &["on", ">read-pass", "~cache:*", "+@read"])
+            .await?;
+        admin.try_set("cache:1", Value::Number(1)).await?;
+        admin.try_set("secret", Value::Number(2)).await?;
+
+        let mut reader = Client::connect(&addr).await?;
+        reader.try_auth("reader", "read-pass").await?;
+        assert_eq!(reader.try_get("cache:1").await?, Some(Value::Number(1)));
+        assert!(is_error(reader.try_get("secret").await, "NOPERM"));
+        assert!(is_error(
+            reader.try_mget(&["cache:1", "secret"]).await,
+            "NOPERM"
+        ));
+        assert!(is_error(
+            reader.try_set("cache:2", Value::Number(2)).await,
+            "NOPERM"
+        ));
+        assert!(is_error(reader.try_acl_list().await, "NOPERM"));
+
+        let Some(Value::Array(users)) = admin.try_acl_list().await? else {
+            panic!("expected users");
+        };
+        let hash = server::acl::hash_password("read-pass");
+        assert!(users.contains(&Value::String(format!(
+            "user reader on #{hash} ~cache:* +@read"
+        ))));
+        assert!(
+            admin
+                .try_acl_set_user("reader", &["+@everything"])
+                .await
+                .is_err()
+        );
+
+        // changes apply to connections that are already authenticated
+        admin.try_acl_set_user("reader", &["+@write"]).await?;
+        reader.try_set("cache:2", Value::Number(2)).await?;
+
+        let mut stream = TcpStream::connect(&addr).await?;
+        let mut buf = vec![0; 1024];
+        stream.write_all(b"*2\r\n$3\r\nGET\r\n$1\r\nk\r\n").await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"-NOAUTH"));
+        stream
+            .write_all(b"*3\r\n$4\r\nAUTH\r\n$6\r\nreader\r\n$9\r\nread-pass\r\n")
+            .await?;
+        let n = stream.read(&mut buf).await?;
+        assert_eq!(&buf[..n], b"+OK\r\n");
+
+        // connection commands need authentication as well, HELLO can provide it
+        let mut stream = TcpStream::connect(&addr).await?;
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"-NOAUTH"));
+        stream
+            .write_all(b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n")
+            .await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"-NOAUTH"));
+        stream
+            .write_all(
+                b"*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$6\r\nreader\r\n$5\r\nwrong\r\n",
+            )
+            .await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"-WRONGPASS"));
+        stream
+            .write_all(b"*5\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$6\r\nreader\r\n$9\r\nread-pass\r\n")
+            .await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"%"));
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+        let n = stream.read(&mut buf).await?;
+        assert_eq!(&buf[..n], b"+PONG\r\n");
+
+        // disabled user loses connections that are already authenticated
+        admin.try_acl_set_user("reader", &["off"]).await?;
+        assert!(is_error(reader.try_get("cache:1").await, "NOAUTH"));
+        stream.write_all(b"*1\r\n$4\r\nPING\r\n").await?;
+        let n = stream.read(&mut buf).await?;
+        assert!(buf[..n].starts_with(b"-NOAUTH"));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    type CertAndKey = (PathBuf, PathBuf);
+
+    /// Writes certificate of authority and certificates signed by it for the server (valid
+    /// for `localhost` and `127.0.0.1`) and a client, each with its key. Returns paths of CA
+    /// certificate, then server and client certificate and key pairs.
+    fn generate_certs(name: &str) -> anyhow::Result<(PathBuf, CertAndKey, CertAndKey)> {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+        let ca_key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(Vec::new())?;
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key)?;
+        let ca_path = temp_path(&format!("{name}-ca.pem"));
+        std::fs::write(&ca_path, ca.pem())?;
+
+        let signed = |owner: &str, names: Vec<String>| -> anyhow::Result<_> {
+            let key = KeyPair::generate()?;
+            let cert = CertificateParams::new(names)?.signed_by(&key, &ca, &ca_key)?;
+            let cert_path = temp_path(&format!("{name}-{owner}.pem"));
+            let key_path = temp_path(&format!("{name}-{owner}.key"));
+            std::fs::write(&cert_path, cert.pem())?;
+            std::fs::write(&key_path, key.serialize_pem())?;
+            Ok((cert_path, key_path))
+        };
+        let server = signed("server", vec!["localhost".into(), "127.0.0.1".into()])?;
+        let client = signed("client", vec!["client".into()])?;
+
+        Ok((ca_path, server, client))
+    }
+
+    #[tokio::test]
+    async fn test_tls() -> anyhow::Result<()> {
+        let (ca, (server_cert, server_key), (client_cert, client_key)) = generate_certs("tls")?;
+        let (other_ca, _, _) = generate_certs("tls-other")?;
+
+        let config = Config {
+            tls: Some(TlsConfig::new(&server_cert, &server_key)),
+            ..Default::default()
+        };
+        let addr = spawn_server_with(config).await?;
+
+        let mut client = Client::connect_tls(&addr, &TlsOptions::new(&ca)).await?;
+        client.try_set("key", Value::Number(1)).await?;
+        assert_eq!(client.try_get("key").await?, Some(Value::Number(1)));
+
+        let port = addr
+            .rsplit_once(':')
+            .map(|(_, port)| port)
+            .unwrap_or_default();
+        let options = TlsOptions::new(&ca).with_server_name("localhost");
+        let mut client = Client::connect_tls(&format!("localhost:{port}"), &options).await?;
+        assert_eq!(client.try_get("key").await?, Some(Value::Number(1)));
+
+        // server certificate isn't signed by the trusted authority
+        assert!(
+            Client::connect_tls(&addr, &TlsOptions::new(&other_ca))
+                .await
+                .is_err()
+        );
+        // nor issued for that name
+        let options = TlsOptions::new(&ca).with_server_name("example.com");
+        assert!(Client::connect_tls(&addr, &options).await.is_err());
+        // plain requests aren't understood
+        let mut plain = Client::connect(&addr).await?;
+        assert!(plain.try_get("key").await.is_err());
+
+        let config = Config {
+            tls: Some(TlsConfig::new(&server_cert, &server_key).with_client_ca(&ca)),
+            ..Default::default()
+        };
+        let addr = spawn_server_with(config).await?;
+
+        let options = TlsOptions::new(&ca).with_client_cert(&client_cert, &client_key);
+        let mut client = Client::connect_tls(&addr, &options).await?;
+        client.try_set("key", Value::Number(2)).await?;
+        assert_eq!(client.try_get("key").await?, Some(Value::Number(2)));
+
+        // with TLS 1.3 client learns that its certificate was rejected only once it reads
+        let anonymous = Client::connect_tls(&addr, &TlsOptions::new(&ca)).await;
+        assert!(match anonymous {
+            Ok(mut client) => client.try_get("key").await.is_err(),
+            Err(_) => true,
+        });
+
+        // peer stuck in handshake doesn't hold up shutdown
+        let config = Config {
+            tls: Some(TlsConfig::new(&server_cert, &server_key)),
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let server = tokio::spawn(server::run(listener, config));
+        let mut client = Client::connect_tls(&addr, &TlsOptions::new(&ca)).await?;
+        let _stuck = TcpStream::connect(&addr).await?;
+        sleep(Duration::from_millis(50)).await;
+        client.try_shutdown(Some(false)).await?;
+        timeout(Duration::from_secs(1), server).await???;
+
+        for path in [
+            ca,
+            server_cert,
+            server_key,
+            client_cert,
+            client_key,
+            other_ca,
+        ] {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("unix.sock");
+        let config = Config {
+            unix_socket: Some(path.clone()),
+            unix_socket_perm: Some(0o700),
+            ..Default::default()
+        };
+        tokio::spawn(server::start(None, config));
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o777,
+            0o700
+        );
+        let mut client = Client::connect_unix(&path).await?;
+        client.try_set("key", Value::Number(1)).await?;
+        assert_eq!(client.try_get("key").await?, Some(Value::Number(1)));
+
+        // socket is served alongside TCP, replacing socket of the previous server
+        let config = Config {
+            unix_socket: Some(path.clone()),
+            ..Default::default()
+        };
+        let addr = spawn_server_with(config).await?;
+        let mut tcp = Client::connect(&addr).await?;
+        tcp.try_set("key", Value::Number(2)).await?;
+        let mut unix = Client::connect_unix(&path).await?;
+        assert_eq!(unix.try_get("key").await?, Some(Value::Number(2)));
+
+        // files that aren't sockets are never removed
+        let file = temp_path("not-a-socket");
+        std::fs::write(&file, "data")?;
+        let config = Config {
+            unix_socket: Some(file.clone()),
+            ..Default::default()
+        };
+        assert!(server::start(None, config).await.is_err());
+        assert_eq!(std::fs::read_to_string(&file)?, "data");
+        assert!(server::start(None, Config::default()).await.is_err());
+
+        std::fs::remove_file(path)?;
+        std::fs::remove_file(file)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() -> anyhow::Result<()> {
+        let path = temp_path("shutdown.rrdb");
+        let config = Config {
+            snapshot_path: Some(path.clone()),
+            save_on_shutdown: true,
+            ..Default::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let server = tokio::spawn(server::run(listener, config.clone()));
+
+        let mut client = Client::connect(&addr).await?;
+        client.try_set("key", Value::Number(1)).await?;
+        let mut blocked = Client::connect(&addr).await?;
+        let blocked = tokio::spawn(async move { blocked.try_blpop(&["queue"], None).await });
+        let mut idle = Client::connect(&addr).await?;
+        sleep(Duration::from_millis(50)).await;
+
+        // NOSAVE overrides config
+        client.try_shutdown(Some(false)).await?;
+        timeout(Duration::from_secs(1), server).await???;
+        assert_eq!(blocked.await??, None);
+        assert!(matches!(
+            idle.try_get("key").await,
+            Err(Error::ConnectionClosed)
+        ));
+        assert!(Client::connect(&addr).await.is_err());
+        assert!(!path.exists());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        let server = tokio::spawn(server::run(listener, config.clone()));
+        let mut client = Client::connect(&addr).await?;
+        client.try_set("key", Value::Number(2)).await?;
+        client.try_shutdown(None).await?;
+        timeout(Duration::from_secs(1), server).await???;
+
+        let addr = spawn_server_with(config).await?;
+        let mut client = Client::connect(&addr).await?;
+        assert_eq!(client.try_get("key").await?, Some(Value::Number(2)));
+
+        // snapshot can't be promised without snapshots
+        let addr = spawn_server().await?;
+        let mut client = Client::connect(&addr).await?;
+        assert!(client.try_shutdown(Some(true)).await.is_err());
+        assert_eq!(client.try_get("key").await?, None);
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
 }
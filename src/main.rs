@@ -1,34 +1,162 @@
-use std::{env, process::exit};
+use std::{env, process::exit, time::Duration};
 
-mod client;
-mod error;
-mod server;
-mod utils;
+use redis_rs::server::{
+    self, Config,
+    aof::FsyncPolicy,
+    storage::{EvictionPolicy, MAX_SHARDS},
+    tls::TlsConfig,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     simple_logger::init()?;
     dotenvy::dotenv()?;
 
-    let host_key = "HOST";
-    let port_key = "PORT";
+    // TCP is served when host and port are set, Unix socket when its path is set
+    let addr = match (env::var("HOST"), env::var("PORT")) {
+        (Ok(host), Ok(port)) => Some(format!("{host}:{port}")),
+        (Err(_), Err(_)) => None,
+        _ => {
+            log::error!("Expected both \"HOST\" and \"PORT\" env.");
+            exit(1);
+        }
+    };
+    let unix_socket = env::var("UNIX_SOCKET").ok();
+    if addr.is_none() && unix_socket.is_none() {
+        log::error!("Expected \"HOST\" and \"PORT\" or \"UNIX_SOCKET\" env.");
+        exit(1);
+    }
+    let unix_socket_perm = match env::var("UNIX_SOCKET_PERM").map(|s| u32::from_str_radix(&s, 8)) {
+        Err(_) => None,
+        Ok(Ok(perm)) if perm <= 0o777 => Some(perm),
+        Ok(_) => {
+            log::error!("Expected \"UNIX_SOCKET_PERM\" env to be octal mode, like 770.");
+            exit(1);
+        }
+    };
 
-    let host = match env::var(host_key) {
-        Ok(h) => h,
-        Err(_) => {
-            log::error!("Expected \"{host_key}\" env.");
+    let snapshot_path = env::var("SNAPSHOT_PATH").unwrap_or_else(|_| "dump.rrdb".into());
+    // periodic snapshots are disabled unless interval (in seconds) is provided
+    let snapshot_interval = match env::var("SNAPSHOT_INTERVAL").map(|s| s.parse::<u64>()) {
+        Ok(Ok(0)) | Err(_) => None,
+        Ok(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Ok(Err(_)) => {
+            log::error!("Expected \"SNAPSHOT_INTERVAL\" env to be number of seconds.");
             exit(1);
         }
     };
-    let port = match env::var(port_key) {
-        Ok(p) => p,
-        Err(_) => {
-            log::error!("Expected \"{port_key}\" env.");
+
+    // append only file is disabled unless path is provided
+    let aof_path = env::var("AOF_PATH").ok();
+    let aof_fsync = match env::var("AOF_FSYNC").map(|s| s.parse()) {
+        Err(_) => FsyncPolicy::default(),
+        Ok(Ok(policy)) => policy,
+        Ok(Err(e)) => {
+            log::error!("Invalid \"AOF_FSYNC\" env: {e}");
             exit(1);
         }
     };
 
-    server::start(&format!("{host}:{port}")).await?;
+    let shards = match env::var("SHARDS").map(|s| s.parse::<usize>()) {
+        Err(_) => None,
+        Ok(Ok(shards)) if shards > 0 && shards <= MAX_SHARDS => Some(shards),
+        Ok(_) => {
+            log::error!("Expected \"SHARDS\" env to be number between 1 and {MAX_SHARDS}.");
+            exit(1);
+        }
+    };
+
+    // memory is unlimited unless limit (in bytes, optionally with kb, mb or gb suffix) is set
+    let max_memory = match env::var("MAXMEMORY").map(|s| parse_memory(&s)) {
+        Ok(Some(0)) | Err(_) => None,
+        Ok(Some(bytes)) => Some(bytes),
+        Ok(None) => {
+            log::error!("Expected \"MAXMEMORY\" env to be number of bytes, like 100mb.");
+            exit(1);
+        }
+    };
+    let eviction_policy = match env::var("MAXMEMORY_POLICY").map(|s| s.parse()) {
+        Err(_) => EvictionPolicy::default(),
+        Ok(Ok(policy)) => policy,
+        Ok(Err(e)) => {
+            log::error!("Invalid \"MAXMEMORY_POLICY\" env: {e}");
+            exit(1);
+        }
+    };
+
+    // only passwordless default user exists unless users are configured
+    let acl_path = env::var("ACL_FILE").ok();
+
+    // connections are plain TCP unless certificate and its key are provided, clients also
+    // have to present certificates when CA for them is provided
+    let tls = match (env::var("TLS_CERT_FILE"), env::var("TLS_KEY_FILE")) {
+        (Ok(cert), Ok(key)) => {
+            let tls = TlsConfig::new(cert, key);
+            Some(match env::var("TLS_CA_CERT_FILE") {
+                Ok(ca) => tls.with_client_ca(ca),
+                Err(_) => tls,
+            })
+        }
+        (Err(_), Err(_)) => None,
+        _ => {
+            log::error!("Expected both \"TLS_CERT_FILE\" and \"TLS_KEY_FILE\" env.");
+            exit(1);
+        }
+    };
+
+    // connections get default time to finish on shutdown unless timeout (in seconds) is set
+    let drain_timeout = match env::var("SHUTDOWN_TIMEOUT").map(|s| s.parse::<u64>()) {
+        Err(_) => None,
+        Ok(Ok(secs)) => Some(Duration::from_secs(secs)),
+        Ok(Err(_)) => {
+            log::error!("Expected \"SHUTDOWN_TIMEOUT\" env to be number of seconds.");
+            exit(1);
+        }
+    };
+    let save_on_shutdown = match env::var("SAVE_ON_SHUTDOWN").map(|s| s.parse::<bool>()) {
+        Err(_) => false,
+        Ok(Ok(save)) => save,
+        Ok(Err(_)) => {
+            log::error!("Expected \"SAVE_ON_SHUTDOWN\" env to be true or false.");
+            exit(1);
+        }
+    };
+
+    let config = Config {
+        shards,
+        snapshot_path: Some(snapshot_path.into()),
+        snapshot_interval,
+        aof_path: aof_path.map(Into::into),
+        aof_fsync,
+        max_memory,
+        eviction_policy,
+        acl_path: acl_path.map(Into::into),
+        tls,
+        unix_socket: unix_socket.map(Into::into),
+        unix_socket_perm,
+        handle_signals: true,
+        drain_timeout,
+        save_on_shutdown,
+    };
+
+    server::start(addr.as_deref(), config).await?;
 
     Ok(())
 }
+
+/// Parses amount of memory like `1024`, `64kb`, `100mb` or `2gb`.
+fn parse_memory(s: &str) -> Option<usize> {
+    let s = s.trim().to_ascii_lowercase();
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(at) => s.split_at(at),
+        None => (s.as_str(), ""),
+    };
+    let unit = match unit {
+        "" | "b" => 1,
+        "kb" | "k" => 1 << 10,
+        "mb" | "m" => 1 << 20,
+        "gb" | "g" => 1 << 30,
+        _ => return None,
+    };
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
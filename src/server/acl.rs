@@ -0,0 +1,450 @@
+//! Users, their passwords and what they are allowed to do.
+//!
+//! Every user is described by rules, the same way as in redis:
+//!
+//! ```text
+//! on / off              user can / can't authenticate, `off` also locks out its connections
+//! >password <password   adds / removes password
+//! #hash !hash           adds / removes SHA-256 of password in hex
+//! nopass resetpass      any password is accepted / forgets all passwords
+//! ~pattern allkeys      adds glob pattern of accessible keys / `~*`
+//! resetkeys             forgets all key patterns
+//! +@category -@category allows / denies category of commands (read, write, admin, pubsub,
+//!                       transaction or all)
+//! allcommands           same as +@all
+//! nocommands            same as -@all
+//! reset                 off, resetpass, resetkeys and nocommands
+//! ```
+//!
+//! ACL file has one `user <name> <rules...>` line per user, lines starting with `#` are
+//! comments. Users are only kept in memory, `ACL SETUSER` changes are lost on restart.
+//!
+//! `default` user starts as `on nopass allkeys allcommands` unless the file defines it, so
+//! connections are authenticated as it until it requires a password. Channels of pub/sub
+//! commands are not restricted by key patterns.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
+    fs,
+    path::Path,
+    str::FromStr,
+    sync::RwLock,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Error,
+    utils::{
+        command::{Command, CommandType},
+        glob,
+    },
+};
+
+pub const DEFAULT_USER: &str = "default";
+
+/// Group of commands that can be allowed to a user together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    Read,
+    Write,
+    /// Persistence, `FLUSHALL`, `SHUTDOWN` and user management.
+    Admin,
+    PubSub,
+    Transaction,
+}
+
+impl Category {
+    const ALL: [Self; 5] = [
+        Self::Read,
+        Self::Write,
+        Self::Admin,
+        Self::PubSub,
+        Self::Transaction,
+    ];
+
+    /// Category of `command`, `None` for commands that anyone can run.
+    fn of(command: &Command) -> Option<Self> {
+        match command.r#type {
+            CommandType::Auth { .. } | CommandType::AclWhoAmI => None,
+            CommandType::Save
+            | CommandType::BgSave
+            | CommandType::RewriteAof
+            | CommandType::FlushAll
+            | CommandType::AclSetUser { .. }
+            | CommandType::AclList
+            | CommandType::Shutdown { .. } => Some(Self::Admin),
+            CommandType::Subscribe { .. }
+            | CommandType::Unsubscribe { .. }
+            | CommandType::PSubscribe { .. }
+            | CommandType::PUnsubscribe { .. }
+            | CommandType::Publish { .. } => Some(Self::PubSub),
+            _ if command.is_transaction() => Some(Self::Transaction),
+            _ if command.is_write() => Some(Self::Write),
+            _ => Some(Self::Read),
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+            Self::PubSub => "pubsub",
+            Self::Transaction => "transaction",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Category {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|category| category.to_string() == s)
+            .ok_or_else(|| Error::BadRequest {
+                msg: format!("unknown command category \"{s}\""),
+            })
+    }
+}
+
+/// SHA-256 of `password` in hex, the way passwords are stored and listed.
+pub fn hash_password(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct User {
+    enabled: bool,
+    /// Any password is accepted.
+    nopass: bool,
+    /// Hashes of accepted passwords, see `hash_password`.
+    passwords: BTreeSet<String>,
+    categories: BTreeSet<Category>,
+    /// Glob patterns of keys that user can access.
+    key_patterns: Vec<String>,
+}
+
+impl User {
+    /// New user is disabled and can't do anything until rules allow it.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn apply_rule(&mut self, rule: &str) -> Result<(), Error> {
+        let invalid = || Error::BadRequest {
+            msg: format!("invalid ACL rule \"{rule}\""),
+        };
+
+        match rule {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.key_patterns = vec!["*".into()],
+            "resetkeys" => self.key_patterns.clear(),
+            "allcommands" => self.categories = Category::ALL.into(),
+            "nocommands" => self.categories.clear(),
+            "reset" => *self = Self::new(),
+            _ => match rule.split_at_checked(1).ok_or_else(invalid)? {
+                (">", password) => {
+                    self.nopass = false;
+                    self.passwords.insert(hash_password(password));
+                }
+                ("<", password) => {
+                    self.passwords.remove(&hash_password(password));
+                }
+                ("#", hash) => {
+                    let is_hash = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+                    if !is_hash {
+                        return Err(invalid());
+                    }
+                    self.nopass = false;
+                    self.passwords.insert(hash.to_ascii_lowercase());
+                }
+                ("!", hash) => {
+                    self.passwords.remove(&hash.to_ascii_lowercase());
+                }
+                ("~", pattern) => self.key_patterns.push(pattern.into()),
+                (sign @ ("+" | "-"), category) => {
+                    let category = category.strip_prefix('@').ok_or_else(invalid)?;
+                    let categories = match category {
+                        "all" => Category::ALL.to_vec(),
+                        category => vec![category.parse()?],
+                    };
+                    for category in categories {
+                        if sign == "+" {
+                            self.categories.insert(category);
+                        } else {
+                            self.categories.remove(&category);
+                        }
+                    }
+                }
+                _ => return Err(invalid()),
+            },
+        }
+        Ok(())
+    }
+
+    fn accepts(&self, password: &str) -> bool {
+        self.enabled && (self.nopass || self.passwords.contains(&hash_password(password)))
+    }
+
+    fn can_access(&self, key: &str) -> bool {
+        self.key_patterns
+            .iter()
+            .any(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes()))
+    }
+
+    /// Rules that create this user from scratch.
+    pub fn rules(&self) -> Vec<String> {
+        let mut rules = vec![if self.enabled { "on" } else { "off" }.to_string()];
+        if self.nopass {
+            rules.push("nopass".into());
+        }
+        rules.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        rules.extend(
+            self.key_patterns
+                .iter()
+                .map(|pattern| format!("~{pattern}")),
+        );
+        if self.categories.len() == Category::ALL.len() {
+            rules.push("+@all".into());
+        } else if self.categories.is_empty() {
+            rules.push("-@all".into());
+        } else {
+            rules.extend(
+                self.categories
+                    .iter()
+                    .map(|category| format!("+@{category}")),
+            );
+        }
+        rules
+    }
+}
+
+/// All users, shared by connections. Changes apply to connections already authenticated as
+/// the user as well.
+pub struct Acl {
+    users: RwLock<BTreeMap<String, User>>,
+}
+
+impl Acl {
+    /// Only has `default` user that can do anything without password.
+    pub fn new() -> Self {
+        let mut user = User::new();
+        for rule in ["on", "nopass", "allkeys", "allcommands"] {
+            user.apply_rule(rule)
+                .expect("default rules should be valid");
+        }
+        Self {
+            users: RwLock::new(BTreeMap::from([(DEFAULT_USER.to_string(), user)])),
+        }
+    }
+
+    /// Reads users from ACL file, see module docs for its format.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let acl = Self::new();
+        let mut users = acl.users.write().unwrap();
+        for (index, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: String| Error::BadRequest {
+                msg: format!("line {} of ACL file: {msg}", index + 1),
+            };
+
+            let mut words = line.split_whitespace();
+            let name = match (words.next(), words.next()) {
+                (Some("user"), Some(name)) => name,
+                _ => return Err(invalid("expected \"user <name> <rules...>\"".into())),
+            };
+            let mut user = User::new();
+            for rule in words {
+                user.apply_rule(rule).map_err(|e| invalid(e.to_string()))?;
+            }
+            users.insert(name.to_string(), user);
+        }
+        drop(users);
+        Ok(acl)
+    }
+
+    /// User that new connections are authenticated as, `None` if they have to authenticate
+    /// first.
+    pub fn default_user(&self) -> Option<String> {
+        let users = self.users.read().unwrap();
+        users
+            .get(DEFAULT_USER)
+            .is_some_and(|user| user.enabled && user.nopass)
+            .then(|| DEFAULT_USER.to_string())
+    }
+
+    pub fn authenticate(&self, user: &str, password: &str) -> Result<(), Error> {
+        let users = self.users.read().unwrap();
+        match users.get(user) {
+            Some(user) if user.accepts(password) => Ok(()),
+            _ => Err(Error::WrongPass),
+        }
+    }
+
+    /// Checks that `user` is still authenticated, `None` is connection that hasn't
+    /// authenticated yet.
+    pub fn check_auth(&self, user: Option<&str>) -> Result<(), Error> {
+        let users = self.users.read().unwrap();
+        authenticated(&users, user).map(|_| ())
+    }
+
+    /// Checks that `user` can run `command` with its keys, `None` is connection that hasn't
+    /// authenticated yet.
+    pub fn check(&self, user: Option<&str>, command: &Command) -> Result<(), Error> {
+        if matches!(command.r#type, CommandType::Auth { .. }) {
+            return Ok(());
+        }
+        let users = self.users.read().unwrap();
+        let (name, user) = authenticated(&users, user)?;
+        let Some(category) = Category::of(command) else {
+            return Ok(());
+        };
+
+        if !user.categories.contains(&category) {
+            return Err(Error::NoPermission {
+                msg: format!("User {name} has no permissions to run @{category} commands"),
+            });
+        }
+        if let Some(key) = command
+            .accessed_keys()
+            .into_iter()
+            .find(|key| !user.can_access(key))
+        {
+            return Err(Error::NoPermission {
+                msg: format!("No permissions to access the '{key}' key"),
+            });
+        }
+        Ok(())
+    }
+
+    /// Applies `rules` to `user`, creating it if it doesn't exist. Nothing changes if any rule
+    /// is invalid.
+    pub fn set_user(&self, name: String, rules: &[String]) -> Result<(), Error> {
+        let mut users = self.users.write().unwrap();
+        let mut user = users.get(&name).cloned().unwrap_or_default();
+        for rule in rules {
+            user.apply_rule(rule)?;
+        }
+        users.insert(name, user);
+        Ok(())
+    }
+
+    /// Every user as a line of ACL file, ordered by name.
+    pub fn list(&self) -> Vec<String> {
+        let users = self.users.read().unwrap();
+        users
+            .iter()
+            .map(|(name, user)| format!("user {name} {}", user.rules().join(" ")))
+            .collect()
+    }
+}
+
+/// Name and rules of the user connection is authenticated as. User that was disabled or
+/// removed since has to authenticate again.
+fn authenticated<'a>(
+    users: &'a BTreeMap<String, User>,
+    user: Option<&str>,
+) -> Result<(&'a String, &'a User), Error> {
+    user.and_then(|name| users.get_key_value(name))
+        .filter(|(_, user)| user.enabled)
+        .ok_or(Error::NoAuth)
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_path;
+
+    #[test]
+    fn test_rules_round_trip() -> anyhow::Result<()> {
+        let acl = Acl::new();
+        let rules = [
+            "on", ">secret", "~cache:*", "+@read", "+@pubsub", "-@pubsub",
+        ];
+        acl.set_user("alice".into(), &rules.map(String::from))?;
+        let hash = hash_password("secret");
+        assert_eq!(
+            acl.list(),
+            [
+                "user alice on #".to_string() + &hash + " ~cache:* +@read",
+                "user default on nopass ~* +@all".to_string(),
+            ]
+        );
+
+        // listed rules create the same user again
+        let path = temp_path("users.acl");
+        fs::write(&path, format!("# users\n\n{}\n", acl.list().join("\n")))?;
+        let loaded = Acl::load(&path)?;
+        fs::remove_file(path)?;
+        assert_eq!(loaded.list(), acl.list());
+
+        acl.authenticate("alice", "secret")?;
+        assert!(matches!(
+            acl.authenticate("alice", "wrong"),
+            Err(Error::WrongPass)
+        ));
+        let alice = Some("alice");
+        acl.check(alice, &Command::get("cache:1"))?;
+        assert!(matches!(
+            acl.check(alice, &Command::get("other")),
+            Err(Error::NoPermission { .. })
+        ));
+        assert!(matches!(
+            acl.check(alice, &Command::mget(&["cache:1", "other"])),
+            Err(Error::NoPermission { .. })
+        ));
+        assert!(matches!(
+            acl.check(alice, &Command::set("cache:1", "v".into())),
+            Err(Error::NoPermission { .. })
+        ));
+        assert!(matches!(
+            acl.check(None, &Command::get("cache:1")),
+            Err(Error::NoAuth)
+        ));
+
+        // invalid rule leaves the user as it was
+        assert!(
+            acl.set_user("alice".into(), &["off".into(), "+@nothing".into()])
+                .is_err()
+        );
+        acl.authenticate("alice", "secret")?;
+
+        // disabled user loses connections that are already authenticated
+        acl.set_user("alice".into(), &["off".into()])?;
+        assert!(matches!(acl.check_auth(alice), Err(Error::NoAuth)));
+        assert!(matches!(
+            acl.check(alice, &Command::get("cache:1")),
+            Err(Error::NoAuth)
+        ));
+        Ok(())
+    }
+}
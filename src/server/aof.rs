@@ -0,0 +1,446 @@
+//! Append only file encoding:
+//!
+//! ```text
+//! +-------------------------------+
+//! | logged at - 8 bytes           |
+//! +-------------------------------+
+//! | command - n bytes             |
+//! +-------------------------------+
+//! | ...                           |
+//! +-------------------------------+
+//! ```
+//!
+//! Every mutating command is stored in the same encoding as on the wire, prefixed with unix
+//! timestamp in milliseconds of when it was logged. Timestamp is needed to replay relative
+//! expirations correctly.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::Error,
+    server::{
+        protocol::{Response, TcpRead, TcpWrite},
+        storage::Database,
+    },
+    utils::{
+        bytes::get_u64,
+        command::{Command, CommandType, Member, Value},
+    },
+};
+
+/// When appended commands are flushed from OS buffers to disk.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// After every command. Slowest, but nothing acknowledged is ever lost.
+    Always,
+    /// Once per second from background task.
+    #[default]
+    EverySecond,
+    /// Leave it to OS.
+    Never,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySecond),
+            "no" => Ok(Self::Never),
+            _ => Err(Error::BadRequest {
+                msg: format!("unknown fsync policy \"{s}\", expected always, everysec or no"),
+            }),
+        }
+    }
+}
+
+pub struct Aof {
+    path: PathBuf,
+    policy: FsyncPolicy,
+    file: Mutex<File>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+fn open_append(path: &Path) -> Result<File, Error> {
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+fn encode_record(command: &Command, logged_at: u64) -> Vec<u8> {
+    let mut encoded = logged_at.to_le_bytes().to_vec();
+    encoded.extend_from_slice(&command.to_bytes());
+    encoded
+}
+
+/// Returns `true` if `response` tells that command of `r#type` left database as it was.
+fn changed_nothing(r#type: &CommandType, response: &Response) -> bool {
+    match r#type {
+        CommandType::Delete
+        | CommandType::LPop
+        | CommandType::RPop
+        | CommandType::BLPop { .. }
+        | CommandType::BRPop { .. }
+        | CommandType::SPop
+        | CommandType::XReadGroup { .. } => matches!(response, Response::Null),
+        CommandType::MDel { .. } => matches!(
+            response,
+            Response::Multi(deleted) if deleted.iter().all(|d| matches!(d, Response::Null))
+        ),
+        CommandType::Expire { .. }
+        | CommandType::Persist
+        | CommandType::RenameNx { .. }
+        | CommandType::SetNx { .. }
+        | CommandType::SetXx { .. }
+        | CommandType::CompareAndSwap { .. } => {
+            matches!(response, Response::Payload(Value::Boolean(false)))
+        }
+        CommandType::HDel { .. }
+        | CommandType::SAdd { .. }
+        | CommandType::SRem { .. }
+        | CommandType::ZRem { .. }
+        | CommandType::XTrim { .. }
+        | CommandType::XAck { .. } => matches!(response, Response::Payload(Value::Number(0))),
+        CommandType::ZPopMin { .. } | CommandType::ZPopMax { .. } => {
+            matches!(response, Response::Payload(Value::Array(popped)) if popped.is_empty())
+        }
+        _ => false,
+    }
+}
+
+/// Returns command that repeats what `command` did when it responded with `response`, or
+/// `None` if it changed nothing. Commands with random outcome are replaced by their outcome,
+/// so that replay ends in the same state.
+fn replayed(command: Command, response: &Response) -> Option<Command> {
+    if changed_nothing(&command.r#type, response) {
+        return None;
+    }
+    let r#type = match (command.r#type, response) {
+        (CommandType::SPop, Response::Payload(member)) => CommandType::SRem {
+            members: vec![Member::try_from(member.clone()).ok()?],
+        },
+        // id generated from current time would differ on replay
+        (CommandType::XAdd { id: None, fields }, Response::Payload(Value::String(id))) => {
+            CommandType::XAdd {
+                id: Some(id.parse().ok()?),
+                fields,
+            }
+        }
+        (r#type, _) => r#type,
+    };
+
+    Some(Command {
+        key: command.key,
+        r#type,
+    })
+}
+
+impl Aof {
+    pub fn open(path: impl Into<PathBuf>, policy: FsyncPolicy) -> Result<Self, Error> {
+        let path = path.into();
+        let file = open_append(&path)?;
+
+        Ok(Self {
+            path,
+            policy,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn policy(&self) -> FsyncPolicy {
+        self.policy
+    }
+
+    /// Runs `execute` on `command` and appends it to the log unless it responded with error,
+    /// see `replayed` for commands that are logged differently. Log stays locked for the whole
+    /// time, so commands are stored in the same order in which they were applied to database.
+    pub fn log(&self, command: Command, execute: impl FnOnce(Command) -> Response) -> Response {
+        let mut file = self.file.lock().unwrap();
+
+        let logged_at = unix_millis();
+        let logged = command.clone();
+        let response = execute(command);
+        if matches!(response, Response::Error(_)) {
+            return response;
+        }
+        let Some(logged) = replayed(logged, &response) else {
+            return response;
+        };
+        let record = encode_record(&logged, logged_at);
+
+        let result = file.write_all(&record).and_then(|_| {
+            if self.policy == FsyncPolicy::Always {
+                file.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+
+        match result {
+            Ok(_) => response,
+            Err(e) => {
+                log::error!("Failed to append to {}: {}", self.path.display(), e);
+                Response::error("failed to persist command")
+            }
+        }
+    }
+
+    /// Flushes appended commands to disk.
+    pub fn sync(&self) -> Result<(), Error> {
+        let file = self.file.lock().unwrap();
+        Ok(file.sync_data()?)
+    }
+
+    /// Replaces log with the shortest sequence of commands that recreates current state of
+    /// `db` and returns number of written commands. Writes are blocked until it finishes.
+    /// Caller must hold exclusive access to `db`, see `Database::entries`.
+    pub fn rewrite(&self, db: &Database<String>) -> Result<usize, Error> {
+        let mut file = self.file.lock().unwrap();
+
+        let logged_at = unix_millis();
+        let entries = db.entries();
+        let count = entries.len();
+
+        let mut encoded = Vec::new();
+        for (key, value, expire) in entries {
+            let command = Command {
+                key,
+                r#type: CommandType::Set { value, expire },
+            };
+            encoded.extend_from_slice(&encode_record(&command, logged_at));
+        }
+
+        let tmp_path = self.path.with_extension("tmp");
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&encoded)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        *file = open_append(&self.path)?;
+
+        Ok(count)
+    }
+}
+
+/// Reads all commands stored in log at `path`. Missing file is treated as empty log.
+///
+/// If the last record is incomplete (process crashed in the middle of append), it is cut off
+/// from the file, so new records can be appended after the last valid one.
+pub fn load(path: &Path) -> Result<Vec<Command>, Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let now = unix_millis();
+    let mut src = Cursor::new(&bytes[..]);
+    let mut commands = Vec::new();
+
+    while (src.position() as usize) < bytes.len() {
+        let start = src.position();
+
+        let corrupted = |e: Error| Error::Corrupted {
+            msg: format!("invalid record at offset {start}: {e}"),
+        };
+
+        let record = get_u64(&mut src).and_then(|logged_at| {
+            let command_start = src.position();
+            Command::validate(&mut src)?;
+            Ok((logged_at, command_start))
+        });
+
+        match record {
+            Ok((logged_at, command_start)) => {
+                let end = src.position();
+                src.set_position(command_start);
+                // record is complete, so even `Incomplete` means it's damaged rather than torn
+                let command = Command::parse(&mut src).map_err(corrupted)?;
+                src.set_position(end);
+                let elapsed = Duration::from_millis(now.saturating_sub(logged_at));
+                commands.push(rebase(command, elapsed));
+            }
+            // only record that runs past the end of file is left from interrupted write
+            Err(Error::Incomplete) => {
+                log::warn!(
+                    "Truncating incomplete record at the end of {}",
+                    path.display()
+                );
+                OpenOptions::new().write(true).open(path)?.set_len(start)?;
+                break;
+            }
+            Err(e) => return Err(corrupted(e)),
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Shortens relative expirations by time that passed since command was logged.
+fn rebase(command: Command, elapsed: Duration) -> Command {
+    let r#type = match command.r#type {
+        CommandType::Set {
+            value,
+            expire: Some(expire),
+        } => {
+            if expire <= elapsed {
+                // key already expired, but it still has to overwrite any older value
+                CommandType::Delete
+            } else {
+                CommandType::Set {
+                    value,
+                    expire: Some(expire - elapsed),
+                }
+            }
+        }
+        CommandType::Expire { expire } => CommandType::Expire {
+            expire: expire.saturating_sub(elapsed),
+        },
+        // conditional writes can't become `Delete`, because they might not have written
+        // anything, so they write already expired value instead
+        CommandType::SetNx { value, expire } => CommandType::SetNx {
+            value,
+            expire: expire.map(|expire| expire.saturating_sub(elapsed)),
+        },
+        CommandType::SetXx { value, expire } => CommandType::SetXx {
+            value,
+            expire: expire.map(|expire| expire.saturating_sub(elapsed)),
+        },
+        other => other,
+    };
+
+    Command {
+        key: command.key,
+        r#type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{server::stream::StreamId, temp_path};
+
+    #[test]
+    fn test_incomplete_tail_is_truncated() -> anyhow::Result<()> {
+        let path = temp_path("tail.aof");
+
+        let mut bytes = encode_record(&Command::set("a", Value::Number(1)), unix_millis());
+        let valid_len = bytes.len() as u64;
+        let partial = encode_record(&Command::set("b", Value::Number(2)), unix_millis());
+        bytes.extend_from_slice(&partial[..partial.len() - 3]);
+        fs::write(&path, bytes)?;
+
+        let commands = load(&path)?;
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].key, "a");
+        assert_eq!(fs::metadata(&path)?.len(), valid_len);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_record_is_not_truncated() -> anyhow::Result<()> {
+        let path = temp_path("damaged.aof");
+
+        let mut bytes = encode_record(&Command::set("a", Value::Number(1)), unix_millis());
+        // well framed GET whose key isn't utf-8
+        bytes.extend_from_slice(&unix_millis().to_le_bytes());
+        bytes.extend_from_slice(&[b'g', 1, 0, 0, 0, 0xff, b'\r', b'\n']);
+        bytes.extend(encode_record(
+            &Command::set("b", Value::Number(2)),
+            unix_millis(),
+        ));
+        let len = bytes.len() as u64;
+        fs::write(&path, bytes)?;
+
+        assert!(matches!(load(&path), Err(Error::Corrupted { .. })));
+        assert_eq!(fs::metadata(&path)?.len(), len);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_spop_is_logged_as_srem() -> anyhow::Result<()> {
+        let path = temp_path("spop.aof");
+        let aof = Aof::open(&path, FsyncPolicy::Never)?;
+
+        let popped = |_| Response::Payload(Value::String("b".into()));
+        aof.log(Command::spop("s"), popped);
+        // nothing to pop, nothing to replay
+        aof.log(Command::spop("s"), |_| Response::Null);
+
+        let commands = load(&path)?;
+        assert_eq!(commands.len(), 1);
+        assert_eq!(
+            commands[0],
+            Command::srem("s", vec![Member::String("b".into())])
+        );
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_op_is_not_logged() -> anyhow::Result<()> {
+        let path = temp_path("noop.aof");
+        let aof = Aof::open(&path, FsyncPolicy::Never)?;
+
+        aof.log(Command::delete("missing"), |_| Response::Null);
+        aof.log(Command::lpop("missing"), |_| Response::Null);
+        aof.log(Command::zpopmin("missing", 1), |_| {
+            Response::Payload(Value::Array(Vec::new()))
+        });
+        aof.log(Command::delete("k"), |_| {
+            Response::Payload(Value::Number(1))
+        });
+
+        let commands = load(&path)?;
+        assert_eq!(commands, vec![Command::delete("k")]);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_is_logged_with_generated_id() -> anyhow::Result<()> {
+        let path = temp_path("xadd.aof");
+        let aof = Aof::open(&path, FsyncPolicy::Never)?;
+
+        let fields = vec![("f".to_string(), Value::Number(1))];
+        let added = |_| Response::Payload(Value::String("5-1".into()));
+        aof.log(Command::xadd("x", None, fields.clone()), added);
+
+        let commands = load(&path)?;
+        assert_eq!(
+            commands,
+            vec![Command::xadd("x", Some(StreamId::new(5, 1)), fields)]
+        );
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebase_expired_set() {
+        let command = Command::set_ex("a", Value::Number(1), Duration::from_secs(1));
+        let rebased = rebase(command, Duration::from_secs(2));
+        assert!(matches!(rebased.r#type, CommandType::Delete));
+    }
+}
@@ -1,18 +1,439 @@
-use std::sync::Arc;
+use std::{
+    fs, io,
+    net::SocketAddr,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
-use tokio::net::TcpListener;
+use tokio::{
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+    signal::unix::{SignalKind, signal},
+    sync::watch,
+};
+use tokio_rustls::TlsAcceptor;
 
+use crate::{
+    error::Error,
+    server::{
+        acl::Acl,
+        aof::{Aof, FsyncPolicy},
+        pubsub::PubSub,
+        snapshot::Snapshotter,
+        storage::{Database, EvictionPolicy},
+        tls::TlsConfig,
+    },
+};
+
+pub mod acl;
+pub mod aof;
 pub mod protocol;
+pub mod pubsub;
+pub mod resp;
+pub mod snapshot;
 pub mod storage;
+pub mod stream;
+pub mod tls;
+pub mod transaction;
+
+/// How often background task removes expired keys that were never accessed again.
+const EXPIRE_SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long peer may take to complete TLS handshake before it's disconnected.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long connections may take to finish on shutdown, same as redis `shutdown-timeout`.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    /// Number of independently locked shards of database. `None` uses `DEFAULT_SHARDS`.
+    pub shards: Option<usize>,
+    /// File that snapshot is loaded from on startup and written to by `SAVE`/`BGSAVE`.
+    /// `None` disables snapshots.
+    pub snapshot_path: Option<PathBuf>,
+    /// How often snapshot is written in background. `None` disables periodic snapshots.
+    pub snapshot_interval: Option<Duration>,
+    /// File that every write is appended to. When enabled, it is replayed on startup instead
+    /// of loading snapshot, because it holds more recent state. `None` disables it.
+    pub aof_path: Option<PathBuf>,
+    pub aof_fsync: FsyncPolicy,
+    /// Most bytes that keys and values may take, see `Database::used_memory`. `None` lets
+    /// database grow without limit.
+    pub max_memory: Option<usize>,
+    pub eviction_policy: EvictionPolicy,
+    /// File with users and their permissions, see `acl`. `None` only has `default` user that
+    /// can do anything without password.
+    pub acl_path: Option<PathBuf>,
+    /// Certificates that TCP connections are encrypted with. `None` serves plain TCP.
+    pub tls: Option<TlsConfig>,
+    /// Unix socket that is served besides TCP, always without TLS. Socket left by previous
+    /// run is replaced.
+    pub unix_socket: Option<PathBuf>,
+    /// Permissions of the Unix socket file, like `0o770`, limiting who can connect.
+    /// `None` keeps permissions given by umask.
+    pub unix_socket_perm: Option<u32>,
+    /// Stop gracefully on SIGINT and SIGTERM. Handlers replace what the signals do to the
+    /// whole process, so it's left to the binary to enable them.
+    pub handle_signals: bool,
+    /// How long connections may take to finish their commands on shutdown, before the
+    /// server stops without them. `None` uses `DEFAULT_DRAIN_TIMEOUT`.
+    pub drain_timeout: Option<Duration>,
+    /// Write snapshot after connections are drained, unless `SHUTDOWN` says otherwise.
+    pub save_on_shutdown: bool,
+}
+
+/// Request to stop the server, seen by every connection once it's made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shutdown {
+    /// Overrides `Config::save_on_shutdown` when set.
+    pub save: Option<bool>,
+}
+
+/// State shared by all connections.
+pub struct Shared {
+    pub db: Database<String>,
+    pub snapshotter: Option<Snapshotter>,
+    pub aof: Option<Aof>,
+    pub pubsub: PubSub,
+    pub acl: Acl,
+    /// `None` until shutdown is requested. Every connection holds a receiver, so server
+    /// knows they are all done once the channel is closed.
+    pub shutdown: watch::Sender<Option<Shutdown>>,
+}
+
+impl Shared {
+    /// Stops accepting connections and tells existing ones to finish. Only the first request
+    /// counts.
+    pub fn request_shutdown(&self, save: Option<bool>) {
+        self.shutdown.send_if_modified(|shutdown| {
+            let first = shutdown.is_none();
+            if first {
+                *shutdown = Some(Shutdown { save });
+            }
+            first
+        });
+    }
+}
+
+/// Listens on TCP `addr`, on `Config::unix_socket` or on both. Returns once server was shut
+/// down, see `Shared::request_shutdown`.
+pub async fn start(addr: Option<&str>, config: Config) -> anyhow::Result<()> {
+    let listener = match addr {
+        Some(addr) => Some(TcpListener::bind(addr).await?),
+        None => None,
+    };
+    serve(listener, config).await
+}
+
+/// Serves connections from an already bound `listener`, and from `Config::unix_socket` if
+/// it's set.
+pub async fn run(listener: TcpListener, config: Config) -> anyhow::Result<()> {
+    serve(Some(listener), config).await
+}
+
+async fn serve(tcp: Option<TcpListener>, config: Config) -> anyhow::Result<()> {
+    let unix = match &config.unix_socket {
+        Some(path) => Some((
+            bind_unix(path, config.unix_socket_perm)?,
+            path.display().to_string(),
+        )),
+        None => None,
+    };
+    anyhow::ensure!(
+        tcp.is_some() || unix.is_some(),
+        "expected TCP address or Unix socket to listen on"
+    );
+
+    let mut db = config
+        .shards
+        .map_or_else(Database::new, Database::with_shards);
+    if let Some(max_memory) = config.max_memory {
+        db = db.with_memory_limit(max_memory, config.eviction_policy);
+    }
+    let snapshotter = config.snapshot_path.map(Snapshotter::new);
+    let acl = match &config.acl_path {
+        Some(path) => Acl::load(path)?,
+        None => Acl::new(),
+    };
+    let tls = config.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+
+    // commands from log are replayed after shared state is ready, because they run through
+    // the same code path as requests
+    let mut replay = Vec::new();
+    if let Some(path) = &config.aof_path {
+        replay = aof::load(path)?;
+    } else if let Some(snapshotter) = &snapshotter {
+        let loaded = snapshotter.load(&db)?;
+        log::info!(
+            "Loaded {} keys from {}",
+            loaded,
+            snapshotter.path().display()
+        );
+    }
+
+    let aof = match config.aof_path {
+        Some(path) => Some(Aof::open(path, config.aof_fsync)?),
+        None => None,
+    };
+
+    let shared = Arc::new(Shared {
+        db,
+        snapshotter,
+        aof,
+        pubsub: PubSub::new(),
+        acl,
+        shutdown: watch::Sender::new(None),
+    });
+
+    // background tasks stop once shutdown is requested and are joined before final save
+    let mut tasks = Vec::new();
+
+    if let Some(aof) = &shared.aof {
+        log::info!(
+            "Replaying {} commands from {}",
+            replay.len(),
+            aof.path().display()
+        );
+        for command in replay {
+            protocol::execute(&shared, command);
+        }
+
+        if aof.policy() == FsyncPolicy::EverySecond {
+            let syncer = shared.clone();
+            let mut stop = shared.shutdown.subscribe();
+            tasks.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = shutdown_requested(&mut stop) => break,
+                    }
+                    if let Some(aof) = &syncer.aof
+                        && let Err(e) = aof.sync()
+                    {
+                        log::error!("Failed to sync append only file: {}", e);
+                    }
+                }
+            }));
+        }
+    }
+
+    let sweeper = shared.clone();
+    let mut stop = shared.shutdown.subscribe();
+    tasks.push(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRE_SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_requested(&mut stop) => break,
+            }
+            let removed = sweeper.db.remove_expired();
+            if removed > 0 {
+                log::debug!("Removed {} expired keys", removed);
+            }
+        }
+    }));
+
+    if let Some(period) = config.snapshot_interval {
+        let saver = shared.clone();
+        let mut stop = shared.shutdown.subscribe();
+        tasks.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // first tick completes immediately and there is nothing new to save yet
+            interval.tick().await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown_requested(&mut stop) => break,
+                }
+                let saver = saver.clone();
+                let _ = tokio::task::spawn_blocking(move || save_snapshot(&saver, false)).await;
+            }
+        }));
+    }
+
+    if config.handle_signals {
+        // registered up front, so that failing to listen for signals fails startup
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        let stopper = shared.clone();
+        let mut stop = shared.shutdown.subscribe();
+        tasks.push(tokio::spawn(async move {
+            let name = tokio::select! {
+                _ = interrupt.recv() => "SIGINT",
+                _ = terminate.recv() => "SIGTERM",
+                _ = shutdown_requested(&mut stop) => return,
+            };
+            log::info!("Received {}, shutting down", name);
+            stopper.request_shutdown(None);
+        }));
+    }
+
+    if let Some(listener) = &tcp {
+        log::info!("Listening on: {}", listener.local_addr()?);
+    }
+    if let Some((_, path)) = &unix {
+        log::info!("Listening on: {}", path);
+    }
+    let mut requested = shared.shutdown.subscribe();
+    let shutdown = loop {
+        tokio::select! {
+            shutdown = shutdown_requested(&mut requested) => break shutdown,
+            accepted = accept_tcp(tcp.as_ref()) => {
+                let (conn, conn_addr) = accepted?;
+                log::info!("Accepted connection from: {}", conn_addr);
+                handle_tcp(conn, conn_addr, tls.as_ref(), &shared);
+            }
+            accepted = accept_unix(unix.as_ref()) => {
+                let (conn, path) = accepted?;
+                log::info!("Accepted connection on: {}", path);
+                let shutdown = shared.shutdown.subscribe();
+                protocol::handle_connection(path, conn, shared.clone(), shutdown);
+            }
+        }
+    };
+
+    drop((tcp, requested));
+    if let Some((listener, path)) = unix {
+        drop(listener);
+        let _ = fs::remove_file(path);
+    }
+    // joined first, so that drain only counts connections
+    for task in tasks {
+        if let Err(e) = task.await {
+            log::error!("Background task failed: {}", e);
+        }
+    }
+    drain(
+        &shared,
+        config.drain_timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT),
+    )
+    .await;
+
+    if let Some(aof) = &shared.aof {
+        aof.sync()?;
+    }
+    let save = shutdown.and_then(|shutdown| shutdown.save);
+    if save.unwrap_or(config.save_on_shutdown) && shared.snapshotter.is_some() {
+        let saver = shared.clone();
+        tokio::task::spawn_blocking(move || save_snapshot(&saver, false)).await??;
+    }
+    log::info!("Server stopped");
+    Ok(())
+}
+
+/// Completes once shutdown is requested. Borrow of the channel ends before it completes, so
+/// waiting can be raced with other futures in `select!`.
+pub async fn shutdown_requested(
+    receiver: &mut watch::Receiver<Option<Shutdown>>,
+) -> Option<Shutdown> {
+    receiver
+        .wait_for(Option::is_some)
+        .await
+        .ok()
+        .and_then(|shutdown| *shutdown)
+}
+
+/// Waits until every connection finishes, at most `timeout`.
+async fn drain(shared: &Shared, timeout: Duration) {
+    let open = shared.shutdown.receiver_count();
+    if open > 0 {
+        log::info!("Waiting for {} connections to finish", open);
+    }
+    if tokio::time::timeout(timeout, shared.shutdown.closed())
+        .await
+        .is_err()
+    {
+        log::warn!(
+            "Stopping without {} connections that didn't finish in {:?}",
+            shared.shutdown.receiver_count(),
+            timeout
+        );
+    }
+}
+
+/// Binds Unix socket at `path`. Only socket files are replaced, so that wrong path doesn't
+/// remove unrelated file.
+fn bind_unix(path: &Path, perm: Option<u32>) -> io::Result<UnixListener> {
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(mode) = perm {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(listener)
+}
+
+/// Accepts TCP connection, never completes without `listener`.
+async fn accept_tcp(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Accepts Unix socket connection, never completes without `listener`. Peers of Unix socket
+/// are unnamed, so they are known by path of the socket instead.
+async fn accept_unix(
+    listener: Option<&(UnixListener, String)>,
+) -> io::Result<(UnixStream, String)> {
+    match listener {
+        Some((listener, path)) => Ok((listener.accept().await?.0, path.clone())),
+        None => std::future::pending().await,
+    }
+}
+
+fn handle_tcp(
+    conn: TcpStream,
+    conn_addr: SocketAddr,
+    tls: Option<&TlsAcceptor>,
+    shared: &Arc<Shared>,
+) {
+    // subscribed before handshake, so that shutdown waits for it as well
+    let mut shutdown = shared.shutdown.subscribe();
+    let Some(acceptor) = tls else {
+        protocol::handle_connection(conn_addr, conn, shared.clone(), shutdown);
+        return;
+    };
+    // handshake waits for the peer, so it mustn't hold up accepting others
+    let acceptor = acceptor.clone();
+    let shared = shared.clone();
+    tokio::spawn(async move {
+        let handshake = tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(conn));
+        let accepted = tokio::select! {
+            accepted = handshake => accepted,
+            _ = shutdown_requested(&mut shutdown) => return,
+        };
+        match accepted {
+            Ok(Ok(stream)) => protocol::handle_connection(conn_addr, stream, shared, shutdown),
+            Ok(Err(e)) => log::warn!("TLS handshake with {} failed: {}", conn_addr, e),
+            Err(_) => log::warn!("TLS handshake with {} timed out", conn_addr),
+        }
+    });
+}
 
-pub async fn start(addr: &str) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(addr).await.unwrap();
-    let db = Arc::new(storage::Database::new());
+/// Writes snapshot if it is enabled, logging the outcome. Unless `exclusive` tells that caller
+/// already holds exclusive access to database, it's taken while entries are copied.
+pub fn save_snapshot(shared: &Shared, exclusive: bool) -> Result<usize, Error> {
+    let Some(snapshotter) = &shared.snapshotter else {
+        return Err(Error::BadRequest {
+            msg: "snapshots are disabled".into(),
+        });
+    };
 
-    log::info!("Listening on: {}", listener.local_addr().unwrap());
-    loop {
-        let (conn, conn_addr) = listener.accept().await?;
-        log::info!("Accepted connection from: {}", conn_addr);
-        protocol::handle_connection(conn_addr, conn, db.clone());
+    let result = snapshotter.save(|| {
+        if exclusive {
+            shared.db.entries()
+        } else {
+            shared.db.capture_entries()
+        }
+    });
+    match &result {
+        Ok(saved) => log::info!("Saved {} keys to {}", saved, snapshotter.path().display()),
+        Err(e) => log::error!("Snapshot failed: {}", e),
     }
+    result
 }
@@ -17,62 +17,915 @@
 //! | separator - 2 bytes |   yes   |   yes   |    yes     |    yes    |
 //! +---------------------+---------+---------+------------+-----------+
 //! ```
+//!
+//! Payload of every command type (fields placed between key and separator):
+//!
+//! ```text
+//! +--------------------+------+------------------------------------------------+
+//! | command            | type | payload                                        |
+//! +--------------------+------+------------------------------------------------+
+//! | GET                | g    | -                                              |
+//! | SET                | s    | value, expire in ms - 8 bytes (0 = never)      |
+//! | DELETE             | d    | -                                              |
+//! | EXPIRE             | x    | expire in ms - 8 bytes                         |
+//! | TTL                | t    | -                                              |
+//! | PERSIST            | p    | -                                              |
+//! | SAVE               | S    | - (empty key)                                  |
+//! | BGSAVE             | B    | - (empty key)                                  |
+//! | REWRITEAOF         | R    | - (empty key)                                  |
+//! | MGET               | G    | array of keys (empty key)                      |
+//! | MSET               | M    | array of alternating keys and values (empty)   |
+//! | MDEL               | D    | array of keys (empty key)                      |
+//! | EXISTS             | E    | array of keys (empty key)                      |
+//! | KEYS               | ,    | pattern as string (empty key)                  |
+//! | SCAN               | %    | cursor - 8 bytes, pattern/null, count (empty)  |
+//! | TYPE               | y    | -                                              |
+//! | RENAME             | -    | new key as string                              |
+//! | RENAMENX           | ~    | new key as string                              |
+//! | DBSIZE             | $    | - (empty key)                                  |
+//! | FLUSHALL           | !    | - (empty key)                                  |
+//! | INCR               | i    | -                                              |
+//! | DECR               | c    | -                                              |
+//! | INCRBY             | I    | delta - 8 bytes                                |
+//! | INCRBYFLOAT        | .    | delta - 8 bytes float                          |
+//! | SETNX              | n    | same as SET                                    |
+//! | SETXX              | X    | same as SET                                    |
+//! | CAS                | C    | expected value, new value                      |
+//! | SUBSCRIBE          | b    | array of channels (empty key)                  |
+//! | UNSUBSCRIBE        | u    | array of channels (empty key)                  |
+//! | PSUBSCRIBE         | q    | array of patterns (empty key)                  |
+//! | PUNSUBSCRIBE       | Q    | array of patterns (empty key)                  |
+//! | PUBLISH            | P    | value (key is the channel)                     |
+//! | MULTI              | T    | - (empty key)                                  |
+//! | EXEC               | e    | - (empty key)                                  |
+//! | DISCARD            | r    | - (empty key)                                  |
+//! | WATCH              | w    | array of keys (empty key)                      |
+//! | UNWATCH            | W    | - (empty key)                                  |
+//! | HSET               | h    | map of fields                                  |
+//! | HGET               | H    | field as string                                |
+//! | HDEL               | j    | array of fields                                |
+//! | HGETALL            | A    | -                                              |
+//! | HINCRBY            | J    | field as string, delta - 8 bytes               |
+//! | HEXISTS            | Y    | field as string                                |
+//! | LPUSH              | l    | array of values                                |
+//! | RPUSH              | L    | array of values                                |
+//! | LPOP               | o    | -                                              |
+//! | RPOP               | O    | -                                              |
+//! | LRANGE             | v    | start - 8 bytes, stop - 8 bytes                |
+//! | LLEN               | z    | -                                              |
+//! | LTRIM              | V    | start - 8 bytes, stop - 8 bytes                |
+//! | LINDEX             | k    | index - 8 bytes                                |
+//! | LSET               | K    | index - 8 bytes, value                         |
+//! | BLPOP              | f    | array of keys, timeout in ms - 8 bytes (0 = ∞) |
+//! | BRPOP              | F    | same as BLPOP                                  |
+//! | SADD               | a    | array of members                               |
+//! | SREM               | m    | array of members                               |
+//! | SISMEMBER          | N    | member                                         |
+//! | SMEMBERS           | U    | -                                              |
+//! | SCARD              | Z    | -                                              |
+//! | SPOP               | 0    | -                                              |
+//! | SRANDMEMBER        | 1    | -                                              |
+//! | SUNION             | 2    | array of keys (empty key)                      |
+//! | SINTER             | 3    | array of keys (empty key)                      |
+//! | SDIFF              | 4    | array of keys (empty key)                      |
+//! | SUNIONSTORE        | 5    | array of keys (key is the destination)         |
+//! | SINTERSTORE        | 6    | array of keys (key is the destination)         |
+//! | SDIFFSTORE         | 7    | array of keys (key is the destination)         |
+//! | ZADD               | 8    | sorted set of members with scores              |
+//! | ZREM               | 9    | array of members                               |
+//! | ZSCORE             | @    | member                                         |
+//! | ZINCRBY            | +    | member, delta - 8 bytes float                  |
+//! | ZRANK              | <    | member                                         |
+//! | ZREVRANK           | >    | member                                         |
+//! | ZRANGE             | (    | start - 8 bytes, stop - 8 bytes                |
+//! | ZREVRANGE          | )    | start - 8 bytes, stop - 8 bytes                |
+//! | ZRANGEBYSCORE      | [    | min, max - 8 bytes floats, offset, count       |
+//! | ZREVRANGEBYSCORE   | ]    | same as ZRANGEBYSCORE (count < 0 = all)        |
+//! | ZCARD              | #    | -                                              |
+//! | ZPOPMIN            | {    | count - 8 bytes                                |
+//! | ZPOPMAX            | }    | count - 8 bytes                                |
+//! | XADD               | &    | id (null = generated), array of fields/values  |
+//! | XRANGE             | /    | start id, end id, count - 8 bytes (< 0 = all)  |
+//! | XREVRANGE          | \    | same as XRANGE                                 |
+//! | XLEN               | ;    | -                                              |
+//! | XTRIM              | :    | max len - 8 bytes                              |
+//! | XREAD              | =    | keys, ids (null = $), count, block ms (empty)  |
+//! | XREADGROUP         | ?    | group, consumer, then same as XREAD (null = >) |
+//! | XACK               | '    | group, array of ids                            |
+//! | XGROUP CREATE      | "    | group, id (null = $), create stream as bool    |
+//! | XPENDING           | `    | group, start id, end id, count, consumer/null  |
+//! | AUTH               | ^    | user, password (empty key)                     |
+//! | ACL SETUSER        | _    | 0 - 8 bytes, array of user and rules (empty)   |
+//! | ACL LIST           | _    | 1 - 8 bytes, empty array (empty key)           |
+//! | ACL WHOAMI         | _    | 2 - 8 bytes, empty array (empty key)           |
+//! | SHUTDOWN           | |    | save as bool, null to use config (empty key)   |
+//! +--------------------+------+------------------------------------------------+
+//! ```
+//!
+//! Responses start with type byte of a value (payload), `-` (null), `e` (error, followed by
+//! message len and message) or `m` (multi, followed by 4 bytes count and that many nested
+//! responses, each with its own separator).
+//!
+//! Subscribed connections also receive pushes (`>`), which are not replies to any request:
+//!
+//! ```text
+//! +-------------------------------+
+//! | type ">" - 1 byte             |
+//! +-------------------------------+
+//! | kind - 1 byte                 |
+//! +-------------------------------+
+//! | channel len - 4 bytes         |
+//! +-------------------------------+
+//! | channel - n bytes             |
+//! +-------------------------------+
+//! | pattern len - 4 bytes         |  only for pattern messages (kind "p")
+//! +-------------------------------+
+//! | pattern - n bytes             |  only for pattern messages (kind "p")
+//! +-------------------------------+
+//! | value - n bytes               |
+//! +-------------------------------+
+//! | separator - 2 bytes           |
+//! +-------------------------------+
+//! ```
+//!
+//! Commands sent between `MULTI` and `EXEC` are answered with `"QUEUED"` string, `EXEC` answers
+//! with `m` response holding their results, or null if a watched key changed.
+//!
+//! Sorted set ranges and pops are answered with array of alternating members and scores. Scores
+//! in responses are floats.
+//!
+//! Blocking pops are answered with array of the key and popped value, or null once timeout
+//! passes. Inside transactions they don't wait.
+//!
+//! Stream ids are sent as `ms-seq` strings. Entries are answered as array of the id and flat
+//! array of alternating fields and values, stream reads with array of `[key, entries]` for
+//! every stream that had any, or null. Negative block time of `XREAD`/`XREADGROUP` doesn't
+//! wait at all, zero waits forever.
+//!
+//! Subscription commands are answered with `m` response holding one push acknowledgement per
+//! channel, with number of active subscriptions as value.
+//!
+//! Connections that have to authenticate get `NOAUTH` error for every command until `AUTH`
+//! succeeds, commands that user isn't allowed to run get `NOPERM` error (see `server::acl`).
+//!
+//! Type byte `*` is reserved, because it starts RESP requests (see `server::resp`).
+//!
+//! Frame length is derived from the type bytes and length prefixes alone, so keys and values
+//! may contain arbitrary bytes (including `\r\n`). The trailing separator is only checked.
 
 use std::{
-    io::{Cursor, Read},
-    net::SocketAddr,
+    collections::VecDeque,
+    fmt,
+    io::{self, Cursor},
     sync::Arc,
 };
 
 use bytes::{Buf, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufWriter},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+    sync::watch,
+    time::Instant,
 };
 
 use crate::{
     error::Error,
-    server::storage::Database,
+    server::{
+        Shared, Shutdown,
+        pubsub::{PubSub, Push, PushKind, Subscriber},
+        resp::{self, RespFrame, RespReply, RespVersion},
+        save_snapshot, shutdown_requested,
+        storage::{Database, SetOp},
+        stream::{Fields, StreamId, unix_millis},
+        transaction::Transaction,
+    },
     utils::{
-        bytes::{get_u8, get_u32},
-        command::{Command, CommandType, Value, get_line},
+        bytes::{expect_separator, get_u8, get_u32, skip},
+        command::{Command, CommandType, Member, Value},
+        glob,
     },
 };
 
-pub fn handle_connection(addr: SocketAddr, stream: TcpStream, db_clone: Arc<Database<String>>) {
+/// Serves requests of a single peer over `stream`, which may be plain TCP, already negotiated
+/// TLS or Unix socket. `addr` only names the peer in logs. `shutdown` has to be subscribed as
+/// soon as the peer is accepted, so that shutdown waits for this connection.
+pub fn handle_connection<S: Transport + 'static>(
+    addr: impl fmt::Display + Send + 'static,
+    stream: S,
+    shared: Arc<Shared>,
+    mut shutdown: watch::Receiver<Option<Shutdown>>,
+) {
     let mut conn = Connection::new(stream);
     tokio::spawn(async move {
+        let (mut subscriber, mut pushes) = shared.pubsub.subscriber();
+        let mut transaction = Transaction::new();
+        let mut user = shared.acl.default_user();
         loop {
-            match conn.read::<Command>().await {
+            let request = tokio::select! {
+                request = conn.read_with_handshake::<Command>(|frame, version| {
+                    resp::handshake(frame, version, &shared.acl, &mut user)
+                }) => request,
+                // requests that were not read yet are dropped, like when connection breaks
+                _ = shutdown_requested(&mut shutdown) => break,
+                // subscriber keeps its own sender, so the queue never closes
+                Some(push) = pushes.recv() => {
+                    if conn.write(Response::Push(push)).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            };
+
+            match request {
                 Ok(Some(command)) => {
-                    log::debug!("{:?}", command);
-                    match command.r#type {
-                        CommandType::Get => {
-                            let response = Response::new(db_clone.get(&command.key));
-                            let _ = conn.write(response).await;
-                        }
-                        CommandType::Set { value } => {
-                            let response = Response::new(db_clone.set(command.key, value));
-                            let _ = conn.write(response).await;
+                    // passwords don't belong to logs
+                    if !matches!(
+                        command.r#type,
+                        CommandType::Auth { .. } | CommandType::AclSetUser { .. }
+                    ) {
+                        log::debug!("{:?}", command);
+                    }
+                    // responses to requests that are already buffered are sent together, `read`
+                    // flushes them once it runs out of complete requests
+                    let sent = if let Err(e) = shared.acl.check(user.as_deref(), &command) {
+                        conn.feed(Response::error(&e.to_string())).await
+                    } else if command.is_auth() {
+                        let response = authenticate(&shared, &mut user, command.r#type);
+                        conn.feed(response).await
+                    } else if transaction.is_queuing() && !command.is_transaction() {
+                        transaction.queue(command);
+                        conn.feed_queued().await
+                    } else if command.is_transaction() {
+                        let ends_queue =
+                            matches!(command.r#type, CommandType::Exec | CommandType::Discard);
+                        let response = transaction.execute(&shared, command.r#type);
+                        if ends_queue {
+                            conn.feed_exec(response).await
+                        } else {
+                            conn.feed(response).await
                         }
-                        CommandType::Delete => {
-                            let response = Response::new(db_clone.delete(&command.key));
-                            // test error to check if it would parse response correctly
-                            // let response = Response::error("try again :D");
-                            let _ = conn.write(response).await;
+                    } else if command.is_blocking() {
+                        // earlier responses shouldn't wait together with this one
+                        if conn.flush().await.is_err() {
+                            break;
                         }
+                        let response = tokio::select! {
+                            response = blocking_read(&shared, command) => response,
+                            // nobody would read the popped value
+                            _ = conn.closed() => break,
+                            // answered the same as if it timed out
+                            _ = shutdown_requested(&mut shutdown) => Response::Null,
+                        };
+                        conn.feed(response).await
+                    } else if command.is_subscription() {
+                        let response =
+                            update_subscriptions(&shared.pubsub, &mut subscriber, command.r#type);
+                        conn.feed(response).await
+                    } else if command.is_exclusive() {
+                        let response = {
+                            let _access = shared.db.exclusive_access();
+                            apply(&shared, command)
+                        };
+                        conn.feed(response).await
+                    } else {
+                        let response = {
+                            let _access = shared.db.shared_access();
+                            apply(&shared, command)
+                        };
+                        conn.feed(response).await
+                    };
+                    if sent.is_err() {
+                        break;
                     }
                 }
                 Ok(None) => {
                     log::info!("Connection from {} closed", addr);
                     break;
                 }
-                Err(e) => log::error!("Error: {}", e),
+                Err(e) => {
+                    // framing cannot be recovered after a malformed request, so drop the client
+                    log::error!("Error: {}", e);
+                    let _ = conn.write(Response::error(&e.to_string())).await;
+                    break;
+                }
             }
         }
+        shared.pubsub.remove(&subscriber);
+        let _ = conn.shutdown().await;
     });
 }
 
+/// Changes subscriptions of the connection and acknowledges every channel separately.
+fn update_subscriptions(
+    pubsub: &PubSub,
+    subscriber: &mut Subscriber,
+    command: CommandType,
+) -> Response {
+    let acks = match command {
+        CommandType::Subscribe { channels } => pubsub.subscribe(subscriber, channels),
+        CommandType::Unsubscribe { channels } => pubsub.unsubscribe(subscriber, channels),
+        CommandType::PSubscribe { patterns } => pubsub.psubscribe(subscriber, patterns),
+        CommandType::PUnsubscribe { patterns } => pubsub.punsubscribe(subscriber, patterns),
+        _ => return Response::error("not a subscription command"),
+    };
+    Response::Multi(acks.into_iter().map(Response::Push).collect())
+}
+
+/// Authenticates the connection as another user or tells which user it is.
+fn authenticate(shared: &Shared, user: &mut Option<String>, command: CommandType) -> Response {
+    match command {
+        CommandType::Auth {
+            user: name,
+            password,
+        } => match shared.acl.authenticate(&name, &password) {
+            Ok(()) => {
+                *user = Some(name);
+                Response::Payload(Value::Boolean(true))
+            }
+            Err(e) => Response::error(&e.to_string()),
+        },
+        CommandType::AclWhoAmI => Response::new(user.clone().map(Value::String)),
+        _ => Response::error("not an authentication command"),
+    }
+}
+
+/// Runs `command`, logging it to append only file first if it's a write. Commands that can
+/// grow the data first evict keys until memory is under the limit.
+pub fn apply(shared: &Arc<Shared>, command: Command) -> Response {
+    if command.may_grow()
+        && let Err(e) = make_room(shared)
+    {
+        return Response::error(&e.to_string());
+    }
+    match &shared.aof {
+        Some(aof) if command.is_write() => aof.log(command, |command| execute(shared, command)),
+        _ => execute(shared, command),
+    }
+}
+
+/// Evicts keys until used memory is under the limit. Keys are deleted through `apply`, so the
+/// eviction is also written to append only file and replaying it ends with the same keys.
+fn make_room(shared: &Arc<Shared>) -> Result<(), Error> {
+    while let Some(key) = shared.db.eviction_candidate()? {
+        log::debug!("Evicting key {key}");
+        apply(shared, Command::delete(&key));
+    }
+    Ok(())
+}
+
+/// Runs `command` against shared state and builds response for it.
+pub fn execute(shared: &Arc<Shared>, command: Command) -> Response {
+    let db = &shared.db;
+    match command.r#type {
+        CommandType::Get => Response::new(db.get(&command.key)),
+        CommandType::Set { value, expire } => {
+            match db.set_with_expiry(command.key, value, expire) {
+                Ok(old) => Response::new(old),
+                Err(e) => Response::error(&e.to_string()),
+            }
+        }
+        CommandType::Delete => Response::new(db.delete(&command.key)),
+        CommandType::Expire { expire } => {
+            result_response(db.expire(&command.key, expire).map(Value::Boolean))
+        }
+        // same convention as redis PTTL: -1 if key never expires, null if it doesn't exist
+        CommandType::Ttl => Response::new(
+            db.ttl(&command.key)
+                .map(|ttl| Value::Number(ttl.map_or(-1, |ttl| ttl.as_millis() as i64))),
+        ),
+        CommandType::Persist => Response::Payload(Value::Boolean(db.persist(&command.key))),
+        CommandType::Save => match save_snapshot(shared, true) {
+            Ok(_) => Response::Payload(Value::Boolean(true)),
+            Err(e) => Response::error(&e.to_string()),
+        },
+        CommandType::BgSave => match &shared.snapshotter {
+            Some(snapshotter) if snapshotter.is_saving() => {
+                Response::error("snapshot is already in progress")
+            }
+            Some(_) => {
+                let shared = shared.clone();
+                tokio::task::spawn_blocking(move || save_snapshot(&shared, false));
+                Response::Payload(Value::Boolean(true))
+            }
+            None => Response::error("snapshots are disabled"),
+        },
+        CommandType::RewriteAof => match &shared.aof {
+            Some(aof) => match aof.rewrite(db) {
+                Ok(_) => Response::Payload(Value::Boolean(true)),
+                Err(e) => Response::error(&e.to_string()),
+            },
+            None => Response::error("append only file is disabled"),
+        },
+        CommandType::MGet { keys } => Response::multi(db.mget(&keys)),
+        CommandType::MSet { entries } => Response::multi(db.mset(entries)),
+        CommandType::MDel { keys } => Response::multi(db.mdelete(&keys)),
+        CommandType::Exists { keys } => Response::Payload(Value::Number(db.exists(&keys) as i64)),
+        CommandType::Keys { pattern } => {
+            let keys = db.keys(|key| glob::matches(pattern.as_bytes(), key.as_bytes()));
+            Response::Payload(Value::Array(keys.into_iter().map(Value::String).collect()))
+        }
+        // cursor is sent as string, same as redis does, since it may not fit into i64
+        CommandType::Scan {
+            cursor,
+            pattern,
+            count,
+        } => {
+            let count = to_count(count).unwrap_or(DEFAULT_SCAN_COUNT);
+            let (cursor, keys) = db.scan(cursor, count, |key| {
+                pattern
+                    .as_ref()
+                    .is_none_or(|pattern| glob::matches(pattern.as_bytes(), key.as_bytes()))
+            });
+            Response::Payload(Value::Array(vec![
+                Value::String(cursor.to_string()),
+                Value::Array(keys.into_iter().map(Value::String).collect()),
+            ]))
+        }
+        CommandType::Type => Response::Payload(Value::String(
+            db.type_of(&command.key).unwrap_or("none").into(),
+        )),
+        CommandType::Rename { new_key } => {
+            result_response(db.rename(&command.key, new_key, true).map(Value::Boolean))
+        }
+        CommandType::RenameNx { new_key } => {
+            result_response(db.rename(&command.key, new_key, false).map(Value::Boolean))
+        }
+        CommandType::DbSize => Response::Payload(Value::Number(db.len() as i64)),
+        CommandType::FlushAll => {
+            db.flush();
+            Response::Payload(Value::Boolean(true))
+        }
+        CommandType::Incr => result_response(db.incr_by(command.key, 1).map(Value::Number)),
+        CommandType::Decr => result_response(db.incr_by(command.key, -1).map(Value::Number)),
+        CommandType::IncrBy { delta } => {
+            result_response(db.incr_by(command.key, delta).map(Value::Number))
+        }
+        CommandType::IncrByFloat { delta } => {
+            result_response(db.incr_by_float(command.key, delta).map(Value::Float))
+        }
+        CommandType::SetNx { value, expire } => {
+            result_response(db.set_nx(command.key, value, expire).map(Value::Boolean))
+        }
+        CommandType::SetXx { value, expire } => {
+            result_response(db.set_xx(command.key, value, expire).map(Value::Boolean))
+        }
+        CommandType::CompareAndSwap { expected, value } => Response::Payload(Value::Boolean(
+            db.compare_and_swap(&command.key, &expected, value),
+        )),
+        CommandType::Publish { payload } => Response::Payload(Value::Number(
+            shared.pubsub.publish(&command.key, payload) as i64,
+        )),
+        CommandType::Subscribe { .. }
+        | CommandType::Unsubscribe { .. }
+        | CommandType::PSubscribe { .. }
+        | CommandType::PUnsubscribe { .. } => {
+            Response::error("subscriptions can only be changed by connection")
+        }
+        CommandType::Multi
+        | CommandType::Exec
+        | CommandType::Discard
+        | CommandType::Watch { .. }
+        | CommandType::Unwatch => Response::error("transactions can only be run by connection"),
+        CommandType::Auth { .. } | CommandType::AclWhoAmI => {
+            Response::error("users can only be changed by connection")
+        }
+        CommandType::AclSetUser { user, rules } => match shared.acl.set_user(user, &rules) {
+            Ok(()) => Response::Payload(Value::Boolean(true)),
+            Err(e) => Response::error(&e.to_string()),
+        },
+        CommandType::AclList => Response::Payload(Value::Array(
+            shared.acl.list().into_iter().map(Value::String).collect(),
+        )),
+        CommandType::Shutdown { save: Some(true) } if shared.snapshotter.is_none() => {
+            Response::error("snapshots are disabled")
+        }
+        CommandType::Shutdown { save } => {
+            shared.request_shutdown(save);
+            Response::Payload(Value::Boolean(true))
+        }
+        CommandType::HSet { fields } => result_response(
+            db.hset(command.key, fields)
+                .map(|n| Value::Number(n as i64)),
+        ),
+        CommandType::HGet { field } => option_response(db.hget(&command.key, &field)),
+        CommandType::HDel { fields } => result_response(
+            db.hdel(&command.key, &fields)
+                .map(|n| Value::Number(n as i64)),
+        ),
+        CommandType::HGetAll => result_response(db.hgetall(&command.key).map(Value::Map)),
+        CommandType::HIncrBy { field, delta } => {
+            result_response(db.hincr_by(command.key, field, delta).map(Value::Number))
+        }
+        CommandType::HExists { field } => {
+            result_response(db.hexists(&command.key, &field).map(Value::Boolean))
+        }
+        CommandType::LPush { values } => result_response(
+            db.push(command.key, values, false)
+                .map(|len| Value::Number(len as i64)),
+        ),
+        CommandType::RPush { values } => result_response(
+            db.push(command.key, values, true)
+                .map(|len| Value::Number(len as i64)),
+        ),
+        CommandType::LPop => option_response(db.pop(&command.key, false)),
+        CommandType::RPop => option_response(db.pop(&command.key, true)),
+        CommandType::LRange { start, stop } => {
+            result_response(db.lrange(&command.key, start, stop).map(Value::Array))
+        }
+        CommandType::LLen => {
+            result_response(db.llen(&command.key).map(|len| Value::Number(len as i64)))
+        }
+        CommandType::LTrim { start, stop } => result_response(
+            db.ltrim(&command.key, start, stop)
+                .map(|_| Value::Boolean(true)),
+        ),
+        CommandType::LIndex { index } => option_response(db.lindex(&command.key, index)),
+        // inside transactions and during replay blocking pops don't wait
+        CommandType::BLPop { keys, .. } => pop_first(db, &keys, false),
+        CommandType::BRPop { keys, .. } => pop_first(db, &keys, true),
+        CommandType::SAdd { members } => result_response(
+            db.sadd(command.key, members)
+                .map(|added| Value::Number(added as i64)),
+        ),
+        CommandType::SRem { members } => result_response(
+            db.srem(&command.key, &members)
+                .map(|removed| Value::Number(removed as i64)),
+        ),
+        CommandType::SIsMember { member } => {
+            result_response(db.sismember(&command.key, &member).map(Value::Boolean))
+        }
+        CommandType::SMembers => result_response(db.smembers(&command.key).map(Value::Set)),
+        CommandType::SCard => {
+            result_response(db.scard(&command.key).map(|len| Value::Number(len as i64)))
+        }
+        CommandType::SPop => {
+            option_response(db.spop(&command.key).map(|member| member.map(Value::from)))
+        }
+        CommandType::SRandMember => option_response(
+            db.srandmember(&command.key)
+                .map(|member| member.map(Value::from)),
+        ),
+        CommandType::SUnion { keys } => {
+            result_response(db.combine(&keys, SetOp::Union).map(Value::Set))
+        }
+        CommandType::SInter { keys } => {
+            result_response(db.combine(&keys, SetOp::Inter).map(Value::Set))
+        }
+        CommandType::SDiff { keys } => {
+            result_response(db.combine(&keys, SetOp::Diff).map(Value::Set))
+        }
+        CommandType::SUnionStore { keys } => store_response(db, command.key, &keys, SetOp::Union),
+        CommandType::SInterStore { keys } => store_response(db, command.key, &keys, SetOp::Inter),
+        CommandType::SDiffStore { keys } => store_response(db, command.key, &keys, SetOp::Diff),
+        CommandType::ZAdd { members } => result_response(
+            db.zadd(command.key, members)
+                .map(|added| Value::Number(added as i64)),
+        ),
+        CommandType::ZRem { members } => result_response(
+            db.zrem(&command.key, &members)
+                .map(|removed| Value::Number(removed as i64)),
+        ),
+        CommandType::ZScore { member } => option_response(
+            db.zscore(&command.key, &member)
+                .map(|s| s.map(Value::Float)),
+        ),
+        CommandType::ZIncrBy { member, delta } => {
+            result_response(db.zincr_by(command.key, member, delta).map(Value::Float))
+        }
+        CommandType::ZRank { member } => option_response(
+            db.zrank(&command.key, &member, false)
+                .map(|rank| rank.map(|rank| Value::Number(rank as i64))),
+        ),
+        CommandType::ZRevRank { member } => option_response(
+            db.zrank(&command.key, &member, true)
+                .map(|rank| rank.map(|rank| Value::Number(rank as i64))),
+        ),
+        CommandType::ZRange { start, stop } => result_response(
+            db.zrange(&command.key, start, stop, false)
+                .map(scored_value),
+        ),
+        CommandType::ZRevRange { start, stop } => {
+            result_response(db.zrange(&command.key, start, stop, true).map(scored_value))
+        }
+        CommandType::ZRangeByScore {
+            min,
+            max,
+            offset,
+            count,
+        } => range_by_score_response(db, &command.key, min, max, offset, count, false),
+        CommandType::ZRevRangeByScore {
+            min,
+            max,
+            offset,
+            count,
+        } => range_by_score_response(db, &command.key, min, max, offset, count, true),
+        CommandType::ZCard => {
+            result_response(db.zcard(&command.key).map(|len| Value::Number(len as i64)))
+        }
+        CommandType::ZPopMin { count } => result_response(
+            db.zpop(&command.key, count.max(0) as usize, false)
+                .map(scored_value),
+        ),
+        CommandType::ZPopMax { count } => result_response(
+            db.zpop(&command.key, count.max(0) as usize, true)
+                .map(scored_value),
+        ),
+        CommandType::LSet { index, value } => result_response(
+            db.lset(&command.key, index, value)
+                .map(|_| Value::Boolean(true)),
+        ),
+        CommandType::XAdd { id, fields } => result_response(
+            db.xadd(command.key, id, fields)
+                .map(|id| Value::String(id.to_string())),
+        ),
+        CommandType::XRange { start, end, count } => result_response(
+            db.xrange(&command.key, start, end, to_count(count), false)
+                .map(entries_value),
+        ),
+        CommandType::XRevRange { start, end, count } => result_response(
+            db.xrange(&command.key, start, end, to_count(count), true)
+                .map(entries_value),
+        ),
+        CommandType::XLen => {
+            result_response(db.xlen(&command.key).map(|len| Value::Number(len as i64)))
+        }
+        CommandType::XTrim { max_len } => result_response(
+            db.xtrim(&command.key, max_len.try_into().unwrap_or(usize::MAX))
+                .map(|removed| Value::Number(removed as i64)),
+        ),
+        // inside transactions and during replay stream reads don't wait either
+        CommandType::XRead {
+            keys, ids, count, ..
+        } => read_streams(db, keys, ids, count),
+        CommandType::XReadGroup {
+            group,
+            consumer,
+            keys,
+            ids,
+            count,
+            ..
+        } => read_group(db, &group, &consumer, keys, ids, count),
+        CommandType::XAck { group, ids } => result_response(
+            db.xack(&command.key, &group, &ids)
+                .map(|acked| Value::Number(acked as i64)),
+        ),
+        CommandType::XGroupCreate { group, id, create } => result_response(
+            db.xgroup_create(command.key, group, id, create)
+                .map(|_| Value::Boolean(true)),
+        ),
+        CommandType::XPending {
+            group,
+            start,
+            end,
+            count,
+            consumer,
+        } => {
+            let now = unix_millis();
+            result_response(
+                db.xpending(
+                    &command.key,
+                    &group,
+                    start,
+                    end,
+                    to_count(count),
+                    consumer.as_deref(),
+                )
+                .map(|pending| {
+                    Value::Array(
+                        pending
+                            .into_iter()
+                            .map(|(id, pending)| {
+                                let idle = now.saturating_sub(pending.delivered_at);
+                                Value::Array(vec![
+                                    Value::String(id.to_string()),
+                                    Value::String(pending.consumer),
+                                    Value::Number(idle as i64),
+                                    Value::Number(pending.deliveries as i64),
+                                ])
+                            })
+                            .collect(),
+                    )
+                }),
+            )
+        }
+    }
+}
+
+/// How many keys `SCAN` walks when count is not given.
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Negative count means no limit.
+fn to_count(count: i64) -> Option<usize> {
+    usize::try_from(count).ok()
+}
+
+/// Stream entry is sent as array of its id and flat array of alternating fields and values.
+/// Entry that no longer exists has null instead of fields.
+pub(crate) fn entry_value(id: StreamId, fields: Option<Fields>) -> Value {
+    let fields = fields.map_or(Value::Null, |fields| {
+        Value::Array(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| [Value::String(field), value])
+                .collect(),
+        )
+    });
+    Value::Array(vec![Value::String(id.to_string()), fields])
+}
+
+fn entries_value(entries: Vec<(StreamId, Fields)>) -> Value {
+    Value::Array(
+        entries
+            .into_iter()
+            .map(|(id, fields)| entry_value(id, Some(fields)))
+            .collect(),
+    )
+}
+
+/// Reads entries after matching id from every stream, responding with array of key and its
+/// entries for every stream that had any, or null if none had.
+fn read_streams(
+    db: &Database<String>,
+    keys: Vec<String>,
+    ids: Vec<Option<StreamId>>,
+    count: i64,
+) -> Response {
+    let mut streams = Vec::new();
+    for (key, id) in keys.into_iter().zip(ids) {
+        let entries = match id.map(StreamId::next) {
+            Some(Some(start)) => db.xrange(&key, start, StreamId::MAX, to_count(count), false),
+            // only entries added later are read, so there is nothing yet, but type is checked
+            _ => db.xlen(&key).map(|_| Vec::new()),
+        };
+        match entries {
+            Ok(entries) if entries.is_empty() => {}
+            Ok(entries) => streams.push(Value::Array(vec![
+                Value::String(key),
+                entries_value(entries),
+            ])),
+            Err(e) => return Response::error(&e.to_string()),
+        }
+    }
+    if streams.is_empty() {
+        return Response::Null;
+    }
+    Response::Payload(Value::Array(streams))
+}
+
+/// Same as `read_streams`, but for a consumer of group. Reading pending entries again always
+/// responds with the stream, even if there are none left.
+fn read_group(
+    db: &Database<String>,
+    group: &str,
+    consumer: &str,
+    keys: Vec<String>,
+    ids: Vec<Option<StreamId>>,
+    count: i64,
+) -> Response {
+    let mut streams = Vec::new();
+    for (key, id) in keys.into_iter().zip(ids) {
+        match db.xread_group(&key, group, consumer, id, to_count(count)) {
+            Ok(entries) if entries.is_empty() && id.is_none() => {}
+            Ok(entries) => {
+                let entries = entries
+                    .into_iter()
+                    .map(|(id, fields)| entry_value(id, fields))
+                    .collect();
+                streams.push(Value::Array(vec![
+                    Value::String(key),
+                    Value::Array(entries),
+                ]));
+            }
+            Err(e) => return Response::error(&e.to_string()),
+        }
+    }
+    if streams.is_empty() {
+        return Response::Null;
+    }
+    Response::Payload(Value::Array(streams))
+}
+
+/// Members of sorted set are sent as flat array of alternating members and scores.
+fn scored_value(members: Vec<(Member, f64)>) -> Value {
+    Value::Array(
+        members
+            .into_iter()
+            .flat_map(|(member, score)| [Value::from(member), Value::Float(score)])
+            .collect(),
+    )
+}
+
+fn range_by_score_response(
+    db: &Database<String>,
+    key: &String,
+    min: f64,
+    max: f64,
+    offset: i64,
+    count: i64,
+    reverse: bool,
+) -> Response {
+    // negative offset gives nothing, the same as in redis
+    if offset < 0 {
+        return Response::Payload(Value::Array(Vec::new()));
+    }
+    let count = (count >= 0).then_some(count as usize);
+    result_response(
+        db.zrange_by_score(key, min, max, offset as usize, count, reverse)
+            .map(scored_value),
+    )
+}
+
+/// Stores combination of sets under `destination`, responding with its size.
+fn store_response(
+    db: &Database<String>,
+    destination: String,
+    keys: &[String],
+    op: SetOp,
+) -> Response {
+    result_response(
+        db.combine_store(destination, keys, op)
+            .map(|len| Value::Number(len as i64)),
+    )
+}
+
+/// Pops from the first non-empty list of `keys`, responding with its key and the value.
+fn pop_first(db: &Database<String>, keys: &[String], back: bool) -> Response {
+    for key in keys {
+        match db.pop(key, back) {
+            Ok(Some(value)) => {
+                return Response::Payload(Value::Array(vec![Value::String(key.clone()), value]));
+            }
+            Ok(None) => {}
+            Err(e) => return Response::error(&e.to_string()),
+        }
+    }
+    Response::Null
+}
+
+/// Runs `BLPOP`/`BRPOP` or blocking stream read, waiting for a push to one of its keys until
+/// timeout passes. Waiting pops are served in the order in which they blocked.
+async fn blocking_read(shared: &Arc<Shared>, mut command: Command) -> Response {
+    let (keys, timeout) = match &command.r#type {
+        CommandType::BLPop { keys, timeout } | CommandType::BRPop { keys, timeout } => {
+            (keys.clone(), *timeout)
+        }
+        // zero blocks forever, like in redis
+        CommandType::XRead { keys, block, .. } | CommandType::XReadGroup { keys, block, .. } => {
+            (keys.clone(), block.filter(|block| !block.is_zero()))
+        }
+        _ => return Response::error("not a blocking command"),
+    };
+    // timeout too long to have a deadline is as good as waiting forever
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    // blocked before checking keys, so a push can't slip in between
+    let blocked = shared.db.block(keys);
+
+    // entries added while waiting have to be read, not just those added after each wakeup
+    if let CommandType::XRead { keys, ids, .. } = &mut command.r#type {
+        for (key, id) in keys.iter().zip(ids.iter_mut()) {
+            if id.is_none() {
+                *id = Some(shared.db.xlast_id(key).unwrap_or(StreamId::MAX));
+            }
+        }
+    }
+    loop {
+        let response = {
+            let _access = shared.db.shared_access();
+            apply(shared, command.clone())
+        };
+        if !matches!(response, Response::Null) {
+            return response;
+        }
+
+        match deadline {
+            Some(deadline) => {
+                if tokio::time::timeout_at(deadline, blocked.wait())
+                    .await
+                    .is_err()
+                {
+                    return Response::Null;
+                }
+            }
+            None => blocked.wait().await,
+        }
+    }
+}
+
+/// Same as `result_response`, but missing value is sent as null.
+fn option_response(result: Result<Option<Value>, Error>) -> Response {
+    match result {
+        Ok(value) => Response::new(value),
+        Err(e) => Response::error(&e.to_string()),
+    }
+}
+
+fn result_response(result: Result<Value, Error>) -> Response {
+    match result {
+        Ok(value) => Response::Payload(value),
+        Err(e) => Response::error(&e.to_string()),
+    }
+}
+
 pub trait TcpRead {
     /// This function should validate if incoming request is correct and advance cursor position to go over request len.
     fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error>;
@@ -80,27 +933,69 @@ pub trait TcpRead {
     fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error>
     where
         Self: Sized;
+
+    /// Whether a well framed request that fails to parse is answered with an error, keeping
+    /// the connection open. Otherwise `read` fails with the error.
+    const ANSWERS_PARSE_ERRORS: bool = false;
+
+    /// Builds request out of RESP frame, together with shape its reply must take. Only
+    /// requests that RESP clients can send need to implement it.
+    fn from_resp(_frame: RespFrame) -> Result<(Self, RespReply), Error>
+    where
+        Self: Sized,
+    {
+        Err(Error::UnknownCommand)
+    }
 }
 
 pub trait TcpWrite {
     /// Anything that implements `to_bytes` can be send over tcp. The problem is if it can be later parsed safely.
-    fn to_bytes(self) -> Vec<u8>;
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// RESP encoding used for connections that speak RESP. `None` falls back to `to_bytes`.
+    fn to_resp(&self, _reply: RespReply, _version: RespVersion) -> Option<RespFrame> {
+        None
+    }
 }
 
-pub struct Connection {
-    stream: BufWriter<TcpStream>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Binary,
+    Resp,
+}
+
+/// Byte stream that connection runs over, like `TcpStream` or TLS stream wrapping it.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Transport for S {}
+
+pub struct Connection<S> {
+    stream: BufWriter<S>,
     buffer: BytesMut,
+    /// Detected from the first byte that peer sends.
+    protocol: Option<Protocol>,
+    resp_version: RespVersion,
+    /// Reply shapes of RESP requests that still wait for response, in order of requests.
+    pending_replies: VecDeque<RespReply>,
+    /// Reply shapes of RESP requests queued by transaction, used to encode result of `EXEC`.
+    queued_replies: Vec<RespReply>,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: Transport> Connection<S> {
+    pub fn new(stream: S) -> Self {
         Self {
             stream: BufWriter::new(stream),
             buffer: BytesMut::with_capacity(4 * 1024),
+            protocol: None,
+            resp_version: RespVersion::default(),
+            pending_replies: VecDeque::new(),
+            queued_replies: Vec::new(),
         }
     }
 
-    fn try_read_request<T: TcpRead>(&mut self) -> Result<Option<T>, Error> {
+    /// Outer error means broken framing, inner one a well framed request that failed to parse
+    /// and was already consumed from the buffer.
+    fn try_read_request<T: TcpRead>(&mut self) -> Result<Option<Result<T, Error>>, Error> {
         if self.buffer.is_empty() {
             return Ok(None);
         }
@@ -112,24 +1007,85 @@ impl Connection {
                 // reset position because command validation advanced it
                 cursor.set_position(0);
 
-                let request = T::parse(&mut cursor)?;
+                let request = T::parse(&mut cursor);
 
                 self.buffer.advance(req_len);
 
                 Ok(Some(request))
             }
-            Err(Error::InvalidBytes) => Ok(None),
+            Err(Error::Incomplete) => Ok(None),
             Err(e) => Err(e),
         }
     }
 
+    /// Returns protocol of this connection, detecting it if enough data has arrived.
+    fn protocol(&mut self) -> Option<Protocol> {
+        if self.protocol.is_none() && !self.buffer.is_empty() {
+            let protocol = if self.buffer[0] == b'*' {
+                Protocol::Resp
+            } else {
+                Protocol::Binary
+            };
+            self.protocol = Some(protocol);
+        }
+        self.protocol
+    }
+
+    /// Reads next request. Partially received data stays in the buffer, so reading can be
+    /// raced against other futures in `select!` without losing it.
     pub async fn read<T: TcpRead>(&mut self) -> Result<Option<T>, Error> {
+        self.read_with_handshake(|_, _| None).await
+    }
+
+    /// Same as `read`, but RESP requests that `handshake` answers, see `resp::handshake`,
+    /// are replied to right away instead of being returned.
+    pub async fn read_with_handshake<T: TcpRead>(
+        &mut self,
+        mut handshake: impl FnMut(&RespFrame, &mut RespVersion) -> Option<RespFrame>,
+    ) -> Result<Option<T>, Error> {
         loop {
-            if let Ok(Some(command)) = self.try_read_request() {
-                return Ok(Some(command));
+            match self.protocol() {
+                Some(Protocol::Binary) => match self.try_read_request()? {
+                    Some(Ok(request)) => return Ok(Some(request)),
+                    // request was framed correctly, so following ones can still be read
+                    Some(Err(e)) if T::ANSWERS_PARSE_ERRORS => {
+                        self.feed(Response::error(&e.to_string())).await?;
+                        continue;
+                    }
+                    Some(Err(e)) => return Err(e),
+                    None => {}
+                },
+                Some(Protocol::Resp) => {
+                    if let Some(frame) = self.try_read_request::<RespFrame>()?.transpose()? {
+                        if let Some(reply) = handshake(&frame, &mut self.resp_version) {
+                            self.write_resp(reply).await?;
+                            continue;
+                        }
+                        match T::from_resp(frame) {
+                            Ok((request, reply)) => {
+                                self.pending_replies.push_back(reply);
+                                return Ok(Some(request));
+                            }
+                            // unlike broken framing, bad arguments don't break the connection
+                            Err(e) => {
+                                self.write_resp(resp::request_error(e)).await?;
+                                continue;
+                            }
+                        }
+                    }
+                }
+                None => {}
             }
 
-            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            // nothing more to process without waiting for peer, so send buffered responses
+            self.stream.flush().await?;
+
+            let read = match self.stream.read_buf(&mut self.buffer).await {
+                // TLS peer may close without notifying, which is the same as closing TCP
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+                read => read?,
+            };
+            if read == 0 {
                 // Connection closed
                 if self.buffer.is_empty() {
                     return Ok(None);
@@ -142,17 +1098,104 @@ impl Connection {
         }
     }
 
+    /// Waits until peer closes the connection. Requests that arrive meanwhile stay buffered for
+    /// `read`, so it's cancel safe as well.
+    pub async fn closed(&mut self) {
+        loop {
+            match self.stream.read_buf(&mut self.buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    async fn write_resp(&mut self, frame: RespFrame) -> Result<(), Error> {
+        self.stream.write_all(&frame.to_bytes()).await?;
+
+        Ok(())
+    }
+
+    /// Writes `data` and sends it right away.
     pub async fn write<T: TcpWrite>(&mut self, data: T) -> Result<(), Error> {
-        let bytes_to_send = data.to_bytes();
+        self.feed(data).await?;
+        self.flush().await
+    }
 
-        self.stream.write_all(&bytes_to_send).await?;
+    /// Sends everything that was buffered by `feed`.
+    pub async fn flush(&mut self) -> Result<(), Error> {
         self.stream.flush().await?;
 
         Ok(())
     }
+
+    /// Sends buffered responses and closes the stream for writing, so that peer reads its
+    /// end instead of broken connection.
+    pub async fn shutdown(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    /// Buffers acknowledgement of request queued by transaction. Its RESP reply shape is kept
+    /// until `feed_exec`.
+    pub async fn feed_queued(&mut self) -> Result<(), Error> {
+        match self.protocol {
+            Some(Protocol::Resp) => {
+                let reply = self.pending_replies.pop_front().unwrap_or_default();
+                self.queued_replies.push(reply);
+                self.write_resp(RespFrame::Simple("QUEUED".into())).await
+            }
+            _ => {
+                self.feed(Response::Payload(Value::String("QUEUED".into())))
+                    .await
+            }
+        }
+    }
+
+    /// Buffers response to `EXEC` or `DISCARD`, encoding results of queued requests in their
+    /// own shapes.
+    pub async fn feed_exec(&mut self, response: Response) -> Result<(), Error> {
+        let queued_replies = std::mem::take(&mut self.queued_replies);
+        match (self.protocol, response) {
+            (Some(Protocol::Resp), Response::Multi(responses)) => {
+                self.pending_replies.pop_front();
+                let frame = RespFrame::Array(
+                    responses
+                        .iter()
+                        .zip(queued_replies)
+                        .map(|(response, reply)| {
+                            resp::response_to_resp(response, reply, self.resp_version)
+                        })
+                        .collect(),
+                );
+                self.write_resp(frame).await
+            }
+            (_, response) => self.feed(response).await,
+        }
+    }
+
+    /// Buffers `data` without sending it. Buffer is sent by `flush` or before `read` has to
+    /// wait for more data from peer, so many writes can share one packet.
+    pub async fn feed<T: TcpWrite>(&mut self, data: T) -> Result<(), Error> {
+        let resp_frame = match self.protocol {
+            Some(Protocol::Resp) => {
+                let reply = self.pending_replies.pop_front().unwrap_or_default();
+                data.to_resp(reply, self.resp_version)
+            }
+            _ => None,
+        };
+        let bytes_to_send = match resp_frame {
+            Some(frame) => frame.to_bytes(),
+            None => data.to_bytes(),
+        };
+
+        self.stream.write_all(&bytes_to_send).await?;
+
+        Ok(())
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Response {
     /// State if response contains some data.
     Payload(Value),
@@ -160,6 +1203,10 @@ pub enum Response {
     Error(String),
     /// State if response is empty or searched key was not found.
     Null,
+    /// Separate response for each key of multi-key command.
+    Multi(Vec<Response>),
+    /// Message for subscribed connection or acknowledgement of subscription change.
+    Push(Push),
 }
 
 impl Response {
@@ -174,6 +1221,10 @@ impl Response {
     pub fn error(msg: &str) -> Self {
         Self::Error(msg.to_string())
     }
+
+    pub fn multi(payloads: Vec<Option<Value>>) -> Self {
+        Self::Multi(payloads.into_iter().map(Response::new).collect())
+    }
 }
 
 impl TcpRead for Response {
@@ -181,20 +1232,35 @@ impl TcpRead for Response {
         let response_type = get_u8(src)?;
 
         match response_type {
-            b'!' | b'#' | b'$' | b'[' | b'{' => {
+            b'!' | b'#' | b'$' | b'[' | b'{' | b'%' | b'(' | b'~' | b'^' | b',' | b'_' | b'|' => {
                 src.set_position(src.position() - 1);
-                Value::validate(src)
-            }
-            b'-' => {
-                get_line(src)?;
-                Ok(())
+                Value::validate(src)?;
             }
+            b'-' => {}
             b'e' => {
-                get_line(src)?;
-                Ok(())
+                let msg_len = get_u32(src)?;
+                skip(src, msg_len as usize)?;
             }
-            _ => Err(Error::UnknownCommand),
+            b'm' => {
+                let len = get_u32(src)?;
+                for _ in 0..len {
+                    Response::validate(src)?;
+                }
+            }
+            b'>' => {
+                let kind = PushKind::from_byte(get_u8(src)?).ok_or(Error::InvalidBytes)?;
+                let channel_len = get_u32(src)?;
+                skip(src, channel_len as usize)?;
+                if kind == PushKind::PMessage {
+                    let pattern_len = get_u32(src)?;
+                    skip(src, pattern_len as usize)?;
+                }
+                Value::validate(src)?;
+            }
+            _ => return Err(Error::UnknownCommand),
         }
+
+        expect_separator(src)
     }
     fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error>
     where
@@ -203,7 +1269,7 @@ impl TcpRead for Response {
         let response_type = get_u8(src)?;
 
         match response_type {
-            b'!' | b'#' | b'$' | b'[' | b'{' => {
+            b'!' | b'#' | b'$' | b'[' | b'{' | b'%' | b'(' | b'~' | b'^' | b',' | b'_' | b'|' => {
                 src.set_position(src.position() - 1);
                 Ok(Response::Payload(Value::parse(src)?))
             }
@@ -218,13 +1284,42 @@ impl TcpRead for Response {
 
                 Ok(Response::Error(msg))
             }
+            b'm' => {
+                let len = get_u32(src)?;
+                let mut responses = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    responses.push(Response::parse(src)?);
+                    // nested responses carry their own separator, validation already checked it
+                    skip(src, 2)?;
+                }
+                Ok(Response::Multi(responses))
+            }
+            b'>' => {
+                let kind = PushKind::from_byte(get_u8(src)?).ok_or(Error::InvalidBytes)?;
+                let channel = read_string(src)?;
+                let pattern = match kind {
+                    PushKind::PMessage => Some(read_string(src)?),
+                    _ => None,
+                };
+                let payload = Value::parse(src)?;
+                Ok(Response::Push(Push {
+                    kind,
+                    pattern,
+                    channel,
+                    payload,
+                }))
+            }
             _ => Err(Error::UnknownCommand),
         }
     }
 }
 
 impl TcpWrite for Response {
-    fn to_bytes(self) -> Vec<u8> {
+    fn to_resp(&self, reply: RespReply, version: RespVersion) -> Option<RespFrame> {
+        Some(resp::response_to_resp(self, reply, version))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
         match self {
             Self::Payload(data) => {
                 let mut encoded = data.to_bytes();
@@ -240,6 +1335,39 @@ impl TcpWrite for Response {
                 encoded
             }
             Self::Null => vec![b'-', b'\r', b'\n'],
+            Self::Multi(responses) => {
+                let mut encoded = vec![b'm'];
+                let len = responses.len() as u32;
+                encoded.extend_from_slice(&len.to_le_bytes());
+                for response in responses {
+                    encoded.extend_from_slice(&response.to_bytes());
+                }
+                encoded.extend_from_slice(b"\r\n");
+                encoded
+            }
+            Self::Push(push) => {
+                let mut encoded = vec![b'>', push.kind.byte_type()];
+                write_string(&mut encoded, &push.channel);
+                if let Some(pattern) = &push.pattern {
+                    write_string(&mut encoded, pattern);
+                }
+                encoded.extend_from_slice(&push.payload.to_bytes());
+                encoded.extend_from_slice(b"\r\n");
+                encoded
+            }
         }
     }
 }
+
+/// Reads string prefixed with its 4 bytes length.
+fn read_string(src: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    let len = get_u32(src)?;
+    let mut buf = vec![0; len as usize];
+    std::io::Read::read_exact(src, &mut buf)?;
+    String::from_utf8(buf).map_err(|_| Error::InvalidBytes)
+}
+
+fn write_string(encoded: &mut Vec<u8>, s: &str) {
+    encoded.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    encoded.extend_from_slice(s.as_bytes());
+}
@@ -0,0 +1,297 @@
+//! Registry of channel subscriptions. Every connection owns a `Subscriber` with its own queue
+//! of pushed messages, publishing puts message into queues of all matching subscribers.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use tokio::sync::mpsc;
+
+use crate::utils::{command::Value, glob};
+
+/// How many messages can wait for a slow subscriber before new ones are dropped.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushKind {
+    /// Message published to subscribed channel.
+    Message,
+    /// Message published to channel matching subscribed pattern.
+    PMessage,
+    Subscribe,
+    Unsubscribe,
+    PSubscribe,
+    PUnsubscribe,
+}
+
+impl PushKind {
+    pub fn byte_type(self) -> u8 {
+        match self {
+            Self::Message => b'm',
+            Self::PMessage => b'p',
+            Self::Subscribe => b's',
+            Self::Unsubscribe => b'u',
+            Self::PSubscribe => b'S',
+            Self::PUnsubscribe => b'U',
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            b'm' => Some(Self::Message),
+            b'p' => Some(Self::PMessage),
+            b's' => Some(Self::Subscribe),
+            b'u' => Some(Self::Unsubscribe),
+            b'S' => Some(Self::PSubscribe),
+            b'U' => Some(Self::PUnsubscribe),
+            _ => None,
+        }
+    }
+
+    /// Name that redis uses for this kind of push.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Message => "message",
+            Self::PMessage => "pmessage",
+            Self::Subscribe => "subscribe",
+            Self::Unsubscribe => "unsubscribe",
+            Self::PSubscribe => "psubscribe",
+            Self::PUnsubscribe => "punsubscribe",
+        }
+    }
+}
+
+/// Data sent to subscriber without request, or as acknowledgement of subscription change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Push {
+    pub kind: PushKind,
+    /// Pattern that channel matched, only set for `PushKind::PMessage`.
+    pub pattern: Option<String>,
+    /// Channel (or pattern for pattern subscriptions) that push is about.
+    pub channel: String,
+    /// Published value, or number of active subscriptions for acknowledgements.
+    pub payload: Value,
+}
+
+impl Push {
+    fn ack(kind: PushKind, channel: String, count: usize) -> Self {
+        Self {
+            kind,
+            pattern: None,
+            channel,
+            payload: Value::Number(count as i64),
+        }
+    }
+}
+
+/// Subscriptions of a single connection.
+pub struct Subscriber {
+    id: u64,
+    sender: mpsc::Sender<Push>,
+    channels: BTreeSet<String>,
+    patterns: BTreeSet<String>,
+}
+
+impl Subscriber {
+    pub fn count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+}
+
+type Subscribers = HashMap<u64, mpsc::Sender<Push>>;
+
+#[derive(Default)]
+struct Registry {
+    channels: HashMap<String, Subscribers>,
+    patterns: HashMap<String, Subscribers>,
+}
+
+#[derive(Default)]
+pub struct PubSub {
+    registry: Mutex<Registry>,
+    next_id: AtomicU64,
+}
+
+/// Removes subscriber `id` from `name`, dropping the entry once nobody listens to it.
+fn remove(map: &mut HashMap<String, Subscribers>, name: &str, id: u64) {
+    if let Some(subscribers) = map.get_mut(name) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            map.remove(name);
+        }
+    }
+}
+
+/// Queues `push` without waiting. Returns `false` if it couldn't be delivered.
+fn deliver(sender: &mpsc::Sender<Push>, push: Push) -> bool {
+    match sender.try_send(push) {
+        Ok(_) => true,
+        Err(mpsc::error::TrySendError::Full(push)) => {
+            log::warn!(
+                "Dropping message to {}, subscriber is too slow",
+                push.channel
+            );
+            false
+        }
+        // connection is gone, it will remove its subscriptions shortly
+        Err(mpsc::error::TrySendError::Closed(_)) => false,
+    }
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates subscriber without any subscriptions together with queue of its pushes.
+    pub fn subscriber(&self) -> (Subscriber, mpsc::Receiver<Push>) {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let subscriber = Subscriber {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            sender,
+            channels: BTreeSet::new(),
+            patterns: BTreeSet::new(),
+        };
+        (subscriber, receiver)
+    }
+
+    pub fn subscribe(&self, subscriber: &mut Subscriber, channels: Vec<String>) -> Vec<Push> {
+        let mut registry = self.registry.lock().unwrap();
+        channels
+            .into_iter()
+            .map(|channel| {
+                registry
+                    .channels
+                    .entry(channel.clone())
+                    .or_default()
+                    .insert(subscriber.id, subscriber.sender.clone());
+                subscriber.channels.insert(channel.clone());
+                Push::ack(PushKind::Subscribe, channel, subscriber.count())
+            })
+            .collect()
+    }
+
+    pub fn psubscribe(&self, subscriber: &mut Subscriber, patterns: Vec<String>) -> Vec<Push> {
+        let mut registry = self.registry.lock().unwrap();
+        patterns
+            .into_iter()
+            .map(|pattern| {
+                registry
+                    .patterns
+                    .entry(pattern.clone())
+                    .or_default()
+                    .insert(subscriber.id, subscriber.sender.clone());
+                subscriber.patterns.insert(pattern.clone());
+                Push::ack(PushKind::PSubscribe, pattern, subscriber.count())
+            })
+            .collect()
+    }
+
+    /// Unsubscribes from `channels`, or from all channels if it's empty.
+    pub fn unsubscribe(&self, subscriber: &mut Subscriber, channels: Vec<String>) -> Vec<Push> {
+        let channels = if channels.is_empty() {
+            subscriber.channels.iter().cloned().collect()
+        } else {
+            channels
+        };
+
+        let mut registry = self.registry.lock().unwrap();
+        let acks: Vec<_> = channels
+            .into_iter()
+            .map(|channel| {
+                remove(&mut registry.channels, &channel, subscriber.id);
+                subscriber.channels.remove(&channel);
+                Push::ack(PushKind::Unsubscribe, channel, subscriber.count())
+            })
+            .collect();
+
+        if acks.is_empty() {
+            // there is always at least one acknowledgement, so client knows request finished
+            return vec![Push::ack(
+                PushKind::Unsubscribe,
+                String::new(),
+                subscriber.count(),
+            )];
+        }
+        acks
+    }
+
+    /// Unsubscribes from `patterns`, or from all patterns if it's empty.
+    pub fn punsubscribe(&self, subscriber: &mut Subscriber, patterns: Vec<String>) -> Vec<Push> {
+        let patterns = if patterns.is_empty() {
+            subscriber.patterns.iter().cloned().collect()
+        } else {
+            patterns
+        };
+
+        let mut registry = self.registry.lock().unwrap();
+        let acks: Vec<_> = patterns
+            .into_iter()
+            .map(|pattern| {
+                remove(&mut registry.patterns, &pattern, subscriber.id);
+                subscriber.patterns.remove(&pattern);
+                Push::ack(PushKind::PUnsubscribe, pattern, subscriber.count())
+            })
+            .collect();
+
+        if acks.is_empty() {
+            return vec![Push::ack(
+                PushKind::PUnsubscribe,
+                String::new(),
+                subscriber.count(),
+            )];
+        }
+        acks
+    }
+
+    /// Drops all subscriptions of `subscriber`, called when its connection closes.
+    pub fn remove(&self, subscriber: &Subscriber) {
+        let mut registry = self.registry.lock().unwrap();
+        for channel in &subscriber.channels {
+            remove(&mut registry.channels, channel, subscriber.id);
+        }
+        for pattern in &subscriber.patterns {
+            remove(&mut registry.patterns, pattern, subscriber.id);
+        }
+    }
+
+    /// Sends `payload` to everyone subscribed to `channel` directly or through a pattern.
+    /// Returns number of subscribers that received it.
+    pub fn publish(&self, channel: &str, payload: Value) -> usize {
+        let registry = self.registry.lock().unwrap();
+        let mut received = 0;
+
+        if let Some(subscribers) = registry.channels.get(channel) {
+            for sender in subscribers.values() {
+                let push = Push {
+                    kind: PushKind::Message,
+                    pattern: None,
+                    channel: channel.to_string(),
+                    payload: payload.clone(),
+                };
+                received += deliver(sender, push) as usize;
+            }
+        }
+
+        for (pattern, subscribers) in &registry.patterns {
+            if !glob::matches(pattern.as_bytes(), channel.as_bytes()) {
+                continue;
+            }
+            for sender in subscribers.values() {
+                let push = Push {
+                    kind: PushKind::PMessage,
+                    pattern: Some(pattern.clone()),
+                    channel: channel.to_string(),
+                    payload: payload.clone(),
+                };
+                received += deliver(sender, push) as usize;
+            }
+        }
+
+        received
+    }
+}
@@ -0,0 +1,1705 @@
+//! Compatibility layer for clients speaking Redis RESP2/RESP3 protocol (`redis-cli`, Redis
+//! client libraries, monitoring tools).
+//!
+//! Connection switches to RESP when the first byte it receives is `*` (RESP array), which is
+//! never a valid command type of the binary protocol. Requests are mapped onto `Command` and
+//! responses are encoded in the shape Redis uses for the given command, see `RespReply`.
+//! Connection starts in RESP2 and can switch to RESP3 with `HELLO 3`.
+
+use std::{collections::HashMap, io::Cursor, time::Duration};
+
+use crate::{
+    error::Error,
+    server::{
+        acl::{Acl, DEFAULT_USER},
+        protocol::{self, Response, TcpRead, TcpWrite},
+        pubsub::Push,
+        storage::SortedSet,
+        stream::StreamId,
+    },
+    utils::{
+        bytes::{expect_separator, get_u8, skip},
+        command::{Command, Member, Value},
+    },
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RespVersion {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RespFrame {
+    /// `+`
+    Simple(String),
+    /// `-`
+    Error(String),
+    /// `:`
+    Integer(i64),
+    /// `$`
+    Bulk(Vec<u8>),
+    /// `*`
+    Array(Vec<RespFrame>),
+    /// `$-1` in RESP2
+    NullBulk,
+    /// `_` in RESP3
+    Null,
+    /// `#` in RESP3
+    Boolean(bool),
+    /// `,` in RESP3, sent as bulk string in RESP2
+    Double(f64),
+    /// `%` in RESP3
+    Map(Vec<(RespFrame, RespFrame)>),
+    /// `~` in RESP3
+    Set(Vec<RespFrame>),
+    /// `>` in RESP3, sent as array in RESP2
+    Push(Vec<RespFrame>),
+    /// Several frames written back to back. Only used for replies that Redis splits into
+    /// multiple frames, never parsed.
+    Sequence(Vec<RespFrame>),
+}
+
+/// Shape of reply that Redis uses for a command, which often differs from `Response` that
+/// binary protocol sends back.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RespReply {
+    /// Payload is sent as is.
+    #[default]
+    Value,
+    /// `+OK` on success.
+    Ok,
+    /// Given status on success.
+    Status(&'static str),
+    /// String payload as status, e.g. name of a type.
+    Simple,
+    /// `:1` if response had payload, `:0` otherwise.
+    Count,
+    /// Boolean payload as integer.
+    Flag,
+    /// `+OK` if boolean payload is true, null otherwise.
+    Written,
+    /// Every nested push is sent as separate frame, the way Redis acknowledges subscriptions.
+    Pushes,
+    /// Time to live converted from milliseconds to seconds, `-2` if key doesn't exist.
+    Seconds,
+    /// Time to live in milliseconds, `-2` if key doesn't exist.
+    Millis,
+    /// Only members of alternating members and scores, for sorted set ranges without
+    /// `WITHSCORES`.
+    Members,
+}
+
+/// Aggregates nested deeper than this are rejected, so that parsing cannot overflow the stack.
+const MAX_DEPTH: usize = 128;
+/// Same as default `proto-max-bulk-len` of Redis.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+/// Frames longer than this are rejected, even while they are still arriving.
+const MAX_FRAME_LEN: usize = 1024 * 1024 * 1024;
+
+fn protocol_error(msg: &str) -> Error {
+    Error::Protocol { msg: msg.into() }
+}
+
+/// Reads line terminated by `\r\n`. Only used for types that cannot contain separator.
+fn read_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
+    let start = src.position() as usize;
+    let buf = *src.get_ref();
+
+    let end = buf[start..]
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .ok_or(Error::Incomplete)?;
+
+    src.set_position((start + end + 2) as u64);
+    Ok(&buf[start..start + end])
+}
+
+fn read_int(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+    let line = read_line(src)?;
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Error::InvalidBytes)
+}
+
+fn read_string(src: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    String::from_utf8(read_line(src)?.to_vec()).map_err(|_| Error::InvalidBytes)
+}
+
+/// Walks a frame that is `depth` aggregates deep.
+fn validate_frame(src: &mut Cursor<&[u8]>, depth: usize) -> Result<(), Error> {
+    match get_u8(src)? {
+        b'+' | b'-' | b':' | b'_' | b'#' | b',' => {
+            read_line(src)?;
+        }
+        b'$' => {
+            let len = read_int(src)?;
+            if len > MAX_BULK_LEN {
+                return Err(protocol_error("invalid bulk length"));
+            }
+            if len >= 0 {
+                skip(src, len as usize)?;
+                expect_separator(src)?;
+            }
+        }
+        b'*' | b'~' | b'%' if depth >= MAX_DEPTH => {
+            return Err(protocol_error("too deeply nested aggregate"));
+        }
+        b'*' | b'~' => {
+            for _ in 0..read_int(src)?.max(0) {
+                validate_frame(src, depth + 1)?;
+            }
+        }
+        b'%' => {
+            for _ in 0..read_int(src)?.max(0).saturating_mul(2) {
+                validate_frame(src, depth + 1)?;
+            }
+        }
+        _ => return Err(Error::InvalidBytes),
+    }
+    Ok(())
+}
+
+impl TcpRead for RespFrame {
+    fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        let start = src.position() as usize;
+        match validate_frame(src, 0) {
+            // unfinished frame takes everything that arrived after its start
+            Err(Error::Incomplete) if src.get_ref().len() - start > MAX_FRAME_LEN => {
+                Err(protocol_error("too big frame"))
+            }
+            Ok(()) if src.position() as usize - start > MAX_FRAME_LEN => {
+                Err(protocol_error("too big frame"))
+            }
+            result => result,
+        }
+    }
+
+    /// Expects frame that passed `validate`, which bounds its nesting.
+    fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error> {
+        match get_u8(src)? {
+            b'+' => Ok(RespFrame::Simple(read_string(src)?)),
+            b'-' => Ok(RespFrame::Error(read_string(src)?)),
+            b':' => Ok(RespFrame::Integer(read_int(src)?)),
+            b'_' => {
+                read_line(src)?;
+                Ok(RespFrame::Null)
+            }
+            b'#' => Ok(RespFrame::Boolean(read_line(src)? == b"t")),
+            b',' => read_string(src)?
+                .parse()
+                .map(RespFrame::Double)
+                .map_err(|_| Error::InvalidBytes),
+            b'$' => {
+                let len = read_int(src)?;
+                if len < 0 {
+                    return Ok(RespFrame::NullBulk);
+                }
+                let start = src.position() as usize;
+                let bytes = src.get_ref()[start..start + len as usize].to_vec();
+                skip(src, len as usize)?;
+                expect_separator(src)?;
+                Ok(RespFrame::Bulk(bytes))
+            }
+            b'*' => {
+                let len = read_int(src)?.max(0);
+                let mut arr = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    arr.push(RespFrame::parse(src)?);
+                }
+                Ok(RespFrame::Array(arr))
+            }
+            b'~' => {
+                let len = read_int(src)?.max(0);
+                let mut set = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    set.push(RespFrame::parse(src)?);
+                }
+                Ok(RespFrame::Set(set))
+            }
+            b'%' => {
+                let len = read_int(src)?.max(0);
+                let mut map = Vec::with_capacity(len as usize);
+                for _ in 0..len {
+                    map.push((RespFrame::parse(src)?, RespFrame::parse(src)?));
+                }
+                Ok(RespFrame::Map(map))
+            }
+            _ => Err(Error::InvalidBytes),
+        }
+    }
+}
+
+impl TcpWrite for RespFrame {
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Simple(s) => format!("+{s}\r\n").into_bytes(),
+            Self::Error(msg) => format!("-{msg}\r\n").into_bytes(),
+            Self::Integer(n) => format!(":{n}\r\n").into_bytes(),
+            Self::Bulk(bytes) => {
+                let mut encoded = format!("${}\r\n", bytes.len()).into_bytes();
+                encoded.extend_from_slice(bytes);
+                encoded.extend_from_slice(b"\r\n");
+                encoded
+            }
+            Self::Array(arr) => {
+                let mut encoded = format!("*{}\r\n", arr.len()).into_bytes();
+                for el in arr {
+                    encoded.extend_from_slice(&el.to_bytes());
+                }
+                encoded
+            }
+            Self::NullBulk => b"$-1\r\n".to_vec(),
+            Self::Null => b"_\r\n".to_vec(),
+            Self::Boolean(b) => format!("#{}\r\n", if *b { 't' } else { 'f' }).into_bytes(),
+            Self::Double(n) => format!(",{n}\r\n").into_bytes(),
+            Self::Map(map) => {
+                let mut encoded = format!("%{}\r\n", map.len()).into_bytes();
+                for (key, value) in map {
+                    encoded.extend_from_slice(&key.to_bytes());
+                    encoded.extend_from_slice(&value.to_bytes());
+                }
+                encoded
+            }
+            Self::Set(set) => {
+                let mut encoded = format!("~{}\r\n", set.len()).into_bytes();
+                for el in set {
+                    encoded.extend_from_slice(&el.to_bytes());
+                }
+                encoded
+            }
+            Self::Push(arr) => {
+                let mut encoded = format!(">{}\r\n", arr.len()).into_bytes();
+                for el in arr {
+                    encoded.extend_from_slice(&el.to_bytes());
+                }
+                encoded
+            }
+            Self::Sequence(frames) => frames.iter().flat_map(|frame| frame.to_bytes()).collect(),
+        }
+    }
+}
+
+impl RespFrame {
+    fn null(version: RespVersion) -> Self {
+        match version {
+            RespVersion::Resp2 => Self::NullBulk,
+            RespVersion::Resp3 => Self::Null,
+        }
+    }
+
+    fn double(n: f64, version: RespVersion) -> Self {
+        match version {
+            RespVersion::Resp2 => bulk(&n.to_string()),
+            RespVersion::Resp3 => Self::Double(n),
+        }
+    }
+
+    fn push(elements: Vec<RespFrame>, version: RespVersion) -> Self {
+        match version {
+            RespVersion::Resp2 => Self::Array(elements),
+            RespVersion::Resp3 => Self::Push(elements),
+        }
+    }
+
+    fn set(elements: Vec<RespFrame>, version: RespVersion) -> Self {
+        match version {
+            RespVersion::Resp2 => Self::Array(elements),
+            RespVersion::Resp3 => Self::Set(elements),
+        }
+    }
+
+    fn map(entries: Vec<(RespFrame, RespFrame)>, version: RespVersion) -> Self {
+        match version {
+            RespVersion::Resp2 => {
+                Self::Array(entries.into_iter().flat_map(|(k, v)| [k, v]).collect())
+            }
+            RespVersion::Resp3 => Self::Map(entries),
+        }
+    }
+
+    /// Builds RESP error, prefixing message with generic `ERR` code unless it has its own code
+    /// (uppercase first word like `WRONGTYPE`).
+    pub fn error(msg: &str) -> Self {
+        let code = msg.split(' ').next().unwrap_or_default();
+        if !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase()) {
+            Self::Error(msg.to_string())
+        } else {
+            Self::Error(format!("ERR {msg}"))
+        }
+    }
+}
+
+pub fn value_to_resp(value: &Value, version: RespVersion) -> RespFrame {
+    match value {
+        Value::Boolean(b) => match version {
+            RespVersion::Resp2 => RespFrame::Integer(*b as i64),
+            RespVersion::Resp3 => RespFrame::Boolean(*b),
+        },
+        Value::Number(n) => RespFrame::Integer(*n),
+        Value::Float(n) => RespFrame::double(*n, version),
+        Value::Null => RespFrame::null(version),
+        Value::String(s) => RespFrame::Bulk(s.as_bytes().to_vec()),
+        Value::Bytes(bytes) => RespFrame::Bulk(bytes.clone()),
+        Value::Array(arr) => {
+            RespFrame::Array(arr.iter().map(|v| value_to_resp(v, version)).collect())
+        }
+        Value::List(list) => {
+            RespFrame::Array(list.iter().map(|v| value_to_resp(v, version)).collect())
+        }
+        Value::Set(set) => RespFrame::set(
+            set.iter()
+                .map(|member| value_to_resp(&Value::from(member.clone()), version))
+                .collect(),
+            version,
+        ),
+        Value::SortedSet(set) => RespFrame::Array(
+            set.iter()
+                .flat_map(|(member, score)| {
+                    [
+                        value_to_resp(&Value::from(member.clone()), version),
+                        RespFrame::double(score, version),
+                    ]
+                })
+                .collect(),
+        ),
+        Value::Stream(stream) => RespFrame::Array(
+            stream
+                .range(StreamId::MIN, StreamId::MAX)
+                .map(|(id, fields)| {
+                    value_to_resp(&protocol::entry_value(*id, Some(fields.clone())), version)
+                })
+                .collect(),
+        ),
+        Value::Map(map) => RespFrame::map(
+            map.iter()
+                .map(|(field, value)| (bulk(field), value_to_resp(value, version)))
+                .collect(),
+            version,
+        ),
+    }
+}
+
+/// Encodes push as `[kind, pattern, channel, payload]` array, pattern is only present for
+/// pattern messages.
+fn push_to_resp(push: &Push, version: RespVersion) -> RespFrame {
+    let mut elements = vec![bulk(push.kind.name())];
+    if let Some(pattern) = &push.pattern {
+        elements.push(bulk(pattern));
+    }
+    elements.push(bulk(&push.channel));
+    elements.push(value_to_resp(&push.payload, version));
+    RespFrame::push(elements, version)
+}
+
+/// Encodes `response` in the shape expected by RESP client.
+pub fn response_to_resp(response: &Response, reply: RespReply, version: RespVersion) -> RespFrame {
+    let payload = match response {
+        Response::Error(msg) => return RespFrame::error(msg),
+        Response::Push(push) => return push_to_resp(push, version),
+        Response::Payload(value) => Some(value),
+        Response::Null => None,
+        Response::Multi(responses) => {
+            return match reply {
+                RespReply::Pushes => RespFrame::Sequence(
+                    responses
+                        .iter()
+                        .map(|r| response_to_resp(r, RespReply::Value, version))
+                        .collect(),
+                ),
+                RespReply::Ok => RespFrame::Simple("OK".into()),
+                RespReply::Status(status) => RespFrame::Simple(status.into()),
+                RespReply::Count => RespFrame::Integer(
+                    responses
+                        .iter()
+                        .filter(|r| matches!(r, Response::Payload(_)))
+                        .count() as i64,
+                ),
+                _ => RespFrame::Array(
+                    responses
+                        .iter()
+                        .map(|r| response_to_resp(r, RespReply::Value, version))
+                        .collect(),
+                ),
+            };
+        }
+    };
+
+    match (reply, payload) {
+        (RespReply::Value, Some(value)) => value_to_resp(value, version),
+        (RespReply::Value, None) => RespFrame::null(version),
+        (RespReply::Ok, _) => RespFrame::Simple("OK".into()),
+        (RespReply::Status(status), _) => RespFrame::Simple(status.into()),
+        (RespReply::Simple, Some(Value::String(s))) => RespFrame::Simple(s.clone()),
+        (RespReply::Count, payload) => RespFrame::Integer(payload.is_some() as i64),
+        (RespReply::Flag, Some(Value::Boolean(b))) => RespFrame::Integer(*b as i64),
+        (RespReply::Written, Some(Value::Boolean(true))) => RespFrame::Simple("OK".into()),
+        (RespReply::Written, _) => RespFrame::null(version),
+        (RespReply::Seconds, Some(Value::Number(ms))) if *ms >= 0 => {
+            RespFrame::Integer((ms + 500) / 1000)
+        }
+        (RespReply::Seconds | RespReply::Millis, Some(Value::Number(n))) => RespFrame::Integer(*n),
+        (RespReply::Seconds | RespReply::Millis, None) => RespFrame::Integer(-2),
+        (RespReply::Members, Some(Value::Array(scored))) => RespFrame::Array(
+            scored
+                .iter()
+                .step_by(2)
+                .map(|member| value_to_resp(member, version))
+                .collect(),
+        ),
+        (_, Some(value)) => value_to_resp(value, version),
+        (_, None) => RespFrame::null(version),
+    }
+}
+
+/// Splits request into uppercase command name and its arguments.
+fn request_args(frame: RespFrame) -> Result<(String, Vec<Vec<u8>>), Error> {
+    let RespFrame::Array(frames) = frame else {
+        return Err(Error::BadRequest {
+            msg: "request must be an array of bulk strings".into(),
+        });
+    };
+
+    let mut args = frames
+        .into_iter()
+        .map(|frame| match frame {
+            RespFrame::Bulk(bytes) => Ok(bytes),
+            RespFrame::Simple(s) => Ok(s.into_bytes()),
+            RespFrame::Integer(n) => Ok(n.to_string().into_bytes()),
+            _ => Err(Error::BadRequest {
+                msg: "request must be an array of bulk strings".into(),
+            }),
+        })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter();
+
+    let name = args.next().ok_or(Error::BadRequest {
+        msg: "empty request".into(),
+    })?;
+
+    Ok((
+        String::from_utf8_lossy(&name).to_ascii_uppercase(),
+        args.collect(),
+    ))
+}
+
+/// Returns arguments as array of exactly `N` elements.
+fn exact<'a, const N: usize>(name: &str, args: &'a [Vec<u8>]) -> Result<&'a [Vec<u8>; N], Error> {
+    args.try_into().map_err(|_| Error::BadRequest {
+        msg: format!(
+            "wrong number of arguments for '{}' command",
+            name.to_ascii_lowercase()
+        ),
+    })
+}
+
+fn to_key(arg: &[u8]) -> Result<String, Error> {
+    String::from_utf8(arg.to_vec()).map_err(|_| Error::BadRequest {
+        msg: "Invalid key utf-8 encoding".into(),
+    })
+}
+
+/// Keys of multi-key commands, which need at least one of them.
+fn to_keys<'a>(name: &str, args: &'a [Vec<u8>]) -> Result<Vec<&'a str>, Error> {
+    if args.is_empty() {
+        return Err(exact::<1>(name, args).unwrap_err());
+    }
+    to_optional_keys(args)
+}
+
+/// Same as `to_keys`, but allows empty list.
+fn to_optional_keys(args: &[Vec<u8>]) -> Result<Vec<&str>, Error> {
+    args.iter()
+        .map(|arg| {
+            std::str::from_utf8(arg).map_err(|_| Error::BadRequest {
+                msg: "Invalid key utf-8 encoding".into(),
+            })
+        })
+        .collect()
+}
+
+fn to_u64(arg: &[u8]) -> Result<u64, Error> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Error::NotInteger)
+}
+
+fn to_i64(arg: &[u8]) -> Result<i64, Error> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(Error::NotInteger)
+}
+
+/// Same as `to_value`, but for set members.
+fn to_member(arg: Vec<u8>) -> Member {
+    match String::from_utf8(arg) {
+        Ok(s) => Member::String(s),
+        Err(e) => Member::Bytes(e.into_bytes()),
+    }
+}
+
+/// Timeout of blocking commands in seconds, which may be fractional. 0 waits forever.
+fn to_timeout(arg: &[u8]) -> Result<Option<Duration>, Error> {
+    let seconds = std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| Error::BadRequest {
+            msg: "timeout is not a float or out of range".into(),
+        })?;
+    if seconds < 0.0 {
+        return Err(Error::BadRequest {
+            msg: "timeout is negative".into(),
+        });
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    let timeout = Duration::try_from_secs_f64(seconds).map_err(|_| Error::BadRequest {
+        msg: "timeout is not a float or out of range".into(),
+    })?;
+    // binary protocol counts whole milliseconds and 0 would mean waiting forever
+    Ok(Some(timeout.max(Duration::from_millis(1))))
+}
+
+/// Expiry of `amount` seconds (or milliseconds when `secs` is false). Like in redis, it has to
+/// fit into i64 milliseconds, which keeps deadlines computed from it from overflowing.
+fn to_expire(amount: &[u8], secs: bool, command: &str) -> Result<Duration, Error> {
+    let amount = to_u64(amount)?;
+    let millis = if secs {
+        amount.checked_mul(1000)
+    } else {
+        Some(amount)
+    };
+    match millis.filter(|millis| *millis <= i64::MAX as u64) {
+        Some(millis) => Ok(Duration::from_millis(millis)),
+        None => Err(Error::BadRequest {
+            msg: format!("invalid expire time in '{command}' command"),
+        }),
+    }
+}
+
+/// Values sent by RESP clients are always byte strings, valid utf-8 is stored as string.
+fn to_value(arg: Vec<u8>) -> Value {
+    match String::from_utf8(arg) {
+        Ok(s) => Value::String(s),
+        Err(e) => Value::Bytes(e.into_bytes()),
+    }
+}
+
+/// Condition that `SET` was called with, `NX` or `XX` if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetCondition {
+    Always,
+    IfAbsent,
+    IfPresent,
+}
+
+/// Parses options of `SET`: `EX seconds` / `PX milliseconds` expiry, `NX`/`XX` condition and
+/// `GET`.
+fn set_options(options: &[Vec<u8>]) -> Result<(Option<Duration>, SetCondition, bool), Error> {
+    let syntax_error = || Error::BadRequest {
+        msg: "syntax error".into(),
+    };
+
+    let mut expire = None;
+    let mut condition = SetCondition::Always;
+    let mut get = false;
+
+    let mut iter = options.iter();
+    while let Some(option) = iter.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            unit @ (b"EX" | b"PX") if expire.is_none() => {
+                let amount = iter.next().ok_or_else(syntax_error)?;
+                let amount = to_expire(amount, unit == b"EX", "set")?;
+                if amount.is_zero() {
+                    return Err(Error::BadRequest {
+                        msg: "invalid expire time in 'set' command".into(),
+                    });
+                }
+                expire = Some(amount);
+            }
+            b"NX" if condition == SetCondition::Always => condition = SetCondition::IfAbsent,
+            b"XX" if condition == SetCondition::Always => condition = SetCondition::IfPresent,
+            b"GET" if !get => get = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    // conditional writes only report whether they happened, not the old value
+    if get && condition != SetCondition::Always {
+        return Err(syntax_error());
+    }
+
+    Ok((expire, condition, get))
+}
+
+/// Parses `MATCH pattern` and `COUNT count` options of `SCAN`.
+fn scan_options(options: &[Vec<u8>]) -> Result<(Option<String>, Option<usize>), Error> {
+    let syntax_error = || Error::BadRequest {
+        msg: "syntax error".into(),
+    };
+
+    let mut pattern = None;
+    let mut count = None;
+
+    let mut iter = options.iter();
+    while let Some(option) = iter.next() {
+        let arg = iter.next().ok_or_else(syntax_error)?;
+        match option.to_ascii_uppercase().as_slice() {
+            b"MATCH" => pattern = Some(to_key(arg)?),
+            b"COUNT" => match to_u64(arg)? {
+                0 => return Err(syntax_error()),
+                n => count = Some(n.min(usize::MAX as u64) as usize),
+            },
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    Ok((pattern, count))
+}
+
+/// Parses score bound of `ZRANGEBYSCORE`, `(` in front makes it exclusive. `upper` tells which
+/// side of the range it is, exclusive bounds are moved to the nearest score inside the range.
+fn to_score_bound(arg: &[u8], upper: bool) -> Result<f64, Error> {
+    let (exclusive, arg) = match arg.split_first() {
+        Some((b'(', rest)) => (true, rest),
+        _ => (false, arg),
+    };
+    let bound = to_f64(arg).map_err(|_| Error::BadRequest {
+        msg: "min or max is not a float".into(),
+    })?;
+    Ok(match (exclusive, upper) {
+        (false, _) => bound,
+        (true, false) => bound.next_up(),
+        (true, true) => bound.next_down(),
+    })
+}
+
+fn to_f64(arg: &[u8]) -> Result<f64, Error> {
+    std::str::from_utf8(arg)
+        .ok()
+        .and_then(|s| s.parse::<f64>().ok())
+        .filter(|n| !n.is_nan())
+        .ok_or(Error::NotFloat)
+}
+
+/// Parses `WITHSCORES` and `LIMIT offset count` options of sorted set ranges.
+fn range_options(options: &[Vec<u8>], limit: bool) -> Result<(bool, Option<(i64, i64)>), Error> {
+    let syntax_error = || Error::BadRequest {
+        msg: "syntax error".into(),
+    };
+
+    let mut with_scores = false;
+    let mut range_limit = None;
+
+    let mut iter = options.iter();
+    while let Some(option) = iter.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"WITHSCORES" => with_scores = true,
+            b"LIMIT" if limit => {
+                let offset = to_i64(iter.next().ok_or_else(syntax_error)?)?;
+                let count = to_i64(iter.next().ok_or_else(syntax_error)?)?;
+                range_limit = Some((offset, count));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    Ok((with_scores, range_limit))
+}
+
+/// Parses id bound of `XRANGE`, `-` and `+` are the smallest and the largest ids. Bound without
+/// sequence covers whole millisecond, `(` in front makes it exclusive.
+fn to_stream_bound(arg: &[u8], upper: bool) -> Result<StreamId, Error> {
+    match arg {
+        b"-" => return Ok(StreamId::MIN),
+        b"+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let (exclusive, arg) = match arg.split_first() {
+        Some((b'(', rest)) => (true, rest),
+        _ => (false, arg),
+    };
+    let arg = std::str::from_utf8(arg).map_err(|_| Error::InvalidStreamId)?;
+    let mut bound: StreamId = arg.parse()?;
+    if upper && !arg.contains('-') {
+        bound.seq = u64::MAX;
+    }
+    match (exclusive, upper) {
+        (false, _) => Ok(bound),
+        (true, false) => bound.next().ok_or(Error::InvalidStreamId),
+        (true, true) => bound.prev().ok_or(Error::InvalidStreamId),
+    }
+}
+
+/// Parses id of `XADD`, `XREAD` and similar, where `special` (`*`, `$` or `>`) means `None`.
+fn to_stream_id(arg: &[u8], special: &[u8]) -> Result<Option<StreamId>, Error> {
+    if arg == special {
+        return Ok(None);
+    }
+    std::str::from_utf8(arg)
+        .map_err(|_| Error::InvalidStreamId)?
+        .parse()
+        .map(Some)
+}
+
+/// Parses `COUNT` and `BLOCK` options of `XREAD` and `XREADGROUP` followed by `STREAMS` with
+/// keys and ids. Returns count, block time and pairs of key and id.
+#[allow(clippy::type_complexity)]
+fn stream_read_options<'a>(
+    args: &'a [Vec<u8>],
+    last: &[u8],
+) -> Result<
+    (
+        Option<usize>,
+        Option<Duration>,
+        Vec<(&'a str, Option<StreamId>)>,
+    ),
+    Error,
+> {
+    let syntax_error = || Error::BadRequest {
+        msg: "syntax error".into(),
+    };
+
+    let mut count = None;
+    let mut block = None;
+
+    let mut iter = args.iter();
+    while let Some(option) = iter.next() {
+        match option.to_ascii_uppercase().as_slice() {
+            b"COUNT" => {
+                count = Some(to_u64(iter.next().ok_or_else(syntax_error)?)? as usize);
+            }
+            b"BLOCK" => {
+                let millis = to_u64(iter.next().ok_or_else(syntax_error)?)?;
+                block = Some(Duration::from_millis(millis));
+            }
+            b"STREAMS" => {
+                let rest = iter.as_slice();
+                if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                    return Err(Error::BadRequest {
+                        msg: "Unbalanced list of streams: for each stream key an ID must be \
+                              specified"
+                            .into(),
+                    });
+                }
+                let (keys, ids) = rest.split_at(rest.len() / 2);
+                let streams = keys
+                    .iter()
+                    .zip(ids)
+                    .map(|(key, id)| {
+                        let key = std::str::from_utf8(key).map_err(|_| Error::BadRequest {
+                            msg: "Invalid key utf-8 encoding".into(),
+                        })?;
+                        Ok((key, to_stream_id(id, last)?))
+                    })
+                    .collect::<Result<_, Error>>()?;
+                return Ok((count, block, streams));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    Err(syntax_error())
+}
+
+/// Maps RESP request onto `Command`, together with shape its reply must take.
+pub fn command_from_resp(frame: RespFrame) -> Result<(Command, RespReply), Error> {
+    let (name, args) = request_args(frame)?;
+
+    let request = match name.as_str() {
+        "GET" => {
+            let [key] = exact(&name, &args)?;
+            (Command::get(&to_key(key)?), RespReply::Value)
+        }
+        "SET" => {
+            let (key, value, options) = match args.as_slice() {
+                [key, value, options @ ..] => (key, value, options),
+                _ => return Err(exact::<2>(&name, &args).unwrap_err()),
+            };
+            let key = to_key(key)?;
+            let value = to_value(value.clone());
+            let (expire, condition, get) = set_options(options)?;
+            match condition {
+                SetCondition::Always => {
+                    let command = match expire {
+                        Some(expire) => Command::set_ex(&key, value, expire),
+                        None => Command::set(&key, value),
+                    };
+                    let reply = if get { RespReply::Value } else { RespReply::Ok };
+                    (command, reply)
+                }
+                SetCondition::IfAbsent => {
+                    (Command::set_nx(&key, value, expire), RespReply::Written)
+                }
+                SetCondition::IfPresent => {
+                    (Command::set_xx(&key, value, expire), RespReply::Written)
+                }
+            }
+        }
+        "SETNX" => {
+            let [key, value] = exact(&name, &args)?;
+            let command = Command::set_nx(&to_key(key)?, to_value(value.clone()), None);
+            (command, RespReply::Flag)
+        }
+        "GETSET" => {
+            let [key, value] = exact(&name, &args)?;
+            let command = Command::set(&to_key(key)?, to_value(value.clone()));
+            (command, RespReply::Value)
+        }
+        "DEL" => match args.as_slice() {
+            [key] => (Command::delete(&to_key(key)?), RespReply::Count),
+            _ => (Command::mdel(&to_keys(&name, &args)?), RespReply::Count),
+        },
+        "MGET" => (Command::mget(&to_keys(&name, &args)?), RespReply::Value),
+        "EXISTS" => (Command::exists(&to_keys(&name, &args)?), RespReply::Value),
+        "KEYS" => {
+            let [pattern] = exact(&name, &args)?;
+            (Command::keys(&to_key(pattern)?), RespReply::Value)
+        }
+        "SCAN" => {
+            let (cursor, options) = match args.split_first() {
+                Some((cursor, options)) => (cursor, options),
+                None => return Err(exact::<1>(&name, &args).unwrap_err()),
+            };
+            let cursor = to_u64(cursor).map_err(|_| Error::BadRequest {
+                msg: "invalid cursor".into(),
+            })?;
+            let (pattern, count) = scan_options(options)?;
+            (
+                Command::scan(cursor, pattern.as_deref(), count),
+                RespReply::Value,
+            )
+        }
+        "TYPE" => {
+            let [key] = exact(&name, &args)?;
+            (Command::key_type(&to_key(key)?), RespReply::Simple)
+        }
+        "RENAME" => {
+            let [key, new_key] = exact(&name, &args)?;
+            let command = Command::rename(&to_key(key)?, &to_key(new_key)?);
+            (command, RespReply::Ok)
+        }
+        "RENAMENX" => {
+            let [key, new_key] = exact(&name, &args)?;
+            let command = Command::rename_nx(&to_key(key)?, &to_key(new_key)?);
+            (command, RespReply::Flag)
+        }
+        "DBSIZE" => {
+            exact::<0>(&name, &args)?;
+            (Command::db_size(), RespReply::Value)
+        }
+        // keys are always removed right away, so both modes behave the same
+        "FLUSHALL" => match args.as_slice() {
+            [] => (Command::flush_all(), RespReply::Ok),
+            [mode] if matches!(mode.to_ascii_uppercase().as_slice(), b"ASYNC" | b"SYNC") => {
+                (Command::flush_all(), RespReply::Ok)
+            }
+            _ => {
+                return Err(Error::BadRequest {
+                    msg: "syntax error".into(),
+                });
+            }
+        },
+        "AUTH" => {
+            let (user, password) = match args.as_slice() {
+                [password] => (DEFAULT_USER.to_string(), password),
+                [user, password] => (to_key(user)?, password),
+                _ => return Err(exact::<1>(&name, &args).unwrap_err()),
+            };
+            (Command::auth(&user, &to_key(password)?), RespReply::Ok)
+        }
+        "ACL" => {
+            let (subcommand, rest) = match args.split_first() {
+                Some((subcommand, rest)) => (subcommand.to_ascii_uppercase(), rest),
+                None => return Err(exact::<1>(&name, &args).unwrap_err()),
+            };
+            match (subcommand.as_slice(), rest) {
+                (b"SETUSER", [user, rules @ ..]) => {
+                    let rules = rules
+                        .iter()
+                        .map(|rule| to_key(rule))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let rules: Vec<_> = rules.iter().map(String::as_str).collect();
+                    (Command::acl_set_user(&to_key(user)?, &rules), RespReply::Ok)
+                }
+                (b"LIST", []) => (Command::acl_list(), RespReply::Value),
+                (b"WHOAMI", []) => (Command::acl_whoami(), RespReply::Value),
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "syntax error".into(),
+                    });
+                }
+            }
+        }
+        "SHUTDOWN" => {
+            let save = match args.as_slice() {
+                [] => None,
+                [mode] => match mode.to_ascii_uppercase().as_slice() {
+                    b"SAVE" => Some(true),
+                    b"NOSAVE" => Some(false),
+                    _ => {
+                        return Err(Error::BadRequest {
+                            msg: "syntax error".into(),
+                        });
+                    }
+                },
+                _ => return Err(exact::<1>(&name, &args).unwrap_err()),
+            };
+            (Command::shutdown(save), RespReply::Ok)
+        }
+        "MSET" => {
+            if args.is_empty() || args.len() % 2 != 0 {
+                return Err(exact::<2>(&name, &[]).unwrap_err());
+            }
+            let entries = args
+                .chunks(2)
+                .map(|pair| Ok((to_key(&pair[0])?, to_value(pair[1].clone()))))
+                .collect::<Result<Vec<_>, Error>>()?;
+            (Command::mset(entries), RespReply::Ok)
+        }
+        "EXPIRE" | "PEXPIRE" => {
+            let [key, amount] = exact(&name, &args)?;
+            let expire = to_expire(amount, name == "EXPIRE", &name.to_ascii_lowercase())?;
+            (Command::expire(&to_key(key)?, expire), RespReply::Flag)
+        }
+        "TTL" | "PTTL" => {
+            let [key] = exact(&name, &args)?;
+            let reply = if name == "TTL" {
+                RespReply::Seconds
+            } else {
+                RespReply::Millis
+            };
+            (Command::ttl(&to_key(key)?), reply)
+        }
+        "INCR" => {
+            let [key] = exact(&name, &args)?;
+            (Command::incr(&to_key(key)?), RespReply::Value)
+        }
+        "DECR" => {
+            let [key] = exact(&name, &args)?;
+            (Command::decr(&to_key(key)?), RespReply::Value)
+        }
+        "INCRBY" | "DECRBY" => {
+            let [key, delta] = exact(&name, &args)?;
+            let delta = to_i64(delta)?;
+            let delta = if name == "INCRBY" {
+                delta
+            } else {
+                delta.checked_neg().ok_or(Error::Overflow)?
+            };
+            (Command::incr_by(&to_key(key)?, delta), RespReply::Value)
+        }
+        "INCRBYFLOAT" => {
+            let [key, delta] = exact(&name, &args)?;
+            (
+                Command::incr_by_float(&to_key(key)?, to_f64(delta)?),
+                RespReply::Value,
+            )
+        }
+        "SUBSCRIBE" => (
+            Command::subscribe(&to_keys(&name, &args)?),
+            RespReply::Pushes,
+        ),
+        "PSUBSCRIBE" => (
+            Command::psubscribe(&to_keys(&name, &args)?),
+            RespReply::Pushes,
+        ),
+        // without arguments both unsubscribe from everything
+        "UNSUBSCRIBE" => (
+            Command::unsubscribe(&to_optional_keys(&args)?),
+            RespReply::Pushes,
+        ),
+        "PUNSUBSCRIBE" => (
+            Command::punsubscribe(&to_optional_keys(&args)?),
+            RespReply::Pushes,
+        ),
+        "PUBLISH" => {
+            let [channel, message] = exact(&name, &args)?;
+            let command = Command::publish(&to_key(channel)?, to_value(message.clone()));
+            (command, RespReply::Value)
+        }
+        "MULTI" => {
+            exact::<0>(&name, &args)?;
+            (Command::multi(), RespReply::Ok)
+        }
+        "EXEC" => {
+            exact::<0>(&name, &args)?;
+            (Command::exec(), RespReply::Value)
+        }
+        "DISCARD" => {
+            exact::<0>(&name, &args)?;
+            (Command::discard(), RespReply::Ok)
+        }
+        "WATCH" => (Command::watch(&to_keys(&name, &args)?), RespReply::Ok),
+        "UNWATCH" => {
+            exact::<0>(&name, &args)?;
+            (Command::unwatch(), RespReply::Ok)
+        }
+        "HSET" => {
+            let (key, pairs) = match args.split_first() {
+                Some((key, pairs)) if !pairs.is_empty() && pairs.len() % 2 == 0 => (key, pairs),
+                _ => return Err(exact::<3>(&name, &[]).unwrap_err()),
+            };
+            let fields = pairs
+                .chunks(2)
+                .map(|pair| Ok((to_key(&pair[0])?, to_value(pair[1].clone()))))
+                .collect::<Result<HashMap<_, _>, Error>>()?;
+            (Command::hset(&to_key(key)?, fields), RespReply::Value)
+        }
+        "HGET" => {
+            let [key, field] = exact(&name, &args)?;
+            (
+                Command::hget(&to_key(key)?, &to_key(field)?),
+                RespReply::Value,
+            )
+        }
+        "HDEL" => {
+            let (key, fields) = match args.split_first() {
+                Some((key, fields)) if !fields.is_empty() => (key, fields),
+                _ => return Err(exact::<2>(&name, &[]).unwrap_err()),
+            };
+            let fields = to_keys(&name, fields)?;
+            (Command::hdel(&to_key(key)?, &fields), RespReply::Value)
+        }
+        "HGETALL" => {
+            let [key] = exact(&name, &args)?;
+            (Command::hgetall(&to_key(key)?), RespReply::Value)
+        }
+        "HINCRBY" => {
+            let [key, field, delta] = exact(&name, &args)?;
+            let command = Command::hincr_by(&to_key(key)?, &to_key(field)?, to_i64(delta)?);
+            (command, RespReply::Value)
+        }
+        "HEXISTS" => {
+            let [key, field] = exact(&name, &args)?;
+            (
+                Command::hexists(&to_key(key)?, &to_key(field)?),
+                RespReply::Flag,
+            )
+        }
+        "LPUSH" | "RPUSH" => {
+            let (key, values) = match args.split_first() {
+                Some((key, values)) if !values.is_empty() => (key, values),
+                _ => return Err(exact::<2>(&name, &[]).unwrap_err()),
+            };
+            let key = to_key(key)?;
+            let values = values.iter().cloned().map(to_value).collect();
+            let command = if name == "LPUSH" {
+                Command::lpush(&key, values)
+            } else {
+                Command::rpush(&key, values)
+            };
+            (command, RespReply::Value)
+        }
+        "BLPOP" | "BRPOP" => {
+            let Some((timeout, keys)) = args.split_last() else {
+                return Err(exact::<2>(&name, &[]).unwrap_err());
+            };
+            let keys = to_keys(&name, keys)?;
+            let timeout = to_timeout(timeout)?;
+            let command = if name == "BLPOP" {
+                Command::blpop(&keys, timeout)
+            } else {
+                Command::brpop(&keys, timeout)
+            };
+            (command, RespReply::Value)
+        }
+        "SADD" | "SREM" => {
+            let (key, members) = match args.split_first() {
+                Some((key, members)) if !members.is_empty() => (key, members),
+                _ => return Err(exact::<2>(&name, &[]).unwrap_err()),
+            };
+            let key = to_key(key)?;
+            let members = members.iter().cloned().map(to_member).collect();
+            let command = if name == "SADD" {
+                Command::sadd(&key, members)
+            } else {
+                Command::srem(&key, members)
+            };
+            (command, RespReply::Value)
+        }
+        "SISMEMBER" => {
+            let [key, member] = exact(&name, &args)?;
+            let command = Command::sismember(&to_key(key)?, to_member(member.clone()));
+            (command, RespReply::Flag)
+        }
+        "SMEMBERS" => {
+            let [key] = exact(&name, &args)?;
+            (Command::smembers(&to_key(key)?), RespReply::Value)
+        }
+        "SCARD" => {
+            let [key] = exact(&name, &args)?;
+            (Command::scard(&to_key(key)?), RespReply::Value)
+        }
+        "SPOP" => {
+            let [key] = exact(&name, &args)?;
+            (Command::spop(&to_key(key)?), RespReply::Value)
+        }
+        "SRANDMEMBER" => {
+            let [key] = exact(&name, &args)?;
+            (Command::srandmember(&to_key(key)?), RespReply::Value)
+        }
+        "SUNION" => (Command::sunion(&to_keys(&name, &args)?), RespReply::Value),
+        "SINTER" => (Command::sinter(&to_keys(&name, &args)?), RespReply::Value),
+        "SDIFF" => (Command::sdiff(&to_keys(&name, &args)?), RespReply::Value),
+        "SUNIONSTORE" | "SINTERSTORE" | "SDIFFSTORE" => {
+            let Some((destination, keys)) = args.split_first() else {
+                return Err(exact::<2>(&name, &[]).unwrap_err());
+            };
+            let destination = to_key(destination)?;
+            let keys = to_keys(&name, keys)?;
+            let command = match name.as_str() {
+                "SUNIONSTORE" => Command::sunion_store(&destination, &keys),
+                "SINTERSTORE" => Command::sinter_store(&destination, &keys),
+                _ => Command::sdiff_store(&destination, &keys),
+            };
+            (command, RespReply::Value)
+        }
+        "ZADD" => {
+            let (key, pairs) = match args.split_first() {
+                Some((key, pairs)) if !pairs.is_empty() && pairs.len() % 2 == 0 => (key, pairs),
+                _ => return Err(exact::<3>(&name, &[]).unwrap_err()),
+            };
+            let mut members = SortedSet::new();
+            for pair in pairs.chunks(2) {
+                members.insert(to_member(pair[1].clone()), to_f64(&pair[0])?);
+            }
+            (Command::zadd(&to_key(key)?, members), RespReply::Value)
+        }
+        "ZREM" => {
+            let (key, members) = match args.split_first() {
+                Some((key, members)) if !members.is_empty() => (key, members),
+                _ => return Err(exact::<2>(&name, &[]).unwrap_err()),
+            };
+            let members = members.iter().cloned().map(to_member).collect();
+            (Command::zrem(&to_key(key)?, members), RespReply::Value)
+        }
+        "ZSCORE" => {
+            let [key, member] = exact(&name, &args)?;
+            let command = Command::zscore(&to_key(key)?, to_member(member.clone()));
+            (command, RespReply::Value)
+        }
+        "ZINCRBY" => {
+            let [key, delta, member] = exact(&name, &args)?;
+            let command =
+                Command::zincr_by(&to_key(key)?, to_member(member.clone()), to_f64(delta)?);
+            (command, RespReply::Value)
+        }
+        "ZRANK" | "ZREVRANK" => {
+            let [key, member] = exact(&name, &args)?;
+            let (key, member) = (to_key(key)?, to_member(member.clone()));
+            let command = if name == "ZRANK" {
+                Command::zrank(&key, member)
+            } else {
+                Command::zrevrank(&key, member)
+            };
+            (command, RespReply::Value)
+        }
+        "ZRANGE" | "ZREVRANGE" => {
+            if args.len() < 3 {
+                return Err(exact::<3>(&name, &[]).unwrap_err());
+            }
+            let (key, start, stop) = (to_key(&args[0])?, to_i64(&args[1])?, to_i64(&args[2])?);
+            let (with_scores, _) = range_options(&args[3..], false)?;
+            let command = if name == "ZRANGE" {
+                Command::zrange(&key, start, stop)
+            } else {
+                Command::zrevrange(&key, start, stop)
+            };
+            let reply = if with_scores {
+                RespReply::Value
+            } else {
+                RespReply::Members
+            };
+            (command, reply)
+        }
+        "ZRANGEBYSCORE" | "ZREVRANGEBYSCORE" => {
+            if args.len() < 3 {
+                return Err(exact::<3>(&name, &[]).unwrap_err());
+            }
+            let key = to_key(&args[0])?;
+            let (with_scores, limit) = range_options(&args[3..], true)?;
+            let command = if name == "ZRANGEBYSCORE" {
+                let min = to_score_bound(&args[1], false)?;
+                let max = to_score_bound(&args[2], true)?;
+                Command::zrange_by_score(&key, min, max, limit)
+            } else {
+                // reversed range starts with the upper bound
+                let max = to_score_bound(&args[1], true)?;
+                let min = to_score_bound(&args[2], false)?;
+                Command::zrevrange_by_score(&key, min, max, limit)
+            };
+            let reply = if with_scores {
+                RespReply::Value
+            } else {
+                RespReply::Members
+            };
+            (command, reply)
+        }
+        "ZCARD" => {
+            let [key] = exact(&name, &args)?;
+            (Command::zcard(&to_key(key)?), RespReply::Value)
+        }
+        "ZPOPMIN" | "ZPOPMAX" => {
+            let (key, count) = match args.as_slice() {
+                [key] => (key, 1),
+                [key, count] => (key, to_i64(count)?),
+                _ => return Err(exact::<1>(&name, &[]).unwrap_err()),
+            };
+            let key = to_key(key)?;
+            let command = if name == "ZPOPMIN" {
+                Command::zpopmin(&key, count)
+            } else {
+                Command::zpopmax(&key, count)
+            };
+            (command, RespReply::Value)
+        }
+        "XADD" => {
+            let (key, id, pairs) = match args.as_slice() {
+                [key, id, pairs @ ..] if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+                    (key, id, pairs)
+                }
+                _ => return Err(exact::<4>(&name, &[]).unwrap_err()),
+            };
+            let fields = pairs
+                .chunks(2)
+                .map(|pair| Ok((to_key(&pair[0])?, to_value(pair[1].clone()))))
+                .collect::<Result<_, Error>>()?;
+            let command = Command::xadd(&to_key(key)?, to_stream_id(id, b"*")?, fields);
+            (command, RespReply::Value)
+        }
+        "XRANGE" | "XREVRANGE" => {
+            let (key, first, second, count) = match args.as_slice() {
+                [key, first, second] => (key, first, second, None),
+                [key, first, second, option, count] if option.eq_ignore_ascii_case(b"COUNT") => {
+                    (key, first, second, Some(to_u64(count)? as usize))
+                }
+                _ => return Err(exact::<3>(&name, &[]).unwrap_err()),
+            };
+            let key = to_key(key)?;
+            let command = if name == "XRANGE" {
+                let start = to_stream_bound(first, false)?;
+                let end = to_stream_bound(second, true)?;
+                Command::xrange(&key, start, end, count)
+            } else {
+                // reversed range starts with the upper bound
+                let end = to_stream_bound(first, true)?;
+                let start = to_stream_bound(second, false)?;
+                Command::xrevrange(&key, start, end, count)
+            };
+            (command, RespReply::Value)
+        }
+        "XLEN" => {
+            let [key] = exact(&name, &args)?;
+            (Command::xlen(&to_key(key)?), RespReply::Value)
+        }
+        "XTRIM" => {
+            let (key, max_len) = match args.as_slice() {
+                [key, strategy, max_len] if strategy.eq_ignore_ascii_case(b"MAXLEN") => {
+                    (key, max_len)
+                }
+                // trimming is always exact, so approximate one is exact as well
+                [key, strategy, exactness, max_len]
+                    if strategy.eq_ignore_ascii_case(b"MAXLEN")
+                        && matches!(exactness.as_slice(), b"=" | b"~") =>
+                {
+                    (key, max_len)
+                }
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "syntax error".into(),
+                    });
+                }
+            };
+            (
+                Command::xtrim(&to_key(key)?, to_u64(max_len)?),
+                RespReply::Value,
+            )
+        }
+        "XREAD" => {
+            let (count, block, streams) = stream_read_options(&args, b"$")?;
+            (Command::xread(&streams, count, block), RespReply::Value)
+        }
+        "XREADGROUP" => {
+            let (group, consumer, options) = match args.as_slice() {
+                [option, group, consumer, options @ ..]
+                    if option.eq_ignore_ascii_case(b"GROUP") =>
+                {
+                    (to_key(group)?, to_key(consumer)?, options)
+                }
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "syntax error".into(),
+                    });
+                }
+            };
+            let (count, block, streams) = stream_read_options(options, b">")?;
+            let command = Command::xread_group(&group, &consumer, &streams, count, block);
+            (command, RespReply::Value)
+        }
+        "XACK" => {
+            let (key, group, ids) = match args.as_slice() {
+                [key, group, ids @ ..] if !ids.is_empty() => (key, group, ids),
+                _ => return Err(exact::<3>(&name, &[]).unwrap_err()),
+            };
+            let ids = ids
+                .iter()
+                .map(|id| to_stream_id(id, b"")?.ok_or(Error::InvalidStreamId))
+                .collect::<Result<Vec<_>, Error>>()?;
+            let command = Command::xack(&to_key(key)?, &to_key(group)?, &ids);
+            (command, RespReply::Value)
+        }
+        "XGROUP" => {
+            let (key, group, id, create) = match args.as_slice() {
+                [subcommand, key, group, id] if subcommand.eq_ignore_ascii_case(b"CREATE") => {
+                    (key, group, id, false)
+                }
+                [subcommand, key, group, id, option]
+                    if subcommand.eq_ignore_ascii_case(b"CREATE")
+                        && option.eq_ignore_ascii_case(b"MKSTREAM") =>
+                {
+                    (key, group, id, true)
+                }
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "only XGROUP CREATE key group id [MKSTREAM] is supported".into(),
+                    });
+                }
+            };
+            let command = Command::xgroup_create(
+                &to_key(key)?,
+                &to_key(group)?,
+                to_stream_id(id, b"$")?,
+                create,
+            );
+            (command, RespReply::Ok)
+        }
+        "XPENDING" => {
+            let (key, group, start, end, count, consumer) = match args.as_slice() {
+                [key, group, start, end, count] => (key, group, start, end, count, None),
+                [key, group, start, end, count, consumer] => {
+                    (key, group, start, end, count, Some(to_key(consumer)?))
+                }
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "only XPENDING key group start end count [consumer] is supported"
+                            .into(),
+                    });
+                }
+            };
+            let command = Command::xpending(
+                &to_key(key)?,
+                &to_key(group)?,
+                to_stream_bound(start, false)?,
+                to_stream_bound(end, true)?,
+                Some(to_u64(count)? as usize),
+                consumer.as_deref(),
+            );
+            (command, RespReply::Value)
+        }
+        "LPOP" => {
+            let [key] = exact(&name, &args)?;
+            (Command::lpop(&to_key(key)?), RespReply::Value)
+        }
+        "RPOP" => {
+            let [key] = exact(&name, &args)?;
+            (Command::rpop(&to_key(key)?), RespReply::Value)
+        }
+        "LRANGE" => {
+            let [key, start, stop] = exact(&name, &args)?;
+            let command = Command::lrange(&to_key(key)?, to_i64(start)?, to_i64(stop)?);
+            (command, RespReply::Value)
+        }
+        "LLEN" => {
+            let [key] = exact(&name, &args)?;
+            (Command::llen(&to_key(key)?), RespReply::Value)
+        }
+        "LTRIM" => {
+            let [key, start, stop] = exact(&name, &args)?;
+            let command = Command::ltrim(&to_key(key)?, to_i64(start)?, to_i64(stop)?);
+            (command, RespReply::Ok)
+        }
+        "LINDEX" => {
+            let [key, index] = exact(&name, &args)?;
+            (
+                Command::lindex(&to_key(key)?, to_i64(index)?),
+                RespReply::Value,
+            )
+        }
+        "LSET" => {
+            let [key, index, value] = exact(&name, &args)?;
+            let command = Command::lset(&to_key(key)?, to_i64(index)?, to_value(value.clone()));
+            (command, RespReply::Ok)
+        }
+        "PERSIST" => {
+            let [key] = exact(&name, &args)?;
+            (Command::persist(&to_key(key)?), RespReply::Flag)
+        }
+        "SAVE" => {
+            exact::<0>(&name, &args)?;
+            (Command::save(), RespReply::Ok)
+        }
+        "BGSAVE" => {
+            exact::<0>(&name, &args)?;
+            (
+                Command::bg_save(),
+                RespReply::Status("Background saving started"),
+            )
+        }
+        "BGREWRITEAOF" => {
+            exact::<0>(&name, &args)?;
+            (
+                Command::rewrite_aof(),
+                RespReply::Status("Background append only file rewriting started"),
+            )
+        }
+        _ => {
+            return Err(Error::BadRequest {
+                msg: format!("unknown command '{name}'"),
+            });
+        }
+    };
+
+    Ok(request)
+}
+
+/// Answers connection level commands that have no `Command` equivalent (`HELLO`, `PING`,
+/// `COMMAND`, `CLIENT`, `SELECT`) for connection authenticated as `user`, which `HELLO ...
+/// AUTH` can change. Returns `None` for everything else.
+pub fn handshake(
+    frame: &RespFrame,
+    version: &mut RespVersion,
+    acl: &Acl,
+    user: &mut Option<String>,
+) -> Option<RespFrame> {
+    let (name, args) = request_args(frame.clone()).ok()?;
+
+    let reply = match name.as_str() {
+        "HELLO" => {
+            let mut args = args.into_iter();
+            let requested = match args.next().as_deref() {
+                None => None,
+                Some(b"2") => Some(RespVersion::Resp2),
+                Some(b"3") => Some(RespVersion::Resp3),
+                Some(_) => {
+                    return Some(RespFrame::Error(
+                        "NOPROTO unsupported protocol version".into(),
+                    ));
+                }
+            };
+            while let Some(option) = args.next() {
+                match (option.to_ascii_uppercase().as_slice(), args.next()) {
+                    (b"AUTH", Some(name)) => {
+                        let Some(password) = args.next() else {
+                            return Some(RespFrame::error("syntax error"));
+                        };
+                        let authenticated = match (to_key(&name), to_key(&password)) {
+                            (Ok(name), Ok(password)) => {
+                                acl.authenticate(&name, &password).map(|()| name)
+                            }
+                            _ => Err(Error::WrongPass),
+                        };
+                        match authenticated {
+                            Ok(name) => *user = Some(name),
+                            Err(e) => return Some(request_error(e)),
+                        }
+                    }
+                    (b"SETNAME", Some(_)) => {}
+                    _ => return Some(RespFrame::error("syntax error")),
+                }
+            }
+            if let Err(e) = acl.check_auth(user.as_deref()) {
+                return Some(request_error(e));
+            }
+            if let Some(requested) = requested {
+                *version = requested;
+            }
+            let proto = match version {
+                RespVersion::Resp2 => 2,
+                RespVersion::Resp3 => 3,
+            };
+            RespFrame::map(
+                vec![
+                    (bulk("server"), bulk("redis-rs")),
+                    (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+                    (bulk("proto"), RespFrame::Integer(proto)),
+                    (bulk("mode"), bulk("standalone")),
+                    (bulk("role"), bulk("master")),
+                    (bulk("modules"), RespFrame::Array(Vec::new())),
+                ],
+                *version,
+            )
+        }
+        "PING" | "COMMAND" | "CLIENT" | "SELECT" if acl.check_auth(user.as_deref()).is_err() => {
+            return Some(request_error(Error::NoAuth));
+        }
+        "PING" => match args.into_iter().next() {
+            Some(msg) => RespFrame::Bulk(msg),
+            None => RespFrame::Simple("PONG".into()),
+        },
+        "COMMAND" => RespFrame::Array(Vec::new()),
+        "CLIENT" | "SELECT" => RespFrame::Simple("OK".into()),
+        _ => return None,
+    };
+
+    Some(reply)
+}
+
+/// Error reply for request that couldn't be mapped onto `Command`.
+pub fn request_error(e: Error) -> RespFrame {
+    match e {
+        Error::BadRequest { msg } => RespFrame::error(&msg),
+        e => RespFrame::error(&e.to_string()),
+    }
+}
+
+fn bulk(s: &str) -> RespFrame {
+    RespFrame::Bulk(s.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::pubsub::PushKind;
+
+    fn request(args: &[&str]) -> RespFrame {
+        RespFrame::Array(args.iter().map(|arg| bulk(arg)).collect())
+    }
+
+    #[test]
+    fn test_frame_round_trip() -> anyhow::Result<()> {
+        let frame = RespFrame::Array(vec![
+            RespFrame::Bulk(b"a\r\nb".to_vec()),
+            RespFrame::Integer(-3),
+            RespFrame::Simple("OK".into()),
+            RespFrame::NullBulk,
+            RespFrame::Map(vec![(bulk("k"), RespFrame::Boolean(true))]),
+        ]);
+        let bytes = frame.to_bytes();
+
+        let mut cursor = Cursor::new(&bytes[..]);
+        RespFrame::validate(&mut cursor)?;
+        assert_eq!(cursor.position() as usize, bytes.len());
+
+        cursor.set_position(0);
+        assert_eq!(RespFrame::parse(&mut cursor)?, frame);
+
+        for end in 0..bytes.len() {
+            let mut cursor = Cursor::new(&bytes[..end]);
+            assert!(matches!(
+                RespFrame::validate(&mut cursor),
+                Err(Error::Incomplete)
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_frame_limits() {
+        let nested = "*1\r\n".repeat(MAX_DEPTH) + "$1\r\na\r\n";
+        assert!(RespFrame::validate(&mut Cursor::new(nested.as_bytes())).is_ok());
+
+        let too_nested = "*1\r\n".repeat(MAX_DEPTH + 1) + "$1\r\na\r\n";
+        assert!(matches!(
+            RespFrame::validate(&mut Cursor::new(too_nested.as_bytes())),
+            Err(Error::Protocol { .. })
+        ));
+
+        // rejected before the bulk arrives
+        let too_long = format!("*1\r\n${}\r\n", MAX_BULK_LEN + 1);
+        assert!(matches!(
+            RespFrame::validate(&mut Cursor::new(too_long.as_bytes())),
+            Err(Error::Protocol { .. })
+        ));
+    }
+
+    #[test]
+    fn test_command_mapping() -> anyhow::Result<()> {
+        let (command, reply) = command_from_resp(request(&["set", "k", "v", "PX", "100"]))?;
+        assert_eq!(command.key, "k");
+        assert_eq!(reply, RespReply::Ok);
+        assert!(matches!(
+            command.r#type,
+            crate::utils::command::CommandType::Set {
+                expire: Some(expire),
+                ..
+            } if expire == Duration::from_millis(100)
+        ));
+
+        let (command, reply) = command_from_resp(request(&["SET", "k", "v", "nx", "EX", "1"]))?;
+        assert_eq!(reply, RespReply::Written);
+        assert!(matches!(
+            command.r#type,
+            crate::utils::command::CommandType::SetNx {
+                expire: Some(expire),
+                ..
+            } if expire == Duration::from_secs(1)
+        ));
+        assert!(command_from_resp(request(&["SET", "k", "v", "NX", "XX"])).is_err());
+        assert!(command_from_resp(request(&["SET", "k", "v", "XX", "GET"])).is_err());
+        // deadline of the key would overflow
+        let max = u64::MAX.to_string();
+        assert!(command_from_resp(request(&["SET", "k", "v", "EX", &max])).is_err());
+        assert!(command_from_resp(request(&["SET", "k", "v", "PX", &max])).is_err());
+        assert!(
+            command_from_resp(request(&["EXPIRE", "k", &(i64::MAX / 999).to_string()])).is_err()
+        );
+
+        let (command, _) = command_from_resp(request(&["decrby", "k", "5"]))?;
+        assert!(matches!(
+            command.r#type,
+            crate::utils::command::CommandType::IncrBy { delta: -5 }
+        ));
+
+        assert!(command_from_resp(request(&["GET"])).is_err());
+        assert!(command_from_resp(request(&["NOPE", "k"])).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reply_shapes() {
+        let v2 = RespVersion::Resp2;
+        assert_eq!(
+            response_to_resp(&Response::Null, RespReply::Value, v2),
+            RespFrame::NullBulk
+        );
+        assert_eq!(
+            response_to_resp(&Response::Null, RespReply::Value, RespVersion::Resp3),
+            RespFrame::Null
+        );
+        assert_eq!(
+            response_to_resp(&Response::Null, RespReply::Seconds, v2),
+            RespFrame::Integer(-2)
+        );
+        assert_eq!(
+            response_to_resp(
+                &Response::Payload(Value::Number(1_600)),
+                RespReply::Seconds,
+                v2
+            ),
+            RespFrame::Integer(2)
+        );
+        assert_eq!(
+            response_to_resp(&Response::error("oops"), RespReply::Ok, v2),
+            RespFrame::Error("ERR oops".into())
+        );
+
+        let ack = Response::Push(Push {
+            kind: PushKind::Subscribe,
+            pattern: None,
+            channel: "news".into(),
+            payload: Value::Number(1),
+        });
+        assert_eq!(
+            response_to_resp(&Response::Multi(vec![ack]), RespReply::Pushes, v2).to_bytes(),
+            b"*3\r\n$9\r\nsubscribe\r\n$4\r\nnews\r\n:1\r\n"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Snapshot file encoding:
+//!
+//! ```text
+//! +-------------------------------+
+//! | magic "RRDB" - 4 bytes        |
+//! +-------------------------------+
+//! | version - 1 byte              |
+//! +-------------------------------+
+//! | entries count - 8 bytes       |
+//! +-------------------------------+
+//! | entries - n bytes             |
+//! +-------------------------------+
+//! | crc32 of all above - 4 bytes  |
+//! +-------------------------------+
+//! ```
+//!
+//! Every entry is encoded as key len (4 bytes), key, expiration time as unix timestamp in
+//! milliseconds (8 bytes, 0 = never) and value in the same encoding as on the wire.
+
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::Error,
+    server::{protocol::TcpRead, storage::Database},
+    utils::{
+        bytes::{get_u8, get_u32, get_u64},
+        command::Value,
+    },
+};
+
+const MAGIC: &[u8; 4] = b"RRDB";
+const VERSION: u8 = 1;
+
+/// Writes and loads snapshots of database under configured path.
+pub struct Snapshotter {
+    path: PathBuf,
+    in_progress: AtomicBool,
+}
+
+impl Snapshotter {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            in_progress: AtomicBool::new(false),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    /// Writes snapshot of entries copied by `capture` and returns number of saved keys, see
+    /// `Database::entries`. Only one save can run at a time.
+    pub fn save(
+        &self,
+        capture: impl FnOnce() -> Vec<(String, Value, Option<Duration>)>,
+    ) -> Result<usize, Error> {
+        if self.in_progress.swap(true, Ordering::AcqRel) {
+            return Err(Error::BadRequest {
+                msg: "snapshot is already in progress".into(),
+            });
+        }
+
+        let result = write_snapshot(capture(), &self.path);
+
+        self.in_progress.store(false, Ordering::Release);
+        result
+    }
+
+    /// Loads snapshot into `db` and returns number of loaded keys. Missing file is treated as
+    /// empty snapshot.
+    pub fn load(&self, db: &Database<String>) -> Result<usize, Error> {
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let entries = decode(&bytes)?;
+        let count = entries.len();
+        for (key, value, ttl) in entries {
+            db.set_with_expiry(key, value, ttl)?;
+        }
+
+        Ok(count)
+    }
+}
+
+fn write_snapshot(
+    entries: Vec<(String, Value, Option<Duration>)>,
+    path: &Path,
+) -> Result<usize, Error> {
+    let count = entries.len();
+    let bytes = encode(entries);
+
+    // write to temporary file first, so crash during save never leaves broken snapshot behind
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes)?;
+    fs::rename(&tmp_path, path)?;
+
+    Ok(count)
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+pub fn encode(entries: Vec<(String, Value, Option<Duration>)>) -> Vec<u8> {
+    let now = SystemTime::now();
+
+    let mut encoded = MAGIC.to_vec();
+    encoded.push(VERSION);
+    encoded.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+    for (key, value, ttl) in entries {
+        encoded.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(key.as_bytes());
+        // clamp to 1, because 0 is reserved for keys without expiration
+        let expires_at = ttl.map_or(0, |ttl| unix_millis(now + ttl).max(1));
+        encoded.extend_from_slice(&expires_at.to_le_bytes());
+        encoded.extend_from_slice(&value.to_bytes());
+    }
+
+    let checksum = crc32fast::hash(&encoded);
+    encoded.extend_from_slice(&checksum.to_le_bytes());
+
+    encoded
+}
+
+/// Decodes snapshot, skipping entries that expired while it was stored.
+pub fn decode(bytes: &[u8]) -> Result<Vec<(String, Value, Option<Duration>)>, Error> {
+    let corrupted = |msg: &str| Error::Corrupted { msg: msg.into() };
+
+    if bytes.len() < MAGIC.len() + 1 + 8 + 4 {
+        return Err(corrupted("snapshot is too short"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    if crc32fast::hash(body).to_le_bytes() != checksum {
+        return Err(corrupted("checksum mismatch"));
+    }
+
+    let mut src = Cursor::new(body);
+    let mut magic = [0; 4];
+    src.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(corrupted("not a snapshot file"));
+    }
+    let version = get_u8(&mut src)?;
+    if version != VERSION {
+        return Err(corrupted(&format!("unsupported version {version}")));
+    }
+
+    let count = get_u64(&mut src)?;
+    let now = unix_millis(SystemTime::now());
+    let mut entries = Vec::new();
+
+    for _ in 0..count {
+        let key_size = get_u32(&mut src)?;
+        let mut key_buf = vec![0; key_size as usize];
+        src.read_exact(&mut key_buf)?;
+        let key = String::from_utf8(key_buf).map_err(|_| corrupted("invalid key encoding"))?;
+
+        let expires_at = get_u64(&mut src)?;
+        let value = Value::parse(&mut src)?;
+
+        match expires_at {
+            0 => entries.push((key, value, None)),
+            at if at > now => {
+                entries.push((key, value, Some(Duration::from_millis(at - now))));
+            }
+            _ => {}
+        }
+    }
+
+    if src.position() as usize != body.len() {
+        return Err(corrupted("unexpected trailing bytes"));
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() -> anyhow::Result<()> {
+        let entries = vec![
+            ("number".to_string(), Value::Number(-7), None),
+            (
+                "array".to_string(),
+                Value::Array(vec![Value::Boolean(true), Value::Bytes(b"\r\n".to_vec())]),
+                Some(Duration::from_secs(60)),
+            ),
+            (
+                "expired".to_string(),
+                Value::Number(0),
+                Some(Duration::ZERO),
+            ),
+        ];
+
+        let decoded = decode(&encode(entries))?;
+
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(&decoded[0], (key, Value::Number(-7), None) if key == "number"));
+        assert!(
+            matches!(&decoded[1], (key, Value::Array(arr), Some(_)) if key == "array" && arr.len() == 2)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupted_snapshot() {
+        let mut bytes = encode(vec![("key".to_string(), Value::Number(1), None)]);
+        bytes[MAGIC.len() + 2] ^= 0xff;
+
+        assert!(matches!(decode(&bytes), Err(Error::Corrupted { .. })));
+        assert!(matches!(decode(b"RRDB"), Err(Error::Corrupted { .. })));
+    }
+}
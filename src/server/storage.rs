@@ -1,30 +1,2241 @@
-use std::{collections::HashMap, hash::Hash, sync::Mutex};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    hash::{BuildHasher, Hash, RandomState},
+    ops::{Bound, Deref},
+    str::FromStr,
+    sync::{
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
-use crate::utils::command::Value;
+use tokio::sync::Notify;
 
+use crate::{
+    error::Error,
+    server::stream::{Fields, Pending, Stream, StreamId},
+    utils::command::{
+        Member, Value, field_encoded_len, group_encoded_len, pending_encoded_len,
+        stream_entry_encoded_len,
+    },
+};
+
+/// Access counter of a new entry, so it isn't evicted before it has a chance to be read again.
+const LFU_INIT: u8 = 5;
+
+/// The higher it is, the more accesses are needed to increase the counter when it's already
+/// high.
+const LFU_LOG_FACTOR: f64 = 10.0;
+
+/// Access counter drops by one for every period in which entry wasn't accessed.
+const LFU_DECAY_PERIOD: Duration = Duration::from_secs(60);
+
+/// Number of keys compared when looking for one to evict.
+const EVICTION_SAMPLES: usize = 5;
+
+struct Entry {
+    value: Value,
+    /// `None` if entry never expires.
+    expires_at: Option<Instant>,
+    /// Changes with every modification of the entry, see `Database::version`.
+    version: u64,
+    /// Approximate memory taken by the entry, length of its key and encoded value. Set when
+    /// entry is inserted, see `Database::insert_entry`.
+    size: usize,
+    /// Last time the entry was read or written.
+    accessed_at: Instant,
+    /// Logarithmic access counter, see `Entry::frequency`.
+    frequency: u8,
+    /// Scan position of the key, see `Shard`. Set when entry is inserted.
+    position: u64,
+    /// Index of the key in `Shard::keys`.
+    slot: usize,
+    /// Index of the key in `Shard::volatile`, `None` if entry never expires.
+    volatile_slot: Option<usize>,
+}
+
+/// Instant when key that expires after `expire` should be removed. Fails instead of
+/// overflowing for expiry too far in the future.
+fn deadline(expire: Option<Duration>) -> Result<Option<Instant>, Error> {
+    expire
+        .map(|expire| {
+            Instant::now()
+                .checked_add(expire)
+                .ok_or(Error::InvalidExpire)
+        })
+        .transpose()
+}
+
+impl Entry {
+    fn new(value: Value, expires_at: Option<Instant>, version: u64) -> Self {
+        Self {
+            value,
+            expires_at,
+            version,
+            size: 0,
+            accessed_at: Instant::now(),
+            frequency: LFU_INIT,
+            position: 0,
+            slot: 0,
+            volatile_slot: None,
+        }
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+
+    /// Access counter decayed by the time since last access. Same as redis LFU counter it only
+    /// grows logarithmically, so it tells rarely used keys from often used ones with a single
+    /// byte.
+    fn frequency(&self, now: Instant) -> u8 {
+        let periods =
+            now.saturating_duration_since(self.accessed_at).as_secs() / LFU_DECAY_PERIOD.as_secs();
+        self.frequency
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    fn record_access(&mut self, now: Instant) {
+        let frequency = self.frequency(now);
+        // the higher counter is, the less likely it's increased
+        let base = frequency.saturating_sub(LFU_INIT) as f64;
+        self.frequency = if random_f64() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            frequency.saturating_add(1)
+        } else {
+            frequency
+        };
+        self.accessed_at = now;
+    }
+}
+
+/// Which keys are evicted when used memory goes over the limit, see
+/// `Database::with_memory_limit`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Nothing is evicted, writes that could grow the data fail instead.
+    #[default]
+    NoEviction,
+    /// Least recently used key.
+    AllKeysLru,
+    /// Least frequently used key.
+    AllKeysLfu,
+    /// Least recently used key of those with expiry.
+    VolatileLru,
+    /// Key with expiry that expires the soonest.
+    VolatileTtl,
+    /// Any key.
+    Random,
+}
+
+impl EvictionPolicy {
+    /// Returns `true` if only keys with expiry are evicted.
+    fn is_volatile(self) -> bool {
+        matches!(self, Self::VolatileLru | Self::VolatileTtl)
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noeviction" => Ok(Self::NoEviction),
+            "allkeys-lru" => Ok(Self::AllKeysLru),
+            "allkeys-lfu" => Ok(Self::AllKeysLfu),
+            "volatile-lru" => Ok(Self::VolatileLru),
+            "volatile-ttl" => Ok(Self::VolatileTtl),
+            "allkeys-random" | "random" => Ok(Self::Random),
+            _ => Err(Error::BadRequest {
+                msg: format!(
+                    "unknown eviction policy \"{s}\", expected noeviction, allkeys-lru, \
+                     allkeys-lfu, volatile-lru, volatile-ttl or random"
+                ),
+            }),
+        }
+    }
+}
+
+/// Connection waiting for a push to one of the lists or streams it's blocked on.
+#[derive(Default)]
+struct Waiter {
+    /// Set when waiter is woken, until it wakes up and checks its lists again.
+    woken: AtomicBool,
+    notify: Notify,
+}
+
+/// Queues of connections blocked on list or stream keys, in order in which they started
+/// waiting.
+///
+/// Pushing to a list only wakes the first waiter of a key. Once it leaves the queue, the next
+/// one is woken in turn, so values pushed together are still handed out in order of waiting.
+/// Appending to a stream wakes everyone, because reading doesn't take entries away.
+struct Notifier<K> {
+    waiting: Mutex<HashMap<K, VecDeque<Arc<Waiter>>>>,
+}
+
+impl<K: Hash + Eq> Notifier<K> {
+    fn wake(&self, key: &K) {
+        let waiting = self.waiting.lock().unwrap();
+        if let Some(waiter) = waiting.get(key).and_then(|queue| queue.front())
+            && !waiter.woken.swap(true, Ordering::AcqRel)
+        {
+            waiter.notify.notify_one();
+        }
+    }
+
+    fn wake_all(&self, key: &K) {
+        let waiting = self.waiting.lock().unwrap();
+        for waiter in waiting.get(key).into_iter().flatten() {
+            if !waiter.woken.swap(true, Ordering::AcqRel) {
+                waiter.notify.notify_one();
+            }
+        }
+    }
+}
+
+/// Registration of a connection blocked on list or stream keys, see `Database::block`. Removed from the
+/// queues when dropped.
+pub struct Blocked<'a, K: Hash + Eq> {
+    db: &'a Database<K>,
+    keys: Vec<K>,
+    waiter: Arc<Waiter>,
+}
+
+impl<K: Hash + Eq> Blocked<'_, K> {
+    /// Waits until a value is pushed to one of the keys. Wakeups can be spurious, so keys have
+    /// to be checked again afterwards.
+    pub async fn wait(&self) {
+        self.waiter.notify.notified().await;
+        self.waiter.woken.store(false, Ordering::Release);
+    }
+}
+
+impl<K: Hash + Eq> Drop for Blocked<'_, K> {
+    fn drop(&mut self) {
+        let mut waiting = self.db.notifier.waiting.lock().unwrap();
+        for key in &self.keys {
+            if let Some(queue) = waiting.get_mut(key) {
+                queue.retain(|waiter| !Arc::ptr_eq(waiter, &self.waiter));
+                if queue.is_empty() {
+                    waiting.remove(key);
+                }
+            }
+        }
+        drop(waiting);
+
+        // lists may still hold values pushed while this waiter was first in line
+        for key in &self.keys {
+            self.db.notifier.wake(key);
+        }
+    }
+}
+
+/// Number of shards used by `Database::new`.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Bits of scan cursor that hold position of a key within its shard, bits above them hold
+/// index of the shard.
+const SCAN_POSITION_BITS: u32 = 48;
+
+/// Position part of scan cursor.
+const SCAN_POSITION_MASK: u64 = (1 << SCAN_POSITION_BITS) - 1;
+
+/// Most shards that database can be split into, so that shard index fits into scan cursor.
+pub const MAX_SHARDS: usize = 1 << (u64::BITS - SCAN_POSITION_BITS);
+
+/// Entries of one shard. Every key also gets scan position of its own, its hash unless another
+/// key already took it, and keys are kept ordered by it, so that scan can continue from cursor
+/// without sorting the shard. Keys are also kept in vectors, so that eviction can sample them
+/// at random. Changes of keys go through `Shard`, so all of them stay in sync.
+struct Shard<K> {
+    map: HashMap<K, Entry>,
+    by_position: BTreeMap<u64, K>,
+    /// All keys in no particular order.
+    keys: Vec<K>,
+    /// Keys that have expiry, sampled by volatile eviction policies.
+    volatile: Vec<K>,
+}
+
+impl<K> Deref for Shard<K> {
+    type Target = HashMap<K, Entry>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+/// Removes key at `slot` by moving the last key into its place, returns the moved key.
+fn take_slot<K>(keys: &mut Vec<K>, slot: usize) -> Option<&K> {
+    keys.swap_remove(slot);
+    keys.get(slot)
+}
+
+impl<K: Hash + Eq + Clone> Shard<K> {
+    fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+            by_position: BTreeMap::new(),
+            keys: Vec::new(),
+            volatile: Vec::new(),
+        }
+    }
+
+    fn get_mut(&mut self, key: &K) -> Option<&mut Entry> {
+        self.map.get_mut(key)
+    }
+
+    /// Inserts `entry` under `key`, returns entry it replaced. New key takes the first free
+    /// scan position from `position` on, replaced entry leaves its position to the new one.
+    fn insert(&mut self, key: K, mut entry: Entry, position: u64) -> Option<Entry> {
+        if let Some(old) = self.map.get_mut(&key) {
+            entry.position = old.position;
+            entry.slot = old.slot;
+            entry.volatile_slot = old.volatile_slot;
+            let old = std::mem::replace(old, entry);
+            self.sync_volatile(&key);
+            return Some(old);
+        }
+
+        let mut position = position;
+        while self.by_position.contains_key(&position) {
+            position = (position + 1) & SCAN_POSITION_MASK;
+        }
+        entry.position = position;
+        self.by_position.insert(position, key.clone());
+        entry.slot = self.keys.len();
+        self.keys.push(key.clone());
+        if entry.expires_at.is_some() {
+            entry.volatile_slot = Some(self.volatile.len());
+            self.volatile.push(key.clone());
+        }
+        self.map.insert(key, entry);
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<Entry> {
+        let entry = self.map.remove(key)?;
+        self.by_position.remove(&entry.position);
+        if let Some(moved) = take_slot(&mut self.keys, entry.slot) {
+            self.map.get_mut(moved).expect("key should exist").slot = entry.slot;
+        }
+        if let Some(slot) = entry.volatile_slot
+            && let Some(moved) = take_slot(&mut self.volatile, slot)
+        {
+            self.map
+                .get_mut(moved)
+                .expect("key should exist")
+                .volatile_slot = Some(slot);
+        }
+        Some(entry)
+    }
+
+    /// Adds `key` to volatile keys or removes it from them after its expiry changed.
+    fn sync_volatile(&mut self, key: &K) {
+        let Some(entry) = self.map.get_mut(key) else {
+            return;
+        };
+        match (entry.expires_at, entry.volatile_slot) {
+            (Some(_), None) => {
+                entry.volatile_slot = Some(self.volatile.len());
+                self.volatile.push(key.clone());
+            }
+            (None, Some(slot)) => {
+                entry.volatile_slot = None;
+                if let Some(moved) = take_slot(&mut self.volatile, slot) {
+                    self.map
+                        .get_mut(moved)
+                        .expect("key should exist")
+                        .volatile_slot = Some(slot);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.by_position.clear();
+        self.keys.clear();
+        self.volatile.clear();
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&K, &Entry) -> bool) {
+        let removed: Vec<_> = self
+            .map
+            .iter()
+            .filter(|(key, entry)| !keep(key, entry))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in removed {
+            self.remove(&key);
+        }
+    }
+}
+
+/// Key-value store split into independently locked shards, so connections working on
+/// different keys rarely wait for each other. Key always lives in the same shard, picked by its
+/// hash.
 pub struct Database<K: Hash + Eq> {
-    map: Mutex<HashMap<K, Value>>,
+    shards: Vec<Mutex<Shard<K>>>,
+    /// Sum of sizes of all entries, expired ones included until they are removed.
+    used: AtomicUsize,
+    /// `None` if database can grow without limit.
+    max_memory: Option<usize>,
+    policy: EvictionPolicy,
+    hasher: RandomState,
+    /// Source of entry versions. Shared by all keys, so a key that is removed and created again
+    /// never gets its old version back.
+    versions: AtomicU64,
+    /// Held shared by single commands and exclusively by transactions.
+    access: RwLock<()>,
+    notifier: Notifier<K>,
+}
+
+/// Adds `delta` to integer `value`, which may also be a string holding an integer.
+fn add(value: &Value, delta: i64) -> Result<i64, Error> {
+    let current = match value {
+        Value::Number(n) => *n,
+        Value::String(s) => s.parse().map_err(|_| Error::NotInteger)?,
+        Value::Map(_) | Value::List(_) | Value::Set(_) | Value::SortedSet(_) | Value::Stream(_) => {
+            return Err(Error::WrongType);
+        }
+        _ => return Err(Error::NotInteger),
+    };
+    current.checked_add(delta).ok_or(Error::Overflow)
+}
+
+fn add_float(value: &Value, delta: f64) -> Result<f64, Error> {
+    let current = match value {
+        Value::Float(n) => *n,
+        Value::Number(n) => *n as f64,
+        Value::String(s) => s
+            .parse::<f64>()
+            .ok()
+            .filter(|n| !n.is_nan())
+            .ok_or(Error::NotFloat)?,
+        Value::Map(_) | Value::List(_) | Value::Set(_) | Value::SortedSet(_) | Value::Stream(_) => {
+            return Err(Error::WrongType);
+        }
+        _ => return Err(Error::NotFloat),
+    };
+    let new = current + delta;
+    if !new.is_finite() {
+        return Err(Error::NotFinite);
+    }
+    Ok(new)
+}
+
+fn as_map(value: &mut Value) -> Result<&mut HashMap<String, Value>, Error> {
+    match value {
+        Value::Map(map) => Ok(map),
+        _ => Err(Error::WrongType),
+    }
+}
+
+fn as_list(value: &mut Value) -> Result<&mut VecDeque<Value>, Error> {
+    match value {
+        Value::List(list) => Ok(list),
+        _ => Err(Error::WrongType),
+    }
+}
+
+fn as_set(value: &mut Value) -> Result<&mut HashSet<Member>, Error> {
+    match value {
+        Value::Set(set) => Ok(set),
+        _ => Err(Error::WrongType),
+    }
+}
+
+fn as_sorted_set(value: &mut Value) -> Result<&mut SortedSet, Error> {
+    match value {
+        Value::SortedSet(set) => Ok(set),
+        _ => Err(Error::WrongType),
+    }
+}
+
+fn as_stream(value: &mut Value) -> Result<&mut Stream, Error> {
+    match value {
+        Value::Stream(stream) => Ok(stream),
+        _ => Err(Error::WrongType),
+    }
+}
+
+/// Random number. Every `RandomState` is seeded differently, which is random enough for
+/// `SPOP`, `SRANDMEMBER` and eviction.
+fn random_u64() -> u64 {
+    RandomState::new().hash_one(Instant::now())
+}
+
+/// Random index into collection of `len`, which must not be empty.
+fn random_index(len: usize) -> usize {
+    random_u64() as usize % len
+}
+
+/// Random number between 0 (inclusive) and 1 (exclusive).
+fn random_f64() -> f64 {
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Picks random member of `set`.
+fn random_member(set: &HashSet<Member>) -> Option<&Member> {
+    if set.is_empty() {
+        return None;
+    }
+    set.iter().nth(random_index(set.len()))
+}
+
+/// Approximate memory taken by entry of `value` under `key`.
+fn entry_size<K: AsRef<[u8]>>(key: &K, value: &Value) -> usize {
+    key.as_ref().len() + value.encoded_len()
+}
+
+/// Way of combining several sets into one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOp {
+    Union,
+    Inter,
+    /// Members of the first set that aren't in any of the others.
+    Diff,
+}
+
+impl SetOp {
+    fn apply(self, sets: Vec<HashSet<Member>>) -> HashSet<Member> {
+        let mut sets = sets.into_iter();
+        let Some(first) = sets.next() else {
+            return HashSet::new();
+        };
+        sets.fold(first, |mut result, set| {
+            match self {
+                Self::Union => result.extend(set),
+                Self::Inter => result.retain(|member| set.contains(member)),
+                Self::Diff => result.retain(|member| !set.contains(member)),
+            }
+            result
+        })
+    }
 }
 
-impl<'a, K: Hash + Eq> Database<K> {
+/// Score of sorted set member, ordered with `f64::total_cmp`. Never NaN.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Members ordered by score, members with equal score are ordered by themselves. Scores are
+/// also indexed by member, so looking one up doesn't have to walk the order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SortedSet {
+    scores: HashMap<Member, f64>,
+    order: BTreeSet<(Score, Member)>,
+}
+
+impl SortedSet {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Sets score of `member`, returns `true` if it wasn't there before. Score can't be NaN.
+    pub fn insert(&mut self, member: Member, score: f64) -> bool {
+        debug_assert!(!score.is_nan(), "score can't be NaN");
+        // -0.0 would be ordered before 0.0, although they are equal
+        let score = score + 0.0;
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.order.remove(&(Score(previous), member.clone()));
+        }
+        self.order.insert((Score(score), member));
+        previous.is_none()
+    }
+
+    pub fn remove(&mut self, member: &Member) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.order.remove(&(Score(score), member.clone()));
+        Some(score)
+    }
+
+    pub fn score(&self, member: &Member) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Position of `member` counted from the lowest score.
+    pub fn rank(&self, member: &Member) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.order.range(..(Score(score), member.clone())).count())
+    }
+
+    /// Members with their scores, from the lowest score.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Member, f64)> + ExactSizeIterator {
+        self.order.iter().map(|(score, member)| (member, score.0))
+    }
+
+    /// Members with score between `min` and `max` (inclusive), from the lowest score.
+    pub fn range_by_score(
+        &self,
+        min: f64,
+        max: f64,
+    ) -> impl DoubleEndedIterator<Item = (&Member, f64)> {
+        // no member is ordered before `false`, so these bounds cover all members of a score
+        let start = (Score(min), Member::Boolean(false));
+        let end = if min > max {
+            // `range` doesn't accept end before start
+            Bound::Excluded(start.clone())
+        } else if max == f64::INFINITY {
+            Bound::Unbounded
+        } else {
+            Bound::Excluded((Score(max.next_up()), Member::Boolean(false)))
+        };
+        self.order
+            .range((Bound::Included(start), end))
+            .map(|(score, member)| (member, score.0))
+    }
+
+    fn pop(&mut self, max: bool) -> Option<(Member, f64)> {
+        let (score, member) = if max {
+            self.order.pop_last()?
+        } else {
+            self.order.pop_first()?
+        };
+        self.scores.remove(&member);
+        Some((member, score.0))
+    }
+}
+
+/// Turns possibly negative `index` counting from the end into position in list of `len`.
+fn list_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// Clamps inclusive range of possibly negative indexes to list of `len`. Returns `None` if the
+/// range is empty.
+fn list_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop && start < len).then_some((start as usize, stop as usize))
+}
+
+/// Locks of several shards, taken in order of shard index so that concurrent multi-key
+/// operations cannot deadlock each other.
+struct ShardGuards<'a, K: Hash + Eq> {
+    db: &'a Database<K>,
+    guards: BTreeMap<usize, MutexGuard<'a, Shard<K>>>,
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> ShardGuards<'_, K> {
+    fn map(&mut self, key: &K) -> &mut Shard<K> {
+        let index = self.db.shard_index(key);
+        self.guards
+            .get_mut(&index)
+            .expect("shard of the key should be locked")
+    }
+
+    /// Copies sets under `keys`, missing keys are empty sets.
+    fn sets(&mut self, keys: &[K]) -> Result<Vec<HashSet<Member>>, Error> {
+        let db = self.db;
+        keys.iter()
+            .map(|key| match db.live_entry(self.map(key), key) {
+                Some(entry) => as_set(&mut entry.value).cloned(),
+                None => Ok(HashSet::new()),
+            })
+            .collect()
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Database<K> {
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates database split into `shards` maps. `shards` of 1 means single global lock.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "database needs at least one shard");
+        assert!(
+            shards <= MAX_SHARDS,
+            "database can have at most {MAX_SHARDS} shards"
+        );
         Self {
-            map: Mutex::new(HashMap::new()),
+            shards: (0..shards).map(|_| Mutex::new(Shard::new())).collect(),
+            used: AtomicUsize::new(0),
+            max_memory: None,
+            policy: EvictionPolicy::default(),
+            hasher: RandomState::new(),
+            versions: AtomicU64::new(0),
+            access: RwLock::new(()),
+            notifier: Notifier {
+                waiting: Mutex::new(HashMap::new()),
+            },
+        }
+    }
+
+    /// Limits memory used by entries to `max_memory` bytes, keys picked by `policy` have to be
+    /// evicted to get under it, see `Database::eviction_candidate`.
+    pub fn with_memory_limit(mut self, max_memory: usize, policy: EvictionPolicy) -> Self {
+        self.max_memory = Some(max_memory);
+        self.policy = policy;
+        self
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Approximate memory used by all entries, sum of lengths of their keys and encoded values.
+    pub fn used_memory(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn shard_index(&self, key: &K) -> usize {
+        self.hasher.hash_one(key) as usize % self.shards.len()
+    }
+
+    fn shard(&self, key: &K) -> MutexGuard<'_, Shard<K>> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    fn next_version(&self) -> u64 {
+        self.versions.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    fn new_entry(&self, value: Value, expires_at: Option<Instant>) -> Entry {
+        Entry::new(value, expires_at, self.next_version())
+    }
+
+    /// Returns live entry for `key` and records access to it, removing it first if it has
+    /// already expired.
+    fn live_entry<'a>(&self, map: &'a mut Shard<K>, key: &K) -> Option<&'a mut Entry> {
+        let now = Instant::now();
+        if map.get(key)?.is_expired(now) {
+            self.remove_entry(map, key);
+            return None;
+        }
+        let entry = map.get_mut(key)?;
+        entry.record_access(now);
+        Some(entry)
+    }
+
+    /// Same as `live_entry`, but missing key is first created with value made by `value`.
+    fn live_entry_or_insert<'a>(
+        &self,
+        map: &'a mut Shard<K>,
+        key: K,
+        value: impl FnOnce() -> Value,
+    ) -> &'a mut Entry {
+        if self.live_entry(map, &key).is_none() {
+            let entry = self.sized_entry(&key, value());
+            map.insert(key.clone(), entry, self.scan_position(&key));
+        }
+        map.get_mut(&key).expect("entry should be live")
+    }
+
+    /// New persistent entry of `value` under `key`, with its size already accounted for.
+    fn sized_entry(&self, key: &K, value: Value) -> Entry {
+        let mut entry = self.new_entry(value, None);
+        entry.size = entry_size(key, &entry.value);
+        self.used.fetch_add(entry.size, Ordering::Relaxed);
+        entry
+    }
+
+    /// Inserts `entry` under `key` and accounts for its size, returns entry it replaced.
+    fn insert_entry(&self, map: &mut Shard<K>, key: K, mut entry: Entry) -> Option<Entry> {
+        entry.size = entry_size(&key, &entry.value);
+        self.used.fetch_add(entry.size, Ordering::Relaxed);
+        let position = self.scan_position(&key);
+        let old = map.insert(key, entry, position);
+        if let Some(old) = &old {
+            self.used.fetch_sub(old.size, Ordering::Relaxed);
+        }
+        old
+    }
+
+    fn remove_entry(&self, map: &mut Shard<K>, key: &K) -> Option<Entry> {
+        let old = map.remove(key)?;
+        self.used.fetch_sub(old.size, Ordering::Relaxed);
+        Some(old)
+    }
+
+    /// Changes recorded size of `entry` to `size`.
+    fn resize(&self, entry: &mut Entry, size: usize) {
+        if size >= entry.size {
+            self.used.fetch_add(size - entry.size, Ordering::Relaxed);
+        } else {
+            self.used.fetch_sub(entry.size - size, Ordering::Relaxed);
         }
+        entry.size = size;
+    }
+
+    /// Updates size of `entry` whose value grew by `added` and shrank by `removed` bytes, so
+    /// large collections don't have to be measured again after every change.
+    fn grow(&self, entry: &mut Entry, added: usize, removed: usize) {
+        self.resize(entry, entry.size + added - removed);
+    }
+
+    /// Lets command run alongside others. Held for as long as the guard lives, so transaction
+    /// can't start in the middle of it.
+    pub fn shared_access(&self) -> RwLockReadGuard<'_, ()> {
+        self.access.read().unwrap()
+    }
+
+    /// Waits for running commands to finish and keeps others from starting until the guard is
+    /// dropped, so everything done in the meantime is atomic.
+    pub fn exclusive_access(&self) -> RwLockWriteGuard<'_, ()> {
+        self.access.write().unwrap()
+    }
+
+    /// Returns current version of `key`, or `None` if it doesn't exist. Version changes with
+    /// every write to the key, so comparing it tells whether key was modified in between.
+    /// Key that is created and removed again in the meantime is not detected.
+    pub fn version(&self, key: &K) -> Option<u64> {
+        let mut lock = self.shard(key);
+        self.live_entry(&mut lock, key).map(|entry| entry.version)
+    }
+
+    /// Locks every shard that any of `keys` lives in.
+    fn lock_shards<'k>(&self, keys: impl IntoIterator<Item = &'k K>) -> ShardGuards<'_, K>
+    where
+        K: 'k,
+    {
+        let indexes: BTreeSet<_> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+        // BTreeSet iterates in ascending order, so locks are always taken in the same order
+        let guards = indexes
+            .into_iter()
+            .map(|index| (index, self.shards[index].lock().unwrap()))
+            .collect();
+        ShardGuards { db: self, guards }
     }
 
     pub fn get(&self, key: &K) -> Option<Value> {
-        let lock = self.map.lock().unwrap();
-        lock.get(key).cloned()
+        let mut lock = self.shard(key);
+        self.live_entry(&mut lock, key)
+            .map(|entry| entry.value.clone())
     }
 
     pub fn set(&self, key: K, value: Value) -> Option<Value> {
-        let mut lock = self.map.lock().unwrap();
-        lock.insert(key, value)
+        let entry = self.new_entry(value, None);
+        let mut lock = self.shard(&key);
+        self.insert_entry(&mut lock, key, entry)
+            .filter(|old| !old.is_expired(Instant::now()))
+            .map(|old| old.value)
+    }
+
+    /// Same as `set`, but entry is removed after `expire` passes. Replaces previous expiry of
+    /// the key, so setting without it makes key persistent again.
+    pub fn set_with_expiry(
+        &self,
+        key: K,
+        value: Value,
+        expire: Option<Duration>,
+    ) -> Result<Option<Value>, Error> {
+        let entry = self.new_entry(value, deadline(expire)?);
+        let mut lock = self.shard(&key);
+        Ok(self
+            .insert_entry(&mut lock, key, entry)
+            .filter(|old| !old.is_expired(Instant::now()))
+            .map(|old| old.value))
+    }
+
+    /// Sets `key` only if it doesn't exist. Returns `true` if value was written.
+    pub fn set_nx(&self, key: K, value: Value, expire: Option<Duration>) -> Result<bool, Error> {
+        let entry = self.new_entry(value, deadline(expire)?);
+        let mut lock = self.shard(&key);
+        if self.live_entry(&mut lock, &key).is_some() {
+            return Ok(false);
+        }
+        self.insert_entry(&mut lock, key, entry);
+        Ok(true)
+    }
+
+    /// Sets `key` only if it already exists. Returns `true` if value was written.
+    pub fn set_xx(&self, key: K, value: Value, expire: Option<Duration>) -> Result<bool, Error> {
+        let entry = self.new_entry(value, deadline(expire)?);
+        let mut lock = self.shard(&key);
+        if self.live_entry(&mut lock, &key).is_none() {
+            return Ok(false);
+        }
+        self.insert_entry(&mut lock, key, entry);
+        Ok(true)
+    }
+
+    /// Replaces value of `key` only if it currently equals `expected`. Expiry of the key is
+    /// kept. Returns `true` if value was swapped.
+    pub fn compare_and_swap(&self, key: &K, expected: &Value, value: Value) -> bool {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) if entry.value == *expected => {
+                self.resize(entry, entry_size(key, &value));
+                entry.value = value;
+                entry.version = self.next_version();
+                true
+            }
+            _ => false,
+        }
     }
 
     pub fn delete(&self, key: &K) -> Option<Value> {
-        let mut lock = self.map.lock().unwrap();
-        lock.remove(key)
+        let mut lock = self.shard(key);
+        self.remove_entry(&mut lock, key)
+            .filter(|old| !old.is_expired(Instant::now()))
+            .map(|old| old.value)
+    }
+
+    /// Sets timeout on existing key. Returns `false` if key doesn't exist.
+    pub fn expire(&self, key: &K, expire: Duration) -> Result<bool, Error> {
+        let expires_at = deadline(Some(expire))?;
+        let mut lock = self.shard(key);
+        Ok(match self.live_entry(&mut lock, key) {
+            Some(entry) => {
+                entry.expires_at = expires_at;
+                entry.version = self.next_version();
+                lock.sync_volatile(key);
+                true
+            }
+            None => false,
+        })
+    }
+
+    /// Returns `None` if key doesn't exist, `Some(None)` if it exists but never expires and
+    /// `Some(Some(ttl))` with remaining time to live otherwise.
+    pub fn ttl(&self, key: &K) -> Option<Option<Duration>> {
+        let mut lock = self.shard(key);
+        self.live_entry(&mut lock, key).map(|entry| {
+            entry
+                .expires_at
+                .map(|at| at.saturating_duration_since(Instant::now()))
+        })
+    }
+
+    /// Removes timeout from key. Returns `true` only if key existed and had timeout.
+    pub fn persist(&self, key: &K) -> bool {
+        let mut lock = self.shard(key);
+        let persisted = self.live_entry(&mut lock, key).is_some_and(|entry| {
+            let persisted = entry.expires_at.take().is_some();
+            if persisted {
+                entry.version = self.next_version();
+            }
+            persisted
+        });
+        lock.sync_volatile(key);
+        persisted
+    }
+
+    /// Adds `delta` to number stored under `key` and returns the result. Missing key is
+    /// treated as 0, string holding an integer is converted to number. Expiry is kept.
+    pub fn incr_by(&self, key: K, delta: i64) -> Result<i64, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Number(delta), None));
+            return Ok(delta);
+        };
+
+        let new = add(&entry.value, delta)?;
+        entry.value = Value::Number(new);
+        self.resize(entry, entry_size(&key, &entry.value));
+        entry.version = self.next_version();
+
+        Ok(new)
+    }
+
+    /// Adds `delta` to number under `key`, missing key counts as 0. Result is always stored as
+    /// float and must stay finite.
+    pub fn incr_by_float(&self, key: K, delta: f64) -> Result<f64, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            let new = add_float(&Value::Number(0), delta)?;
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Float(new), None));
+            return Ok(new);
+        };
+
+        let new = add_float(&entry.value, delta)?;
+        entry.value = Value::Float(new);
+        self.resize(entry, entry_size(&key, &entry.value));
+        entry.version = self.next_version();
+
+        Ok(new)
+    }
+
+    /// Sets `fields` of hash under `key`, creating it if it doesn't exist. Returns number of
+    /// fields that were added rather than updated.
+    pub fn hset(&self, key: K, fields: HashMap<String, Value>) -> Result<usize, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            // hash without fields mustn't be left behind
+            if fields.is_empty() {
+                return Ok(0);
+            }
+            let added = fields.len();
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Map(fields), None));
+            return Ok(added);
+        };
+
+        let map = as_map(&mut entry.value)?;
+        let mut added = 0;
+        let (mut grown, mut shrunk) = (0, 0);
+        for (field, value) in fields {
+            match map.get(&field) {
+                Some(old) => shrunk += field_encoded_len(&field, old),
+                None => added += 1,
+            }
+            grown += field_encoded_len(&field, &value);
+            map.insert(field, value);
+        }
+        self.grow(entry, grown, shrunk);
+        entry.version = self.next_version();
+
+        Ok(added)
+    }
+
+    pub fn hget(&self, key: &K, field: &str) -> Result<Option<Value>, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_map(&mut entry.value)?.get(field).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes `fields` from hash and returns how many of them existed. Hash without fields is
+    /// removed.
+    pub fn hdel(&self, key: &K, fields: &[String]) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(0);
+        };
+
+        let map = as_map(&mut entry.value)?;
+        let mut removed = 0;
+        let mut shrunk = 0;
+        for field in fields {
+            if let Some(old) = map.remove(field) {
+                removed += 1;
+                shrunk += field_encoded_len(field, &old);
+            }
+        }
+        let is_empty = map.is_empty();
+        if removed > 0 {
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(removed)
+    }
+
+    /// Returns all fields of hash, empty if it doesn't exist.
+    pub fn hgetall(&self, key: &K) -> Result<HashMap<String, Value>, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_map(&mut entry.value)?.clone()),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    /// Same as `incr_by`, but for a field of hash.
+    pub fn hincr_by(&self, key: K, field: String, delta: i64) -> Result<i64, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            let map = HashMap::from([(field, Value::Number(delta))]);
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Map(map), None));
+            return Ok(delta);
+        };
+
+        let map = as_map(&mut entry.value)?;
+        let (new, shrunk) = match map.get(&field) {
+            Some(value) => (add(value, delta)?, field_encoded_len(&field, value)),
+            None => (delta, 0),
+        };
+        let value = Value::Number(new);
+        let grown = field_encoded_len(&field, &value);
+        map.insert(field, value);
+        self.grow(entry, grown, shrunk);
+        entry.version = self.next_version();
+
+        Ok(new)
+    }
+
+    pub fn hexists(&self, key: &K, field: &str) -> Result<bool, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_map(&mut entry.value)?.contains_key(field)),
+            None => Ok(false),
+        }
+    }
+
+    /// Pushes `values` one by one to the front (or back if `back` is set) of list under `key`,
+    /// creating it if needed. Returns length of the list.
+    pub fn push(&self, key: K, values: Vec<Value>, back: bool) -> Result<usize, Error> {
+        let mut lock = self.shard(&key);
+        // empty list mustn't be left behind
+        if values.is_empty() {
+            return match self.live_entry(&mut lock, &key) {
+                Some(entry) => Ok(as_list(&mut entry.value)?.len()),
+                None => Ok(0),
+            };
+        }
+        // woken connection can't pop before the shard is unlocked, so it may be woken first
+        self.notifier.wake(&key);
+
+        let entry = self.live_entry_or_insert(&mut lock, key, || Value::List(VecDeque::new()));
+
+        let list = as_list(&mut entry.value)?;
+        let grown = values.iter().map(Value::encoded_len).sum();
+        for value in values {
+            if back {
+                list.push_back(value);
+            } else {
+                list.push_front(value);
+            }
+        }
+        let len = list.len();
+        self.grow(entry, grown, 0);
+        entry.version = self.next_version();
+
+        Ok(len)
+    }
+
+    /// Removes first (or last if `back` is set) element of list. Empty list is removed.
+    pub fn pop(&self, key: &K, back: bool) -> Result<Option<Value>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(None);
+        };
+
+        let list = as_list(&mut entry.value)?;
+        let value = if back {
+            list.pop_back()
+        } else {
+            list.pop_front()
+        };
+        let is_empty = list.is_empty();
+        if let Some(value) = &value {
+            self.grow(entry, 0, value.encoded_len());
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(value)
+    }
+
+    /// Returns elements between `start` and `stop` (inclusive).
+    pub fn lrange(&self, key: &K, start: i64, stop: i64) -> Result<Vec<Value>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(Vec::new());
+        };
+
+        let list = as_list(&mut entry.value)?;
+        Ok(match list_range(start, stop, list.len()) {
+            Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    pub fn llen(&self, key: &K) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_list(&mut entry.value)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Keeps only elements between `start` and `stop` (inclusive). List left empty is removed.
+    pub fn ltrim(&self, key: &K, start: i64, stop: i64) -> Result<(), Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(());
+        };
+
+        let list = as_list(&mut entry.value)?;
+        let len = list.len();
+        match list_range(start, stop, len) {
+            Some((start, stop)) => {
+                list.truncate(stop + 1);
+                list.drain(..start);
+            }
+            None => list.clear(),
+        }
+        let is_empty = list.is_empty();
+        if list.len() < len {
+            self.resize(entry, entry_size(key, &entry.value));
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(())
+    }
+
+    pub fn lindex(&self, key: &K, index: i64) -> Result<Option<Value>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(None);
+        };
+
+        let list = as_list(&mut entry.value)?;
+        Ok(list_index(index, list.len()).map(|index| list[index].clone()))
+    }
+
+    pub fn lset(&self, key: &K, index: i64, value: Value) -> Result<(), Error> {
+        let mut lock = self.shard(key);
+        let entry = self.live_entry(&mut lock, key).ok_or(Error::NoSuchKey)?;
+
+        let list = as_list(&mut entry.value)?;
+        let index = list_index(index, list.len()).ok_or(Error::OutOfRange)?;
+        let grown = value.encoded_len();
+        let old = std::mem::replace(&mut list[index], value);
+        self.grow(entry, grown, old.encoded_len());
+        entry.version = self.next_version();
+
+        Ok(())
+    }
+
+    /// Adds `members` to set under `key`, creating it if needed. Returns number of members that
+    /// weren't there before.
+    pub fn sadd(&self, key: K, members: Vec<Member>) -> Result<usize, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            // set without members mustn't be left behind
+            if members.is_empty() {
+                return Ok(0);
+            }
+            let set: HashSet<_> = members.into_iter().collect();
+            let added = set.len();
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Set(set), None));
+            return Ok(added);
+        };
+
+        let set = as_set(&mut entry.value)?;
+        let mut added = 0;
+        let mut grown = 0;
+        for member in members {
+            let size = member.encoded_len();
+            if set.insert(member) {
+                added += 1;
+                grown += size;
+            }
+        }
+        self.grow(entry, grown, 0);
+        entry.version = self.next_version();
+
+        Ok(added)
+    }
+
+    /// Removes `members` from set, returns how many of them were there. Empty set is removed.
+    pub fn srem(&self, key: &K, members: &[Member]) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(0);
+        };
+
+        let set = as_set(&mut entry.value)?;
+        let mut removed = 0;
+        let mut shrunk = 0;
+        for member in members {
+            if set.remove(member) {
+                removed += 1;
+                shrunk += member.encoded_len();
+            }
+        }
+        let is_empty = set.is_empty();
+        if removed > 0 {
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(removed)
+    }
+
+    pub fn sismember(&self, key: &K, member: &Member) -> Result<bool, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_set(&mut entry.value)?.contains(member)),
+            None => Ok(false),
+        }
+    }
+
+    pub fn smembers(&self, key: &K) -> Result<HashSet<Member>, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_set(&mut entry.value)?.clone()),
+            None => Ok(HashSet::new()),
+        }
+    }
+
+    pub fn scard(&self, key: &K) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_set(&mut entry.value)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes and returns random member of set. Empty set is removed.
+    pub fn spop(&self, key: &K) -> Result<Option<Member>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(None);
+        };
+
+        let set = as_set(&mut entry.value)?;
+        let Some(member) = random_member(set).cloned() else {
+            return Ok(None);
+        };
+        set.remove(&member);
+        let is_empty = set.is_empty();
+        self.grow(entry, 0, member.encoded_len());
+        entry.version = self.next_version();
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(Some(member))
+    }
+
+    pub fn srandmember(&self, key: &K) -> Result<Option<Member>, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(random_member(as_set(&mut entry.value)?).cloned()),
+            None => Ok(None),
+        }
+    }
+
+    /// Combines sets under `keys` with `op`, reading all of them at once.
+    pub fn combine(&self, keys: &[K], op: SetOp) -> Result<HashSet<Member>, Error> {
+        let mut guards = self.lock_shards(keys);
+        Ok(op.apply(guards.sets(keys)?))
+    }
+
+    /// Same as `combine`, but result replaces `destination`. Empty result removes it. Returns
+    /// size of the result.
+    pub fn combine_store(&self, destination: K, keys: &[K], op: SetOp) -> Result<usize, Error> {
+        let mut guards = self.lock_shards(keys.iter().chain([&destination]));
+        let result = op.apply(guards.sets(keys)?);
+        let len = result.len();
+
+        let map = guards.map(&destination);
+        if result.is_empty() {
+            self.remove_entry(map, &destination);
+        } else {
+            self.insert_entry(map, destination, self.new_entry(Value::Set(result), None));
+        }
+        Ok(len)
+    }
+
+    /// Adds `members` to sorted set under `key` or updates their scores, creating the set if
+    /// needed. Returns number of members that weren't there before.
+    pub fn zadd(&self, key: K, members: SortedSet) -> Result<usize, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            // sorted set without members mustn't be left behind
+            if members.is_empty() {
+                return Ok(0);
+            }
+            let added = members.len();
+            self.insert_entry(
+                &mut lock,
+                key,
+                self.new_entry(Value::SortedSet(members), None),
+            );
+            return Ok(added);
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let mut added = 0;
+        let mut grown = 0;
+        for (member, score) in members.iter() {
+            if set.insert(member.clone(), score) {
+                added += 1;
+                grown += member.encoded_len() + 8;
+            }
+        }
+        self.grow(entry, grown, 0);
+        entry.version = self.next_version();
+
+        Ok(added)
+    }
+
+    /// Removes `members`, returns how many of them were there. Empty sorted set is removed.
+    pub fn zrem(&self, key: &K, members: &[Member]) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(0);
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let mut removed = 0;
+        let mut shrunk = 0;
+        for member in members {
+            if set.remove(member).is_some() {
+                removed += 1;
+                shrunk += member.encoded_len() + 8;
+            }
+        }
+        let is_empty = set.is_empty();
+        if removed > 0 {
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(removed)
+    }
+
+    pub fn zscore(&self, key: &K, member: &Member) -> Result<Option<f64>, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_sorted_set(&mut entry.value)?.score(member)),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds `delta` to score of `member`, which starts from 0 if it's missing. Returns the new
+    /// score.
+    pub fn zincr_by(&self, key: K, member: Member, delta: f64) -> Result<f64, Error> {
+        let mut lock = self.shard(&key);
+
+        let entry =
+            self.live_entry_or_insert(&mut lock, key, || Value::SortedSet(SortedSet::new()));
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let score = set.score(&member).unwrap_or(0.0) + delta;
+        if score.is_nan() {
+            return Err(Error::NanScore);
+        }
+        let size = member.encoded_len() + 8;
+        if set.insert(member, score) {
+            self.grow(entry, size, 0);
+        }
+        entry.version = self.next_version();
+
+        Ok(score)
+    }
+
+    /// Position of `member` counted from the lowest score, or the highest if `reverse` is set.
+    pub fn zrank(&self, key: &K, member: &Member, reverse: bool) -> Result<Option<usize>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(None);
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        Ok(set
+            .rank(member)
+            .map(|rank| if reverse { set.len() - 1 - rank } else { rank }))
+    }
+
+    /// Returns members between ranks `start` and `stop` (inclusive) with their scores.
+    pub fn zrange(
+        &self,
+        key: &K,
+        start: i64,
+        stop: i64,
+        reverse: bool,
+    ) -> Result<Vec<(Member, f64)>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(Vec::new());
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let Some((start, stop)) = list_range(start, stop, set.len()) else {
+            return Ok(Vec::new());
+        };
+        let members = set.iter().map(|(member, score)| (member.clone(), score));
+        Ok(if reverse {
+            members.rev().skip(start).take(stop - start + 1).collect()
+        } else {
+            members.skip(start).take(stop - start + 1).collect()
+        })
+    }
+
+    /// Returns members with score between `min` and `max` (inclusive) with their scores. First
+    /// `offset` of them are skipped and at most `count` are returned, if it's given.
+    pub fn zrange_by_score(
+        &self,
+        key: &K,
+        min: f64,
+        max: f64,
+        offset: usize,
+        count: Option<usize>,
+        reverse: bool,
+    ) -> Result<Vec<(Member, f64)>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(Vec::new());
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let members = set
+            .range_by_score(min, max)
+            .map(|(member, score)| (member.clone(), score));
+        let count = count.unwrap_or(usize::MAX);
+        Ok(if reverse {
+            members.rev().skip(offset).take(count).collect()
+        } else {
+            members.skip(offset).take(count).collect()
+        })
+    }
+
+    pub fn zcard(&self, key: &K) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_sorted_set(&mut entry.value)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes up to `count` members with the lowest scores, or the highest if `max` is set.
+    /// Empty sorted set is removed.
+    pub fn zpop(&self, key: &K, count: usize, max: bool) -> Result<Vec<(Member, f64)>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(Vec::new());
+        };
+
+        let set = as_sorted_set(&mut entry.value)?;
+        let popped: Vec<_> = (0..count).map_while(|_| set.pop(max)).collect();
+        let is_empty = set.is_empty();
+        let shrunk = popped
+            .iter()
+            .map(|(member, _)| member.encoded_len() + 8)
+            .sum();
+        if !popped.is_empty() {
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+
+        if is_empty {
+            self.remove_entry(&mut lock, key);
+        }
+        Ok(popped)
+    }
+
+    /// Appends entry to stream under `key`, creating it if needed. Returns id of the entry.
+    pub fn xadd(&self, key: K, id: Option<StreamId>, fields: Fields) -> Result<StreamId, Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            let mut stream = Stream::new();
+            let id = stream.add(id, fields)?;
+            // woken connections can't read before the shard is unlocked
+            self.notifier.wake_all(&key);
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Stream(stream), None));
+            return Ok(id);
+        };
+
+        let grown = stream_entry_encoded_len(&fields);
+        let id = as_stream(&mut entry.value)?.add(id, fields)?;
+        self.grow(entry, grown, 0);
+        entry.version = self.next_version();
+        self.notifier.wake_all(&key);
+
+        Ok(id)
+    }
+
+    /// Entries with id between `start` and `end` (inclusive), at most `count` of them.
+    pub fn xrange(
+        &self,
+        key: &K,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        reverse: bool,
+    ) -> Result<Vec<(StreamId, Fields)>, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(Vec::new());
+        };
+
+        let stream = as_stream(&mut entry.value)?;
+        let entries = stream
+            .range(start, end)
+            .map(|(id, fields)| (*id, fields.clone()));
+        let count = count.unwrap_or(usize::MAX);
+        Ok(if reverse {
+            entries.rev().take(count).collect()
+        } else {
+            entries.take(count).collect()
+        })
+    }
+
+    pub fn xlen(&self, key: &K) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_stream(&mut entry.value)?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes the oldest entries, so that at most `max_len` remain. Unlike other collections,
+    /// stream is kept when it becomes empty, together with its groups and last id.
+    pub fn xtrim(&self, key: &K, max_len: usize) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(0);
+        };
+
+        let stream = as_stream(&mut entry.value)?;
+        let shrunk = stream
+            .range(StreamId::MIN, StreamId::MAX)
+            .take(stream.len().saturating_sub(max_len))
+            .map(|(_, fields)| stream_entry_encoded_len(fields))
+            .sum();
+        let removed = stream.trim(max_len);
+        if removed > 0 {
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+        Ok(removed)
+    }
+
+    /// Id of the last entry added to stream under `key`, `StreamId::MIN` if it doesn't exist.
+    pub fn xlast_id(&self, key: &K) -> Result<StreamId, Error> {
+        let mut lock = self.shard(key);
+        match self.live_entry(&mut lock, key) {
+            Some(entry) => Ok(as_stream(&mut entry.value)?.last_id()),
+            None => Ok(StreamId::MIN),
+        }
+    }
+
+    /// Creates consumer `group` of stream under `key`, which must exist unless `create` is set.
+    pub fn xgroup_create(
+        &self,
+        key: K,
+        group: String,
+        id: Option<StreamId>,
+        create: bool,
+    ) -> Result<(), Error> {
+        let mut lock = self.shard(&key);
+
+        let Some(entry) = self.live_entry(&mut lock, &key) else {
+            if !create {
+                return Err(Error::NoSuchKey);
+            }
+            let mut stream = Stream::new();
+            stream.create_group(group, id)?;
+            self.insert_entry(&mut lock, key, self.new_entry(Value::Stream(stream), None));
+            return Ok(());
+        };
+
+        let grown = group_encoded_len(&group);
+        as_stream(&mut entry.value)?.create_group(group, id)?;
+        self.grow(entry, grown, 0);
+        entry.version = self.next_version();
+        Ok(())
+    }
+
+    /// See `Stream::read_group`. Missing key is reported the same way as missing group.
+    pub fn xread_group(
+        &self,
+        key: &K,
+        group: &str,
+        consumer: &str,
+        id: Option<StreamId>,
+        count: Option<usize>,
+    ) -> Result<Vec<(StreamId, Option<Fields>)>, Error> {
+        let mut lock = self.shard(key);
+        let entry = self.live_entry(&mut lock, key).ok_or(Error::NoGroup)?;
+
+        let stream = as_stream(&mut entry.value)?;
+        let read = stream.read_group(group, consumer, id, count.unwrap_or(usize::MAX))?;
+        if !read.is_empty() {
+            // only entries that weren't delivered yet become pending
+            if id.is_none() {
+                self.grow(entry, read.len() * pending_encoded_len(consumer), 0);
+            }
+            entry.version = self.next_version();
+        }
+        Ok(read)
+    }
+
+    /// Acknowledges pending entries of `group`, returns how many of them were pending.
+    pub fn xack(&self, key: &K, group: &str, ids: &[StreamId]) -> Result<usize, Error> {
+        let mut lock = self.shard(key);
+        let Some(entry) = self.live_entry(&mut lock, key) else {
+            return Ok(0);
+        };
+
+        let acked = as_stream(&mut entry.value)?.ack(group, ids)?;
+        if !acked.is_empty() {
+            let shrunk = acked
+                .iter()
+                .map(|pending| pending_encoded_len(&pending.consumer))
+                .sum();
+            self.grow(entry, 0, shrunk);
+            entry.version = self.next_version();
+        }
+        Ok(acked.len())
+    }
+
+    /// Pending entries of `group` with id between `start` and `end`, at most `count` of them.
+    pub fn xpending(
+        &self,
+        key: &K,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        consumer: Option<&str>,
+    ) -> Result<Vec<(StreamId, Pending)>, Error> {
+        let mut lock = self.shard(key);
+        let entry = self.live_entry(&mut lock, key).ok_or(Error::NoGroup)?;
+
+        let stream = as_stream(&mut entry.value)?;
+        Ok(stream
+            .pending(group, start, end, consumer)?
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, pending)| (*id, pending.clone()))
+            .collect())
+    }
+
+    /// Puts connection at the end of waiting queues of `keys`, so it's woken by pushes to them.
+    /// Keys should be checked after blocking, otherwise a push that came just before could be
+    /// missed.
+    pub fn block(&self, keys: Vec<K>) -> Blocked<'_, K> {
+        let waiter = Arc::new(Waiter::default());
+        let mut waiting = self.notifier.waiting.lock().unwrap();
+        for key in &keys {
+            waiting
+                .entry(key.clone())
+                .or_default()
+                .push_back(waiter.clone());
+        }
+        Blocked {
+            db: self,
+            keys,
+            waiter,
+        }
+    }
+
+    /// Reads all `keys` at once, so result is consistent even with concurrent writes.
+    pub fn mget(&self, keys: &[K]) -> Vec<Option<Value>> {
+        let mut guards = self.lock_shards(keys);
+        keys.iter()
+            .map(|key| {
+                self.live_entry(guards.map(key), key)
+                    .map(|entry| entry.value.clone())
+            })
+            .collect()
+    }
+
+    /// Sets all `entries` atomically and returns their previous values.
+    pub fn mset(&self, entries: Vec<(K, Value)>) -> Vec<Option<Value>> {
+        let mut guards = self.lock_shards(entries.iter().map(|(key, _)| key));
+        let now = Instant::now();
+
+        entries
+            .into_iter()
+            .map(|(key, value)| {
+                let map = guards.map(&key);
+                self.insert_entry(map, key, self.new_entry(value, None))
+                    .filter(|old| !old.is_expired(now))
+                    .map(|old| old.value)
+            })
+            .collect()
+    }
+
+    /// Deletes all `keys` atomically and returns their values.
+    pub fn mdelete(&self, keys: &[K]) -> Vec<Option<Value>> {
+        let mut guards = self.lock_shards(keys);
+        let now = Instant::now();
+        keys.iter()
+            .map(|key| {
+                self.remove_entry(guards.map(key), key)
+                    .filter(|old| !old.is_expired(now))
+                    .map(|old| old.value)
+            })
+            .collect()
+    }
+
+    /// Returns how many of `keys` exist. Repeated keys are counted multiple times.
+    pub fn exists(&self, keys: &[K]) -> usize {
+        let mut guards = self.lock_shards(keys);
+        keys.iter()
+            .filter(|key| self.live_entry(guards.map(key), key).is_some())
+            .count()
+    }
+
+    /// Returns all live keys that `filter` accepts. Shards are read one by one, so keys
+    /// written in the meantime may or may not be included.
+    pub fn keys(&self, filter: impl Fn(&K) -> bool) -> Vec<K> {
+        let now = Instant::now();
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                let lock = shard.lock().unwrap();
+                lock.iter()
+                    .filter(|(key, entry)| !entry.is_expired(now) && filter(key))
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Preferred scan position of `key`, see `Shard`.
+    fn scan_position(&self, key: &K) -> u64 {
+        self.hasher.hash_one(key) >> (u64::BITS - SCAN_POSITION_BITS)
+    }
+
+    /// Walks about `count` keys starting at `cursor` (0 to start from the beginning) and
+    /// returns those that `filter` accepts together with cursor to continue from, which is 0
+    /// once all keys were visited. Only one shard is locked at a time.
+    ///
+    /// Keys that exist for the whole scan are returned at least once, keys added or removed in
+    /// the meantime may or may not be.
+    pub fn scan(&self, cursor: u64, count: usize, filter: impl Fn(&K) -> bool) -> (u64, Vec<K>) {
+        let count = count.max(1);
+        let mut shard = (cursor >> SCAN_POSITION_BITS) as usize;
+        let mut start = cursor & SCAN_POSITION_MASK;
+        let mut keys = Vec::new();
+        let mut visited = 0;
+
+        while shard < self.shards.len() {
+            let lock = self.shards[shard].lock().unwrap();
+            let now = Instant::now();
+            // key keeps its position while it exists, so none is skipped between calls
+            for (&position, key) in lock.by_position.range(start..) {
+                if visited == count {
+                    return (((shard as u64) << SCAN_POSITION_BITS) | position, keys);
+                }
+                visited += 1;
+                if !lock[key].is_expired(now) && filter(key) {
+                    keys.push(key.clone());
+                }
+            }
+            shard += 1;
+            start = 0;
+            if visited >= count {
+                break;
+            }
+        }
+
+        if shard >= self.shards.len() {
+            return (0, keys);
+        }
+        ((shard as u64) << SCAN_POSITION_BITS, keys)
+    }
+
+    /// Returns name of the type of value under `key`, see `Value::type_name`.
+    pub fn type_of(&self, key: &K) -> Option<&'static str> {
+        let mut lock = self.shard(key);
+        self.live_entry(&mut lock, key)
+            .map(|entry| entry.value.type_name())
+    }
+
+    /// Moves value under `key` to `new_key`, together with its expiry. Existing `new_key` is
+    /// overwritten only if `replace` is set, returns whether value was moved.
+    pub fn rename(&self, key: &K, new_key: K, replace: bool) -> Result<bool, Error> {
+        let mut guards = self.lock_shards([key, &new_key]);
+        if self.live_entry(guards.map(key), key).is_none() {
+            return Err(Error::NoSuchKey);
+        }
+        if key == &new_key {
+            return Ok(replace);
+        }
+        if !replace && self.live_entry(guards.map(&new_key), &new_key).is_some() {
+            return Ok(false);
+        }
+
+        let mut entry = self
+            .remove_entry(guards.map(key), key)
+            .expect("entry should be live");
+        entry.version = self.next_version();
+        // same as push, connections blocked on the new key can't read before shards unlock
+        match entry.value {
+            Value::List(_) => self.notifier.wake(&new_key),
+            Value::Stream(_) => self.notifier.wake_all(&new_key),
+            _ => {}
+        }
+        let map = guards.map(&new_key);
+        self.insert_entry(map, new_key, entry);
+        Ok(true)
+    }
+
+    /// Number of live keys. Shards are counted one by one, so it's only exact when nothing
+    /// is written in the meantime.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.shards
+            .iter()
+            .map(|shard| {
+                let lock = shard.lock().unwrap();
+                lock.values().filter(|entry| !entry.is_expired(now)).count()
+            })
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes all keys.
+    pub fn flush(&self) {
+        // all shards are locked first, so nobody sees some of them already empty
+        let mut locks: Vec<_> = self.shards.iter().map(|s| s.lock().unwrap()).collect();
+        for lock in &mut locks {
+            lock.clear();
+        }
+        self.used.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns copy of all live entries together with their remaining time to live. Caller
+    /// must hold `exclusive_access`, so that the copy is consistent point-in-time view even
+    /// though shards are copied one by one.
+    pub fn entries(&self) -> Vec<(K, Value, Option<Duration>)> {
+        let now = Instant::now();
+        let mut entries = Vec::new();
+        for shard in &self.shards {
+            let lock = shard.lock().unwrap();
+            entries.extend(lock.iter().filter(|(_, entry)| !entry.is_expired(now)).map(
+                |(key, entry)| {
+                    let ttl = entry.expires_at.map(|at| at.saturating_duration_since(now));
+                    (key.clone(), entry.value.clone(), ttl)
+                },
+            ));
+        }
+        entries
+    }
+
+    /// Same as `entries` for callers that don't hold access yet. Commands are held off only
+    /// while entries are copied.
+    pub fn capture_entries(&self) -> Vec<(K, Value, Option<Duration>)> {
+        let _exclusive = self.exclusive_access();
+        self.entries()
+    }
+
+    /// Drops all expired entries and returns how many were removed. Keys are also expired
+    /// lazily on access, this only reclaims memory of keys that nobody reads anymore.
+    pub fn remove_expired(&self) -> usize {
+        let now = Instant::now();
+        // shards are swept one by one, so the rest of them stay available in the meantime
+        self.shards
+            .iter()
+            .map(|shard| {
+                let mut lock = shard.lock().unwrap();
+                let before = lock.len();
+                lock.retain(|_, entry| {
+                    let expired = entry.is_expired(now);
+                    if expired {
+                        self.used.fetch_sub(entry.size, Ordering::Relaxed);
+                    }
+                    !expired
+                });
+                before - lock.len()
+            })
+            .sum()
+    }
+
+    /// Picks key to evict if used memory is over the limit, `None` if it isn't or there is no
+    /// limit. Fails with `Error::OutOfMemory` if policy doesn't allow eviction or no key can be
+    /// evicted.
+    ///
+    /// Same as redis it's approximate: only a few keys of a random shard are sampled and the
+    /// best of them according to the policy is picked.
+    pub fn eviction_candidate(&self) -> Result<Option<K>, Error> {
+        match self.max_memory {
+            Some(max_memory) if self.used_memory() > max_memory => {}
+            _ => return Ok(None),
+        }
+        if self.policy == EvictionPolicy::NoEviction {
+            return Err(Error::OutOfMemory);
+        }
+
+        let volatile = self.policy.is_volatile();
+        let first = random_index(self.shards.len());
+        let now = Instant::now();
+        for i in 0..self.shards.len() {
+            let lock = self.shards[(first + i) % self.shards.len()].lock().unwrap();
+            let keys = if volatile { &lock.volatile } else { &lock.keys };
+            if keys.is_empty() {
+                continue;
+            }
+
+            // few keys are all compared, otherwise every sample is picked on its own, so the
+            // same key may be compared twice
+            let sampled: Vec<_> = if keys.len() <= EVICTION_SAMPLES {
+                keys.iter().collect()
+            } else {
+                (0..EVICTION_SAMPLES)
+                    .map(|_| &keys[random_index(keys.len())])
+                    .collect()
+            };
+            let mut samples = sampled.into_iter().map(|key| (key, &lock[key]));
+            let picked = match self.policy {
+                EvictionPolicy::AllKeysLru | EvictionPolicy::VolatileLru => {
+                    samples.min_by_key(|(_, entry)| entry.accessed_at)
+                }
+                EvictionPolicy::AllKeysLfu => {
+                    samples.min_by_key(|(_, entry)| (entry.frequency(now), entry.accessed_at))
+                }
+                EvictionPolicy::VolatileTtl => samples.min_by_key(|(_, entry)| entry.expires_at),
+                EvictionPolicy::Random | EvictionPolicy::NoEviction => samples.next(),
+            };
+            return Ok(picked.map(|(key, _)| key.clone()));
+        }
+        Err(Error::OutOfMemory)
+    }
+}
+
+impl<K: Hash + Eq + Clone + AsRef<[u8]>> Default for Database<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_shards() {
+        let db = Arc::new(Database::with_shards(4));
+
+        let handles: Vec<_> = (0..8)
+            .map(|t| {
+                let db = db.clone();
+                thread::spawn(move || {
+                    for i in 0..1_000 {
+                        db.set(format!("{t}:{i}"), Value::Number(i));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(db.entries().len(), 8 * 1_000);
+        assert!(matches!(
+            db.get(&"7:999".to_string()),
+            Some(Value::Number(999))
+        ));
+        assert!(db.shards.iter().all(|s| !s.lock().unwrap().is_empty()));
+    }
+
+    #[test]
+    fn test_capture_waits_for_exclusive_access() {
+        let db = Arc::new(Database::with_shards(4));
+        let exclusive = db.exclusive_access();
+        let capture = {
+            let db = db.clone();
+            thread::spawn(move || db.capture_entries().len())
+        };
+
+        // both writes of the transaction end up in the copy, not just the first one
+        db.set("a".to_string(), Value::Number(1));
+        thread::sleep(Duration::from_millis(20));
+        db.set("b".to_string(), Value::Number(2));
+        drop(exclusive);
+
+        assert_eq!(capture.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_empty_input_leaves_no_key() {
+        let db = Database::with_shards(1);
+        let key = "key".to_string();
+        assert_eq!(db.push(key.clone(), Vec::new(), true).unwrap(), 0);
+        assert_eq!(db.hset(key.clone(), HashMap::new()).unwrap(), 0);
+        assert_eq!(db.sadd(key.clone(), Vec::new()).unwrap(), 0);
+        assert_eq!(db.zadd(key.clone(), SortedSet::new()).unwrap(), 0);
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn test_expiry_out_of_range() {
+        let db = Database::with_shards(1);
+        let key = "key".to_string();
+        assert!(matches!(
+            db.set_with_expiry(key.clone(), Value::Number(1), Some(Duration::MAX)),
+            Err(Error::InvalidExpire)
+        ));
+        db.set(key.clone(), Value::Number(2));
+        assert!(matches!(
+            db.expire(&key, Duration::MAX),
+            Err(Error::InvalidExpire)
+        ));
+        // shard is still usable and the key kept no expiry
+        assert_eq!(db.ttl(&key), Some(None));
+        assert!(matches!(db.get(&key), Some(Value::Number(2))));
+    }
+
+    #[test]
+    fn test_scan_visits_every_key() {
+        let db = Database::with_shards(4);
+        for i in 0..1_000 {
+            db.set(i.to_string(), Value::Number(i));
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, 7, |key: &String| !key.ends_with('0'));
+            for key in keys {
+                assert!(seen.insert(key), "key returned twice");
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            // writes between batches don't make scan skip keys that were there from the start
+            db.set(format!("new:{cursor}"), Value::Null);
+            db.delete(&format!("new:{cursor}"));
+        }
+
+        assert_eq!(seen.len(), 900);
+        assert_eq!(db.len(), 1_000);
+    }
+
+    #[test]
+    fn test_shard_positions_are_unique() {
+        let mut shard = Shard::new();
+        let entry = || Entry::new(Value::Null, None, 0);
+        shard.insert("a", entry(), 7);
+        shard.insert("b", entry(), 7);
+        // replacing entry keeps its position
+        shard.insert("b", entry(), 3);
+        shard.insert("c", entry(), SCAN_POSITION_MASK);
+        assert_eq!(shard["b"].position, 8);
+        assert_eq!(shard["c"].position, SCAN_POSITION_MASK);
+
+        shard.insert("d", entry(), SCAN_POSITION_MASK);
+        assert_eq!(shard["d"].position, 0);
+
+        shard.remove(&"a");
+        shard.retain(|key, _| *key != "c");
+        shard.insert("e", entry(), 7);
+        assert_eq!(
+            shard.by_position.into_iter().collect::<Vec<_>>(),
+            vec![(0, "d"), (7, "e"), (8, "b")]
+        );
+    }
+
+    #[test]
+    fn test_volatile_eviction_samples_keys_with_expiry() {
+        let db = Database::with_shards(1).with_memory_limit(1, EvictionPolicy::VolatileLru);
+        for i in 0..100 {
+            db.set(i.to_string(), Value::Number(i));
+        }
+        let key = "50".to_string();
+        db.expire(&key, Duration::from_secs(60)).unwrap();
+        for _ in 0..20 {
+            assert_eq!(db.eviction_candidate().unwrap(), Some(key.clone()));
+        }
+
+        // last key moves into the slot of the deleted one
+        db.delete(&"0".to_string());
+        db.persist(&key);
+        assert!(matches!(db.eviction_candidate(), Err(Error::OutOfMemory)));
+
+        let shard = db.shards[0].lock().unwrap();
+        assert_eq!(shard.keys.len(), 99);
+        assert!(shard.volatile.is_empty());
+        assert!(
+            shard
+                .keys
+                .iter()
+                .enumerate()
+                .all(|(slot, key)| shard[key].slot == slot)
+        );
+    }
+
+    #[test]
+    fn test_used_memory_follows_changes() {
+        let db = Database::with_shards(2);
+        let key = |name: &str| name.to_string();
+        let measured = |db: &Database<String>| -> usize {
+            db.entries()
+                .iter()
+                .map(|(key, value, _)| entry_size(key, value))
+                .sum()
+        };
+
+        db.set(key("string"), Value::String("value".into()));
+        db.push(key("list"), vec![Value::Number(1), "two".into()], true)
+            .unwrap();
+        db.lset(&key("list"), 0, Value::String("one".into()))
+            .unwrap();
+        db.pop(&key("list"), false).unwrap();
+        db.hset(key("hash"), HashMap::from([("a".into(), Value::Number(1))]))
+            .unwrap();
+        db.hset(key("hash"), HashMap::from([("a".into(), "longer".into())]))
+            .unwrap();
+        db.hincr_by(key("hash"), "b".into(), 2).unwrap();
+        db.sadd(key("set"), vec![Member::Number(1), Member::Number(2)])
+            .unwrap();
+        db.srem(&key("set"), &[Member::Number(1)]).unwrap();
+        db.zincr_by(key("zset"), Member::String("m".into()), 1.0)
+            .unwrap();
+        db.xadd(key("stream"), None, vec![("f".into(), Value::Number(1))])
+            .unwrap();
+        db.xgroup_create(key("stream"), "group".into(), Some(StreamId::MIN), false)
+            .unwrap();
+        db.xread_group(&key("stream"), "group", "alice", None, None)
+            .unwrap();
+        assert_eq!(db.used_memory(), measured(&db));
+
+        let id = db.xadd(key("stream"), None, Vec::new()).unwrap();
+        db.xread_group(&key("stream"), "group", "bob", None, None)
+            .unwrap();
+        db.xack(&key("stream"), "group", &[id]).unwrap();
+        db.xtrim(&key("stream"), 1).unwrap();
+        db.incr_by(key("string"), 1).unwrap_err();
+        db.rename(&key("string"), key("renamed"), true).unwrap();
+        db.delete(&key("set"));
+        assert_eq!(db.used_memory(), measured(&db));
+
+        db.flush();
+        assert_eq!(db.used_memory(), 0);
     }
 }
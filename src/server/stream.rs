@@ -0,0 +1,271 @@
+//! Append only log of entries, each made of field-value pairs and identified by a `StreamId`
+//! that only grows. Consumer groups read the log together: every entry is delivered to one
+//! consumer of the group and stays pending until the consumer acknowledges it.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{error::Error, utils::command::Value};
+
+/// Id of stream entry, milliseconds of unix time when it was added and sequence number of
+/// entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: Self = Self { ms: 0, seq: 0 };
+    pub const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Smallest id after this one, `None` for `StreamId::MAX`.
+    pub fn next(self) -> Option<Self> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// Largest id before this one, `None` for `StreamId::MIN`.
+    pub fn prev(self) -> Option<Self> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(Self::new(self.ms, seq)),
+            None => Some(Self::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Parses `ms-seq`, or just `ms` with sequence 0.
+impl FromStr for StreamId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ms, seq) = s.split_once('-').unwrap_or((s, "0"));
+        match (ms.parse(), seq.parse()) {
+            (Ok(ms), Ok(seq)) => Ok(Self::new(ms, seq)),
+            _ => Err(Error::InvalidStreamId),
+        }
+    }
+}
+
+/// Field-value pairs of a single entry, in order in which they were added.
+pub type Fields = Vec<(String, Value)>;
+
+/// Entry delivered to a consumer of a group, but not acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pending {
+    pub consumer: String,
+    /// Unix time in milliseconds of the last delivery.
+    pub delivered_at: u64,
+    pub deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConsumerGroup {
+    /// Id of the last entry delivered to any consumer, new reads continue after it.
+    pub last_delivered: StreamId,
+    pub pending: BTreeMap<StreamId, Pending>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    /// Id of the last added entry. Kept when entries are trimmed, so ids never repeat.
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+impl Stream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rebuilds stream out of its parts, used when decoding it.
+    pub fn from_parts(
+        entries: BTreeMap<StreamId, Fields>,
+        last_id: StreamId,
+        groups: BTreeMap<String, ConsumerGroup>,
+    ) -> Self {
+        Self {
+            entries,
+            last_id,
+            groups,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    pub fn groups(&self) -> impl ExactSizeIterator<Item = (&String, &ConsumerGroup)> {
+        self.groups.iter()
+    }
+
+    /// Appends entry under `id`, or under id generated from current time if it's `None`.
+    /// Explicit id has to be greater than id of any entry added before.
+    pub fn add(&mut self, id: Option<StreamId>, fields: Fields) -> Result<StreamId, Error> {
+        let id = match id {
+            Some(id) if id > self.last_id => id,
+            Some(_) => return Err(Error::StreamIdTooSmall),
+            // clock may go backwards, ids still have to grow
+            None => match unix_millis() {
+                ms if ms > self.last_id.ms => StreamId::new(ms, 0),
+                _ => self.last_id.next().ok_or(Error::StreamIdTooSmall)?,
+            },
+        };
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    /// Entries with id between `start` and `end` (inclusive), from the oldest.
+    pub fn range(
+        &self,
+        start: StreamId,
+        end: StreamId,
+    ) -> impl DoubleEndedIterator<Item = (&StreamId, &Fields)> {
+        // `range` doesn't accept end before start
+        let is_empty = start > end;
+        self.entries
+            .range(start..=end.max(start))
+            .filter(move |_| !is_empty)
+    }
+
+    /// Removes the oldest entries, so that at most `max_len` remain. Returns how many were
+    /// removed.
+    pub fn trim(&mut self, max_len: usize) -> usize {
+        let mut removed = 0;
+        while self.entries.len() > max_len {
+            self.entries.pop_first();
+            removed += 1;
+        }
+        removed
+    }
+
+    /// Creates group that delivers entries added after `id`, or only new entries if it's
+    /// `None`.
+    pub fn create_group(&mut self, name: String, id: Option<StreamId>) -> Result<(), Error> {
+        if self.groups.contains_key(&name) {
+            return Err(Error::GroupExists);
+        }
+        let group = ConsumerGroup {
+            last_delivered: id.unwrap_or(self.last_id),
+            pending: BTreeMap::new(),
+        };
+        self.groups.insert(name, group);
+        Ok(())
+    }
+
+    /// Reads entries for `consumer` of `group`. With `id` of `None` delivers entries that no
+    /// consumer of the group got yet and marks them as pending. Otherwise delivers again
+    /// pending entries of the consumer with id greater than `id`, entries that were trimmed in
+    /// the meantime have no fields.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        id: Option<StreamId>,
+        count: usize,
+    ) -> Result<Vec<(StreamId, Option<Fields>)>, Error> {
+        let group = self.groups.get_mut(group).ok_or(Error::NoGroup)?;
+        let now = unix_millis();
+
+        let Some(id) = id else {
+            let start = match group.last_delivered.next() {
+                Some(start) => start,
+                None => return Ok(Vec::new()),
+            };
+            let read: Vec<_> = self
+                .entries
+                .range(start..)
+                .take(count)
+                .map(|(id, fields)| (*id, Some(fields.clone())))
+                .collect();
+            for (id, _) in &read {
+                group.last_delivered = *id;
+                let pending = Pending {
+                    consumer: consumer.to_string(),
+                    delivered_at: now,
+                    deliveries: 1,
+                };
+                group.pending.insert(*id, pending);
+            }
+            return Ok(read);
+        };
+
+        let Some(start) = id.next() else {
+            return Ok(Vec::new());
+        };
+        Ok(group
+            .pending
+            .range_mut(start..)
+            .filter(|(_, pending)| pending.consumer == consumer)
+            .take(count)
+            .map(|(id, pending)| {
+                pending.delivered_at = now;
+                pending.deliveries += 1;
+                (*id, self.entries.get(id).cloned())
+            })
+            .collect())
+    }
+
+    /// Removes `ids` from pending entries of `group`, returns those that were pending.
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> Result<Vec<Pending>, Error> {
+        let group = self.groups.get_mut(group).ok_or(Error::NoGroup)?;
+        Ok(ids
+            .iter()
+            .filter_map(|id| group.pending.remove(id))
+            .collect())
+    }
+
+    /// Pending entries of `group` with id between `start` and `end` (inclusive), optionally
+    /// only those delivered to `consumer`.
+    pub fn pending(
+        &self,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        consumer: Option<&str>,
+    ) -> Result<impl Iterator<Item = (&StreamId, &Pending)>, Error> {
+        let group = self.groups.get(group).ok_or(Error::NoGroup)?;
+        let is_empty = start > end;
+        Ok(group
+            .pending
+            .range(start..=end.max(start))
+            .filter(move |(_, pending)| {
+                !is_empty && consumer.is_none_or(|consumer| pending.consumer == consumer)
+            }))
+    }
+}
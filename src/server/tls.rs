@@ -0,0 +1,58 @@
+//! TLS termination for the server. Once `Config::tls` is set, every connection of the
+//! listener has to start with TLS handshake, requests inside are the same as over plain TCP.
+
+use std::{path::PathBuf, sync::Arc};
+
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{ServerConfig, server::WebPkiClientVerifier},
+};
+
+use crate::{
+    error::Error,
+    utils::tls::{load_certs, load_key, load_roots},
+};
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file with certificate chain of the server.
+    pub cert_path: PathBuf,
+    /// PEM file with private key of the server certificate.
+    pub key_path: PathBuf,
+    /// PEM file with authorities that client certificates have to be signed by. When set,
+    /// clients without valid certificate are rejected (mutual TLS).
+    pub client_ca_path: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn new(cert_path: impl Into<PathBuf>, key_path: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_path: cert_path.into(),
+            key_path: key_path.into(),
+            client_ca_path: None,
+        }
+    }
+
+    /// Requires clients to present certificate signed by authority from `path`.
+    pub fn with_client_ca(mut self, path: impl Into<PathBuf>) -> Self {
+        self.client_ca_path = Some(path.into());
+        self
+    }
+
+    /// Loads certificates and keys, so that invalid files are reported on startup.
+    pub fn acceptor(&self) -> Result<TlsAcceptor, Error> {
+        let builder = match &self.client_ca_path {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder(Arc::new(load_roots(path)?))
+                    .build()
+                    .map_err(|e| Error::Tls { msg: e.to_string() })?;
+                ServerConfig::builder().with_client_cert_verifier(verifier)
+            }
+            None => ServerConfig::builder().with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(load_certs(&self.cert_path)?, load_key(&self.key_path)?)
+            .map_err(|e| Error::Tls { msg: e.to_string() })?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
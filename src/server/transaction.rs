@@ -0,0 +1,102 @@
+//! `MULTI`/`EXEC` transactions with optimistic locking through `WATCH`.
+//!
+//! After `MULTI`, commands of the connection are queued instead of executed. `EXEC` runs the
+//! whole queue with exclusive access to the database, so no other command can interleave with
+//! it. If any of the watched keys changed version since `WATCH`, nothing runs and `EXEC`
+//! responds with null, so client can read state again and retry.
+
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    server::{
+        Shared,
+        protocol::{self, Response},
+    },
+    utils::command::{Command, CommandType, Value},
+};
+
+/// Transaction state of a single connection.
+#[derive(Default)]
+pub struct Transaction {
+    /// `Some` between `MULTI` and `EXEC`/`DISCARD`.
+    queue: Option<Vec<Command>>,
+    /// Versions of watched keys at the time they were watched.
+    watched: HashMap<String, Option<u64>>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns `true` if commands are being queued.
+    pub fn is_queuing(&self) -> bool {
+        self.queue.is_some()
+    }
+
+    pub fn queue(&mut self, command: Command) {
+        if let Some(queue) = &mut self.queue {
+            queue.push(command);
+        }
+    }
+
+    /// Handles transaction `command` and builds response for it.
+    pub fn execute(&mut self, shared: &Arc<Shared>, command: CommandType) -> Response {
+        match command {
+            CommandType::Multi => {
+                if self.queue.is_some() {
+                    return Response::error("MULTI calls can not be nested");
+                }
+                self.queue = Some(Vec::new());
+                Response::Payload(Value::Boolean(true))
+            }
+            CommandType::Exec => self.exec(shared),
+            CommandType::Discard => {
+                if self.queue.take().is_none() {
+                    return Response::error("DISCARD without MULTI");
+                }
+                self.watched.clear();
+                Response::Payload(Value::Boolean(true))
+            }
+            CommandType::Watch { keys } => {
+                if self.queue.is_some() {
+                    return Response::error("WATCH inside MULTI is not allowed");
+                }
+                for key in keys {
+                    let version = shared.db.version(&key);
+                    // keep the version from the first watch, later writes must still abort
+                    self.watched.entry(key).or_insert(version);
+                }
+                Response::Payload(Value::Boolean(true))
+            }
+            CommandType::Unwatch => {
+                self.watched.clear();
+                Response::Payload(Value::Boolean(true))
+            }
+            _ => Response::error("not a transaction command"),
+        }
+    }
+
+    fn exec(&mut self, shared: &Arc<Shared>) -> Response {
+        let Some(queue) = self.queue.take() else {
+            return Response::error("EXEC without MULTI");
+        };
+        let watched = std::mem::take(&mut self.watched);
+
+        let _exclusive = shared.db.exclusive_access();
+
+        let changed = watched
+            .iter()
+            .any(|(key, version)| shared.db.version(key) != *version);
+        if changed {
+            return Response::Null;
+        }
+
+        Response::Multi(
+            queue
+                .into_iter()
+                .map(|command| protocol::apply(shared, command))
+                .collect(),
+        )
+    }
+}
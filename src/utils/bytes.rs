@@ -3,40 +3,68 @@ use bytes::Buf;
 use crate::error::{Error, Result};
 
 pub fn get_u8(src: &mut impl Buf) -> Result<u8> {
-    if !src.has_remaining() {
-        return Err(Error::InvalidBytes);
+    if src.remaining() < 1 {
+        return Err(Error::Incomplete);
     }
     Ok(src.get_u8())
 }
 
 pub fn get_bool(src: &mut impl Buf) -> Result<bool> {
-    Ok(get_u8(src)? == 0)
+    Ok(get_u8(src)? != 0)
 }
 
 pub fn get_u16(src: &mut impl Buf) -> Result<u16> {
-    if !src.has_remaining() {
-        return Err(Error::InvalidBytes);
+    if src.remaining() < 2 {
+        return Err(Error::Incomplete);
     }
     Ok(src.get_u16_le())
 }
 
 pub fn get_u32(src: &mut impl Buf) -> Result<u32> {
-    if !src.has_remaining() {
-        return Err(Error::InvalidBytes);
+    if src.remaining() < 4 {
+        return Err(Error::Incomplete);
     }
     Ok(src.get_u32_le())
 }
 
 pub fn get_i64(src: &mut impl Buf) -> Result<i64> {
-    if !src.has_remaining() {
-        return Err(Error::InvalidBytes);
+    if src.remaining() < 8 {
+        return Err(Error::Incomplete);
     }
     Ok(src.get_i64_le())
 }
 
 pub fn get_u64(src: &mut impl Buf) -> Result<u64> {
-    if !src.has_remaining() {
-        return Err(Error::InvalidBytes);
+    if src.remaining() < 8 {
+        return Err(Error::Incomplete);
     }
     Ok(src.get_u64_le())
 }
+
+pub fn get_f64(src: &mut impl Buf) -> Result<f64> {
+    if src.remaining() < 8 {
+        return Err(Error::Incomplete);
+    }
+    Ok(src.get_f64_le())
+}
+
+/// Advances `src` over `n` bytes without reading them.
+pub fn skip(src: &mut impl Buf, n: usize) -> Result<()> {
+    if src.remaining() < n {
+        return Err(Error::Incomplete);
+    }
+    src.advance(n);
+    Ok(())
+}
+
+/// Consumes the `\r\n` that terminates every frame. Frame length is always known from
+/// the encoded lengths, so the separator only serves as a sanity check.
+pub fn expect_separator(src: &mut impl Buf) -> Result<()> {
+    if src.remaining() < 2 {
+        return Err(Error::Incomplete);
+    }
+    if src.get_u8() != b'\r' || src.get_u8() != b'\n' {
+        return Err(Error::InvalidBytes);
+    }
+    Ok(())
+}
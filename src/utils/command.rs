@@ -1,12 +1,21 @@
-use std::io::{Cursor, Read};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::{Cursor, Read},
+    time::Duration,
+};
 
 use crate::{
     error::Error,
-    server::protocol,
-    utils::bytes::{get_bool, get_i64, get_u8, get_u32},
+    server::{
+        protocol,
+        resp::{self, RespFrame, RespReply},
+        storage::SortedSet,
+        stream::{ConsumerGroup, Fields, Pending, Stream, StreamId},
+    },
+    utils::bytes::{expect_separator, get_bool, get_f64, get_i64, get_u8, get_u32, get_u64, skip},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     /// represeted as !
     Boolean(bool),
@@ -18,106 +27,888 @@ pub enum Value {
     Array(Vec<Value>),
     /// represeted as { and len included
     Bytes(Vec<u8>),
+    /// represeted as % and number of fields included, every field is encoded as its len,
+    /// name and value
+    Map(HashMap<String, Value>),
+    /// represeted as ( and len included
+    List(VecDeque<Value>),
+    /// represeted as ~ and number of members included, members are encoded as values
+    Set(HashSet<Member>),
+    /// represeted as ^ and number of members included, every member is encoded as value
+    /// followed by its score (8 bytes float), from the lowest score
+    SortedSet(SortedSet),
+    /// represeted as , followed by 8 bytes
+    Float(f64),
+    /// represeted as _ alone, e.g. missing element inside an array
+    Null,
+    /// represeted as |, last id, number of entries and entries, each as its id, number of
+    /// fields and fields encoded like in map, followed by number of consumer groups and groups
+    /// (see `stream_to_bytes`)
+    Stream(Stream),
+}
+
+/// Scalar value that can be stored in `Value::Set` or `Value::SortedSet`. Members of different
+/// types are never equal, so number 1 and string "1" are separate members.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Member {
+    Boolean(bool),
+    Number(i64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+impl From<Member> for Value {
+    fn from(member: Member) -> Self {
+        match member {
+            Member::Boolean(b) => Value::Boolean(b),
+            Member::Number(n) => Value::Number(n),
+            Member::String(s) => Value::String(s),
+            Member::Bytes(bytes) => Value::Bytes(bytes),
+        }
+    }
+}
+
+impl TryFrom<Value> for Member {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Boolean(b) => Ok(Member::Boolean(b)),
+            Value::Number(n) => Ok(Member::Number(n)),
+            Value::String(s) => Ok(Member::String(s)),
+            Value::Bytes(bytes) => Ok(Member::Bytes(bytes)),
+            _ => Err(Error::BadRequest {
+                msg: "Set member has to be a scalar value".into(),
+            }),
+        }
+    }
+}
+
+impl Member {
+    /// Encodes member the same way as the matching `Value`.
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Boolean(b) => vec![b'!', *b as u8],
+            Self::Number(n) => number_to_bytes(*n),
+            Self::String(s) => blob_to_bytes(b'$', s.as_bytes()),
+            Self::Bytes(bytes) => blob_to_bytes(b'{', bytes),
+        }
+    }
+
+    /// Length of `to_bytes` output, computed without encoding.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Boolean(_) => 2,
+            Self::Number(_) => 9,
+            Self::String(s) => 5 + s.len(),
+            Self::Bytes(bytes) => 5 + bytes.len(),
+        }
+    }
+}
+
+/// Encoded length of a single hash field together with its value.
+pub(crate) fn field_encoded_len(field: &str, value: &Value) -> usize {
+    4 + field.len() + value.encoded_len()
+}
+
+/// Encoded length of a single stream entry, see `stream_to_bytes`.
+pub(crate) fn stream_entry_encoded_len(fields: &Fields) -> usize {
+    let fields: usize = fields
+        .iter()
+        .map(|(field, value)| field_encoded_len(field, value))
+        .sum();
+    16 + 4 + fields
+}
+
+/// Encoded length of a consumer group without its pending entries, see `stream_to_bytes`.
+pub(crate) fn group_encoded_len(name: &str) -> usize {
+    4 + name.len() + 16 + 4
+}
+
+/// Encoded length of a single pending entry of consumer group, see `stream_to_bytes`.
+pub(crate) fn pending_encoded_len(consumer: &str) -> usize {
+    16 + 4 + consumer.len() + 8 + 8
 }
 
 impl Value {
-    pub fn to_bytes(self) -> Vec<u8> {
+    pub fn to_bytes(&self) -> Vec<u8> {
         match self {
-            Self::Boolean(b) => vec![b'!', b as u8],
-            Self::Number(n) => {
-                let mut encoded = vec![b'#'];
+            Self::Boolean(b) => vec![b'!', *b as u8],
+            Self::Number(n) => number_to_bytes(*n),
+            Self::String(s) => blob_to_bytes(b'$', s.as_bytes()),
+            Self::Array(arr) => sequence_to_bytes(b'[', arr),
+            Self::Bytes(bytes) => blob_to_bytes(b'{', bytes),
+            Self::Map(map) => map_to_bytes(map),
+            Self::List(list) => sequence_to_bytes(b'(', list),
+            Self::Set(set) => members_to_bytes(b'~', set),
+            Self::SortedSet(set) => sorted_set_to_bytes(set),
+            Self::Float(n) => {
+                let mut encoded = vec![b','];
                 encoded.extend_from_slice(&n.to_le_bytes());
                 encoded
             }
-            Self::String(s) => {
-                let mut encoded = vec![b'$'];
-                let len = s.len() as u32;
-                encoded.extend_from_slice(&len.to_le_bytes());
-                encoded.extend_from_slice(s.as_bytes());
-                encoded
+            Self::Null => vec![b'_'],
+            Self::Stream(stream) => stream_to_bytes(stream),
+        }
+    }
+
+    /// Length of `to_bytes` output, computed without encoding. Used to estimate how much memory
+    /// stored value takes.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Self::Boolean(_) => 2,
+            Self::Number(_) | Self::Float(_) => 9,
+            Self::String(s) => 5 + s.len(),
+            Self::Bytes(bytes) => 5 + bytes.len(),
+            Self::Array(values) => 5 + values.iter().map(Self::encoded_len).sum::<usize>(),
+            Self::List(values) => 5 + values.iter().map(Self::encoded_len).sum::<usize>(),
+            Self::Map(map) => {
+                let fields: usize = map
+                    .iter()
+                    .map(|(field, value)| field_encoded_len(field, value))
+                    .sum();
+                5 + fields
             }
-            Self::Array(arr) => {
-                let mut encoded = vec![b'['];
-                let len = arr.len() as u32;
-                encoded.extend_from_slice(&len.to_le_bytes());
-                for el in arr {
-                    encoded.extend_from_slice(&el.to_bytes());
-                }
-                encoded
+            Self::Set(set) => 5 + set.iter().map(Member::encoded_len).sum::<usize>(),
+            Self::SortedSet(set) => {
+                5 + set
+                    .iter()
+                    .map(|(member, _)| member.encoded_len() + 8)
+                    .sum::<usize>()
             }
-            Self::Bytes(bytes) => {
-                let mut encoded = vec![b'{'];
-                let len = bytes.len() as u32;
-                encoded.extend_from_slice(&len.to_le_bytes());
-                encoded.extend_from_slice(&bytes);
-                encoded
+            Self::Null => 1,
+            Self::Stream(stream) => {
+                let entries: usize = stream
+                    .range(StreamId::MIN, StreamId::MAX)
+                    .map(|(_, fields)| stream_entry_encoded_len(fields))
+                    .sum();
+                let groups: usize = stream
+                    .groups()
+                    .map(|(name, group)| {
+                        let pending: usize = group
+                            .pending
+                            .values()
+                            .map(|pending| pending_encoded_len(&pending.consumer))
+                            .sum();
+                        group_encoded_len(name) + pending
+                    })
+                    .sum();
+                1 + 16 + 4 + entries + 4 + groups
             }
         }
     }
+
+    /// Name of the variant reported by `TYPE`. Collections use names that redis uses for
+    /// them.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Boolean(_) => "boolean",
+            Self::Number(_) => "number",
+            Self::String(_) => "string",
+            Self::Array(_) => "array",
+            Self::Bytes(_) => "bytes",
+            Self::Map(_) => "hash",
+            Self::List(_) => "list",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Float(_) => "float",
+            Self::Null => "null",
+            Self::Stream(_) => "stream",
+        }
+    }
 }
 
-impl protocol::TcpRead for Value {
-    fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
-        let valute_type = get_u8(src)?;
-        if matches!(valute_type, b'!' | b'#' | b'$' | b'[' | b'{') {
-            get_line(src)?;
+fn number_to_bytes(n: i64) -> Vec<u8> {
+    let mut encoded = vec![b'#'];
+    encoded.extend_from_slice(&n.to_le_bytes());
+    encoded
+}
+
+/// Encodes string or bytes, depending on `value_type`.
+fn blob_to_bytes(value_type: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![value_type];
+    let len = bytes.len() as u32;
+    encoded.extend_from_slice(&len.to_le_bytes());
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// Encodes array or list of `values`, depending on `value_type`.
+fn sequence_to_bytes<'a>(
+    value_type: u8,
+    values: impl IntoIterator<Item = &'a Value, IntoIter: ExactSizeIterator>,
+) -> Vec<u8> {
+    let values = values.into_iter();
+    let mut encoded = vec![value_type];
+    let len = values.len() as u32;
+    encoded.extend_from_slice(&len.to_le_bytes());
+    for el in values {
+        encoded.extend_from_slice(&el.to_bytes());
+    }
+    encoded
+}
+
+/// Encodes `members` as set, or as array if `value_type` is `[`.
+fn members_to_bytes<'a>(
+    value_type: u8,
+    members: impl IntoIterator<Item = &'a Member, IntoIter: ExactSizeIterator>,
+) -> Vec<u8> {
+    let members = members.into_iter();
+    let mut encoded = vec![value_type];
+    let len = members.len() as u32;
+    encoded.extend_from_slice(&len.to_le_bytes());
+    for member in members {
+        encoded.extend_from_slice(&member.to_bytes());
+    }
+    encoded
+}
+
+/// Encodes sorted set the same way as `Value::SortedSet`, without having to own it.
+fn sorted_set_to_bytes(set: &SortedSet) -> Vec<u8> {
+    let mut encoded = vec![b'^'];
+    let len = set.len() as u32;
+    encoded.extend_from_slice(&len.to_le_bytes());
+    for (member, score) in set.iter() {
+        encoded.extend_from_slice(&member.to_bytes());
+        encoded.extend_from_slice(&score.to_le_bytes());
+    }
+    encoded
+}
+
+fn str_to_bytes(encoded: &mut Vec<u8>, s: &str) {
+    encoded.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    encoded.extend_from_slice(s.as_bytes());
+}
+
+fn stream_id_to_bytes(encoded: &mut Vec<u8>, id: StreamId) {
+    encoded.extend_from_slice(&id.ms.to_le_bytes());
+    encoded.extend_from_slice(&id.seq.to_le_bytes());
+}
+
+/// Every consumer group is encoded as its name len, name, id of last delivered entry and
+/// number of pending entries. Pending entry is its id, consumer name len, consumer name, time
+/// of last delivery (8 bytes) and number of deliveries (8 bytes).
+fn stream_to_bytes(stream: &Stream) -> Vec<u8> {
+    let mut encoded = vec![b'|'];
+    stream_id_to_bytes(&mut encoded, stream.last_id());
+    encoded.extend_from_slice(&(stream.len() as u32).to_le_bytes());
+    for (id, fields) in stream.range(StreamId::MIN, StreamId::MAX) {
+        stream_id_to_bytes(&mut encoded, *id);
+        encoded.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        for (field, value) in fields {
+            str_to_bytes(&mut encoded, field);
+            encoded.extend_from_slice(&value.to_bytes());
+        }
+    }
+
+    let groups = stream.groups();
+    encoded.extend_from_slice(&(groups.len() as u32).to_le_bytes());
+    for (name, group) in groups {
+        str_to_bytes(&mut encoded, name);
+        stream_id_to_bytes(&mut encoded, group.last_delivered);
+        encoded.extend_from_slice(&(group.pending.len() as u32).to_le_bytes());
+        for (id, pending) in &group.pending {
+            stream_id_to_bytes(&mut encoded, *id);
+            str_to_bytes(&mut encoded, &pending.consumer);
+            encoded.extend_from_slice(&pending.delivered_at.to_le_bytes());
+            encoded.extend_from_slice(&pending.deliveries.to_le_bytes());
+        }
+    }
+    encoded
+}
+
+fn get_string(src: &mut Cursor<&[u8]>) -> Result<String, Error> {
+    let len = get_u32(src)?;
+    let mut buf = vec![0; len as usize];
+    src.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|_| Error::BadRequest {
+        msg: "Invalid field utf-8 encoding".into(),
+    })
+}
+
+fn get_stream_id(src: &mut Cursor<&[u8]>) -> Result<StreamId, Error> {
+    Ok(StreamId::new(get_u64(src)?, get_u64(src)?))
+}
+
+fn parse_stream(src: &mut Cursor<&[u8]>, depth: usize) -> Result<Stream, Error> {
+    let last_id = get_stream_id(src)?;
+    let mut entries = BTreeMap::new();
+    for _ in 0..get_u32(src)? {
+        let id = get_stream_id(src)?;
+        let len = get_u32(src)?;
+        let mut fields = Fields::with_capacity(len as usize);
+        for _ in 0..len {
+            fields.push((get_string(src)?, parse_value(src, depth + 1)?));
+        }
+        entries.insert(id, fields);
+    }
+
+    let mut groups = BTreeMap::new();
+    for _ in 0..get_u32(src)? {
+        let name = get_string(src)?;
+        let mut group = ConsumerGroup {
+            last_delivered: get_stream_id(src)?,
+            pending: BTreeMap::new(),
+        };
+        for _ in 0..get_u32(src)? {
+            let id = get_stream_id(src)?;
+            let pending = Pending {
+                consumer: get_string(src)?,
+                delivered_at: get_u64(src)?,
+                deliveries: get_u64(src)?,
+            };
+            group.pending.insert(id, pending);
+        }
+        groups.insert(name, group);
+    }
+
+    Ok(Stream::from_parts(entries, last_id, groups))
+}
+
+fn validate_stream(src: &mut Cursor<&[u8]>, depth: usize) -> Result<(), Error> {
+    skip(src, 16)?;
+    for _ in 0..get_u32(src)? {
+        skip(src, 16)?;
+        for _ in 0..get_u32(src)? {
+            let field_len = get_u32(src)?;
+            skip(src, field_len as usize)?;
+            validate_value(src, depth + 1)?;
+        }
+    }
+    for _ in 0..get_u32(src)? {
+        let name_len = get_u32(src)?;
+        skip(src, name_len as usize + 16)?;
+        for _ in 0..get_u32(src)? {
+            skip(src, 16)?;
+            let consumer_len = get_u32(src)?;
+            skip(src, consumer_len as usize + 16)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes map the same way as `Value::Map`, without having to own it.
+fn map_to_bytes(map: &HashMap<String, Value>) -> Vec<u8> {
+    let mut encoded = vec![b'%'];
+    let len = map.len() as u32;
+    encoded.extend_from_slice(&len.to_le_bytes());
+    for (field, value) in map {
+        encoded.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(field.as_bytes());
+        encoded.extend_from_slice(&value.to_bytes());
+    }
+    encoded
+}
+
+/// Collections nested deeper than this are rejected, so that decoding cannot overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+fn check_depth(depth: usize) -> Result<(), Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::Protocol {
+            msg: "too deeply nested value".into(),
+        });
+    }
+    Ok(())
+}
+
+/// Walks value that is nested in `depth` collections, see `Value::validate`.
+fn validate_value(src: &mut Cursor<&[u8]>, depth: usize) -> Result<(), Error> {
+    check_depth(depth)?;
+    let value_type = get_u8(src)?;
+
+    match value_type {
+        b'!' => skip(src, 1),
+        b'#' | b',' => skip(src, 8),
+        b'_' => Ok(()),
+        b'$' | b'{' => {
+            let len = get_u32(src)?;
+            skip(src, len as usize)
+        }
+        b'[' | b'(' | b'~' => {
+            let len = get_u32(src)?;
+            for _ in 0..len {
+                validate_value(src, depth + 1)?;
+            }
+            Ok(())
+        }
+        b'%' => {
+            let len = get_u32(src)?;
+            for _ in 0..len {
+                let field_len = get_u32(src)?;
+                skip(src, field_len as usize)?;
+                validate_value(src, depth + 1)?;
+            }
+            Ok(())
+        }
+        b'^' => {
+            let len = get_u32(src)?;
+            for _ in 0..len {
+                validate_value(src, depth + 1)?;
+                skip(src, 8)?;
+            }
             Ok(())
-        } else {
-            Err(Error::UnknownCommand)
         }
+        b'|' => validate_stream(src, depth),
+        _ => Err(Error::UnknownCommand),
     }
+}
 
-    // Is it pretty? I don't think so, but it works like a charm :D
-    fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error>
-    where
-        Self: Sized,
-    {
-        let data_type = get_u8(src)?;
-
-        match data_type {
-            b'!' => Ok(Value::Boolean(get_bool(src)?)),
-            b'#' => Ok(Value::Number(get_i64(src)?)),
-            b'$' => {
-                let len = get_u32(src)?;
-                let mut string_buf = vec![0; len as usize];
-                src.read_exact(&mut string_buf)?;
-                Ok(Value::String(String::from_utf8(string_buf).map_err(
-                    |_| Error::BadRequest {
-                        msg: "Invalid key utf-8 encoding".into(),
-                    },
-                )?))
+// Is it pretty? I don't think so, but it works like a charm :D
+fn parse_value(src: &mut Cursor<&[u8]>, depth: usize) -> Result<Value, Error> {
+    check_depth(depth)?;
+    let data_type = get_u8(src)?;
+
+    match data_type {
+        b'!' => Ok(Value::Boolean(get_bool(src)?)),
+        b'#' => Ok(Value::Number(get_i64(src)?)),
+        b',' => Ok(Value::Float(get_f64(src)?)),
+        b'_' => Ok(Value::Null),
+        b'$' => {
+            let len = get_u32(src)?;
+            let mut string_buf = vec![0; len as usize];
+            src.read_exact(&mut string_buf)?;
+            Ok(Value::String(String::from_utf8(string_buf).map_err(
+                |_| Error::BadRequest {
+                    msg: "Invalid key utf-8 encoding".into(),
+                },
+            )?))
+        }
+        b'[' => {
+            let len = get_u32(src)?;
+            let mut arr = Vec::with_capacity(len as usize);
+
+            for _ in 0..len {
+                arr.push(parse_value(src, depth + 1)?);
             }
-            b'[' => {
-                let len = get_u32(src)?;
-                let mut arr = Vec::with_capacity(len as usize);
 
-                for _ in 0..len {
-                    arr.push(Value::parse(&mut *src)?);
-                }
+            Ok(Value::Array(arr))
+        }
+        b'(' => {
+            let len = get_u32(src)?;
+            let mut list = VecDeque::with_capacity(len as usize);
 
-                Ok(Value::Array(arr))
+            for _ in 0..len {
+                list.push_back(parse_value(src, depth + 1)?);
             }
-            b'{' => {
-                let len = get_u32(src)?;
-                let mut bytes = vec![0; len as usize];
-                src.read_exact(&mut bytes)?;
-                Ok(Value::Bytes(bytes))
+
+            Ok(Value::List(list))
+        }
+        b'~' => {
+            let len = get_u32(src)?;
+            let mut set = HashSet::with_capacity(len as usize);
+
+            for _ in 0..len {
+                set.insert(Member::try_from(parse_value(src, depth + 1)?)?);
             }
-            _ => Err(Error::BadRequest {
-                msg: "Invalid data type".into(),
-            }),
+
+            Ok(Value::Set(set))
+        }
+        b'^' => {
+            let len = get_u32(src)?;
+            let mut set = SortedSet::new();
+
+            for _ in 0..len {
+                let member = Member::try_from(parse_value(src, depth + 1)?)?;
+                set.insert(member, to_score(get_f64(src)?)?);
+            }
+
+            Ok(Value::SortedSet(set))
+        }
+        b'{' => {
+            let len = get_u32(src)?;
+            let mut bytes = vec![0; len as usize];
+            src.read_exact(&mut bytes)?;
+            Ok(Value::Bytes(bytes))
+        }
+        b'%' => {
+            let len = get_u32(src)?;
+            let mut map = HashMap::with_capacity(len as usize);
+
+            for _ in 0..len {
+                let field_len = get_u32(src)?;
+                let mut field_buf = vec![0; field_len as usize];
+                src.read_exact(&mut field_buf)?;
+                let field = String::from_utf8(field_buf).map_err(|_| Error::BadRequest {
+                    msg: "Invalid field utf-8 encoding".into(),
+                })?;
+                map.insert(field, parse_value(src, depth + 1)?);
+            }
+
+            Ok(Value::Map(map))
         }
+        b'|' => Ok(Value::Stream(parse_stream(src, depth)?)),
+        _ => Err(Error::BadRequest {
+            msg: "Invalid data type".into(),
+        }),
+    }
+}
+
+impl protocol::TcpRead for Value {
+    /// Walks the encoded value using its type byte and length prefixes, so payloads are free to
+    /// contain `\r\n`. Does not expect a separator, because values are always embedded in a frame.
+    fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        validate_value(src, 0)
+    }
+
+    fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error>
+    where
+        Self: Sized,
+    {
+        parse_value(src, 0)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CommandType {
     Get,
-    Set { value: Value },
+    /// `expire` of `None` makes key persistent.
+    Set {
+        value: Value,
+        expire: Option<Duration>,
+    },
     Delete,
+    Expire {
+        expire: Duration,
+    },
+    Ttl,
+    Persist,
+    /// Synchronously writes snapshot. Has no key.
+    Save,
+    /// Writes snapshot in background. Has no key.
+    BgSave,
+    /// Compacts append only file to current state of database. Has no key.
+    RewriteAof,
+    /// Has no key, all keys are part of the variant. Same for other multi-key commands.
+    MGet {
+        keys: Vec<String>,
+    },
+    /// Sets all entries atomically, removing their expiry.
+    MSet {
+        entries: Vec<(String, Value)>,
+    },
+    MDel {
+        keys: Vec<String>,
+    },
+    /// Counts how many of keys exist.
+    Exists {
+        keys: Vec<String>,
+    },
+    /// Returns all keys matching glob `pattern`. Has no key.
+    Keys {
+        pattern: String,
+    },
+    /// Returns next batch of keys after `cursor` together with cursor for the following one,
+    /// see `Database::scan`. `pattern` of `None` matches every key, `count` is how many keys
+    /// to walk (not to return), negative means default. Has no key.
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: i64,
+    },
+    Type,
+    /// Moves value of the key to `new_key`, overwriting it.
+    Rename {
+        new_key: String,
+    },
+    /// Same as `Rename`, but only if `new_key` doesn't exist.
+    RenameNx {
+        new_key: String,
+    },
+    /// Counts all keys. Has no key.
+    DbSize,
+    /// Removes all keys. Has no key.
+    FlushAll,
+    Incr,
+    Decr,
+    IncrBy {
+        delta: i64,
+    },
+    IncrByFloat {
+        delta: f64,
+    },
+    /// Sets key only if it doesn't exist yet.
+    SetNx {
+        value: Value,
+        expire: Option<Duration>,
+    },
+    /// Sets key only if it already exists.
+    SetXx {
+        value: Value,
+        expire: Option<Duration>,
+    },
+    /// Replaces value only if current one equals `expected`, keeping expiry of the key.
+    CompareAndSwap {
+        expected: Value,
+        value: Value,
+    },
+    /// Subscriptions change state of connection, so they are handled by the connection itself
+    /// rather than `execute`. Have no key.
+    Subscribe {
+        channels: Vec<String>,
+    },
+    /// Empty `channels` unsubscribes from all of them.
+    Unsubscribe {
+        channels: Vec<String>,
+    },
+    PSubscribe {
+        patterns: Vec<String>,
+    },
+    PUnsubscribe {
+        patterns: Vec<String>,
+    },
+    /// Key is the channel.
+    Publish {
+        payload: Value,
+    },
+    /// Starts queuing commands of the connection until `Exec` or `Discard`. Transaction
+    /// commands have no key and, like subscriptions, are handled by the connection.
+    Multi,
+    /// Atomically runs queued commands, unless any of watched keys changed.
+    Exec,
+    Discard,
+    /// Makes next `Exec` abort if any of `keys` is modified before it.
+    Watch {
+        keys: Vec<String>,
+    },
+    Unwatch,
+    /// Sets fields of hash, creating it if needed.
+    HSet {
+        fields: HashMap<String, Value>,
+    },
+    HGet {
+        field: String,
+    },
+    /// Removes fields of hash, removing the whole key once it's empty.
+    HDel {
+        fields: Vec<String>,
+    },
+    HGetAll,
+    HIncrBy {
+        field: String,
+        delta: i64,
+    },
+    HExists {
+        field: String,
+    },
+    /// Pushes values to the front of list one by one, creating it if needed.
+    LPush {
+        values: Vec<Value>,
+    },
+    RPush {
+        values: Vec<Value>,
+    },
+    LPop,
+    RPop,
+    /// Indexes of list commands can be negative to count from the end, `stop` is inclusive.
+    LRange {
+        start: i64,
+        stop: i64,
+    },
+    LLen,
+    LTrim {
+        start: i64,
+        stop: i64,
+    },
+    LIndex {
+        index: i64,
+    },
+    LSet {
+        index: i64,
+        value: Value,
+    },
+    /// Pops from the first non-empty list of `keys`, waiting for a push if all are empty.
+    /// `None` timeout waits forever.
+    BLPop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    BRPop {
+        keys: Vec<String>,
+        timeout: Option<Duration>,
+    },
+    SAdd {
+        members: Vec<Member>,
+    },
+    SRem {
+        members: Vec<Member>,
+    },
+    SIsMember {
+        member: Member,
+    },
+    SMembers,
+    SCard,
+    SPop,
+    SRandMember,
+    SUnion {
+        keys: Vec<String>,
+    },
+    SInter {
+        keys: Vec<String>,
+    },
+    /// Members of the first set that aren't in any of the others.
+    SDiff {
+        keys: Vec<String>,
+    },
+    /// Same as `SUnion`, but result is stored under the key of the command.
+    SUnionStore {
+        keys: Vec<String>,
+    },
+    SInterStore {
+        keys: Vec<String>,
+    },
+    SDiffStore {
+        keys: Vec<String>,
+    },
+    /// Adds members or updates their scores.
+    ZAdd {
+        members: SortedSet,
+    },
+    ZRem {
+        members: Vec<Member>,
+    },
+    ZScore {
+        member: Member,
+    },
+    ZIncrBy {
+        member: Member,
+        delta: f64,
+    },
+    ZRank {
+        member: Member,
+    },
+    /// Rank counted from the highest score.
+    ZRevRank {
+        member: Member,
+    },
+    /// Members between ranks `start` and `stop` (inclusive) with their scores.
+    ZRange {
+        start: i64,
+        stop: i64,
+    },
+    ZRevRange {
+        start: i64,
+        stop: i64,
+    },
+    /// Members with score between `min` and `max` (inclusive) with their scores, skipping
+    /// `offset` of them and returning at most `count` (negative means all).
+    ZRangeByScore {
+        min: f64,
+        max: f64,
+        offset: i64,
+        count: i64,
+    },
+    ZRevRangeByScore {
+        min: f64,
+        max: f64,
+        offset: i64,
+        count: i64,
+    },
+    ZCard,
+    ZPopMin {
+        count: i64,
+    },
+    ZPopMax {
+        count: i64,
+    },
+    /// Appends entry to stream, creating it if needed. `id` of `None` is generated from
+    /// current time.
+    XAdd {
+        id: Option<StreamId>,
+        fields: Vec<(String, Value)>,
+    },
+    /// Entries with id between `start` and `end` (inclusive), returning at most `count` of them
+    /// (negative means all).
+    XRange {
+        start: StreamId,
+        end: StreamId,
+        count: i64,
+    },
+    XRevRange {
+        start: StreamId,
+        end: StreamId,
+        count: i64,
+    },
+    XLen,
+    /// Removes the oldest entries, so that at most `max_len` remain.
+    XTrim {
+        max_len: u64,
+    },
+    /// Reads entries with id greater than the matching id of `ids` from every stream of `keys`,
+    /// id of `None` only reads entries added later. `block` of `None` doesn't wait for
+    /// entries, zero waits forever. Has no key.
+    XRead {
+        keys: Vec<String>,
+        ids: Vec<Option<StreamId>>,
+        count: i64,
+        block: Option<Duration>,
+    },
+    /// Same as `XRead`, but reads for `consumer` of `group`. Id of `None` reads entries not
+    /// delivered to the group yet, otherwise pending entries of the consumer are read again.
+    XReadGroup {
+        group: String,
+        consumer: String,
+        keys: Vec<String>,
+        ids: Vec<Option<StreamId>>,
+        count: i64,
+        block: Option<Duration>,
+    },
+    /// Removes entries from pending entries of the group.
+    XAck {
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    /// Creates group that delivers entries added after `id`, `None` means the last entry.
+    /// Missing stream is created empty only if `create` is set.
+    XGroupCreate {
+        group: String,
+        id: Option<StreamId>,
+        create: bool,
+    },
+    /// Pending entries of the group with id between `start` and `end` (inclusive), returning
+    /// at most `count` of them (negative means all). `consumer` of `None` means any consumer.
+    XPending {
+        group: String,
+        start: StreamId,
+        end: StreamId,
+        count: i64,
+        consumer: Option<String>,
+    },
+    /// Authenticates connection as `user`. Like transactions, handled by the connection itself.
+    /// Has no key.
+    Auth {
+        user: String,
+        password: String,
+    },
+    /// Creates `user` or changes the existing one by applying `rules` in order, see
+    /// `server::acl`. ACL commands have no key.
+    AclSetUser {
+        user: String,
+        rules: Vec<String>,
+    },
+    /// Describes every user with rules that would create it.
+    AclList,
+    /// Name of the user that connection is authenticated as.
+    AclWhoAmI,
+    /// Stops the server once connections finish their current commands. `save` overrides
+    /// whether snapshot is written before exit, see `server::Config::save_on_shutdown`.
+    /// Has no key.
+    Shutdown {
+        save: Option<bool>,
+    },
+}
+
+/// Pieces that payload of a command (everything between key and separator) is made of.
+/// Used to walk a frame during validation without parsing it.
+enum Field {
+    /// Little-endian 8 byte integer, e.g. duration in milliseconds or increment.
+    Int64,
+    /// Little-endian 8 byte float, e.g. score of sorted set member.
+    Float64,
+    Value,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Command {
     /// Common filed for all commands
     pub key: String,
@@ -135,9 +926,23 @@ impl Command {
     pub fn set(key: &str, value: Value) -> Self {
         Self {
             key: key.to_string(),
-            r#type: CommandType::Set { value },
+            r#type: CommandType::Set {
+                value,
+                expire: None,
+            },
+        }
+    }
+
+    pub fn set_ex(key: &str, value: Value, expire: Duration) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Set {
+                value,
+                expire: Some(expire),
+            },
         }
     }
+
     pub fn delete(key: &str) -> Self {
         Self {
             key: key.to_string(),
@@ -145,104 +950,2003 @@ impl Command {
         }
     }
 
-    fn byte_type(&self) -> u8 {
-        match &self.r#type {
-            CommandType::Get => b'g',
-            CommandType::Set { value: _ } => b's',
-            CommandType::Delete => b'd',
+    pub fn expire(key: &str, expire: Duration) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Expire { expire },
         }
     }
 
-    fn validate_command_type(command_type: u8) -> Result<(), Error> {
-        if !matches!(command_type, b'g' | b's' | b'd') {
-            return Err(Error::UnknownCommand);
+    pub fn ttl(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Ttl,
         }
-        Ok(())
     }
-}
 
-impl protocol::TcpRead for Command {
-    fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
-        let command_type = get_u8(src)?;
-        if matches!(command_type, b'g' | b's' | b'd') {
-            get_line(src)?;
-            Ok(())
-        } else {
-            Err(Error::UnknownCommand)
+    pub fn persist(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Persist,
         }
     }
 
-    fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error> {
-        let command_type = get_u8(src)?;
+    pub fn save() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Save,
+        }
+    }
 
-        Self::validate_command_type(command_type)?;
+    pub fn bg_save() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::BgSave,
+        }
+    }
 
-        let key_size = get_u32(src)?;
-        let mut key_buf = vec![0; key_size as usize];
+    pub fn rewrite_aof() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::RewriteAof,
+        }
+    }
 
-        src.read_exact(&mut key_buf)?;
+    pub fn mget(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::MGet {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
 
-        let key = String::from_utf8(key_buf).map_err(|_| Error::BadRequest {
-            msg: "Invalid key utf-8 encoding".into(),
-        })?;
+    pub fn mset(entries: Vec<(String, Value)>) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::MSet { entries },
+        }
+    }
 
-        let r#type = match command_type {
-            b'g' => CommandType::Get,
-            b'd' => CommandType::Delete,
-            b's' => {
-                let value = Value::parse(src)?;
-                CommandType::Set { value }
-            }
-            _ => unreachable!(),
-        };
+    pub fn mdel(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::MDel {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
 
-        Ok(Command { key, r#type })
+    pub fn exists(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Exists {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
     }
-}
 
-impl protocol::TcpWrite for Command {
-    fn to_bytes(self) -> Vec<u8> {
-        let mut encoded = vec![self.byte_type()];
+    pub fn keys(pattern: &str) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Keys {
+                pattern: pattern.to_string(),
+            },
+        }
+    }
 
-        let key_size = self.key.len() as u32;
-        encoded.extend_from_slice(&key_size.to_le_bytes());
-        encoded.extend_from_slice(self.key.as_bytes());
+    pub fn scan(cursor: u64, pattern: Option<&str>, count: Option<usize>) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Scan {
+                cursor,
+                pattern: pattern.map(str::to_string),
+                count: count_to_i64(count),
+            },
+        }
+    }
 
-        if let CommandType::Set { value } = self.r#type {
-            encoded.extend_from_slice(&value.to_bytes());
+    pub fn key_type(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Type,
         }
+    }
 
-        encoded.extend_from_slice(b"\r\n");
+    pub fn rename(key: &str, new_key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Rename {
+                new_key: new_key.to_string(),
+            },
+        }
+    }
 
-        encoded
+    pub fn rename_nx(key: &str, new_key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::RenameNx {
+                new_key: new_key.to_string(),
+            },
+        }
     }
-}
 
-pub fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
-    let start = src.position() as usize;
-    let end = src.get_ref().len() - 1;
+    pub fn db_size() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::DbSize,
+        }
+    }
 
-    for i in start..end {
-        if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
-            // advance src to go over request separator
-            src.set_position((i + 2) as u64);
-            return Ok(&src.get_ref()[start..i]);
+    pub fn flush_all() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::FlushAll,
         }
     }
 
-    Err(Error::Incomplete)
-}
+    pub fn auth(user: &str, password: &str) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Auth {
+                user: user.to_string(),
+                password: password.to_string(),
+            },
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn acl_set_user(user: &str, rules: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::AclSetUser {
+                user: user.to_string(),
+                rules: rules.iter().map(|rule| rule.to_string()).collect(),
+            },
+        }
+    }
 
-    #[test]
-    fn test_value() -> anyhow::Result<()> {
-        let val_1 = Value::Number(1);
-        let val_2 = Value::String("maciek".into());
-        let val_3 = Value::Number(3);
-        let value = Value::Array(vec![val_1, val_2, val_3]);
+    pub fn acl_list() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::AclList,
+        }
+    }
+
+    pub fn acl_whoami() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::AclWhoAmI,
+        }
+    }
+
+    pub fn shutdown(save: Option<bool>) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Shutdown { save },
+        }
+    }
+
+    pub fn incr(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Incr,
+        }
+    }
+
+    pub fn decr(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::Decr,
+        }
+    }
+
+    pub fn incr_by(key: &str, delta: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::IncrBy { delta },
+        }
+    }
+
+    pub fn incr_by_float(key: &str, delta: f64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::IncrByFloat { delta },
+        }
+    }
+
+    pub fn set_nx(key: &str, value: Value, expire: Option<Duration>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SetNx { value, expire },
+        }
+    }
+
+    pub fn set_xx(key: &str, value: Value, expire: Option<Duration>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SetXx { value, expire },
+        }
+    }
+
+    pub fn compare_and_swap(key: &str, expected: Value, value: Value) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::CompareAndSwap { expected, value },
+        }
+    }
+
+    pub fn subscribe(channels: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Subscribe {
+                channels: channels.iter().map(|channel| channel.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn unsubscribe(channels: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Unsubscribe {
+                channels: channels.iter().map(|channel| channel.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn psubscribe(patterns: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::PSubscribe {
+                patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn punsubscribe(patterns: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::PUnsubscribe {
+                patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn publish(channel: &str, payload: Value) -> Self {
+        Self {
+            key: channel.to_string(),
+            r#type: CommandType::Publish { payload },
+        }
+    }
+
+    pub fn multi() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Multi,
+        }
+    }
+
+    pub fn exec() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Exec,
+        }
+    }
+
+    pub fn discard() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Discard,
+        }
+    }
+
+    pub fn watch(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Watch {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn unwatch() -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::Unwatch,
+        }
+    }
+
+    pub fn hset(key: &str, fields: HashMap<String, Value>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HSet { fields },
+        }
+    }
+
+    pub fn hget(key: &str, field: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HGet {
+                field: field.to_string(),
+            },
+        }
+    }
+
+    pub fn hdel(key: &str, fields: &[&str]) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HDel {
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn hgetall(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HGetAll,
+        }
+    }
+
+    pub fn hincr_by(key: &str, field: &str, delta: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HIncrBy {
+                field: field.to_string(),
+                delta,
+            },
+        }
+    }
+
+    pub fn hexists(key: &str, field: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::HExists {
+                field: field.to_string(),
+            },
+        }
+    }
+
+    pub fn lpush(key: &str, values: Vec<Value>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LPush { values },
+        }
+    }
+
+    pub fn rpush(key: &str, values: Vec<Value>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::RPush { values },
+        }
+    }
+
+    pub fn lpop(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LPop,
+        }
+    }
+
+    pub fn rpop(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::RPop,
+        }
+    }
+
+    pub fn lrange(key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LRange { start, stop },
+        }
+    }
+
+    pub fn llen(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LLen,
+        }
+    }
+
+    pub fn ltrim(key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LTrim { start, stop },
+        }
+    }
+
+    pub fn lindex(key: &str, index: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LIndex { index },
+        }
+    }
+
+    pub fn lset(key: &str, index: i64, value: Value) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::LSet { index, value },
+        }
+    }
+
+    pub fn blpop(keys: &[&str], timeout: Option<Duration>) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::BLPop {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+                timeout,
+            },
+        }
+    }
+
+    pub fn brpop(keys: &[&str], timeout: Option<Duration>) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::BRPop {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+                timeout,
+            },
+        }
+    }
+
+    pub fn sadd(key: &str, members: Vec<Member>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SAdd { members },
+        }
+    }
+
+    pub fn srem(key: &str, members: Vec<Member>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SRem { members },
+        }
+    }
+
+    pub fn sismember(key: &str, member: Member) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SIsMember { member },
+        }
+    }
+
+    pub fn smembers(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SMembers,
+        }
+    }
+
+    pub fn scard(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SCard,
+        }
+    }
+
+    pub fn spop(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SPop,
+        }
+    }
+
+    pub fn srandmember(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::SRandMember,
+        }
+    }
+
+    pub fn sunion(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::SUnion {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn sinter(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::SInter {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn sdiff(keys: &[&str]) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::SDiff {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn sunion_store(destination: &str, keys: &[&str]) -> Self {
+        Self {
+            key: destination.to_string(),
+            r#type: CommandType::SUnionStore {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn sinter_store(destination: &str, keys: &[&str]) -> Self {
+        Self {
+            key: destination.to_string(),
+            r#type: CommandType::SInterStore {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn sdiff_store(destination: &str, keys: &[&str]) -> Self {
+        Self {
+            key: destination.to_string(),
+            r#type: CommandType::SDiffStore {
+                keys: keys.iter().map(|key| key.to_string()).collect(),
+            },
+        }
+    }
+
+    pub fn zadd(key: &str, members: SortedSet) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZAdd { members },
+        }
+    }
+
+    pub fn zrem(key: &str, members: Vec<Member>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRem { members },
+        }
+    }
+
+    pub fn zscore(key: &str, member: Member) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZScore { member },
+        }
+    }
+
+    pub fn zincr_by(key: &str, member: Member, delta: f64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZIncrBy { member, delta },
+        }
+    }
+
+    pub fn zrank(key: &str, member: Member) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRank { member },
+        }
+    }
+
+    pub fn zrevrank(key: &str, member: Member) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRevRank { member },
+        }
+    }
+
+    pub fn zrange(key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRange { start, stop },
+        }
+    }
+
+    pub fn zrevrange(key: &str, start: i64, stop: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRevRange { start, stop },
+        }
+    }
+
+    pub fn zrange_by_score(key: &str, min: f64, max: f64, limit: Option<(i64, i64)>) -> Self {
+        let (offset, count) = limit.unwrap_or((0, -1));
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRangeByScore {
+                min,
+                max,
+                offset,
+                count,
+            },
+        }
+    }
+
+    pub fn zrevrange_by_score(key: &str, min: f64, max: f64, limit: Option<(i64, i64)>) -> Self {
+        let (offset, count) = limit.unwrap_or((0, -1));
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZRevRangeByScore {
+                min,
+                max,
+                offset,
+                count,
+            },
+        }
+    }
+
+    pub fn zcard(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZCard,
+        }
+    }
+
+    pub fn zpopmin(key: &str, count: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZPopMin { count },
+        }
+    }
+
+    pub fn zpopmax(key: &str, count: i64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::ZPopMax { count },
+        }
+    }
+
+    pub fn xadd(key: &str, id: Option<StreamId>, fields: Vec<(String, Value)>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XAdd { id, fields },
+        }
+    }
+
+    pub fn xrange(key: &str, start: StreamId, end: StreamId, count: Option<usize>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XRange {
+                start,
+                end,
+                count: count_to_i64(count),
+            },
+        }
+    }
+
+    pub fn xrevrange(key: &str, start: StreamId, end: StreamId, count: Option<usize>) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XRevRange {
+                start,
+                end,
+                count: count_to_i64(count),
+            },
+        }
+    }
+
+    pub fn xlen(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XLen,
+        }
+    }
+
+    pub fn xtrim(key: &str, max_len: u64) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XTrim { max_len },
+        }
+    }
+
+    pub fn xread(
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::XRead {
+                keys: streams.iter().map(|(key, _)| key.to_string()).collect(),
+                ids: streams.iter().map(|(_, id)| *id).collect(),
+                count: count_to_i64(count),
+                block,
+            },
+        }
+    }
+
+    pub fn xread_group(
+        group: &str,
+        consumer: &str,
+        streams: &[(&str, Option<StreamId>)],
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Self {
+        Self {
+            key: String::new(),
+            r#type: CommandType::XReadGroup {
+                group: group.to_string(),
+                consumer: consumer.to_string(),
+                keys: streams.iter().map(|(key, _)| key.to_string()).collect(),
+                ids: streams.iter().map(|(_, id)| *id).collect(),
+                count: count_to_i64(count),
+                block,
+            },
+        }
+    }
+
+    pub fn xack(key: &str, group: &str, ids: &[StreamId]) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XAck {
+                group: group.to_string(),
+                ids: ids.to_vec(),
+            },
+        }
+    }
+
+    pub fn xgroup_create(key: &str, group: &str, id: Option<StreamId>, create: bool) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XGroupCreate {
+                group: group.to_string(),
+                id,
+                create,
+            },
+        }
+    }
+
+    pub fn xpending(
+        key: &str,
+        group: &str,
+        start: StreamId,
+        end: StreamId,
+        count: Option<usize>,
+        consumer: Option<&str>,
+    ) -> Self {
+        Self {
+            key: key.to_string(),
+            r#type: CommandType::XPending {
+                group: group.to_string(),
+                start,
+                end,
+                count: count_to_i64(count),
+                consumer: consumer.map(str::to_string),
+            },
+        }
+    }
+
+    /// Returns `true` if command controls transaction of the connection.
+    pub fn is_transaction(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::Multi
+                | CommandType::Exec
+                | CommandType::Discard
+                | CommandType::Watch { .. }
+                | CommandType::Unwatch
+        )
+    }
+
+    /// Keys that command reads or writes. Channels of pub/sub commands are not keys.
+    pub fn accessed_keys(&self) -> Vec<&str> {
+        match &self.r#type {
+            CommandType::MGet { keys }
+            | CommandType::MDel { keys }
+            | CommandType::Exists { keys }
+            | CommandType::Watch { keys }
+            | CommandType::BLPop { keys, .. }
+            | CommandType::BRPop { keys, .. }
+            | CommandType::SUnion { keys }
+            | CommandType::SInter { keys }
+            | CommandType::SDiff { keys }
+            | CommandType::XRead { keys, .. }
+            | CommandType::XReadGroup { keys, .. } => keys.iter().map(String::as_str).collect(),
+            CommandType::MSet { entries } => entries.iter().map(|(key, _)| key.as_str()).collect(),
+            CommandType::SUnionStore { keys }
+            | CommandType::SInterStore { keys }
+            | CommandType::SDiffStore { keys } => {
+                let sources = keys.iter().map(String::as_str);
+                [self.key.as_str()].into_iter().chain(sources).collect()
+            }
+            CommandType::Rename { new_key } | CommandType::RenameNx { new_key } => {
+                vec![&self.key, new_key]
+            }
+            CommandType::Save
+            | CommandType::BgSave
+            | CommandType::RewriteAof
+            | CommandType::Keys { .. }
+            | CommandType::Scan { .. }
+            | CommandType::DbSize
+            | CommandType::FlushAll
+            | CommandType::Subscribe { .. }
+            | CommandType::Unsubscribe { .. }
+            | CommandType::PSubscribe { .. }
+            | CommandType::PUnsubscribe { .. }
+            | CommandType::Publish { .. }
+            | CommandType::Multi
+            | CommandType::Exec
+            | CommandType::Discard
+            | CommandType::Unwatch
+            | CommandType::Auth { .. }
+            | CommandType::AclSetUser { .. }
+            | CommandType::AclList
+            | CommandType::AclWhoAmI
+            | CommandType::Shutdown { .. } => Vec::new(),
+            _ => vec![&self.key],
+        }
+    }
+
+    /// Returns `true` if command authenticates the connection or asks who it's authenticated
+    /// as, so it's handled by the connection.
+    pub fn is_auth(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::Auth { .. } | CommandType::AclWhoAmI
+        )
+    }
+
+    /// Returns `true` if command changes subscriptions of the connection.
+    pub fn is_subscription(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::Subscribe { .. }
+                | CommandType::Unsubscribe { .. }
+                | CommandType::PSubscribe { .. }
+                | CommandType::PUnsubscribe { .. }
+        )
+    }
+
+    /// Returns `true` if command copies the whole database, so it runs with exclusive access
+    /// the same as transaction, see `Database::entries`.
+    pub fn is_exclusive(&self) -> bool {
+        matches!(self.r#type, CommandType::Save | CommandType::RewriteAof)
+    }
+
+    /// Returns `true` if command may wait for other connections before it responds.
+    pub fn is_blocking(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::BLPop { .. }
+                | CommandType::BRPop { .. }
+                | CommandType::XRead { block: Some(_), .. }
+                | CommandType::XReadGroup { block: Some(_), .. }
+        )
+    }
+
+    /// Returns `true` if command modifies database and has to be stored in append only file.
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::Set { .. }
+                | CommandType::Delete
+                | CommandType::Expire { .. }
+                | CommandType::Persist
+                | CommandType::MSet { .. }
+                | CommandType::MDel { .. }
+                | CommandType::Rename { .. }
+                | CommandType::RenameNx { .. }
+                | CommandType::FlushAll
+                | CommandType::Incr
+                | CommandType::Decr
+                | CommandType::IncrBy { .. }
+                | CommandType::IncrByFloat { .. }
+                | CommandType::SetNx { .. }
+                | CommandType::SetXx { .. }
+                | CommandType::CompareAndSwap { .. }
+                | CommandType::HSet { .. }
+                | CommandType::HDel { .. }
+                | CommandType::HIncrBy { .. }
+                | CommandType::LPush { .. }
+                | CommandType::RPush { .. }
+                | CommandType::LPop
+                | CommandType::RPop
+                | CommandType::LTrim { .. }
+                | CommandType::LSet { .. }
+                | CommandType::BLPop { .. }
+                | CommandType::BRPop { .. }
+                | CommandType::SAdd { .. }
+                | CommandType::SRem { .. }
+                | CommandType::SPop
+                | CommandType::SUnionStore { .. }
+                | CommandType::SInterStore { .. }
+                | CommandType::SDiffStore { .. }
+                | CommandType::ZAdd { .. }
+                | CommandType::ZRem { .. }
+                | CommandType::ZIncrBy { .. }
+                | CommandType::ZPopMin { .. }
+                | CommandType::ZPopMax { .. }
+                | CommandType::XAdd { .. }
+                | CommandType::XTrim { .. }
+                | CommandType::XReadGroup { .. }
+                | CommandType::XAck { .. }
+                | CommandType::XGroupCreate { .. }
+        )
+    }
+
+    /// Returns `true` if command can make database use more memory, so it's refused when the
+    /// memory limit is reached and nothing can be evicted.
+    pub fn may_grow(&self) -> bool {
+        matches!(
+            self.r#type,
+            CommandType::Set { .. }
+                | CommandType::SetNx { .. }
+                | CommandType::SetXx { .. }
+                | CommandType::MSet { .. }
+                | CommandType::Incr
+                | CommandType::Decr
+                | CommandType::IncrBy { .. }
+                | CommandType::IncrByFloat { .. }
+                | CommandType::CompareAndSwap { .. }
+                | CommandType::HSet { .. }
+                | CommandType::HIncrBy { .. }
+                | CommandType::LPush { .. }
+                | CommandType::RPush { .. }
+                | CommandType::LSet { .. }
+                | CommandType::SAdd { .. }
+                | CommandType::SUnionStore { .. }
+                | CommandType::SInterStore { .. }
+                | CommandType::SDiffStore { .. }
+                | CommandType::ZAdd { .. }
+                | CommandType::ZIncrBy { .. }
+                | CommandType::XAdd { .. }
+                | CommandType::XReadGroup { .. }
+                | CommandType::XGroupCreate { .. }
+        )
+    }
+
+    fn byte_type(&self) -> u8 {
+        match &self.r#type {
+            CommandType::Get => b'g',
+            CommandType::Set { .. } => b's',
+            CommandType::Delete => b'd',
+            CommandType::Expire { .. } => b'x',
+            CommandType::Ttl => b't',
+            CommandType::Persist => b'p',
+            CommandType::Save => b'S',
+            CommandType::BgSave => b'B',
+            CommandType::RewriteAof => b'R',
+            CommandType::MGet { .. } => b'G',
+            CommandType::MSet { .. } => b'M',
+            CommandType::MDel { .. } => b'D',
+            CommandType::Exists { .. } => b'E',
+            CommandType::Keys { .. } => b',',
+            CommandType::Scan { .. } => b'%',
+            CommandType::Type => b'y',
+            CommandType::Rename { .. } => b'-',
+            CommandType::RenameNx { .. } => b'~',
+            CommandType::DbSize => b'$',
+            CommandType::FlushAll => b'!',
+            CommandType::Incr => b'i',
+            CommandType::Decr => b'c',
+            CommandType::IncrBy { .. } => b'I',
+            CommandType::IncrByFloat { .. } => b'.',
+            CommandType::SetNx { .. } => b'n',
+            CommandType::SetXx { .. } => b'X',
+            CommandType::CompareAndSwap { .. } => b'C',
+            CommandType::Subscribe { .. } => b'b',
+            CommandType::Unsubscribe { .. } => b'u',
+            CommandType::PSubscribe { .. } => b'q',
+            CommandType::PUnsubscribe { .. } => b'Q',
+            CommandType::Publish { .. } => b'P',
+            CommandType::Multi => b'T',
+            CommandType::Exec => b'e',
+            CommandType::Discard => b'r',
+            CommandType::Watch { .. } => b'w',
+            CommandType::Unwatch => b'W',
+            CommandType::HSet { .. } => b'h',
+            CommandType::HGet { .. } => b'H',
+            CommandType::HDel { .. } => b'j',
+            CommandType::HGetAll => b'A',
+            CommandType::HIncrBy { .. } => b'J',
+            CommandType::HExists { .. } => b'Y',
+            CommandType::LPush { .. } => b'l',
+            CommandType::RPush { .. } => b'L',
+            CommandType::LPop => b'o',
+            CommandType::RPop => b'O',
+            CommandType::LRange { .. } => b'v',
+            CommandType::LLen => b'z',
+            CommandType::LTrim { .. } => b'V',
+            CommandType::LIndex { .. } => b'k',
+            CommandType::LSet { .. } => b'K',
+            CommandType::BLPop { .. } => b'f',
+            CommandType::BRPop { .. } => b'F',
+            CommandType::SAdd { .. } => b'a',
+            CommandType::SRem { .. } => b'm',
+            CommandType::SIsMember { .. } => b'N',
+            CommandType::SMembers => b'U',
+            CommandType::SCard => b'Z',
+            CommandType::SPop => b'0',
+            CommandType::SRandMember => b'1',
+            CommandType::SUnion { .. } => b'2',
+            CommandType::SInter { .. } => b'3',
+            CommandType::SDiff { .. } => b'4',
+            CommandType::SUnionStore { .. } => b'5',
+            CommandType::SInterStore { .. } => b'6',
+            CommandType::SDiffStore { .. } => b'7',
+            CommandType::ZAdd { .. } => b'8',
+            CommandType::ZRem { .. } => b'9',
+            CommandType::ZScore { .. } => b'@',
+            CommandType::ZIncrBy { .. } => b'+',
+            CommandType::ZRank { .. } => b'<',
+            CommandType::ZRevRank { .. } => b'>',
+            CommandType::ZRange { .. } => b'(',
+            CommandType::ZRevRange { .. } => b')',
+            CommandType::ZRangeByScore { .. } => b'[',
+            CommandType::ZRevRangeByScore { .. } => b']',
+            CommandType::ZCard => b'#',
+            CommandType::ZPopMin { .. } => b'{',
+            CommandType::ZPopMax { .. } => b'}',
+            CommandType::XAdd { .. } => b'&',
+            CommandType::XRange { .. } => b'/',
+            CommandType::XRevRange { .. } => b'\\',
+            CommandType::XLen => b';',
+            CommandType::XTrim { .. } => b':',
+            CommandType::XRead { .. } => b'=',
+            CommandType::XReadGroup { .. } => b'?',
+            CommandType::XAck { .. } => b'\'',
+            CommandType::XGroupCreate { .. } => b'"',
+            CommandType::XPending { .. } => b'`',
+            CommandType::Auth { .. } => b'^',
+            CommandType::AclSetUser { .. } | CommandType::AclList | CommandType::AclWhoAmI => b'_',
+            CommandType::Shutdown { .. } => b'|',
+        }
+    }
+
+    /// Returns layout of payload for given command type.
+    fn payload_fields(command_type: u8) -> Result<&'static [Field], Error> {
+        match command_type {
+            b'g' | b'd' | b't' | b'p' | b'S' | b'B' | b'R' | b'i' | b'c' | b'T' | b'e' | b'r'
+            | b'W' | b'A' | b'o' | b'O' | b'z' | b'U' | b'Z' | b'0' | b'1' | b'#' | b';' | b'y'
+            | b'$' | b'!' => Ok(&[]),
+            b'%' => Ok(&[Field::Int64, Field::Value, Field::Int64]),
+            b's' | b'n' | b'X' => Ok(&[Field::Value, Field::Int64]),
+            b'C' | b'&' | b'\'' | b'^' => Ok(&[Field::Value, Field::Value]),
+            b'/' | b'\\' => Ok(&[Field::Value, Field::Value, Field::Int64]),
+            b'=' => Ok(&[Field::Value, Field::Value, Field::Int64, Field::Int64]),
+            b'?' => Ok(&[
+                Field::Value,
+                Field::Value,
+                Field::Value,
+                Field::Value,
+                Field::Int64,
+                Field::Int64,
+            ]),
+            b'"' => Ok(&[Field::Value, Field::Value, Field::Value]),
+            b'`' => Ok(&[
+                Field::Value,
+                Field::Value,
+                Field::Value,
+                Field::Int64,
+                Field::Value,
+            ]),
+            b'x' | b'I' | b'k' | b'{' | b'}' | b':' => Ok(&[Field::Int64]),
+            b'v' | b'V' | b'(' | b')' => Ok(&[Field::Int64, Field::Int64]),
+            b'+' => Ok(&[Field::Value, Field::Float64]),
+            b'.' => Ok(&[Field::Float64]),
+            b'[' | b']' => Ok(&[Field::Float64, Field::Float64, Field::Int64, Field::Int64]),
+            b'K' | b'_' => Ok(&[Field::Int64, Field::Value]),
+            b'J' | b'f' | b'F' => Ok(&[Field::Value, Field::Int64]),
+            b'G' | b'M' | b'D' | b'E' | b'b' | b'u' | b'q' | b'Q' | b'P' | b'w' | b'h' | b'H'
+            | b'j' | b'Y' | b'l' | b'L' | b'a' | b'm' | b'N' | b'2' | b'3' | b'4' | b'5' | b'6'
+            | b'7' | b'8' | b'9' | b'@' | b'<' | b'>' | b',' | b'-' | b'~' | b'|' => {
+                Ok(&[Field::Value])
+            }
+            _ => Err(Error::UnknownCommand),
+        }
+    }
+}
+
+/// ACL commands share type byte, subcommand is sent as 8 bytes before array of its arguments.
+const ACL_SET_USER: i64 = 0;
+const ACL_LIST: i64 = 1;
+const ACL_WHOAMI: i64 = 2;
+
+/// Expiry is sent as milliseconds, where 0 means that key never expires.
+fn expire_to_millis(expire: Option<Duration>) -> u64 {
+    expire.map_or(0, |duration| duration.as_millis() as u64)
+}
+
+fn millis_to_expire(millis: u64) -> Option<Duration> {
+    (millis != 0).then(|| Duration::from_millis(millis))
+}
+
+/// Lists of keys are sent as `Value::Array` of `Value::String`.
+fn keys_to_value(keys: &[String]) -> Value {
+    Value::Array(keys.iter().cloned().map(Value::String).collect())
+}
+
+fn value_to_keys(value: Value) -> Result<Vec<String>, Error> {
+    let bad_request = || Error::BadRequest {
+        msg: "Expected array of keys".into(),
+    };
+
+    let Value::Array(arr) = value else {
+        return Err(bad_request());
+    };
+    arr.into_iter()
+        .map(|key| match key {
+            Value::String(key) => Ok(key),
+            _ => Err(bad_request()),
+        })
+        .collect()
+}
+
+/// Values pushed to list are sent as `Value::Array`.
+fn value_to_values(value: Value) -> Result<Vec<Value>, Error> {
+    match value {
+        Value::Array(values) => Ok(values),
+        _ => Err(Error::BadRequest {
+            msg: "Expected array of values".into(),
+        }),
+    }
+}
+
+/// Error for command sent without arguments it can't do without, such as push without values.
+pub(crate) fn wrong_arity(command: &str) -> Error {
+    Error::BadRequest {
+        msg: format!("wrong number of arguments for '{command}' command"),
+    }
+}
+
+/// Fails with `wrong_arity` if `items` is empty.
+fn non_empty<T>(items: Vec<T>, command: &str) -> Result<Vec<T>, Error> {
+    if items.is_empty() {
+        return Err(wrong_arity(command));
+    }
+    Ok(items)
+}
+
+/// Scores can be infinite, but never NaN.
+pub(crate) fn to_score(score: f64) -> Result<f64, Error> {
+    if score.is_nan() {
+        return Err(Error::NotFloat);
+    }
+    Ok(score)
+}
+
+/// Set members are sent as `Value::Array` of scalar values.
+fn value_to_members(value: Value) -> Result<Vec<Member>, Error> {
+    value_to_values(value)?
+        .into_iter()
+        .map(Member::try_from)
+        .collect()
+}
+
+/// Single hash field is sent as `Value::String`.
+fn value_to_field(value: Value) -> Result<String, Error> {
+    match value {
+        Value::String(field) => Ok(field),
+        _ => Err(Error::BadRequest {
+            msg: "Expected field name".into(),
+        }),
+    }
+}
+
+/// Entries of `MSET` are sent as flat `Value::Array` of alternating keys and values.
+fn entries_to_value(entries: &[(String, Value)]) -> Value {
+    Value::Array(
+        entries
+            .iter()
+            .flat_map(|(key, value)| [Value::String(key.clone()), value.clone()])
+            .collect(),
+    )
+}
+
+fn value_to_entries(value: Value) -> Result<Vec<(String, Value)>, Error> {
+    let bad_request = || Error::BadRequest {
+        msg: "Expected array of key-value pairs".into(),
+    };
+
+    let Value::Array(arr) = value else {
+        return Err(bad_request());
+    };
+    if arr.len() % 2 != 0 {
+        return Err(bad_request());
+    }
+
+    let mut entries = Vec::with_capacity(arr.len() / 2);
+    let mut iter = arr.into_iter();
+    while let (Some(key), Some(value)) = (iter.next(), iter.next()) {
+        let Value::String(key) = key else {
+            return Err(bad_request());
+        };
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+/// Optional counts are sent as 8 bytes, where negative means no limit.
+fn count_to_i64(count: Option<usize>) -> i64 {
+    count.map_or(-1, |count| count.min(i64::MAX as usize) as i64)
+}
+
+/// Blocking time of stream reads is sent as milliseconds, where negative means no blocking
+/// and 0 blocking forever.
+fn block_to_millis(block: Option<Duration>) -> i64 {
+    block.map_or(-1, |block| block.as_millis().min(i64::MAX as u128) as i64)
+}
+
+fn millis_to_block(millis: i64) -> Option<Duration> {
+    (millis >= 0).then(|| Duration::from_millis(millis as u64))
+}
+
+/// Stream ids are sent as `Value::String` in `ms-seq` format, missing id as `Value::Null`.
+fn id_to_value(id: Option<StreamId>) -> Value {
+    id.map_or(Value::Null, |id| Value::String(id.to_string()))
+}
+
+fn value_to_id(value: Value) -> Result<Option<StreamId>, Error> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(id) => id.parse().map(Some),
+        _ => Err(Error::InvalidStreamId),
+    }
+}
+
+fn ids_to_value(ids: impl Iterator<Item = Option<StreamId>>) -> Value {
+    Value::Array(ids.map(id_to_value).collect())
+}
+
+fn value_to_ids(value: Value) -> Result<Vec<Option<StreamId>>, Error> {
+    value_to_values(value)?
+        .into_iter()
+        .map(value_to_id)
+        .collect()
+}
+
+fn value_to_required_id(value: Value) -> Result<StreamId, Error> {
+    value_to_id(value)?.ok_or(Error::InvalidStreamId)
+}
+
+/// Stream reads need exactly one id per key.
+fn streams_from(keys: Value, ids: Value) -> Result<(Vec<String>, Vec<Option<StreamId>>), Error> {
+    let keys = value_to_keys(keys)?;
+    let ids = value_to_ids(ids)?;
+    if keys.len() != ids.len() {
+        return Err(Error::BadRequest {
+            msg: "Expected one id per stream".into(),
+        });
+    }
+    Ok((keys, ids))
+}
+
+impl protocol::TcpRead for Command {
+    fn validate(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        let command_type = get_u8(src)?;
+
+        let fields = Self::payload_fields(command_type)?;
+
+        let key_size = get_u32(src)?;
+        skip(src, key_size as usize)?;
+
+        for field in fields {
+            match field {
+                Field::Int64 | Field::Float64 => skip(src, 8)?,
+                Field::Value => Value::validate(src)?,
+            }
+        }
+
+        expect_separator(src)
+    }
+
+    fn parse(src: &mut Cursor<&[u8]>) -> Result<Self, Error> {
+        let command_type = get_u8(src)?;
+
+        Self::payload_fields(command_type)?;
+
+        let key_size = get_u32(src)?;
+        let mut key_buf = vec![0; key_size as usize];
+
+        src.read_exact(&mut key_buf)?;
+
+        let key = String::from_utf8(key_buf).map_err(|_| Error::BadRequest {
+            msg: "Invalid key utf-8 encoding".into(),
+        })?;
+
+        let r#type = match command_type {
+            b'g' => CommandType::Get,
+            b'd' => CommandType::Delete,
+            b's' => {
+                let value = Value::parse(src)?;
+                let expire = millis_to_expire(get_u64(src)?);
+                CommandType::Set { value, expire }
+            }
+            b'x' => CommandType::Expire {
+                expire: Duration::from_millis(get_u64(src)?),
+            },
+            b't' => CommandType::Ttl,
+            b'p' => CommandType::Persist,
+            b'S' => CommandType::Save,
+            b'B' => CommandType::BgSave,
+            b'R' => CommandType::RewriteAof,
+            b'G' => CommandType::MGet {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'M' => CommandType::MSet {
+                entries: value_to_entries(Value::parse(src)?)?,
+            },
+            b'D' => CommandType::MDel {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b',' => CommandType::Keys {
+                pattern: value_to_field(Value::parse(src)?)?,
+            },
+            b'%' => CommandType::Scan {
+                cursor: get_u64(src)?,
+                pattern: match Value::parse(src)? {
+                    Value::Null => None,
+                    pattern => Some(value_to_field(pattern)?),
+                },
+                count: get_i64(src)?,
+            },
+            b'y' => CommandType::Type,
+            b'-' => CommandType::Rename {
+                new_key: value_to_field(Value::parse(src)?)?,
+            },
+            b'~' => CommandType::RenameNx {
+                new_key: value_to_field(Value::parse(src)?)?,
+            },
+            b'$' => CommandType::DbSize,
+            b'!' => CommandType::FlushAll,
+            b'E' => CommandType::Exists {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'i' => CommandType::Incr,
+            b'c' => CommandType::Decr,
+            b'I' => CommandType::IncrBy {
+                delta: get_i64(src)?,
+            },
+            b'.' => CommandType::IncrByFloat {
+                delta: to_score(get_f64(src)?)?,
+            },
+            b'n' => CommandType::SetNx {
+                value: Value::parse(src)?,
+                expire: millis_to_expire(get_u64(src)?),
+            },
+            b'X' => CommandType::SetXx {
+                value: Value::parse(src)?,
+                expire: millis_to_expire(get_u64(src)?),
+            },
+            b'C' => CommandType::CompareAndSwap {
+                expected: Value::parse(src)?,
+                value: Value::parse(src)?,
+            },
+            b'b' => CommandType::Subscribe {
+                channels: value_to_keys(Value::parse(src)?)?,
+            },
+            b'u' => CommandType::Unsubscribe {
+                channels: value_to_keys(Value::parse(src)?)?,
+            },
+            b'q' => CommandType::PSubscribe {
+                patterns: value_to_keys(Value::parse(src)?)?,
+            },
+            b'Q' => CommandType::PUnsubscribe {
+                patterns: value_to_keys(Value::parse(src)?)?,
+            },
+            b'P' => CommandType::Publish {
+                payload: Value::parse(src)?,
+            },
+            b'T' => CommandType::Multi,
+            b'e' => CommandType::Exec,
+            b'r' => CommandType::Discard,
+            b'w' => CommandType::Watch {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'W' => CommandType::Unwatch,
+            b'h' => match Value::parse(src)? {
+                Value::Map(fields) if fields.is_empty() => return Err(wrong_arity("hset")),
+                Value::Map(fields) => CommandType::HSet { fields },
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "Expected map of fields".into(),
+                    });
+                }
+            },
+            b'H' => CommandType::HGet {
+                field: value_to_field(Value::parse(src)?)?,
+            },
+            b'j' => CommandType::HDel {
+                fields: value_to_keys(Value::parse(src)?)?,
+            },
+            b'A' => CommandType::HGetAll,
+            b'J' => CommandType::HIncrBy {
+                field: value_to_field(Value::parse(src)?)?,
+                delta: get_i64(src)?,
+            },
+            b'Y' => CommandType::HExists {
+                field: value_to_field(Value::parse(src)?)?,
+            },
+            b'l' => CommandType::LPush {
+                values: non_empty(value_to_values(Value::parse(src)?)?, "lpush")?,
+            },
+            b'L' => CommandType::RPush {
+                values: non_empty(value_to_values(Value::parse(src)?)?, "rpush")?,
+            },
+            b'o' => CommandType::LPop,
+            b'O' => CommandType::RPop,
+            b'v' => CommandType::LRange {
+                start: get_i64(src)?,
+                stop: get_i64(src)?,
+            },
+            b'z' => CommandType::LLen,
+            b'V' => CommandType::LTrim {
+                start: get_i64(src)?,
+                stop: get_i64(src)?,
+            },
+            b'k' => CommandType::LIndex {
+                index: get_i64(src)?,
+            },
+            b'K' => CommandType::LSet {
+                index: get_i64(src)?,
+                value: Value::parse(src)?,
+            },
+            b'f' => CommandType::BLPop {
+                keys: non_empty(value_to_keys(Value::parse(src)?)?, "blpop")?,
+                timeout: millis_to_expire(get_u64(src)?),
+            },
+            b'F' => CommandType::BRPop {
+                keys: non_empty(value_to_keys(Value::parse(src)?)?, "brpop")?,
+                timeout: millis_to_expire(get_u64(src)?),
+            },
+            b'a' => CommandType::SAdd {
+                members: non_empty(value_to_members(Value::parse(src)?)?, "sadd")?,
+            },
+            b'm' => CommandType::SRem {
+                members: value_to_members(Value::parse(src)?)?,
+            },
+            b'N' => CommandType::SIsMember {
+                member: Member::try_from(Value::parse(src)?)?,
+            },
+            b'U' => CommandType::SMembers,
+            b'Z' => CommandType::SCard,
+            b'0' => CommandType::SPop,
+            b'1' => CommandType::SRandMember,
+            b'2' => CommandType::SUnion {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'3' => CommandType::SInter {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'4' => CommandType::SDiff {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'5' => CommandType::SUnionStore {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'6' => CommandType::SInterStore {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'7' => CommandType::SDiffStore {
+                keys: value_to_keys(Value::parse(src)?)?,
+            },
+            b'8' => match Value::parse(src)? {
+                Value::SortedSet(members) if members.is_empty() => {
+                    return Err(wrong_arity("zadd"));
+                }
+                Value::SortedSet(members) => CommandType::ZAdd { members },
+                _ => {
+                    return Err(Error::BadRequest {
+                        msg: "Expected sorted set of members".into(),
+                    });
+                }
+            },
+            b'9' => CommandType::ZRem {
+                members: value_to_members(Value::parse(src)?)?,
+            },
+            b'@' => CommandType::ZScore {
+                member: Member::try_from(Value::parse(src)?)?,
+            },
+            b'+' => CommandType::ZIncrBy {
+                member: Member::try_from(Value::parse(src)?)?,
+                delta: to_score(get_f64(src)?)?,
+            },
+            b'<' => CommandType::ZRank {
+                member: Member::try_from(Value::parse(src)?)?,
+            },
+            b'>' => CommandType::ZRevRank {
+                member: Member::try_from(Value::parse(src)?)?,
+            },
+            b'(' => CommandType::ZRange {
+                start: get_i64(src)?,
+                stop: get_i64(src)?,
+            },
+            b')' => CommandType::ZRevRange {
+                start: get_i64(src)?,
+                stop: get_i64(src)?,
+            },
+            b'[' => CommandType::ZRangeByScore {
+                min: to_score(get_f64(src)?)?,
+                max: to_score(get_f64(src)?)?,
+                offset: get_i64(src)?,
+                count: get_i64(src)?,
+            },
+            b']' => CommandType::ZRevRangeByScore {
+                min: to_score(get_f64(src)?)?,
+                max: to_score(get_f64(src)?)?,
+                offset: get_i64(src)?,
+                count: get_i64(src)?,
+            },
+            b'#' => CommandType::ZCard,
+            b'{' => CommandType::ZPopMin {
+                count: get_i64(src)?,
+            },
+            b'}' => CommandType::ZPopMax {
+                count: get_i64(src)?,
+            },
+            b'&' => CommandType::XAdd {
+                id: value_to_id(Value::parse(src)?)?,
+                fields: value_to_entries(Value::parse(src)?)?,
+            },
+            b'/' => CommandType::XRange {
+                start: value_to_required_id(Value::parse(src)?)?,
+                end: value_to_required_id(Value::parse(src)?)?,
+                count: get_i64(src)?,
+            },
+            b'\\' => CommandType::XRevRange {
+                start: value_to_required_id(Value::parse(src)?)?,
+                end: value_to_required_id(Value::parse(src)?)?,
+                count: get_i64(src)?,
+            },
+            b';' => CommandType::XLen,
+            b':' => CommandType::XTrim {
+                max_len: get_u64(src)?,
+            },
+            b'=' => {
+                let (keys, ids) = streams_from(Value::parse(src)?, Value::parse(src)?)?;
+                CommandType::XRead {
+                    keys,
+                    ids,
+                    count: get_i64(src)?,
+                    block: millis_to_block(get_i64(src)?),
+                }
+            }
+            b'?' => {
+                let group = value_to_field(Value::parse(src)?)?;
+                let consumer = value_to_field(Value::parse(src)?)?;
+                let (keys, ids) = streams_from(Value::parse(src)?, Value::parse(src)?)?;
+                CommandType::XReadGroup {
+                    group,
+                    consumer,
+                    keys,
+                    ids,
+                    count: get_i64(src)?,
+                    block: millis_to_block(get_i64(src)?),
+                }
+            }
+            b'\'' => CommandType::XAck {
+                group: value_to_field(Value::parse(src)?)?,
+                ids: value_to_values(Value::parse(src)?)?
+                    .into_iter()
+                    .map(value_to_required_id)
+                    .collect::<Result<_, _>>()?,
+            },
+            b'"' => CommandType::XGroupCreate {
+                group: value_to_field(Value::parse(src)?)?,
+                id: value_to_id(Value::parse(src)?)?,
+                create: match Value::parse(src)? {
+                    Value::Boolean(create) => create,
+                    _ => {
+                        return Err(Error::BadRequest {
+                            msg: "Expected boolean".into(),
+                        });
+                    }
+                },
+            },
+            b'`' => CommandType::XPending {
+                group: value_to_field(Value::parse(src)?)?,
+                start: value_to_required_id(Value::parse(src)?)?,
+                end: value_to_required_id(Value::parse(src)?)?,
+                count: get_i64(src)?,
+                consumer: match Value::parse(src)? {
+                    Value::Null => None,
+                    consumer => Some(value_to_field(consumer)?),
+                },
+            },
+            b'^' => CommandType::Auth {
+                user: value_to_field(Value::parse(src)?)?,
+                password: value_to_field(Value::parse(src)?)?,
+            },
+            b'_' => {
+                let subcommand = get_i64(src)?;
+                let mut args = value_to_keys(Value::parse(src)?)?.into_iter();
+                match (subcommand, args.next()) {
+                    (ACL_SET_USER, Some(user)) => CommandType::AclSetUser {
+                        user,
+                        rules: args.collect(),
+                    },
+                    (ACL_LIST, None) => CommandType::AclList,
+                    (ACL_WHOAMI, None) => CommandType::AclWhoAmI,
+                    _ => return Err(Error::UnknownCommand),
+                }
+            }
+            b'|' => CommandType::Shutdown {
+                save: match Value::parse(src)? {
+                    Value::Null => None,
+                    Value::Boolean(save) => Some(save),
+                    _ => return Err(Error::InvalidBytes),
+                },
+            },
+            _ => unreachable!(),
+        };
+
+        Ok(Command { key, r#type })
+    }
+
+    const ANSWERS_PARSE_ERRORS: bool = true;
+
+    fn from_resp(frame: RespFrame) -> Result<(Self, RespReply), Error> {
+        resp::command_from_resp(frame)
+    }
+}
+
+impl protocol::TcpWrite for Command {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut encoded = vec![self.byte_type()];
+
+        let key_size = self.key.len() as u32;
+        encoded.extend_from_slice(&key_size.to_le_bytes());
+        encoded.extend_from_slice(self.key.as_bytes());
+
+        match &self.r#type {
+            CommandType::Set { value, expire }
+            | CommandType::SetNx { value, expire }
+            | CommandType::SetXx { value, expire } => {
+                encoded.extend_from_slice(&value.to_bytes());
+                encoded.extend_from_slice(&expire_to_millis(*expire).to_le_bytes());
+            }
+            CommandType::Expire { expire } => {
+                encoded.extend_from_slice(&expire_to_millis(Some(*expire)).to_le_bytes());
+            }
+            CommandType::MGet { keys }
+            | CommandType::MDel { keys }
+            | CommandType::Exists { keys }
+            | CommandType::Subscribe { channels: keys }
+            | CommandType::Unsubscribe { channels: keys }
+            | CommandType::PSubscribe { patterns: keys }
+            | CommandType::PUnsubscribe { patterns: keys }
+            | CommandType::Watch { keys }
+            | CommandType::HDel { fields: keys }
+            | CommandType::SUnion { keys }
+            | CommandType::SInter { keys }
+            | CommandType::SDiff { keys }
+            | CommandType::SUnionStore { keys }
+            | CommandType::SInterStore { keys }
+            | CommandType::SDiffStore { keys } => {
+                encoded.extend_from_slice(&keys_to_value(keys).to_bytes());
+            }
+            CommandType::SAdd { members } | CommandType::SRem { members } => {
+                encoded.extend_from_slice(&members_to_bytes(b'[', members));
+            }
+            CommandType::SIsMember { member }
+            | CommandType::ZScore { member }
+            | CommandType::ZRank { member }
+            | CommandType::ZRevRank { member } => {
+                encoded.extend_from_slice(&member.to_bytes());
+            }
+            CommandType::ZAdd { members } => {
+                encoded.extend_from_slice(&sorted_set_to_bytes(members));
+            }
+            CommandType::ZRem { members } => {
+                encoded.extend_from_slice(&members_to_bytes(b'[', members));
+            }
+            CommandType::ZIncrBy { member, delta } => {
+                encoded.extend_from_slice(&member.to_bytes());
+                encoded.extend_from_slice(&delta.to_le_bytes());
+            }
+            CommandType::ZRange { start, stop } | CommandType::ZRevRange { start, stop } => {
+                encoded.extend_from_slice(&start.to_le_bytes());
+                encoded.extend_from_slice(&stop.to_le_bytes());
+            }
+            CommandType::ZRangeByScore {
+                min,
+                max,
+                offset,
+                count,
+            }
+            | CommandType::ZRevRangeByScore {
+                min,
+                max,
+                offset,
+                count,
+            } => {
+                encoded.extend_from_slice(&min.to_le_bytes());
+                encoded.extend_from_slice(&max.to_le_bytes());
+                encoded.extend_from_slice(&offset.to_le_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+            }
+            CommandType::ZPopMin { count } | CommandType::ZPopMax { count } => {
+                encoded.extend_from_slice(&count.to_le_bytes());
+            }
+            CommandType::Publish { payload } => {
+                encoded.extend_from_slice(&payload.to_bytes());
+            }
+            CommandType::XAdd { id, fields } => {
+                encoded.extend_from_slice(&id_to_value(*id).to_bytes());
+                encoded.extend_from_slice(&entries_to_value(fields).to_bytes());
+            }
+            CommandType::XRange { start, end, count }
+            | CommandType::XRevRange { start, end, count } => {
+                encoded.extend_from_slice(&id_to_value(Some(*start)).to_bytes());
+                encoded.extend_from_slice(&id_to_value(Some(*end)).to_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+            }
+            CommandType::XTrim { max_len } => {
+                encoded.extend_from_slice(&max_len.to_le_bytes());
+            }
+            CommandType::XRead {
+                keys,
+                ids,
+                count,
+                block,
+            } => {
+                encoded.extend_from_slice(&keys_to_value(keys).to_bytes());
+                encoded.extend_from_slice(&ids_to_value(ids.iter().copied()).to_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+                encoded.extend_from_slice(&block_to_millis(*block).to_le_bytes());
+            }
+            CommandType::XReadGroup {
+                group,
+                consumer,
+                keys,
+                ids,
+                count,
+                block,
+            } => {
+                encoded.extend_from_slice(&Value::String(group.clone()).to_bytes());
+                encoded.extend_from_slice(&Value::String(consumer.clone()).to_bytes());
+                encoded.extend_from_slice(&keys_to_value(keys).to_bytes());
+                encoded.extend_from_slice(&ids_to_value(ids.iter().copied()).to_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+                encoded.extend_from_slice(&block_to_millis(*block).to_le_bytes());
+            }
+            CommandType::XAck { group, ids } => {
+                encoded.extend_from_slice(&Value::String(group.clone()).to_bytes());
+                encoded.extend_from_slice(&ids_to_value(ids.iter().copied().map(Some)).to_bytes());
+            }
+            CommandType::XGroupCreate { group, id, create } => {
+                encoded.extend_from_slice(&Value::String(group.clone()).to_bytes());
+                encoded.extend_from_slice(&id_to_value(*id).to_bytes());
+                encoded.extend_from_slice(&Value::Boolean(*create).to_bytes());
+            }
+            CommandType::XPending {
+                group,
+                start,
+                end,
+                count,
+                consumer,
+            } => {
+                encoded.extend_from_slice(&Value::String(group.clone()).to_bytes());
+                encoded.extend_from_slice(&id_to_value(Some(*start)).to_bytes());
+                encoded.extend_from_slice(&id_to_value(Some(*end)).to_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+                let consumer = consumer.clone().map_or(Value::Null, Value::String);
+                encoded.extend_from_slice(&consumer.to_bytes());
+            }
+            CommandType::Keys { pattern } => {
+                encoded.extend_from_slice(&Value::String(pattern.clone()).to_bytes());
+            }
+            CommandType::Scan {
+                cursor,
+                pattern,
+                count,
+            } => {
+                encoded.extend_from_slice(&cursor.to_le_bytes());
+                let pattern = pattern.clone().map_or(Value::Null, Value::String);
+                encoded.extend_from_slice(&pattern.to_bytes());
+                encoded.extend_from_slice(&count.to_le_bytes());
+            }
+            CommandType::Rename { new_key } | CommandType::RenameNx { new_key } => {
+                encoded.extend_from_slice(&Value::String(new_key.clone()).to_bytes());
+            }
+            CommandType::Auth { user, password } => {
+                encoded.extend_from_slice(&Value::String(user.clone()).to_bytes());
+                encoded.extend_from_slice(&Value::String(password.clone()).to_bytes());
+            }
+            CommandType::AclSetUser { user, rules } => {
+                encoded.extend_from_slice(&ACL_SET_USER.to_le_bytes());
+                let args: Vec<_> = [user].into_iter().chain(rules).cloned().collect();
+                encoded.extend_from_slice(&keys_to_value(&args).to_bytes());
+            }
+            CommandType::AclList => {
+                encoded.extend_from_slice(&ACL_LIST.to_le_bytes());
+                encoded.extend_from_slice(&keys_to_value(&[]).to_bytes());
+            }
+            CommandType::AclWhoAmI => {
+                encoded.extend_from_slice(&ACL_WHOAMI.to_le_bytes());
+                encoded.extend_from_slice(&keys_to_value(&[]).to_bytes());
+            }
+            CommandType::Shutdown { save } => {
+                encoded.extend_from_slice(&Value::from(*save).to_bytes());
+            }
+            CommandType::HSet { fields } => {
+                encoded.extend_from_slice(&map_to_bytes(fields));
+            }
+            CommandType::HGet { field } | CommandType::HExists { field } => {
+                encoded.extend_from_slice(&Value::String(field.clone()).to_bytes());
+            }
+            CommandType::LPush { values } | CommandType::RPush { values } => {
+                encoded.extend_from_slice(&sequence_to_bytes(b'[', values));
+            }
+            CommandType::LRange { start, stop } | CommandType::LTrim { start, stop } => {
+                encoded.extend_from_slice(&start.to_le_bytes());
+                encoded.extend_from_slice(&stop.to_le_bytes());
+            }
+            CommandType::LIndex { index } => {
+                encoded.extend_from_slice(&index.to_le_bytes());
+            }
+            CommandType::LSet { index, value } => {
+                encoded.extend_from_slice(&index.to_le_bytes());
+                encoded.extend_from_slice(&value.to_bytes());
+            }
+            CommandType::BLPop { keys, timeout } | CommandType::BRPop { keys, timeout } => {
+                encoded.extend_from_slice(&keys_to_value(keys).to_bytes());
+                encoded.extend_from_slice(&expire_to_millis(*timeout).to_le_bytes());
+            }
+            CommandType::HIncrBy { field, delta } => {
+                encoded.extend_from_slice(&Value::String(field.clone()).to_bytes());
+                encoded.extend_from_slice(&delta.to_le_bytes());
+            }
+            CommandType::MSet { entries } => {
+                encoded.extend_from_slice(&entries_to_value(entries).to_bytes());
+            }
+            CommandType::IncrBy { delta } => {
+                encoded.extend_from_slice(&delta.to_le_bytes());
+            }
+            CommandType::IncrByFloat { delta } => {
+                encoded.extend_from_slice(&delta.to_le_bytes());
+            }
+            CommandType::CompareAndSwap { expected, value } => {
+                encoded.extend_from_slice(&expected.to_bytes());
+                encoded.extend_from_slice(&value.to_bytes());
+            }
+            CommandType::Get
+            | CommandType::Delete
+            | CommandType::Ttl
+            | CommandType::Persist
+            | CommandType::Save
+            | CommandType::BgSave
+            | CommandType::RewriteAof
+            | CommandType::Incr
+            | CommandType::Decr
+            | CommandType::Multi
+            | CommandType::Exec
+            | CommandType::Discard
+            | CommandType::Unwatch
+            | CommandType::HGetAll
+            | CommandType::LPop
+            | CommandType::RPop
+            | CommandType::LLen
+            | CommandType::SMembers
+            | CommandType::SCard
+            | CommandType::SPop
+            | CommandType::SRandMember
+            | CommandType::ZCard
+            | CommandType::XLen
+            | CommandType::Type
+            | CommandType::DbSize
+            | CommandType::FlushAll => {}
+        }
+
+        encoded.extend_from_slice(b"\r\n");
+
+        encoded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        protocol::{Response, TcpRead, TcpWrite},
+        pubsub::{Push, PushKind},
+    };
+
+    /// Small xorshift generator, good enough to produce varied payloads without extra deps.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn bytes(&mut self, len: usize) -> Vec<u8> {
+            (0..len)
+                .map(|_| {
+                    // bias towards separator bytes so they show up in most payloads
+                    match self.next() % 4 {
+                        0 => b'\r',
+                        1 => b'\n',
+                        _ => self.next() as u8,
+                    }
+                })
+                .collect()
+        }
+
+        fn member(&mut self) -> Member {
+            let len = (self.next() % 16) as usize;
+            match self.next() % 4 {
+                0 => Member::Boolean(self.next().is_multiple_of(2)),
+                1 => Member::Number(self.next() as i64),
+                2 => Member::String(String::from_utf8_lossy(&self.bytes(len)).into_owned()),
+                _ => Member::Bytes(self.bytes(len)),
+            }
+        }
+
+        fn sorted_set(&mut self, len: usize) -> SortedSet {
+            let mut set = SortedSet::new();
+            for _ in 0..len {
+                let member = self.member();
+                set.insert(member, self.next() as i64 as f64 / 1000.0);
+            }
+            set
+        }
+
+        /// Stream with `len` entries, half of them delivered to a consumer group.
+        fn stream(&mut self, len: usize, depth: u32) -> Stream {
+            let mut stream = Stream::new();
+            for ms in 1..=len as u64 {
+                let fields = (0..self.next() % 4)
+                    .map(|_| {
+                        let field = String::from_utf8_lossy(&self.bytes(8)).into_owned();
+                        (field, self.value(depth))
+                    })
+                    .collect();
+                stream
+                    .add(Some(StreamId::new(ms, self.next())), fields)
+                    .unwrap();
+            }
+            stream.create_group("\r\n".into(), None).unwrap();
+            stream
+                .create_group("group".into(), Some(StreamId::MIN))
+                .unwrap();
+            stream.read_group("group", "\r\n", None, len / 2).unwrap();
+            stream
+        }
+
+        fn value(&mut self, depth: u32) -> Value {
+            let len = (self.next() % 64) as usize;
+            match self.next() % if depth == 0 { 8 } else { 12 } {
+                0 => Value::Boolean(self.next().is_multiple_of(2)),
+                1 => Value::Number(self.next() as i64),
+                2 => Value::String(String::from_utf8_lossy(&self.bytes(len)).into_owned()),
+                3 => Value::Bytes(self.bytes(len)),
+                4 => Value::Set((0..len % 8).map(|_| self.member()).collect()),
+                5 => Value::SortedSet(self.sorted_set(len % 8)),
+                6 => Value::Float(self.next() as i64 as f64 / 1000.0),
+                7 => Value::Null,
+                8 => Value::Array((0..len % 8).map(|_| self.value(depth - 1)).collect()),
+                9 => Value::List((0..len % 8).map(|_| self.value(depth - 1)).collect()),
+                10 => Value::Stream(self.stream(len % 8, depth - 1)),
+                _ => Value::Map(
+                    (0..len % 8)
+                        .map(|_| {
+                            let field = String::from_utf8_lossy(&self.bytes(8)).into_owned();
+                            (field, self.value(depth - 1))
+                        })
+                        .collect(),
+                ),
+            }
+        }
+    }
+
+    /// Validates `bytes` as a single frame and returns the parsed request.
+    fn read_frame<T: TcpRead>(bytes: &[u8]) -> Result<T, Error> {
+        let mut cursor = Cursor::new(bytes);
+        T::validate(&mut cursor)?;
+        assert_eq!(cursor.position() as usize, bytes.len());
+        cursor.set_position(0);
+        T::parse(&mut cursor)
+    }
+
+    #[test]
+    fn test_value() -> anyhow::Result<()> {
+        let val_1 = Value::Number(1);
+        let val_2 = Value::String("maciek".into());
+        let val_3 = Value::Number(3);
+        let value = Value::Array(vec![val_1, val_2, val_3]);
 
         let bytes = value.to_bytes();
 
@@ -253,6 +2957,172 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_value_with_separator_inside() -> anyhow::Result<()> {
+        // both the payload and the little-endian length (0x0a0d) contain "\r\n"
+        let value = Value::Bytes(b"a\r\nb".repeat(0x0a0d / 4 + 1)[..0x0a0d].to_vec());
+        let command = Command::set("blob\r\n", value);
+        let bytes = command.to_bytes();
+
+        let parsed = read_frame::<Command>(&bytes)?;
+        assert_eq!(parsed.key, "blob\r\n");
+        assert_eq!(parsed.to_bytes(), bytes);
+
+        let number = Command::set("n", Value::Number(0x0a0d_0a0d));
+        let bytes = number.to_bytes();
+        assert_eq!(read_frame::<Command>(&bytes)?.to_bytes(), bytes);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_binary_round_trip() -> anyhow::Result<()> {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..1_000 {
+            let key = String::from_utf8_lossy(&rng.bytes(16)).into_owned();
+            // expiry of 0x0d0a ms puts separator bytes in the middle of the frame as well
+            let command = match rng.next() % 23 {
+                0 => Command::set(&key, rng.value(3)),
+                1 => Command::set_ex(&key, rng.value(3), Duration::from_millis(0x0d0a)),
+                2 => Command::mget(&[&key, "\r\n"]),
+                3 => Command::mset(vec![
+                    (key.clone(), rng.value(3)),
+                    ("\r\n".into(), rng.value(3)),
+                ]),
+                4 => Command::incr_by(&key, rng.next() as i64),
+                5 => Command::set_nx(&key, rng.value(3), Some(Duration::from_millis(0x0d0a))),
+                6 => Command::compare_and_swap(&key, rng.value(3), rng.value(3)),
+                7 => Command::lpush(&key, vec![rng.value(3), rng.value(3)]),
+                8 => Command::lset(&key, rng.next() as i64, rng.value(3)),
+                9 => Command::brpop(&[&key, "\r\n"], Some(Duration::from_millis(0x0d0a))),
+                10 => Command::sadd(&key, vec![rng.member(), rng.member()]),
+                11 => Command::zadd(&key, rng.sorted_set(3)),
+                12 => Command::zrevrange_by_score(&key, f64::NEG_INFINITY, 0.5, Some((1, 0x0d0a))),
+                13 => Command::incr_by_float(&key, f64::from_bits(0x0d0a_0d0a_0d0a_0d0a)),
+                14 => Command::xadd(
+                    &key,
+                    Some(StreamId::new(0x0d0a, 1)),
+                    vec![("\r\n".into(), rng.value(3))],
+                ),
+                15 => Command::xread_group(
+                    "group",
+                    &key,
+                    &[(&key, None), ("\r\n", Some(StreamId::MAX))],
+                    Some(0x0d0a),
+                    Some(Duration::ZERO),
+                ),
+                16 => Command::scan(0x0d0a_0d0a, Some("\r\n*"), Some(0x0d0a)),
+                17 => Command::rename_nx(&key, "\r\n"),
+                18 => Command::auth(&key, "\r\n"),
+                19 => Command::acl_set_user(&key, &["on", ">\r\n"]),
+                20 => Command::acl_whoami(),
+                21 => Command::shutdown([None, Some(true), Some(false)][rng.next() as usize % 3]),
+                _ => Command::expire(&key, Duration::from_millis(rng.next() >> 16)),
+            };
+            // maps may be encoded in different order, so frames are compared after parsing
+            let bytes = command.to_bytes();
+            assert_eq!(read_frame::<Command>(&bytes)?, command);
+
+            let response = Response::Multi(vec![
+                Response::Payload(rng.value(3)),
+                Response::Null,
+                Response::error("\r\n"),
+                Response::Push(Push {
+                    kind: PushKind::PMessage,
+                    pattern: Some("\r\n*".into()),
+                    channel: key.clone(),
+                    payload: rng.value(3),
+                }),
+            ]);
+            let bytes = response.to_bytes();
+            assert_eq!(read_frame::<Response>(&bytes)?, response);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoded_len() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..200 {
+            let value = rng.value(3);
+            assert_eq!(value.encoded_len(), value.to_bytes().len(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn test_partial_frame_is_incomplete() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let bytes = Command::set("key", rng.value(3)).to_bytes();
+
+        for end in 0..bytes.len() {
+            let mut cursor = Cursor::new(&bytes[..end]);
+            assert!(matches!(
+                Command::validate(&mut cursor),
+                Err(Error::Incomplete)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_nesting_is_limited() -> anyhow::Result<()> {
+        let nested =
+            |depth| (0..depth).fold(Value::Number(1), |value, _| Value::Array(vec![value]));
+
+        let value = nested(MAX_DEPTH);
+        assert_eq!(read_frame::<Value>(&value.to_bytes())?, value);
+
+        let bytes = nested(MAX_DEPTH + 1).to_bytes();
+        assert!(matches!(
+            Value::validate(&mut Cursor::new(&bytes[..])),
+            Err(Error::Protocol { .. })
+        ));
+        assert!(matches!(
+            Value::parse(&mut Cursor::new(&bytes[..])),
+            Err(Error::Protocol { .. })
+        ));
+
+        // rejected long before the end, which would take too deep recursion to reach
+        let bytes = b"[\x01\0\0\0".repeat(500_000);
+        assert!(matches!(
+            Value::validate(&mut Cursor::new(&bytes[..])),
+            Err(Error::Protocol { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_collections_are_rejected() {
+        let is_arity_error = |command: Command| {
+            matches!(
+                read_frame::<Command>(&command.to_bytes()),
+                Err(Error::BadRequest { msg }) if msg.starts_with("wrong number of arguments")
+            )
+        };
+        assert!(is_arity_error(Command::lpush("k", Vec::new())));
+        assert!(is_arity_error(Command::rpush("k", Vec::new())));
+        assert!(is_arity_error(Command::hset("k", HashMap::new())));
+        assert!(is_arity_error(Command::sadd("k", Vec::new())));
+        assert!(is_arity_error(Command::zadd("k", SortedSet::new())));
+        assert!(is_arity_error(Command::blpop(&[], None)));
+        assert!(is_arity_error(Command::brpop(&[], None)));
+    }
+
+    #[test]
+    fn test_bad_separator_is_rejected() {
+        let mut bytes = Command::get("key").to_bytes();
+        let len = bytes.len();
+        bytes[len - 2..].copy_from_slice(b"xx");
+
+        let mut cursor = Cursor::new(&bytes[..]);
+        assert!(matches!(
+            Command::validate(&mut cursor),
+            Err(Error::InvalidBytes)
+        ));
+    }
+
     // #[test]
     // fn test_command() -> anyhow::Result<()> {
     //     let val_1 = Value::Number(1);
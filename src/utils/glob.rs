@@ -0,0 +1,102 @@
+//! Redis style glob patterns, used to match channel names and keys.
+//!
+//! Supported syntax: `*` (any sequence), `?` (any single byte), `[abc]` / `[a-z]` (byte from
+//! set or range, `[^...]` negates it) and `\` escaping the next byte.
+
+/// Returns `true` if whole `text` matches `pattern`.
+pub fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            // collapse repeated stars, they match the same as a single one
+            let rest = match rest.iter().position(|&b| b != b'*') {
+                Some(start) => &rest[start..],
+                None => return true,
+            };
+            (0..=text.len()).any(|skip| matches(rest, &text[skip..]))
+        }
+        Some((b'?', rest)) => !text.is_empty() && matches(rest, &text[1..]),
+        Some((b'[', rest)) => {
+            let Some((&byte, text_rest)) = text.split_first() else {
+                return false;
+            };
+            match match_class(rest, byte) {
+                Some((true, rest)) => matches(rest, text_rest),
+                Some((false, _)) => false,
+                // unterminated class is matched literally, like redis does
+                None => byte == b'[' && matches(rest, text_rest),
+            }
+        }
+        Some((b'\\', rest)) if !rest.is_empty() => {
+            text.first() == Some(&rest[0]) && matches(&rest[1..], &text[1..])
+        }
+        Some((&expected, rest)) => text.first() == Some(&expected) && matches(rest, &text[1..]),
+    }
+}
+
+/// Matches `byte` against class that `pattern` starts with (just after `[`). Returns whether it
+/// matched together with rest of the pattern after closing `]`, or `None` if class is not
+/// terminated.
+fn match_class(pattern: &[u8], byte: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((matched != negate, rest)),
+            [b'\\', escaped, rest @ ..] => {
+                matched |= *escaped == byte;
+                pattern = rest;
+            }
+            [from, b'-', to, rest @ ..] if *to != b']' => {
+                let (low, high) = if from <= to { (from, to) } else { (to, from) };
+                matched |= (*low..=*high).contains(&byte);
+                pattern = rest;
+            }
+            [other, rest @ ..] => {
+                matched |= *other == byte;
+                pattern = rest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("news.*", "news.sport", true),
+            ("news.*", "news.", true),
+            ("news.*", "weather", false),
+            ("*", "", true),
+            ("h?llo", "hello", true),
+            ("h?llo", "hllo", false),
+            ("h[ae]llo", "hallo", true),
+            ("h[ae]llo", "hillo", false),
+            ("h[^e]llo", "hallo", true),
+            ("h[^e]llo", "hello", false),
+            ("h[a-c]llo", "hbllo", true),
+            ("h[c-a]llo", "hbllo", true),
+            ("h\\*llo", "h*llo", true),
+            ("h\\*llo", "hello", false),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("[abc", "[abc", true),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                matches(pattern.as_bytes(), text.as_bytes()),
+                *expected,
+                "{pattern} ~ {text}"
+            );
+        }
+    }
+}
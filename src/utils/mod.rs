@@ -1,2 +1,4 @@
+pub mod bytes;
 pub mod command;
-pub mod bytes;
\ No newline at end of file
+pub mod glob;
+pub mod tls;
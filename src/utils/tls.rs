@@ -0,0 +1,43 @@
+//! Loading of PEM files with certificates and private keys, shared by TLS server and client.
+
+use std::path::Path;
+
+use tokio_rustls::rustls::{
+    RootCertStore,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+use crate::error::Error;
+
+/// Certificate chain from `path`, starting with the certificate of the owner.
+pub fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| Error::Tls {
+            msg: format!("can't read certificates from {}: {e}", path.display()),
+        })?;
+    if certs.is_empty() {
+        return Err(Error::Tls {
+            msg: format!("no certificates in {}", path.display()),
+        });
+    }
+    Ok(certs)
+}
+
+/// The first private key from `path`, in PKCS#1, PKCS#8 or SEC1 format.
+pub fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| Error::Tls {
+        msg: format!("can't read private key from {}: {e}", path.display()),
+    })
+}
+
+/// Certificate authorities from `path` that peer certificates are verified against.
+pub fn load_roots(path: &Path) -> Result<RootCertStore, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(|e| Error::Tls {
+            msg: format!("invalid CA certificate in {}: {e}", path.display()),
+        })?;
+    }
+    Ok(roots)
+}